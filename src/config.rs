@@ -10,6 +10,9 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::utils::compression::Compression;
+use crate::utils::storage::StorageLayout;
+
 /// Uses CARGO_MANIFEST_DIR environment variable to know where is project root at runtime
 pub fn get_project_root() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -18,11 +21,47 @@ pub fn get_project_root() -> PathBuf {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub buffer_pool: BufferPoolConfig,
+    #[serde(default)]
+    pub transactions: TransactionConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DatabaseConfig {
     pub url: String,
+    /// How a new database is stored, existing ones keep their layout
+    #[serde(default)]
+    pub layout: StorageLayout,
+    /// Compression of the data files of tables created without choosing one
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BufferPoolConfig {
+    /// Number of pages cached in memory
+    pub pages: usize,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        BufferPoolConfig { pages: 256 }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TransactionConfig {
+    /// How long a statement waits for a lock held by another transaction before failing
+    pub lock_timeout_ms: u64,
+}
+
+impl Default for TransactionConfig {
+    fn default() -> Self {
+        TransactionConfig {
+            lock_timeout_ms: 5000,
+        }
+    }
 }
 
 impl Config {
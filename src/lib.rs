@@ -0,0 +1,6 @@
+pub mod config;
+pub mod prompts;
+pub mod repl;
+pub mod sql_compilator;
+pub mod utils;
+pub mod virtual_machine;
@@ -1,14 +1,15 @@
-mod config;
-mod prompts;
-pub mod repl;
-pub mod utils;
-
-mod sql_compilator;
-mod virtual_machine;
-
 use std::fs::OpenOptions;
 use std::io::Write;
-use utils::file_handler;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+use trusdb::config::{self, BufferPoolConfig, Config, TransactionConfig};
+use trusdb::sql_compilator::parser::Instruction;
+use trusdb::utils::compression::Compression;
+use trusdb::utils::file_handler;
+use trusdb::utils::storage::{self, StorageLayout};
+use trusdb::virtual_machine::instruction_processor::{ExecutionOutput, InstructionProcessor};
+use trusdb::{prompts, repl};
 
 const DATABASE_DEFAULT_PATH: &str = "trusdb";
 const DATABASE_NAME: &str = "TrusDB";
@@ -43,13 +44,101 @@ fn build_logger() {
         .init();
 }
 
-fn create_database_if_not_exists() -> std::io::Result<file_handler::Database> {
-    file_handler::Database::create(DATABASE_DEFAULT_PATH, DATABASE_NAME)
+/// Reads the storage layout, default compression, buffer pool and transaction settings from
+/// etc/config.toml, falling back to defaults if the file cannot be loaded
+fn load_config() -> (
+    StorageLayout,
+    Compression,
+    BufferPoolConfig,
+    TransactionConfig,
+) {
+    let path = config::get_project_root().join("etc/config.toml");
+    match Config::load(&path) {
+        Ok(config) => (
+            config.database.layout,
+            config.database.compression,
+            config.buffer_pool,
+            config.transactions,
+        ),
+        Err(error) => {
+            log::warn!("Could not load {}: {error}", path.display());
+            Default::default()
+        }
+    }
+}
+
+fn create_database_if_not_exists(
+    layout: StorageLayout,
+    buffer_pool: &BufferPoolConfig,
+) -> std::io::Result<file_handler::Database> {
+    file_handler::Database::create_with_layout(
+        DATABASE_DEFAULT_PATH,
+        DATABASE_NAME,
+        buffer_pool.pages,
+        layout,
+    )
+}
+
+/// Copies the database stored at `source` to `destination` with the other storage layout
+fn convert_database(source: &str, destination: &str) -> std::io::Result<()> {
+    let layout = storage::convert(Path::new(source), Path::new(destination))?;
+    println!("Converted {source} to {destination}, stored as a {layout}");
+    Ok(())
+}
+
+/// Checks the database stored at `path` instead of starting the prompt, see `CHECK DATABASE`,
+/// without changing it. The process exits with status 1 if problems were found.
+fn check_database(path: &str, buffer_pool: &BufferPoolConfig) -> std::io::Result<()> {
+    // Recovering the log or upgrading the format would change the database being checked
+    let mut database = file_handler::Database::open_read_only(path, buffer_pool.pages)?;
+    let session = database.open_session();
+    let output = InstructionProcessor::new(&mut database, session)
+        .process_instruction(&Instruction::CheckDatabase)
+        .map_err(std::io::Error::other)?;
+    drop(database);
+    match output {
+        ExecutionOutput::Message(message) => println!("{message}"),
+        ExecutionOutput::Rows(result_set) => {
+            prompts::print_result_set(&result_set);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
 }
 
-fn main() -> std::io::Result<()> {
+fn main() -> ExitCode {
     build_logger();
-    let database = create_database_if_not_exists()?;
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        // Such as another process using the database
+        Err(error) => {
+            prompts::print_error(&error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> std::io::Result<()> {
+    let (layout, compression, buffer_pool, transactions) = load_config();
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let mut database = match arguments.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--check", ref path @ ..] => {
+            let path = path.first().copied().unwrap_or(DATABASE_DEFAULT_PATH);
+            return check_database(path, &buffer_pool);
+        }
+        ["--convert", source, destination] => return convert_database(source, destination),
+        // Other processes may read the database at the same time, but none may write to it
+        ["--read-only"] => {
+            file_handler::Database::open_read_only(DATABASE_DEFAULT_PATH, buffer_pool.pages)?
+        }
+        // Throwaway database, lost once the program stops
+        ["--memory"] => {
+            file_handler::Database::create(storage::MEMORY_PATH, DATABASE_NAME, buffer_pool.pages)?
+        }
+        _ => create_database_if_not_exists(layout, &buffer_pool)?,
+    };
+    database.set_lock_timeout(Duration::from_millis(transactions.lock_timeout_ms));
+    database.set_default_compression(compression);
     repl::run_repl(database);
     Ok(())
 }
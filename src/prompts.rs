@@ -1,4 +1,58 @@
+use crate::virtual_machine::instruction_processor::ResultSet;
+
 pub fn print_welcome_prompt() {
     // TODO: Implement --help argument
     println!("Welcome to TrusDB!\n\nTo print help about available commands, use trusdb --help")
 }
+
+pub fn print_error(error: &dyn std::error::Error) {
+    println!("Error: {error}")
+}
+
+/// Prints rows as a table, with a header holding column names and a final row count
+pub fn print_result_set(result_set: &ResultSet) {
+    let cells: Vec<Vec<String>> = result_set
+        .rows
+        .iter()
+        .map(|row| row.iter().map(|value| value.to_string()).collect())
+        .collect();
+    let widths: Vec<usize> = result_set
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(position, name)| {
+            cells
+                .iter()
+                .map(|row| row[position].chars().count())
+                .chain([name.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let format_line = |values: &[String]| {
+        values
+            .iter()
+            .zip(&widths)
+            // Padded by hand, as format widths are limited to 16 bits and values may be larger
+            .map(|(value, &width)| {
+                let padding = " ".repeat(width - value.chars().count());
+                format!(" {value}{padding} ")
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+    println!("{}", format_line(&result_set.columns));
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+")
+    );
+    for row in &cells {
+        println!("{}", format_line(row));
+    }
+    let row_count = cells.len();
+    println!("({row_count} row{})", if row_count == 1 { "" } else { "s" });
+}
@@ -0,0 +1,199 @@
+use crate::utils::collation::Collation;
+use crate::utils::file_handler::Value;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BinaryOperator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    And,
+    Or,
+    /// `->`: extracts a JSON node
+    JsonGet,
+    /// `->>`: extracts a JSON node as text
+    JsonGetText,
+}
+
+impl BinaryOperator {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Equal
+                | BinaryOperator::NotEqual
+                | BinaryOperator::Less
+                | BinaryOperator::LessOrEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterOrEqual
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UnaryOperator {
+    Not,
+    Minus,
+}
+
+/// Expression tree, as found in select lists, WHERE clauses or inserted values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expression {
+    Literal(Value),
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expression>,
+    },
+    Binary {
+        left: Box<Expression>,
+        operator: BinaryOperator,
+        right: Box<Expression>,
+    },
+    IsNull {
+        operand: Box<Expression>,
+        negated: bool,
+    },
+    /// `IS [NOT] TRUE` / `IS [NOT] FALSE`, which never evaluate to NULL
+    IsTruth {
+        operand: Box<Expression>,
+        expected: bool,
+        negated: bool,
+    },
+    /// `IS [NOT] DISTINCT FROM`: equality where NULL is a regular value
+    IsDistinctFrom {
+        left: Box<Expression>,
+        right: Box<Expression>,
+        negated: bool,
+    },
+    InList {
+        operand: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+    Function {
+        name: String,
+        arguments: Vec<Expression>,
+    },
+    /// `operand COLLATE name`: same value, compared with an explicit collation
+    Collate {
+        operand: Box<Expression>,
+        collation: Collation,
+    },
+    /// `ARRAY[a, b, ...]`
+    Array(Vec<Expression>),
+    /// `operand[index]`, indexes starting at 1
+    Subscript {
+        operand: Box<Expression>,
+        index: Box<Expression>,
+    },
+    /// `operand[lower:upper]`, both bounds being inclusive and optional
+    Slice {
+        operand: Box<Expression>,
+        lower: Option<Box<Expression>>,
+        upper: Option<Box<Expression>>,
+    },
+    /// `left <operator> ANY (array)` / `left <operator> ALL (array)`
+    Quantified {
+        left: Box<Expression>,
+        operator: BinaryOperator,
+        all: bool,
+        array: Box<Expression>,
+    },
+}
+
+/// Functions computing a single value out of every row of a group
+pub const AGGREGATE_FUNCTIONS: [&str; 5] = ["count", "sum", "avg", "min", "max"];
+
+impl Expression {
+    /// Name given to the column produced by this expression in a result set
+    pub fn output_name(&self) -> String {
+        match self {
+            Expression::Column { name, .. } => name.clone(),
+            Expression::Collate { operand, .. }
+            | Expression::Subscript { operand, .. }
+            | Expression::Slice { operand, .. } => operand.output_name(),
+            Expression::Array(_) => String::from("array"),
+            Expression::Function { name, .. } => name.to_lowercase(),
+            Expression::Binary {
+                operator: BinaryOperator::JsonGet | BinaryOperator::JsonGetText,
+                right,
+                ..
+            } => match right.as_ref() {
+                Expression::Literal(Value::Text(key)) => key.clone(),
+                _ => String::from("?column?"),
+            },
+            _ => String::from("?column?"),
+        }
+    }
+
+    /// Expressions this one is directly made of
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Literal(_) | Expression::Column { .. } => Vec::new(),
+            Expression::Function { arguments, .. } | Expression::Array(arguments) => {
+                arguments.iter().collect()
+            }
+            Expression::Unary { operand, .. }
+            | Expression::IsNull { operand, .. }
+            | Expression::IsTruth { operand, .. }
+            | Expression::Collate { operand, .. } => vec![operand],
+            Expression::Binary { left, right, .. }
+            | Expression::IsDistinctFrom { left, right, .. }
+            | Expression::Subscript {
+                operand: left,
+                index: right,
+            }
+            | Expression::Quantified {
+                left, array: right, ..
+            } => vec![left, right],
+            Expression::Slice {
+                operand,
+                lower,
+                upper,
+            } => std::iter::once(operand)
+                .chain(lower)
+                .chain(upper)
+                .map(Box::as_ref)
+                .collect(),
+            Expression::InList { operand, list, .. } => {
+                std::iter::once(operand.as_ref()).chain(list).collect()
+            }
+        }
+    }
+
+    /// Whether this expression reads a column of given name, whatever its table
+    pub fn references_column(&self, name: &str) -> bool {
+        match self {
+            Expression::Column { name: column, .. } => column.eq_ignore_ascii_case(name),
+            _ => self
+                .children()
+                .into_iter()
+                .any(|child| child.references_column(name)),
+        }
+    }
+
+    pub fn is_aggregate_call(&self) -> bool {
+        matches!(self, Expression::Function { name, .. } if AGGREGATE_FUNCTIONS.contains(&name.as_str()))
+    }
+
+    /// Whether this expression contains a call to an aggregate function
+    pub fn contains_aggregate(&self) -> bool {
+        self.is_aggregate_call()
+            || self
+                .children()
+                .into_iter()
+                .any(Expression::contains_aggregate)
+    }
+}
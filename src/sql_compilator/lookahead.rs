@@ -17,7 +17,7 @@ impl<I: Iterator> Lookahead<I> {
 
     /// Returns a reference to the n-th element of the original iterator
     pub fn peek(&mut self, n: usize) -> Option<&I::Item> {
-        while self.buf.len() < n {
+        while self.buf.len() <= n {
             if let Some(item) = self.iter.next() {
                 self.buf.push_back(item);
             } else {
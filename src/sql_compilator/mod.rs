@@ -1,3 +1,4 @@
+pub mod expression;
 pub mod lookahead;
 pub mod parser;
 pub mod tokenizer;
@@ -1,6 +1,12 @@
-use crate::sql_compilator::lookahead::LookaheadExt;
-use crate::sql_compilator::tokenizer::{CommandType, Token, TokenType};
-use crate::utils::file_handler::{Column, DataType};
+use crate::sql_compilator::expression::{BinaryOperator, Expression, UnaryOperator};
+use crate::sql_compilator::lookahead::{Lookahead, LookaheadExt};
+use crate::sql_compilator::tokenizer::{
+    CommandType, DelimiterType, OperatorType, Token, TokenType,
+};
+use crate::utils::collation::Collation;
+use crate::utils::compression::Compression;
+use crate::utils::file_handler::{Column, DataType, TableLayout, Value};
+use crate::utils::mvcc::IsolationLevel;
 use thiserror::Error;
 
 // TODO list (general for this script):
@@ -12,29 +18,28 @@ use thiserror::Error;
 pub enum ParsingError {
     #[error("First token for statement was not a valid command: '{found_content}'")]
     FirstTokenNotCommand { found_content: String },
-    #[error("Expected token was not found")]
+    #[error("Expected token was not found: {expected:?}")]
     TokenNotFound { expected: TokenType },
-    #[error(
-        "A character is missing in a token: '{missing_char}' is expected at position \
-        {char_expected_position}, found '{found_content}' instead"
-    )]
-    MissingCharInToken {
-        missing_char: char,
-        char_expected_position: usize,
+    #[error("Expected {expected}, found '{found_content}' instead")]
+    UnexpectedToken {
+        expected: String,
         found_content: String,
     },
-    #[error("Unexpected '{unexpected_char}' found at position {char_position} in '{content}'")]
-    UnexpectedCharInToken {
-        unexpected_char: char,
-        char_position: usize,
-        content: String,
-    },
-    #[error("Tokens to parse a column were found empty")]
-    EmptyColumnTokens,
+    #[error("Invalid literal '{content}'")]
+    InvalidLiteral { content: String },
     #[error("No data type was provided for column {column_name}")]
     NoDataTypeProvided { column_name: String },
     #[error("Unexpected data type '{found}'")]
     UnexpectedDataTypeProvided { found: String },
+    #[error("Unknown collation '{found}'")]
+    UnknownCollation { found: String },
+    #[error("Unknown compression '{found}'")]
+    UnknownCompression { found: String },
+    #[error("Collation cannot be applied to column {column_name} of type {data_type:?}")]
+    NonCollatableType {
+        column_name: String,
+        data_type: DataType,
+    },
     #[error("Expected '{missing_char}' at the end of statement")]
     MissingEndOfStatementChar { missing_char: char },
 }
@@ -42,34 +47,233 @@ pub enum ParsingError {
 type InstructionResult = Result<Option<Instruction>, ParsingError>;
 
 #[derive(Debug)]
-pub struct Instruction {
-    pub base_command: CommandType,
+pub enum Instruction {
+    /// `CREATE TABLE name (column, ...) [STORED AS {ROWS | COLUMNAR}] [WITH (compression = 'lz4')]`
+    CreateTable {
+        target_table: String,
+        columns: Vec<Column>,
+        layout: TableLayout,
+        /// Compression of the data file, the default one of the database if not given
+        compression: Option<Compression>,
+    },
+    InsertInto(InsertStatement),
+    Select(SelectStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
+    CreateType {
+        name: String,
+        labels: Vec<String>,
+    },
+    AlterType(AlterTypeStatement),
+    CreateIndex(CreateIndexStatement),
+    DropIndex {
+        name: String,
+        /// Whether dropping an index that does not exist is silently ignored
+        if_exists: bool,
+    },
+    /// `BEGIN [TRANSACTION | WORK]`
+    Begin,
+    /// `COMMIT [TRANSACTION | WORK]`
+    Commit,
+    /// `ROLLBACK [TRANSACTION | WORK]`
+    Rollback,
+    /// `SAVEPOINT name`
+    Savepoint {
+        name: String,
+    },
+    /// `ROLLBACK [TRANSACTION | WORK] TO [SAVEPOINT] name`
+    RollbackToSavepoint {
+        name: String,
+    },
+    /// `RELEASE [SAVEPOINT] name`
+    ReleaseSavepoint {
+        name: String,
+    },
+    /// `SET TRANSACTION ISOLATION LEVEL {READ COMMITTED | REPEATABLE READ | SERIALIZABLE}`
+    SetTransaction {
+        isolation_level: IsolationLevel,
+    },
+    /// `VACUUM [table]`, every table when none is given
+    Vacuum {
+        target_table: Option<String>,
+    },
+    /// `CHECK DATABASE`, or `PRAGMA integrity_check`
+    CheckDatabase,
+}
+
+/// `CREATE [UNIQUE] INDEX [IF NOT EXISTS] name ON table (expression [ASC | DESC], ...)`
+#[derive(Debug)]
+pub struct CreateIndexStatement {
+    pub name: String,
     pub target_table: String,
-    pub columns: Vec<Column>,
+    /// Whether two rows may not have the same key, unless it holds a NULL
+    pub unique: bool,
+    /// Whether creating an index that already exists is silently ignored
+    pub if_not_exists: bool,
+    /// Indexed columns or expressions, along with whether they are sorted in descending order
+    pub columns: Vec<(Expression, bool)>,
 }
 
-const CHARACTERS_TO_CLEAN: [char; 3] = [',', ')', ';'];
+/// `ALTER TYPE name ADD VALUE [IF NOT EXISTS] 'label' [BEFORE | AFTER 'label']`
+#[derive(Debug)]
+pub struct AlterTypeStatement {
+    pub name: String,
+    pub new_label: String,
+    /// Whether adding a label that already exists is silently ignored
+    pub if_not_exists: bool,
+    /// Where the label is placed in the order of the type, at the end by default
+    pub position: Option<LabelPosition>,
+}
+
+#[derive(Debug)]
+pub enum LabelPosition {
+    Before(String),
+    After(String),
+}
+
+#[derive(Debug)]
+pub struct InsertStatement {
+    pub target_table: String,
+    /// Columns listed after the table name, `None` meaning every column in schema order
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Expression>>,
+}
+
+/// `UPDATE table SET column = expression, ... [WHERE condition]`
+#[derive(Debug)]
+pub struct UpdateStatement {
+    pub target_table: String,
+    pub assignments: Vec<(String, Expression)>,
+    pub filter: Option<Expression>,
+}
+
+/// `DELETE FROM table [WHERE condition]`
+#[derive(Debug)]
+pub struct DeleteStatement {
+    pub target_table: String,
+    pub filter: Option<Expression>,
+}
+
+#[derive(Debug)]
+pub struct SelectStatement {
+    /// Whether duplicate output rows are removed
+    pub distinct: bool,
+    pub projection: Vec<SelectItem>,
+    pub from: Vec<FromItem>,
+    pub filter: Option<Expression>,
+    pub group_by: Vec<Expression>,
+    pub having: Option<Expression>,
+    pub order_by: Vec<OrderByItem>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum SelectItem {
+    Wildcard,
+    Expression {
+        expression: Expression,
+        alias: Option<String>,
+    },
+}
+
+/// Source of rows in a FROM clause. Items are cross joined from left to right, and arguments of
+/// a table-valued function may reference columns of the items on its left.
+#[derive(Debug)]
+pub enum FromItem {
+    Table {
+        name: String,
+        alias: Option<String>,
+    },
+    Function {
+        name: String,
+        arguments: Vec<Expression>,
+        alias: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct OrderByItem {
+    pub expression: Expression,
+    pub descending: bool,
+    /// Explicit `NULLS FIRST` / `NULLS LAST`. When absent, NULLs sort as if they were larger than
+    /// any other value, like in PostgreSQL.
+    pub nulls_first: Option<bool>,
+}
+
+/// Words that end an expression or a select item, so they cannot be used as implicit aliases
+const RESERVED_KEYWORDS: [&str; 18] = [
+    "from", "where", "group", "having", "order", "by", "limit", "as", "and", "or", "not", "is",
+    "in", "null", "values", "nulls", "collate", "distinct",
+];
 
 pub struct Parser<'token> {
-    tokens: &'token [Token],
+    tokens: Lookahead<std::slice::Iter<'token, Token>>,
 }
 
 impl<'token> Parser<'token> {
     pub fn new(tokens: &'token [Token]) -> Self {
-        Parser { tokens }
+        Parser {
+            tokens: tokens.iter().lookahead(),
+        }
     }
 
-    pub fn parse_tokens(&self) -> InstructionResult {
-        let some_starting_command: Option<&Token> = self.tokens.first();
+    /// Parses the next statement, up to and including its final `;`. Returns `None` once every
+    /// token has been consumed.
+    pub fn parse_tokens(&mut self) -> InstructionResult {
+        let some_starting_command: Option<&Token> = self.tokens.next();
         if let Some(starting_command) = some_starting_command {
             if let TokenType::Command(cmd_type) = &starting_command.token_type {
                 let instruction = match cmd_type {
-                    CommandType::CreateTable => self.parse_create_table(cmd_type)?,
+                    CommandType::CreateTable => self.parse_create_table()?,
+                    CommandType::CreateType => self.parse_create_type()?,
+                    CommandType::AlterType => self.parse_alter_type()?,
+                    CommandType::CreateIndex => self.parse_create_index(false)?,
+                    CommandType::CreateUniqueIndex => {
+                        self.expect_keyword("index")?;
+                        self.parse_create_index(true)?
+                    }
+                    CommandType::DropIndex => self.parse_drop_index()?,
                     CommandType::Select => self.parse_select()?,
                     CommandType::InsertInto => self.parse_insert_into()?,
                     CommandType::Update => self.parse_update()?,
                     CommandType::Delete => self.parse_delete()?,
+                    CommandType::Begin => {
+                        self.consume_transaction_keyword();
+                        Some(Instruction::Begin)
+                    }
+                    CommandType::Commit => {
+                        self.consume_transaction_keyword();
+                        Some(Instruction::Commit)
+                    }
+                    CommandType::Rollback => self.parse_rollback()?,
+                    CommandType::Savepoint => Some(Instruction::Savepoint {
+                        name: self.parse_identifier()?,
+                    }),
+                    CommandType::Release => {
+                        self.consume_savepoint_keyword();
+                        Some(Instruction::ReleaseSavepoint {
+                            name: self.parse_identifier()?,
+                        })
+                    }
+                    CommandType::SetTransaction => self.parse_set_transaction()?,
+                    CommandType::Vacuum => {
+                        let target_table = match self.peek_token() {
+                            Some(token) if token.token_type == TokenType::Expression => {
+                                Some(self.parse_identifier()?)
+                            }
+                            _ => None,
+                        };
+                        Some(Instruction::Vacuum { target_table })
+                    }
+                    CommandType::CheckDatabase => Some(Instruction::CheckDatabase),
+                    CommandType::Pragma => {
+                        self.expect_keyword("integrity_check")?;
+                        Some(Instruction::CheckDatabase)
+                    }
                 };
+                if !self.consume_delimiter(DelimiterType::Semicolon) {
+                    return Err(ParsingError::MissingEndOfStatementChar { missing_char: ';' });
+                }
                 Ok(instruction)
             } else {
                 Err(ParsingError::FirstTokenNotCommand {
@@ -77,148 +281,943 @@ impl<'token> Parser<'token> {
                 })
             }
         } else {
-            // In the case of an empty query, an empty Vec of instructions should be returned
+            // In the case of an empty query, no instruction should be returned
             Ok(None)
         }
     }
 
-    fn parse_create_table(&self, cmd_type: &CommandType) -> InstructionResult {
-        // At this point, the second token should be the name of the table (else raise a ParsingError)
-        if self.tokens.len() <= 1 {
-            return Err(ParsingError::TokenNotFound {
-                expected: TokenType::Expression,
-            });
+    fn parse_create_table(&mut self) -> InstructionResult {
+        // At this point, the next token should be the name of the table (else raise a
+        // ParsingError)
+        let table_name = self.parse_identifier()?;
+        self.expect_delimiter(DelimiterType::OpenParen)?;
+        let mut found_columns: Vec<Column> = Vec::new();
+        loop {
+            found_columns.push(self.parse_column_declaration()?);
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
+            }
         }
-        // After the check above, we can safely make a peekable iterator over tokens, and ignore the
-        // first element.
-        let mut tokens_iter = self.tokens[1..].iter().peekable();
-        // Here we can also safely unwrap, thanks to the check above
-        let table_name = tokens_iter.next().unwrap();
-        if let Some(open_paren_position) = table_name.content.chars().position(|c| c == '(') {
-            return Err(ParsingError::UnexpectedCharInToken {
-                unexpected_char: '(',
-                char_position: open_paren_position,
-                content: table_name.content.to_string(),
-            });
+        self.expect_delimiter(DelimiterType::CloseParen)?;
+        let mut layout = TableLayout::default();
+        if self.consume_keyword("stored") {
+            self.expect_keyword("as")?;
+            layout = match self.parse_identifier()?.to_lowercase().as_str() {
+                "rows" => TableLayout::Rows,
+                "columnar" => TableLayout::Columnar,
+                found => {
+                    return Err(ParsingError::UnexpectedToken {
+                        expected: String::from("ROWS or COLUMNAR"),
+                        found_content: found.to_string(),
+                    });
+                }
+            };
         }
-        {
-            let some_open_paren = tokens_iter.peek();
-            if some_open_paren.is_none() {
-                return Err(ParsingError::TokenNotFound {
-                    expected: TokenType::Expression,
-                });
-            }
-            if !&some_open_paren.unwrap().content.starts_with('(') {
-                return Err(ParsingError::MissingCharInToken {
-                    missing_char: '(',
-                    char_expected_position: 0,
-                    found_content: some_open_paren.unwrap().content.to_string(),
-                });
+        let mut compression = None;
+        if self.consume_keyword("with") {
+            self.expect_delimiter(DelimiterType::OpenParen)?;
+            loop {
+                self.expect_keyword("compression")?;
+                if !self.consume_operator(OperatorType::Equal) {
+                    return Err(ParsingError::TokenNotFound {
+                        expected: TokenType::Operator(OperatorType::Equal),
+                    });
+                }
+                compression = Some(Compression::from_string(self.parse_string_literal()?)?);
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
             }
+            self.expect_delimiter(DelimiterType::CloseParen)?;
         }
-        let mut found_columns: Vec<Column> = Vec::new();
-        while tokens_iter.peek().is_some() {
-            let mut cloned_iter = tokens_iter.clone();
-            let some_end_char_position: Option<usize> = cloned_iter
-                .clone()
-                .position(|token| token.content.chars().any(|c| [',', ')'].contains(&c)));
-            if let Some(end_pos) = some_end_char_position {
-                let column_tokens: Vec<&Token> = cloned_iter.by_ref().take(end_pos + 1).collect();
-                found_columns.push(self.parse_column_declaration(column_tokens)?);
-                tokens_iter = cloned_iter;
-            }
-        }
-        if let Some(last_token) = tokens_iter.last() {
-            if !last_token.content.contains(')') {
-                let expected_closing_paren_position: Option<usize> = last_token
-                    .content
-                    .char_indices()
-                    .find(|&(_, c)| c.is_ascii_alphabetic())
-                    .map(|(i, _)| i);
-                return Err(ParsingError::MissingCharInToken {
-                    missing_char: ')',
-                    char_expected_position: expected_closing_paren_position.unwrap(),
-                    found_content: last_token.content.to_string(),
-                });
+        Ok(Some(Instruction::CreateTable {
+            target_table: table_name,
+            columns: found_columns,
+            layout,
+            compression,
+        }))
+    }
+
+    fn parse_create_type(&mut self) -> InstructionResult {
+        let name = self.parse_identifier()?;
+        self.expect_keyword("as")?;
+        self.expect_keyword("enum")?;
+        self.expect_delimiter(DelimiterType::OpenParen)?;
+        let mut labels = Vec::new();
+        if !self.consume_delimiter(DelimiterType::CloseParen) {
+            loop {
+                labels.push(self.parse_string_literal()?);
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
             }
-            if !last_token.content.ends_with(';') {
-                return Err(ParsingError::MissingEndOfStatementChar { missing_char: ';' });
+            self.expect_delimiter(DelimiterType::CloseParen)?;
+        }
+        Ok(Some(Instruction::CreateType { name, labels }))
+    }
+
+    fn parse_alter_type(&mut self) -> InstructionResult {
+        let name = self.parse_identifier()?;
+        self.expect_keyword("add")?;
+        self.expect_keyword("value")?;
+        let if_not_exists = self.consume_keyword("if");
+        if if_not_exists {
+            self.expect_keyword("not")?;
+            self.expect_keyword("exists")?;
+        }
+        let new_label = self.parse_string_literal()?;
+        let position = if self.consume_keyword("before") {
+            Some(LabelPosition::Before(self.parse_string_literal()?))
+        } else if self.consume_keyword("after") {
+            Some(LabelPosition::After(self.parse_string_literal()?))
+        } else {
+            None
+        };
+        Ok(Some(Instruction::AlterType(AlterTypeStatement {
+            name,
+            new_label,
+            if_not_exists,
+            position,
+        })))
+    }
+
+    fn parse_create_index(&mut self, unique: bool) -> InstructionResult {
+        let if_not_exists = self.consume_keyword("if");
+        if if_not_exists {
+            self.expect_keyword("not")?;
+            self.expect_keyword("exists")?;
+        }
+        let name = self.parse_identifier()?;
+        self.expect_keyword("on")?;
+        let target_table = self.parse_identifier()?;
+        self.expect_delimiter(DelimiterType::OpenParen)?;
+        let mut columns = Vec::new();
+        loop {
+            let expression = self.parse_expression()?;
+            let descending = if self.consume_keyword("desc") {
+                true
+            } else {
+                self.consume_keyword("asc");
+                false
+            };
+            columns.push((expression, descending));
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
             }
         }
-        Ok(Some(Instruction {
-            base_command: cmd_type.clone(),
-            target_table: table_name.content.to_string(),
-            columns: found_columns,
-        }))
+        self.expect_delimiter(DelimiterType::CloseParen)?;
+        Ok(Some(Instruction::CreateIndex(CreateIndexStatement {
+            name,
+            target_table,
+            unique,
+            if_not_exists,
+            columns,
+        })))
     }
 
-    fn parse_column_declaration(&self, column_tokens: Vec<&Token>) -> Result<Column, ParsingError> {
-        if column_tokens.is_empty() {
-            return Err(ParsingError::EmptyColumnTokens);
+    fn parse_drop_index(&mut self) -> InstructionResult {
+        let if_exists = self.consume_keyword("if");
+        if if_exists {
+            self.expect_keyword("exists")?;
         }
-        let mut column_tokens_iter = column_tokens.into_iter().lookahead();
-        let column_name: String = column_tokens_iter
-            .next()
-            .unwrap()
-            .content
-            .chars()
-            .filter(|&c| c != '(')
-            .collect();
-        let column_type: DataType = match column_tokens_iter.next() {
-            None => Err(ParsingError::NoDataTypeProvided {
-                column_name: column_name.clone(),
+        let name = self.parse_identifier()?;
+        Ok(Some(Instruction::DropIndex { name, if_exists }))
+    }
+
+    /// Skips the optional `TRANSACTION` or `WORK` keyword of transaction commands
+    fn consume_transaction_keyword(&mut self) {
+        let _ = self.consume_keyword("transaction") || self.consume_keyword("work");
+    }
+
+    /// Skips the optional `SAVEPOINT` keyword before a savepoint name
+    fn consume_savepoint_keyword(&mut self) {
+        let _ = self.consume_keyword("savepoint");
+    }
+
+    fn parse_set_transaction(&mut self) -> InstructionResult {
+        self.expect_keyword("isolation")?;
+        self.expect_keyword("level")?;
+        let isolation_level = if self.consume_keyword("serializable") {
+            IsolationLevel::Serializable
+        } else if self.consume_keyword("repeatable") {
+            self.expect_keyword("read")?;
+            IsolationLevel::RepeatableRead
+        } else if self.consume_keyword("read") {
+            self.expect_keyword("committed")?;
+            IsolationLevel::ReadCommitted
+        } else {
+            return Err(ParsingError::UnexpectedToken {
+                expected: String::from("READ COMMITTED, REPEATABLE READ or SERIALIZABLE"),
+                found_content: self
+                    .tokens
+                    .peek(0)
+                    .map(|token| token.content.to_string())
+                    .unwrap_or_default(),
+            });
+        };
+        Ok(Some(Instruction::SetTransaction { isolation_level }))
+    }
+
+    fn parse_rollback(&mut self) -> InstructionResult {
+        self.consume_transaction_keyword();
+        if !self.consume_keyword("to") {
+            return Ok(Some(Instruction::Rollback));
+        }
+        self.consume_savepoint_keyword();
+        let name = self.parse_identifier()?;
+        Ok(Some(Instruction::RollbackToSavepoint { name }))
+    }
+
+    fn parse_string_literal(&mut self) -> Result<String, ParsingError> {
+        let token = self.next_token(TokenType::Value)?;
+        match parse_literal(&token.content)? {
+            Value::Text(text) => Ok(text),
+            _ => Err(ParsingError::UnexpectedToken {
+                expected: String::from("a string literal"),
+                found_content: token.content.to_string(),
             }),
-            Some(token) => DataType::from_string(
-                token
-                    .content
-                    .chars()
-                    .filter(|c| ![',', ')', ';'].contains(c))
-                    .collect(),
-            ),
-        }?;
+        }
+    }
+
+    fn parse_column_declaration(&mut self) -> Result<Column, ParsingError> {
+        let column_name: String = self.parse_identifier()?;
+        let column_type: DataType = match self.peek_token() {
+            Some(token) if token.token_type == TokenType::Expression => {
+                self.tokens.next();
+                let data_type = DataType::from_string(token.content.to_string())?;
+                if self.consume_delimiter(DelimiterType::OpenBracket) {
+                    self.expect_delimiter(DelimiterType::CloseBracket)?;
+                    DataType::Array(Box::new(data_type))
+                } else {
+                    data_type
+                }
+            }
+            _ => {
+                return Err(ParsingError::NoDataTypeProvided {
+                    column_name: column_name.clone(),
+                });
+            }
+        };
         // By default, this value is set to false, unless a primary key token is found
         let mut is_primary_key = false;
-        log::debug!("{:?}", column_tokens_iter.peek(2));
-        if column_tokens_iter.peek(2).is_some() {
-            let result: String = column_tokens_iter
-                .take(2)
-                .map(|t| t.content.as_str())
-                .collect::<Vec<_>>()
-                .join(" ");
-            log::debug!("Primary key content: {result:?}");
-            let clean_result: String = result
-                .to_lowercase()
-                .chars()
-                .filter(|c| !CHARACTERS_TO_CLEAN.contains(c))
-                .collect();
-            is_primary_key = clean_result == "primary key";
+        let mut is_unique = false;
+        let mut nullable = true;
+        let mut default = None;
+        let mut collation = None;
+        loop {
+            if self.consume_keyword("primary") {
+                self.expect_keyword("key")?;
+                is_primary_key = true;
+                nullable = false;
+            } else if self.consume_keyword("unique") {
+                is_unique = true;
+            } else if self.consume_keyword("not") {
+                self.expect_keyword("null")?;
+                nullable = false;
+            } else if self.consume_keyword("null") {
+                nullable = true;
+            } else if self.consume_keyword("default") {
+                default = Some(self.parse_expression()?);
+            } else if self.consume_keyword("collate") {
+                collation = Some(self.parse_collation()?);
+            } else {
+                break;
+            }
+        }
+        let is_collatable = match &column_type {
+            DataType::Array(element_type) => **element_type == DataType::Text,
+            data_type => *data_type == DataType::Text,
+        };
+        if collation.is_some() && !is_collatable {
+            return Err(ParsingError::NonCollatableType {
+                column_name,
+                data_type: column_type,
+            });
         }
         Ok(Column {
             name: column_name,
             data_type: column_type,
             values: Vec::new(),
             is_primary_key,
+            is_unique,
+            nullable,
+            default,
+            collation: collation.unwrap_or_default(),
         })
     }
 
-    fn parse_select(&self) -> InstructionResult {
-        log::debug!("{:?}", self.tokens);
-        todo!("Not implemented");
-        Ok(None)
+    fn parse_select(&mut self) -> InstructionResult {
+        let distinct = self.consume_keyword("distinct");
+        if !distinct {
+            self.consume_keyword("all");
+        }
+        let mut projection = Vec::new();
+        loop {
+            projection.push(self.parse_select_item()?);
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
+            }
+        }
+        let mut from = Vec::new();
+        if self.consume_keyword("from") {
+            loop {
+                from.push(self.parse_from_item()?);
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
+            }
+        }
+        let filter = self.parse_where_clause()?;
+        let mut group_by = Vec::new();
+        if self.consume_keyword("group") {
+            self.expect_keyword("by")?;
+            loop {
+                group_by.push(self.parse_expression()?);
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
+            }
+        }
+        let having = if self.consume_keyword("having") {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let mut order_by = Vec::new();
+        if self.consume_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let expression = self.parse_expression()?;
+                let descending = if self.consume_keyword("desc") {
+                    true
+                } else {
+                    self.consume_keyword("asc");
+                    false
+                };
+                let nulls_first = if self.consume_keyword("nulls") {
+                    if self.consume_keyword("first") {
+                        Some(true)
+                    } else {
+                        self.expect_keyword("last")?;
+                        Some(false)
+                    }
+                } else {
+                    None
+                };
+                order_by.push(OrderByItem {
+                    expression,
+                    descending,
+                    nulls_first,
+                });
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
+            }
+        }
+        let limit = if self.consume_keyword("limit") {
+            let token = self.next_token(TokenType::Value)?;
+            Some(
+                token
+                    .content
+                    .parse()
+                    .map_err(|_| ParsingError::InvalidLiteral {
+                        content: token.content.to_string(),
+                    })?,
+            )
+        } else {
+            None
+        };
+        Ok(Some(Instruction::Select(SelectStatement {
+            distinct,
+            projection,
+            from,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+        })))
     }
 
-    fn parse_insert_into(&self) -> InstructionResult {
-        todo!("Not implemented");
-        Ok(None)
+    fn parse_select_item(&mut self) -> Result<SelectItem, ParsingError> {
+        if self.consume_operator(OperatorType::Star) {
+            return Ok(SelectItem::Wildcard);
+        }
+        let expression = self.parse_expression()?;
+        let alias = self.parse_alias()?;
+        Ok(SelectItem::Expression { expression, alias })
     }
 
-    fn parse_update(&self) -> InstructionResult {
-        todo!("Not implemented");
-        Ok(None)
+    fn parse_from_item(&mut self) -> Result<FromItem, ParsingError> {
+        let name = self.parse_identifier()?;
+        if self.consume_delimiter(DelimiterType::OpenParen) {
+            let arguments = self.parse_arguments()?;
+            let alias = self.parse_alias()?;
+            return Ok(FromItem::Function {
+                name,
+                arguments,
+                alias,
+            });
+        }
+        let alias = self.parse_alias()?;
+        Ok(FromItem::Table { name, alias })
     }
 
-    fn parse_delete(&self) -> InstructionResult {
-        todo!("Not implemented");
+    /// Parses an optional `[AS] alias`
+    fn parse_alias(&mut self) -> Result<Option<String>, ParsingError> {
+        if self.consume_keyword("as") {
+            return Ok(Some(self.parse_identifier()?));
+        }
+        match self.peek_token() {
+            Some(token)
+                if token.token_type == TokenType::Expression
+                    && !RESERVED_KEYWORDS.contains(&token.content.to_lowercase().as_str()) =>
+            {
+                self.tokens.next();
+                Ok(Some(token.content.to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_insert_into(&mut self) -> InstructionResult {
+        let target_table = self.parse_identifier()?;
+        let columns = if self.consume_delimiter(DelimiterType::OpenParen) {
+            let mut columns = Vec::new();
+            loop {
+                columns.push(self.parse_identifier()?);
+                if !self.consume_delimiter(DelimiterType::Comma) {
+                    break;
+                }
+            }
+            self.expect_delimiter(DelimiterType::CloseParen)?;
+            Some(columns)
+        } else {
+            None
+        };
+        self.expect_keyword("values")?;
+        let mut rows = Vec::new();
+        loop {
+            self.expect_delimiter(DelimiterType::OpenParen)?;
+            rows.push(self.parse_arguments()?);
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
+            }
+        }
+        Ok(Some(Instruction::InsertInto(InsertStatement {
+            target_table,
+            columns,
+            rows,
+        })))
+    }
+
+    fn parse_update(&mut self) -> InstructionResult {
+        let target_table = self.parse_identifier()?;
+        self.expect_keyword("set")?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.parse_identifier()?;
+            if !self.consume_operator(OperatorType::Equal) {
+                return Err(ParsingError::TokenNotFound {
+                    expected: TokenType::Operator(OperatorType::Equal),
+                });
+            }
+            assignments.push((column, self.parse_expression()?));
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
+            }
+        }
+        let filter = self.parse_where_clause()?;
+        Ok(Some(Instruction::Update(UpdateStatement {
+            target_table,
+            assignments,
+            filter,
+        })))
+    }
+
+    fn parse_delete(&mut self) -> InstructionResult {
+        self.expect_keyword("from")?;
+        let target_table = self.parse_identifier()?;
+        let filter = self.parse_where_clause()?;
+        Ok(Some(Instruction::Delete(DeleteStatement {
+            target_table,
+            filter,
+        })))
+    }
+
+    /// Parses an optional `WHERE <condition>`
+    fn parse_where_clause(&mut self) -> Result<Option<Expression>, ParsingError> {
+        if self.consume_keyword("where") {
+            return Ok(Some(self.parse_expression()?));
+        }
         Ok(None)
     }
+
+    /// Parses a comma separated list of expressions, the opening parenthesis being already
+    /// consumed
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, ParsingError> {
+        let mut arguments = Vec::new();
+        if self.consume_delimiter(DelimiterType::CloseParen) {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.parse_expression()?);
+            if !self.consume_delimiter(DelimiterType::Comma) {
+                break;
+            }
+        }
+        self.expect_delimiter(DelimiterType::CloseParen)?;
+        Ok(arguments)
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expression, ParsingError> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, ParsingError> {
+        let mut left = self.parse_and()?;
+        while self.consume_keyword("or") {
+            let right = self.parse_and()?;
+            left = binary(left, BinaryOperator::Or, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ParsingError> {
+        let mut left = self.parse_not()?;
+        while self.consume_keyword("and") {
+            let right = self.parse_not()?;
+            left = binary(left, BinaryOperator::And, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, ParsingError> {
+        if self.consume_keyword("not") {
+            let operand = self.parse_not()?;
+            return Ok(Expression::Unary {
+                operator: UnaryOperator::Not,
+                operand: Box::new(operand),
+            });
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParsingError> {
+        let left = self.parse_other_operators()?;
+        if self.consume_keyword("is") {
+            return self.parse_is_test(left);
+        }
+        let next_tokens = (self.peek_token(), self.tokens.peek(1).copied());
+        let is_not_in = matches!(
+            next_tokens,
+            (Some(not), Some(in_keyword))
+                if not.content.eq_ignore_ascii_case("not")
+                    && in_keyword.content.eq_ignore_ascii_case("in")
+        );
+        if is_not_in || self.peek_keyword("in") {
+            if is_not_in {
+                self.tokens.next();
+            }
+            self.expect_keyword("in")?;
+            self.expect_delimiter(DelimiterType::OpenParen)?;
+            return Ok(Expression::InList {
+                operand: Box::new(left),
+                list: self.parse_arguments()?,
+                negated: is_not_in,
+            });
+        }
+        let operator = match self.peek_operator() {
+            Some(OperatorType::Equal) => BinaryOperator::Equal,
+            Some(OperatorType::NotEqual) => BinaryOperator::NotEqual,
+            Some(OperatorType::Less) => BinaryOperator::Less,
+            Some(OperatorType::LessOrEqual) => BinaryOperator::LessOrEqual,
+            Some(OperatorType::Greater) => BinaryOperator::Greater,
+            Some(OperatorType::GreaterOrEqual) => BinaryOperator::GreaterOrEqual,
+            _ => return Ok(left),
+        };
+        self.tokens.next();
+        let is_quantified = matches!(
+            (self.peek_token(), self.tokens.peek(1).copied()),
+            (Some(quantifier), Some(paren))
+                if ["any", "some", "all"]
+                    .iter()
+                    .any(|keyword| quantifier.content.eq_ignore_ascii_case(keyword))
+                    && paren.token_type == TokenType::Delimiter(DelimiterType::OpenParen)
+        );
+        if is_quantified {
+            let all = self.consume_keyword("all");
+            if !all && !self.consume_keyword("any") {
+                self.expect_keyword("some")?;
+            }
+            self.expect_delimiter(DelimiterType::OpenParen)?;
+            let array = self.parse_expression()?;
+            self.expect_delimiter(DelimiterType::CloseParen)?;
+            return Ok(Expression::Quantified {
+                left: Box::new(left),
+                operator,
+                all,
+                array: Box::new(array),
+            });
+        }
+        let right = self.parse_other_operators()?;
+        Ok(binary(left, operator, right))
+    }
+
+    /// Parses what follows `<operand> IS`: `[NOT] NULL`, `[NOT] UNKNOWN`, `[NOT] TRUE`,
+    /// `[NOT] FALSE` or `[NOT] DISTINCT FROM <expression>`
+    fn parse_is_test(&mut self, operand: Expression) -> Result<Expression, ParsingError> {
+        let operand = Box::new(operand);
+        let negated = self.consume_keyword("not");
+        if self.consume_keyword("null") || self.consume_keyword("unknown") {
+            return Ok(Expression::IsNull { operand, negated });
+        }
+        if self.consume_keyword("distinct") {
+            self.expect_keyword("from")?;
+            let right = self.parse_other_operators()?;
+            return Ok(Expression::IsDistinctFrom {
+                left: operand,
+                right: Box::new(right),
+                negated,
+            });
+        }
+        match self.peek_token() {
+            Some(token) if token.token_type == TokenType::Value => {
+                if let Value::Bool(expected) = parse_literal(&token.content)? {
+                    self.tokens.next();
+                    return Ok(Expression::IsTruth {
+                        operand,
+                        expected,
+                        negated,
+                    });
+                }
+                Err(ParsingError::UnexpectedToken {
+                    expected: String::from("NULL, TRUE, FALSE, UNKNOWN or DISTINCT FROM"),
+                    found_content: token.content.to_string(),
+                })
+            }
+            _ => Err(ParsingError::UnexpectedToken {
+                expected: String::from("NULL, TRUE, FALSE, UNKNOWN or DISTINCT FROM"),
+                found_content: self
+                    .peek_token()
+                    .map(|token| token.content.to_string())
+                    .unwrap_or_default(),
+            }),
+        }
+    }
+
+    /// Operators that do not have a precedence of their own in SQL (`||`, `->`, `->>`): they bind
+    /// looser than arithmetic and tighter than comparisons
+    fn parse_other_operators(&mut self) -> Result<Expression, ParsingError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let operator = match self.peek_operator() {
+                Some(OperatorType::Concat) => BinaryOperator::Concat,
+                Some(OperatorType::Arrow) => BinaryOperator::JsonGet,
+                Some(OperatorType::DoubleArrow) => BinaryOperator::JsonGetText,
+                _ => return Ok(left),
+            };
+            self.tokens.next();
+            let right = self.parse_additive()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expression, ParsingError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let operator = match self.peek_operator() {
+                Some(OperatorType::Plus) => BinaryOperator::Plus,
+                Some(OperatorType::Minus) => BinaryOperator::Minus,
+                _ => return Ok(left),
+            };
+            self.tokens.next();
+            let right = self.parse_multiplicative()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expression, ParsingError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let operator = match self.peek_operator() {
+                Some(OperatorType::Star) => BinaryOperator::Multiply,
+                Some(OperatorType::Slash) => BinaryOperator::Divide,
+                Some(OperatorType::Percent) => BinaryOperator::Modulo,
+                _ => return Ok(left),
+            };
+            self.tokens.next();
+            let right = self.parse_unary()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParsingError> {
+        if self.consume_operator(OperatorType::Minus) {
+            // The sign is part of an integer literal, as the smallest one cannot be negated from
+            // a positive value
+            if let Some(token) = self.peek_token()
+                && token.token_type == TokenType::Value
+                && token.content.bytes().all(|byte| byte.is_ascii_digit())
+            {
+                self.tokens.next();
+                let literal = parse_literal(&format!("-{}", token.content))?;
+                return Ok(Expression::Literal(literal));
+            }
+            let operand = self.parse_unary()?;
+            return Ok(match operand {
+                Expression::Literal(Value::Integer(integer)) if integer != i64::MIN => {
+                    Expression::Literal(Value::Integer(-integer))
+                }
+                Expression::Literal(Value::Real(real)) => Expression::Literal(Value::Real(-real)),
+                operand => Expression::Unary {
+                    operator: UnaryOperator::Minus,
+                    operand: Box::new(operand),
+                },
+            });
+        }
+        let mut expression = self.parse_primary()?;
+        loop {
+            if self.consume_delimiter(DelimiterType::OpenBracket) {
+                expression = self.parse_subscript(expression)?;
+            } else if self.consume_keyword("collate") {
+                expression = Expression::Collate {
+                    operand: Box::new(expression),
+                    collation: self.parse_collation()?,
+                };
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    /// Parses what follows `<operand>[`: either `index]` or `[lower]:[upper]]`
+    fn parse_subscript(&mut self, operand: Expression) -> Result<Expression, ParsingError> {
+        let operand = Box::new(operand);
+        let lower = if self.peek_delimiter(DelimiterType::Colon) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        if !self.consume_delimiter(DelimiterType::Colon) {
+            self.expect_delimiter(DelimiterType::CloseBracket)?;
+            return Ok(Expression::Subscript {
+                operand,
+                index: lower.ok_or(ParsingError::TokenNotFound {
+                    expected: TokenType::Expression,
+                })?,
+            });
+        }
+        let upper = if self.peek_delimiter(DelimiterType::CloseBracket) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        self.expect_delimiter(DelimiterType::CloseBracket)?;
+        Ok(Expression::Slice {
+            operand,
+            lower,
+            upper,
+        })
+    }
+
+    fn parse_collation(&mut self) -> Result<Collation, ParsingError> {
+        Collation::from_string(self.parse_identifier()?)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParsingError> {
+        let token = self.next_token(TokenType::Expression)?;
+        match &token.token_type {
+            TokenType::Value => Ok(Expression::Literal(parse_literal(&token.content)?)),
+            TokenType::Delimiter(DelimiterType::OpenParen) => {
+                let expression = self.parse_expression()?;
+                self.expect_delimiter(DelimiterType::CloseParen)?;
+                Ok(expression)
+            }
+            TokenType::Expression if token.content.eq_ignore_ascii_case("null") => {
+                Ok(Expression::Literal(Value::Null))
+            }
+            TokenType::Expression
+                if token.content.eq_ignore_ascii_case("array")
+                    && self.consume_delimiter(DelimiterType::OpenBracket) =>
+            {
+                let mut elements = Vec::new();
+                if !self.consume_delimiter(DelimiterType::CloseBracket) {
+                    loop {
+                        elements.push(self.parse_expression()?);
+                        if !self.consume_delimiter(DelimiterType::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect_delimiter(DelimiterType::CloseBracket)?;
+                }
+                Ok(Expression::Array(elements))
+            }
+            TokenType::Expression => {
+                if self.consume_delimiter(DelimiterType::OpenParen) {
+                    // `count(*)` is represented as a call without any argument
+                    if self.consume_operator(OperatorType::Star) {
+                        self.expect_delimiter(DelimiterType::CloseParen)?;
+                        return Ok(Expression::Function {
+                            name: token.content.to_lowercase(),
+                            arguments: Vec::new(),
+                        });
+                    }
+                    return Ok(Expression::Function {
+                        name: token.content.to_lowercase(),
+                        arguments: self.parse_arguments()?,
+                    });
+                }
+                if self.consume_delimiter(DelimiterType::Dot) {
+                    return Ok(Expression::Column {
+                        table: Some(token.content.to_string()),
+                        name: self.parse_identifier()?,
+                    });
+                }
+                Ok(Expression::Column {
+                    table: None,
+                    name: token.content.to_string(),
+                })
+            }
+            _ => Err(ParsingError::UnexpectedToken {
+                expected: String::from("an expression"),
+                found_content: token.content.to_string(),
+            }),
+        }
+    }
+
+    fn peek_token(&mut self) -> Option<&'token Token> {
+        self.tokens.peek(0).copied()
+    }
+
+    fn next_token(&mut self, expected: TokenType) -> Result<&'token Token, ParsingError> {
+        self.tokens
+            .next()
+            .ok_or(ParsingError::TokenNotFound { expected })
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParsingError> {
+        let token = self.next_token(TokenType::Expression)?;
+        if token.token_type != TokenType::Expression {
+            return Err(ParsingError::UnexpectedToken {
+                expected: String::from("an identifier"),
+                found_content: token.content.to_string(),
+            });
+        }
+        Ok(token.content.to_string())
+    }
+
+    fn peek_operator(&mut self) -> Option<OperatorType> {
+        match self.peek_token() {
+            Some(Token {
+                token_type: TokenType::Operator(operator),
+                ..
+            }) => Some(operator.clone()),
+            _ => None,
+        }
+    }
+
+    fn consume_operator(&mut self, operator: OperatorType) -> bool {
+        if self.peek_operator() == Some(operator) {
+            self.tokens.next();
+            return true;
+        }
+        false
+    }
+
+    fn peek_delimiter(&mut self, delimiter: DelimiterType) -> bool {
+        matches!(
+            self.peek_token(),
+            Some(token) if token.token_type == TokenType::Delimiter(delimiter)
+        )
+    }
+
+    fn consume_delimiter(&mut self, delimiter: DelimiterType) -> bool {
+        let is_expected = self.peek_delimiter(delimiter);
+        if is_expected {
+            self.tokens.next();
+        }
+        is_expected
+    }
+
+    fn expect_delimiter(&mut self, delimiter: DelimiterType) -> Result<(), ParsingError> {
+        if self.consume_delimiter(delimiter.clone()) {
+            return Ok(());
+        }
+        match self.peek_token() {
+            Some(token) => Err(ParsingError::UnexpectedToken {
+                expected: format!("{delimiter:?}"),
+                found_content: token.content.to_string(),
+            }),
+            None => Err(ParsingError::TokenNotFound {
+                expected: TokenType::Delimiter(delimiter),
+            }),
+        }
+    }
+
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        matches!(
+            self.peek_token(),
+            Some(token) if token.token_type == TokenType::Expression
+                && token.content.eq_ignore_ascii_case(keyword)
+        )
+    }
+
+    /// Consumes next token if it is given keyword (case insensitive)
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        let is_expected = self.peek_keyword(keyword);
+        if is_expected {
+            self.tokens.next();
+        }
+        is_expected
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParsingError> {
+        if self.consume_keyword(keyword) {
+            return Ok(());
+        }
+        Err(ParsingError::UnexpectedToken {
+            expected: keyword.to_uppercase(),
+            found_content: self
+                .tokens
+                .peek(0)
+                .map(|token| token.content.to_string())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+fn binary(left: Expression, operator: BinaryOperator, right: Expression) -> Expression {
+    Expression::Binary {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+    }
+}
+
+/// Converts the content of a Value token to the value it represents
+fn parse_literal(content: &str) -> Result<Value, ParsingError> {
+    let invalid_literal = || ParsingError::InvalidLiteral {
+        content: content.to_string(),
+    };
+    if let Some(quoted) = content.strip_prefix('\'') {
+        let text = quoted.strip_suffix('\'').ok_or_else(invalid_literal)?;
+        return Ok(Value::Text(text.replace("''", "'")));
+    }
+    if content.eq_ignore_ascii_case("true") {
+        return Ok(Value::Bool(true));
+    }
+    if content.eq_ignore_ascii_case("false") {
+        return Ok(Value::Bool(false));
+    }
+    if content.contains('.') {
+        return content
+            .parse()
+            .map(Value::Real)
+            .map_err(|_| invalid_literal());
+    }
+    content
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| invalid_literal())
 }
@@ -1,4 +1,6 @@
+use crate::sql_compilator::lookahead::{Lookahead, LookaheadExt};
 use regex::Regex;
+use std::str::CharIndices;
 use thiserror::Error;
 
 const EXPRESSION_PATTERN: &str = r"(?i)^(?:'(?:[^']*)'|-?\d+(?:\.\d+)?|true|false)$";
@@ -17,29 +19,73 @@ pub enum TokenizingError {
         expected_word_after: String,
         found_word_after: String,
     },
+    #[error("String literal starting at position {start_position} is never closed")]
+    UnterminatedString { start_position: usize },
+    #[error("Unexpected character '{found}' at position {position}")]
+    UnexpectedCharacter { found: char, position: usize },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CommandType {
     CreateTable,
+    CreateType,
+    CreateIndex,
+    /// `CREATE UNIQUE`, which must be followed by `INDEX`
+    CreateUniqueIndex,
+    DropIndex,
+    AlterType,
     Select,
     InsertInto,
     Update,
     Delete,
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint,
+    Release,
+    Vacuum,
+    SetTransaction,
+    CheckDatabase,
+    Pragma,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     Command(CommandType),
     Operator(OperatorType),
+    Delimiter(DelimiterType),
     Expression,
     Value,
 }
 
-#[derive(Debug, Clone)]
-enum OperatorType {
+#[derive(Debug, Clone, PartialEq)]
+pub enum OperatorType {
     Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
     Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Concat,
+    Arrow,
+    DoubleArrow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DelimiterType {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+    Semicolon,
+    Dot,
+    Colon,
 }
 
 #[derive(Debug, Clone)]
@@ -57,38 +103,179 @@ impl Token {
     }
 }
 
+/// Raw pieces of user input, before they are classified into tokens
+#[derive(Debug)]
+enum Lexeme {
+    Word(String),
+    Quoted(String),
+    Symbol { content: String, position: usize },
+}
+
 pub fn tokenize_user_input(user_input: &str) -> Result<Vec<Token>, TokenizingError> {
     let mut tokens: Vec<Token> = Vec::new();
-    let mut word_iter = user_input.split_whitespace().peekable();
+    let lexemes = split_lexemes(user_input)?;
+    let mut lexeme_iter = lexemes.iter().peekable();
     let expression_regex: Regex = make_expression_regex();
     let mut ignore_next_word = false;
-    while let Some(word) = word_iter.next() {
+    let mut starts_statement = true;
+    while let Some(lexeme) = lexeme_iter.next() {
         if ignore_next_word {
             ignore_next_word = false;
             continue;
         }
-        let (current_token, ignore_next) = build_token(word, word_iter.peek(), &expression_regex)?;
+        let some_next = match lexeme_iter.peek() {
+            Some(Lexeme::Word(next)) => Some(next.as_str()),
+            _ => None,
+        };
+        let (current_token, ignore_next) =
+            build_token(lexeme, some_next, starts_statement, &expression_regex)?;
         ignore_next_word = ignore_next;
+        starts_statement =
+            current_token.token_type == TokenType::Delimiter(DelimiterType::Semicolon);
         tokens.push(current_token);
     }
     Ok(tokens)
 }
 
+/// Splits user input into words, quoted strings and symbols. Whitespace and `--` comments are
+/// dropped.
+fn split_lexemes(user_input: &str) -> Result<Vec<Lexeme>, TokenizingError> {
+    let mut lexemes: Vec<Lexeme> = Vec::new();
+    let mut chars = user_input.char_indices().lookahead();
+    while let Some((position, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == '-' && matches!(chars.peek(0), Some((_, '-'))) {
+            while chars.next().is_some_and(|(_, c)| c != '\n') {}
+            continue;
+        }
+        if c == '\'' {
+            lexemes.push(Lexeme::Quoted(read_quoted(&mut chars, position)?));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut word = String::from(c);
+            let starts_with_digit = c.is_ascii_digit();
+            while let Some(&(_, next)) = chars.peek(0) {
+                let is_decimal_point = starts_with_digit
+                    && next == '.'
+                    && matches!(chars.peek(1), Some((_, d)) if d.is_ascii_digit());
+                if !(next.is_alphanumeric() || next == '_' || is_decimal_point) {
+                    break;
+                }
+                word.push(next);
+                chars.next();
+            }
+            lexemes.push(Lexeme::Word(word));
+        } else {
+            let mut symbol = String::from(c);
+            let some_next = chars.peek(0).map(|&(_, next)| next);
+            let some_after_next = chars.peek(1).map(|&(_, next)| next);
+            let extra_chars = match (c, some_next, some_after_next) {
+                ('-', Some('>'), Some('>')) => 2,
+                ('<', Some('=' | '>'), _)
+                | ('>', Some('='), _)
+                | ('!', Some('='), _)
+                | ('|', Some('|'), _)
+                | ('-', Some('>'), _) => 1,
+                _ => 0,
+            };
+            for _ in 0..extra_chars {
+                let (_, next) = chars.next().unwrap();
+                symbol.push(next);
+            }
+            lexemes.push(Lexeme::Symbol {
+                content: symbol,
+                position,
+            });
+        }
+    }
+    Ok(lexemes)
+}
+
+/// Reads a single-quoted string literal, the opening quote being already consumed. Doubled
+/// quotes are kept as-is so that the parser can unescape them.
+fn read_quoted(
+    chars: &mut Lookahead<CharIndices>,
+    start_position: usize,
+) -> Result<String, TokenizingError> {
+    let mut content = String::from('\'');
+    while let Some((_, c)) = chars.next() {
+        content.push(c);
+        if c == '\'' {
+            if matches!(chars.peek(0), Some((_, '\''))) {
+                chars.next();
+                content.push('\'');
+            } else {
+                return Ok(content);
+            }
+        }
+    }
+    Err(TokenizingError::UnterminatedString { start_position })
+}
+
 // TODO: refactor in a Tokenizer class ?
+/// Builds the token of a lexeme. Words which are commands on their own, such as `BEGIN`, are
+/// only commands when `starts_statement`, so that they can name tables and columns elsewhere.
 fn build_token(
-    word: &str,
-    some_next: Option<&&str>,
+    lexeme: &Lexeme,
+    some_next: Option<&str>,
+    starts_statement: bool,
     expression_regex: &Regex,
 ) -> Result<(Token, bool), TokenizingError> {
+    let word = match lexeme {
+        Lexeme::Word(word) => word,
+        Lexeme::Quoted(content) => {
+            return Ok((Token::new(TokenType::Value, content.to_string()), false));
+        }
+        Lexeme::Symbol { content, position } => {
+            return Ok((build_symbol_token(content, *position)?, false));
+        }
+    };
     let owned_word: String = word.to_string();
     let resulting_token = match word.to_lowercase().as_str() {
         // Commands
+        "create" if some_next.is_some_and(|next| next.eq_ignore_ascii_case("type")) => {
+            generate_multiple_words_token(
+                TokenType::Command(CommandType::CreateType),
+                word,
+                some_next.unwrap(),
+                "type",
+            )
+        }
+        "create" if some_next.is_some_and(|next| next.eq_ignore_ascii_case("index")) => {
+            generate_multiple_words_token(
+                TokenType::Command(CommandType::CreateIndex),
+                word,
+                some_next.unwrap(),
+                "index",
+            )
+        }
+        "create" if some_next.is_some_and(|next| next.eq_ignore_ascii_case("unique")) => {
+            generate_multiple_words_token(
+                TokenType::Command(CommandType::CreateUniqueIndex),
+                word,
+                some_next.unwrap(),
+                "unique",
+            )
+        }
         "create" if some_next.is_some() => generate_multiple_words_token(
             TokenType::Command(CommandType::CreateTable),
             word,
             some_next.unwrap(),
             "table",
         ),
+        "alter" if some_next.is_some() => generate_multiple_words_token(
+            TokenType::Command(CommandType::AlterType),
+            word,
+            some_next.unwrap(),
+            "type",
+        ),
+        "drop" if some_next.is_some() => generate_multiple_words_token(
+            TokenType::Command(CommandType::DropIndex),
+            word,
+            some_next.unwrap(),
+            "index",
+        ),
         "select" => Ok(Token::new(
             TokenType::Command(CommandType::Select),
             owned_word,
@@ -107,13 +294,48 @@ fn build_token(
             TokenType::Command(CommandType::Delete),
             owned_word,
         )),
-        // Operators
-        "=" => Ok(Token::new(
-            TokenType::Operator(OperatorType::Equal),
+        "begin" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Begin),
+            owned_word,
+        )),
+        "commit" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Commit),
+            owned_word,
+        )),
+        "rollback" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Rollback),
+            owned_word,
+        )),
+        "savepoint" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Savepoint),
+            owned_word,
+        )),
+        "release" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Release),
             owned_word,
         )),
-        "+" => Ok(Token::new(
-            TokenType::Operator(OperatorType::Plus),
+        "set" if some_next.is_some_and(|next| next.eq_ignore_ascii_case("transaction")) => {
+            generate_multiple_words_token(
+                TokenType::Command(CommandType::SetTransaction),
+                word,
+                some_next.unwrap(),
+                "transaction",
+            )
+        }
+        "vacuum" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Vacuum),
+            owned_word,
+        )),
+        "check" if some_next.is_some_and(|next| next.eq_ignore_ascii_case("database")) => {
+            generate_multiple_words_token(
+                TokenType::Command(CommandType::CheckDatabase),
+                word,
+                some_next.unwrap(),
+                "database",
+            )
+        }
+        "pragma" if starts_statement => Ok(Token::new(
+            TokenType::Command(CommandType::Pragma),
             owned_word,
         )),
         // Expressions and column names
@@ -134,20 +356,56 @@ fn build_token(
     }
 }
 
+fn build_symbol_token(symbol: &str, position: usize) -> Result<Token, TokenizingError> {
+    let token_type = match symbol {
+        // Operators
+        "=" => TokenType::Operator(OperatorType::Equal),
+        "<>" | "!=" => TokenType::Operator(OperatorType::NotEqual),
+        "<" => TokenType::Operator(OperatorType::Less),
+        "<=" => TokenType::Operator(OperatorType::LessOrEqual),
+        ">" => TokenType::Operator(OperatorType::Greater),
+        ">=" => TokenType::Operator(OperatorType::GreaterOrEqual),
+        "+" => TokenType::Operator(OperatorType::Plus),
+        "-" => TokenType::Operator(OperatorType::Minus),
+        "*" => TokenType::Operator(OperatorType::Star),
+        "/" => TokenType::Operator(OperatorType::Slash),
+        "%" => TokenType::Operator(OperatorType::Percent),
+        "||" => TokenType::Operator(OperatorType::Concat),
+        "->" => TokenType::Operator(OperatorType::Arrow),
+        "->>" => TokenType::Operator(OperatorType::DoubleArrow),
+        // Delimiters
+        "(" => TokenType::Delimiter(DelimiterType::OpenParen),
+        ")" => TokenType::Delimiter(DelimiterType::CloseParen),
+        "[" => TokenType::Delimiter(DelimiterType::OpenBracket),
+        "]" => TokenType::Delimiter(DelimiterType::CloseBracket),
+        "," => TokenType::Delimiter(DelimiterType::Comma),
+        ";" => TokenType::Delimiter(DelimiterType::Semicolon),
+        "." => TokenType::Delimiter(DelimiterType::Dot),
+        ":" => TokenType::Delimiter(DelimiterType::Colon),
+        _ => {
+            return Err(TokenizingError::UnexpectedCharacter {
+                found: symbol.chars().next().unwrap_or_default(),
+                position,
+            });
+        }
+    };
+    Ok(Token::new(token_type, symbol.to_string()))
+}
+
 fn generate_multiple_words_token(
     output_token_type: TokenType,
     current_word: &str,
     word_after: &str,
     expected_word_after: &str,
 ) -> Result<Token, TokenizingError> {
-    if word_after == expected_word_after {
+    if word_after.eq_ignore_ascii_case(expected_word_after) {
         let content = format!("{} {}", current_word, word_after);
         Ok(Token::new(output_token_type, content))
     } else {
         Err(TokenizingError::KeywordNotFound {
             word_before: current_word.to_string(),
             expected_word_after: expected_word_after.to_string(),
-            found_word_after: expected_word_after.to_string(),
+            found_word_after: word_after.to_string(),
         })
     }
 }
@@ -190,4 +448,59 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_tokenize_json_operators() {
+        let tokens = tokenize_user_input("SELECT data->>'name', data->'tags' FROM t;").unwrap();
+        let types: Vec<TokenType> = tokens.into_iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Command(CommandType::Select),
+                TokenType::Expression,
+                TokenType::Operator(OperatorType::DoubleArrow),
+                TokenType::Value,
+                TokenType::Delimiter(DelimiterType::Comma),
+                TokenType::Expression,
+                TokenType::Operator(OperatorType::Arrow),
+                TokenType::Value,
+                TokenType::Expression,
+                TokenType::Expression,
+                TokenType::Delimiter(DelimiterType::Semicolon),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_quoted_strings_with_spaces() {
+        let tokens =
+            tokenize_user_input("insert into t values ('it''s a {\"json\"}', 3.5)").unwrap();
+        assert_eq!(tokens[0].content, "insert into");
+        assert_eq!(tokens[4].content, "'it''s a {\"json\"}'");
+        assert_eq!(tokens[6].content, "3.5");
+        assert_eq!(tokens[6].token_type, TokenType::Value);
+    }
+
+    #[test]
+    fn test_standalone_commands_only_start_statements() {
+        let tokens =
+            tokenize_user_input("begin; select begin, commit from release; vacuum pragma").unwrap();
+        let types: Vec<TokenType> = tokens.into_iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Command(CommandType::Begin),
+                TokenType::Delimiter(DelimiterType::Semicolon),
+                TokenType::Command(CommandType::Select),
+                TokenType::Expression,
+                TokenType::Delimiter(DelimiterType::Comma),
+                TokenType::Expression,
+                TokenType::Expression,
+                TokenType::Expression,
+                TokenType::Delimiter(DelimiterType::Semicolon),
+                TokenType::Command(CommandType::Vacuum),
+                TokenType::Expression,
+            ]
+        );
+    }
 }
@@ -0,0 +1,98 @@
+// Textual form of arrays, as in PostgreSQL: `{1,2,3}`, `{"a b",c,NULL}`. Elements holding
+// spaces, commas, braces, quotes or backslashes (or spelling NULL) are double-quoted, with
+// quotes and backslashes escaped by a backslash.
+
+/// Splits the textual form of a one-dimensional array into its elements, `None` standing for
+/// NULL. Returns `None` if the text is not a valid array.
+pub fn parse_text(text: &str) -> Option<Vec<Option<String>>> {
+    let inner = text.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut elements = Vec::new();
+    if inner.trim().is_empty() {
+        return Some(elements);
+    }
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let element = if chars.next_if_eq(&'"').is_some() {
+            let mut element = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => element.push(chars.next()?),
+                    c => element.push(c),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            Some(element)
+        } else {
+            let mut element = String::new();
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                if matches!(c, '{' | '}' | '"') {
+                    return None;
+                }
+                element.push(c);
+            }
+            let element = element.trim_end();
+            if element.is_empty() {
+                return None;
+            }
+            (!element.eq_ignore_ascii_case("null")).then(|| element.to_string())
+        };
+        elements.push(element);
+        match chars.next() {
+            Some(',') => continue,
+            None => return Some(elements),
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Writes the textual form of an array out of the text of its elements, `None` standing for
+/// NULL
+pub fn format_text<'element>(elements: impl IntoIterator<Item = Option<&'element str>>) -> String {
+    let elements: Vec<String> = elements
+        .into_iter()
+        .map(|element| match element {
+            None => String::from("NULL"),
+            Some(text) if needs_quotes(text) => {
+                format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Some(text) => text.to_string(),
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+fn needs_quotes(text: &str) -> bool {
+    text.is_empty()
+        || text.eq_ignore_ascii_case("null")
+        || text
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, ',' | '{' | '}' | '"' | '\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_array_text_round_trip() {
+        let elements = parse_text(r#"{1, "a b" ,NULL,"NULL","q\"uote"}"#).unwrap();
+        assert_eq!(
+            elements,
+            [
+                Some(String::from("1")),
+                Some(String::from("a b")),
+                None,
+                Some(String::from("NULL")),
+                Some(String::from("q\"uote")),
+            ]
+        );
+        let text = format_text(elements.iter().map(Option::as_deref));
+        assert_eq!(text, r#"{1,"a b",NULL,"NULL","q\"uote"}"#);
+        assert_eq!(parse_text("{}"), Some(Vec::new()));
+        for invalid in ["1,2", "{1,,2}", "{1,2", r#"{"a}"#, "{{1}}"] {
+            assert_eq!(parse_text(invalid), None, "Failed: `{}`", invalid);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Small files such as schemas are replaced as a whole. Writing them in place would leave a
+// truncated file if the process stopped half-way, so the new content is first written and synced
+// to a temporary file next to it, which then replaces the file in a single rename. Syncing the
+// directory afterwards makes the rename itself durable.
+
+/// Extension added to the name of a file while its new content is written
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// Replaces the content of a file, creating it if needed. Whenever the process stops, the file
+/// holds either its previous content or the new one.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary_path = temporary_path(path);
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temporary_path, path)?;
+    sync_directory(path)
+}
+
+/// Makes the creation, renaming or removal of a file durable, by syncing its directory
+pub fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    // Other platforms do not allow opening a directory, and make renames durable on their own
+    if cfg!(unix) {
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Removes the temporary files left in a directory by writes which were interrupted, whose
+/// files still hold their previous content
+pub fn remove_temporary_files(directory: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == TEMPORARY_EXTENSION)
+        {
+            log::warn!("Removing {}, left by an interrupted write", path.display());
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().map_or_else(OsString::new, OsString::from);
+    file_name.push(".");
+    file_name.push(TEMPORARY_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_directory::TempDirectory;
+
+    #[test]
+    fn test_interrupted_writes_leave_the_previous_content() {
+        let directory = TempDirectory::new("atomic-file");
+        let path = directory.join("t.meta.ron");
+        write(&path, b"(name: \"t\")").unwrap();
+        write(&path, b"(name: \"u\")").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"(name: \"u\")");
+
+        // A write stopped before its rename only leaves a temporary file behind
+        std::fs::write(temporary_path(&path), b"(name: ").unwrap();
+        remove_temporary_files(&directory).unwrap();
+        let file_names: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(file_names, ["t.meta.ron"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"(name: \"u\")");
+    }
+}
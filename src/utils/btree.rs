@@ -0,0 +1,544 @@
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::utils::buffer_pool::{BufferPool, FileId, PageId};
+use crate::utils::heap_file::RowId;
+use crate::utils::page::{self, PageLayout};
+
+// Layout of an index file, made of pages of `page_size` bytes:
+//   - page 0, the meta page: [u8 kind][u8 reserved][u16 reserved][u32 root page][u32 page count]
+//     [u64 entry count]
+//   - node pages: [u8 kind][u8 reserved][u16 entry count][u32 link], then the entries.
+//     A leaf links to the next leaf (0 for the last one), and holds `[u16 key length][key]
+//     [u32 page][u16 slot]` entries, sorted by key. An internal node links to its first child,
+//     and holds `[u16 key length][key][u32 child]` entries: every key of a child is greater than
+//     or equal to the key before it, and lower than the key after it.
+// Every integer is little-endian. Keys are compared byte by byte.
+
+const KIND_META: u8 = 2;
+const KIND_LEAF: u8 = 3;
+const KIND_INTERNAL: u8 = 4;
+const NODE_HEADER_SIZE: usize = 8;
+/// Bytes taken by a leaf entry besides its key: key length and row id
+const LEAF_ENTRY_OVERHEAD: usize = 8;
+
+/// Keys of a leaf, sorted, along with the row each one maps to
+type LeafEntries = Vec<(Vec<u8>, RowId)>;
+
+enum Node {
+    Leaf {
+        entries: LeafEntries,
+        next: u32,
+    },
+    Internal {
+        keys: Vec<Vec<u8>>,
+        /// One more child than keys
+        children: Vec<u32>,
+    },
+}
+
+impl Node {
+    fn from_bytes(data: &[u8]) -> Option<Node> {
+        let count = u16::from_le_bytes([data[2], data[3]]) as usize;
+        let link = read_u32(data, 4)?;
+        let mut position = NODE_HEADER_SIZE;
+        let read_key = |position: &mut usize| -> Option<Vec<u8>> {
+            let length = u16::from_le_bytes(data.get(*position..*position + 2)?.try_into().ok()?);
+            let key = data.get(*position + 2..*position + 2 + length as usize)?;
+            *position += 2 + length as usize;
+            Some(key.to_vec())
+        };
+        match data[0] {
+            KIND_LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = read_key(&mut position)?;
+                    let page = read_u32(data, position)?;
+                    let slot =
+                        u16::from_le_bytes(data.get(position + 4..position + 6)?.try_into().ok()?);
+                    position += 6;
+                    entries.push((key, RowId { page, slot }));
+                }
+                Some(Node::Leaf {
+                    entries,
+                    next: link,
+                })
+            }
+            KIND_INTERNAL => {
+                let mut keys = Vec::with_capacity(count);
+                let mut children = vec![link];
+                for _ in 0..count {
+                    keys.push(read_key(&mut position)?);
+                    children.push(read_u32(data, position)?);
+                    position += 4;
+                }
+                Some(Node::Internal { keys, children })
+            }
+            _ => None,
+        }
+    }
+
+    fn to_bytes(&self, page_size: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(page_size);
+        let (kind, count, link) = match self {
+            Node::Leaf { entries, next } => (KIND_LEAF, entries.len(), *next),
+            Node::Internal { keys, children } => (KIND_INTERNAL, keys.len(), children[0]),
+        };
+        data.extend([kind, 0]);
+        data.extend((count as u16).to_le_bytes());
+        data.extend(link.to_le_bytes());
+        match self {
+            Node::Leaf { entries, .. } => {
+                for (key, row_id) in entries {
+                    data.extend((key.len() as u16).to_le_bytes());
+                    data.extend(key);
+                    data.extend(row_id.page.to_le_bytes());
+                    data.extend(row_id.slot.to_le_bytes());
+                }
+            }
+            Node::Internal { keys, children } => {
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    data.extend((key.len() as u16).to_le_bytes());
+                    data.extend(key);
+                    data.extend(child.to_le_bytes());
+                }
+            }
+        }
+        data.resize(data.len().max(page_size), 0);
+        data
+    }
+
+    /// Bytes taken by every entry of the node, the first child of an internal node being part of
+    /// the header
+    fn entry_sizes(&self) -> Vec<usize> {
+        match self {
+            Node::Leaf { entries, .. } => entries
+                .iter()
+                .map(|(key, _)| key.len() + LEAF_ENTRY_OVERHEAD)
+                .collect(),
+            Node::Internal { keys, .. } => keys.iter().map(|key| key.len() + 6).collect(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        NODE_HEADER_SIZE + self.entry_sizes().iter().sum::<usize>()
+    }
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Result of inserting in a subtree
+enum Insertion {
+    Done,
+    /// The key is already in the tree
+    Duplicate,
+    /// The node was split: the new node, on its right, starts at given key
+    Split(Vec<u8>, u32),
+}
+
+/// B+tree stored in its own file, mapping unique byte-string keys to row ids. Its pages are read
+/// and written through the buffer pool of the database. Nodes are split when they overflow, but
+/// deleting entries never merges nodes: emptied leaves stay in the tree until it is rebuilt.
+pub struct BTree {
+    buffer_pool: Arc<Mutex<BufferPool>>,
+    file_id: FileId,
+    /// Bytes of a page available to nodes
+    page_size: usize,
+    root: u32,
+    page_count: u32,
+    entry_count: u64,
+}
+
+impl BTree {
+    /// Opens the index stored at `path`, creating an empty one if the file is empty
+    pub fn open(
+        path: &Path,
+        layout: PageLayout,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Self> {
+        page::check_page_size(layout.page_size)?;
+        let page_size = layout.content_size();
+        let file_id = buffer_pool.lock().unwrap().open_file(path, layout)?;
+        let is_new = buffer_pool.lock().unwrap().file_size(file_id)? == 0;
+        let mut tree = BTree {
+            buffer_pool,
+            file_id,
+            page_size,
+            root: 1,
+            page_count: 2,
+            entry_count: 0,
+        };
+        if is_new {
+            let mut buffer_pool = tree.buffer_pool.lock().unwrap();
+            let frame = buffer_pool.create(tree.page_id(0), tree.meta_page())?;
+            buffer_pool.unpin(frame, false);
+            let root = Node::Leaf {
+                entries: Vec::new(),
+                next: 0,
+            };
+            let frame = buffer_pool.create(tree.page_id(1), root.to_bytes(page_size))?;
+            buffer_pool.unpin(frame, false);
+        } else {
+            tree.read_meta_page().map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+            })?;
+        }
+        Ok(tree)
+    }
+
+    /// Largest key the tree accepts: any node holding up to a page of entries, plus one more,
+    /// must split into two nodes fitting in a page
+    pub fn max_key_size(&self) -> usize {
+        (self.page_size - NODE_HEADER_SIZE) / 4 - LEAF_ENTRY_OVERHEAD
+    }
+
+    pub fn len(&self) -> u64 {
+        self.entry_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> io::Result<Option<RowId>> {
+        let (_, entries, _) = self.find_leaf(key)?;
+        Ok(entries
+            .binary_search_by(|(entry, _)| entry.as_slice().cmp(key))
+            .ok()
+            .map(|index| entries[index].1))
+    }
+
+    /// Adds a key to the tree. Returns `false`, leaving the tree unchanged, if it already holds
+    /// the key.
+    pub fn insert(&mut self, key: &[u8], row_id: RowId) -> io::Result<bool> {
+        if key.len() > self.max_key_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Index key of {} bytes exceeds the maximum of {} bytes",
+                    key.len(),
+                    self.max_key_size()
+                ),
+            ));
+        }
+        match self.insert_into(self.root, key, row_id)? {
+            Insertion::Duplicate => return Ok(false),
+            Insertion::Done => {}
+            Insertion::Split(separator, right) => {
+                let root = Node::Internal {
+                    keys: vec![separator],
+                    children: vec![self.root, right],
+                };
+                self.root = self.allocate_node(&root)?;
+            }
+        }
+        self.entry_count += 1;
+        self.write_meta_page()?;
+        Ok(true)
+    }
+
+    /// Removes a key from the tree, returning the row id it was mapped to
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<Option<RowId>> {
+        let (page_number, mut entries, next) = self.find_leaf(key)?;
+        let Ok(index) = entries.binary_search_by(|(entry, _)| entry.as_slice().cmp(key)) else {
+            return Ok(None);
+        };
+        let (_, row_id) = entries.remove(index);
+        self.write_node(page_number, &Node::Leaf { entries, next })?;
+        self.entry_count -= 1;
+        self.write_meta_page()?;
+        Ok(Some(row_id))
+    }
+
+    /// Returns the entries whose key lies within given bounds, sorted by key
+    pub fn range(
+        &mut self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> io::Result<Vec<(Vec<u8>, RowId)>> {
+        let start: &[u8] = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => key,
+            Bound::Unbounded => &[],
+        };
+        let (_, mut entries, mut next) = self.find_leaf(start)?;
+        let mut found = Vec::new();
+        loop {
+            for (key, row_id) in entries {
+                let is_above_lower = match lower {
+                    Bound::Included(lower) => key.as_slice() >= lower,
+                    Bound::Excluded(lower) => key.as_slice() > lower,
+                    Bound::Unbounded => true,
+                };
+                let is_below_upper = match upper {
+                    Bound::Included(upper) => key.as_slice() <= upper,
+                    Bound::Excluded(upper) => key.as_slice() < upper,
+                    Bound::Unbounded => true,
+                };
+                if !is_below_upper {
+                    return Ok(found);
+                }
+                if is_above_lower {
+                    found.push((key, row_id));
+                }
+            }
+            if next == 0 {
+                return Ok(found);
+            }
+            match self.read_node(next)? {
+                Node::Leaf {
+                    entries: next_entries,
+                    next: next_leaf,
+                } => {
+                    entries = next_entries;
+                    next = next_leaf;
+                }
+                Node::Internal { .. } => return Err(self.corrupted(next, "leaf links to a node")),
+            }
+        }
+    }
+
+    /// Returns the leaf where given key belongs, along with its entries and next leaf
+    fn find_leaf(&mut self, key: &[u8]) -> io::Result<(u32, LeafEntries, u32)> {
+        let mut page_number = self.root;
+        loop {
+            match self.read_node(page_number)? {
+                Node::Leaf { entries, next } => return Ok((page_number, entries, next)),
+                Node::Internal { keys, children } => {
+                    page_number =
+                        children[keys.partition_point(|separator| separator.as_slice() <= key)];
+                }
+            }
+        }
+    }
+
+    fn insert_into(
+        &mut self,
+        page_number: u32,
+        key: &[u8],
+        row_id: RowId,
+    ) -> io::Result<Insertion> {
+        let mut node = self.read_node(page_number)?;
+        match &mut node {
+            Node::Leaf { entries, .. } => {
+                match entries.binary_search_by(|(entry, _)| entry.as_slice().cmp(key)) {
+                    Ok(_) => return Ok(Insertion::Duplicate),
+                    Err(index) => entries.insert(index, (key.to_vec(), row_id)),
+                }
+            }
+            Node::Internal { keys, children } => {
+                let index = keys.partition_point(|separator| separator.as_slice() <= key);
+                match self.insert_into(children[index], key, row_id)? {
+                    Insertion::Split(separator, right) => {
+                        keys.insert(index, separator);
+                        children.insert(index + 1, right);
+                    }
+                    insertion => return Ok(insertion),
+                }
+            }
+        }
+        if node.size() <= self.page_size {
+            self.write_node(page_number, &node)?;
+            return Ok(Insertion::Done);
+        }
+        self.split(page_number, node)
+    }
+
+    /// Moves the upper half of an overflowing node, by size, to a new node on its right
+    fn split(&mut self, page_number: u32, node: Node) -> io::Result<Insertion> {
+        let sizes = node.entry_sizes();
+        let half = sizes.iter().sum::<usize>() / 2;
+        let mut middle = 0;
+        let mut left_size = 0;
+        while middle < sizes.len() - 1 && left_size + sizes[middle] <= half {
+            left_size += sizes[middle];
+            middle += 1;
+        }
+        let middle = middle.max(1);
+        let right_page = self.page_count;
+        let (left, right, separator) = match node {
+            Node::Leaf { mut entries, next } => {
+                let right_entries = entries.split_off(middle);
+                let separator = right_entries[0].0.clone();
+                let left = Node::Leaf {
+                    entries,
+                    next: right_page,
+                };
+                let right = Node::Leaf {
+                    entries: right_entries,
+                    next,
+                };
+                (left, right, separator)
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                // The middle key moves up to the parent, between both nodes
+                let right_keys = keys.split_off(middle + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(middle + 1);
+                let left = Node::Internal { keys, children };
+                let right = Node::Internal {
+                    keys: right_keys,
+                    children: right_children,
+                };
+                (left, right, separator)
+            }
+        };
+        self.allocate_node(&right)?;
+        self.write_node(page_number, &left)?;
+        Ok(Insertion::Split(separator, right_page))
+    }
+
+    fn page_id(&self, page_number: u32) -> PageId {
+        PageId {
+            file: self.file_id,
+            page: page_number,
+        }
+    }
+
+    fn corrupted(&self, page_number: u32, reason: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupted index page {page_number}: {reason}"),
+        )
+    }
+
+    fn read_node(&mut self, page_number: u32) -> io::Result<Node> {
+        if page_number == 0 || page_number >= self.page_count {
+            return Err(self.corrupted(page_number, "link to a page outside of the index"));
+        }
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(page_number))?;
+        let node = Node::from_bytes(buffer_pool.data(frame));
+        buffer_pool.unpin(frame, false);
+        drop(buffer_pool);
+        node.ok_or_else(|| self.corrupted(page_number, "invalid node"))
+    }
+
+    fn write_node(&mut self, page_number: u32, node: &Node) -> io::Result<()> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(page_number))?;
+        buffer_pool
+            .data_mut(frame)
+            .copy_from_slice(&node.to_bytes(self.page_size));
+        buffer_pool.unpin(frame, true);
+        Ok(())
+    }
+
+    /// Writes a node in a new page at the end of the file, returning its page number
+    fn allocate_node(&mut self, node: &Node) -> io::Result<u32> {
+        let page_number = self.page_count;
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.create(self.page_id(page_number), node.to_bytes(self.page_size))?;
+        buffer_pool.unpin(frame, false);
+        self.page_count += 1;
+        Ok(page_number)
+    }
+
+    fn meta_page(&self) -> Vec<u8> {
+        let mut data = vec![KIND_META, 0, 0, 0];
+        data.extend(self.root.to_le_bytes());
+        data.extend(self.page_count.to_le_bytes());
+        data.extend(self.entry_count.to_le_bytes());
+        data.resize(self.page_size, 0);
+        data
+    }
+
+    fn read_meta_page(&mut self) -> io::Result<()> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(0))?;
+        let data = buffer_pool.data(frame);
+        let meta = (data[0] == KIND_META).then(|| {
+            (
+                read_u32(data, 4).unwrap(),
+                read_u32(data, 8).unwrap(),
+                u64::from_le_bytes(data[12..20].try_into().unwrap()),
+            )
+        });
+        buffer_pool.unpin(frame, false);
+        drop(buffer_pool);
+        let (root, page_count, entry_count) =
+            meta.ok_or_else(|| self.corrupted(0, "not an index file"))?;
+        self.root = root;
+        self.page_count = page_count;
+        self.entry_count = entry_count;
+        Ok(())
+    }
+
+    fn write_meta_page(&mut self) -> io::Result<()> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(0))?;
+        buffer_pool
+            .data_mut(frame)
+            .copy_from_slice(&self.meta_page());
+        buffer_pool.unpin(frame, true);
+        Ok(())
+    }
+}
+
+impl Drop for BTree {
+    fn drop(&mut self) {
+        if let Err(error) = self.buffer_pool.lock().unwrap().close_file(self.file_id) {
+            log::error!("Could not write back the pages of an index: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::storage::MemoryStorage;
+
+    #[test]
+    fn test_splits_keep_keys_sorted() {
+        let storage = Arc::new(MemoryStorage::default());
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage, 16)));
+        let path = Path::new("t.idx.bin");
+        let key = |number: u32| format!("key{:05}", number * 7919 % 3000).into_bytes();
+        let row_id = |number: u32| RowId {
+            page: number,
+            slot: 0,
+        };
+        {
+            let mut tree = BTree::open(
+                path,
+                PageLayout::with_checksums(page::MIN_PAGE_SIZE),
+                buffer_pool.clone(),
+            )
+            .unwrap();
+            for number in 0..3000 {
+                assert!(tree.insert(&key(number), row_id(number)).unwrap());
+            }
+            assert!(!tree.insert(&key(5), row_id(0)).unwrap());
+            for number in (0..3000).step_by(2) {
+                assert_eq!(tree.delete(&key(number)).unwrap(), Some(row_id(number)));
+            }
+        }
+        let mut tree = BTree::open(
+            path,
+            PageLayout::with_checksums(page::MIN_PAGE_SIZE),
+            buffer_pool,
+        )
+        .unwrap();
+        assert_eq!(tree.len(), 1500);
+        assert_eq!(tree.get(&key(7)).unwrap(), Some(row_id(7)));
+        assert_eq!(tree.get(&key(8)).unwrap(), None);
+        let range = tree
+            .range(Bound::Included(b"key01000"), Bound::Excluded(b"key01010"))
+            .unwrap();
+        let keys: Vec<Vec<u8>> = range.into_iter().map(|(key, _)| key).collect();
+        // Keys of odd numbers are odd, as 7919 and 3000 are coprime and 3000 is even
+        let expected: Vec<Vec<u8>> = (1001..1010)
+            .step_by(2)
+            .map(|number| format!("key{number:05}").into_bytes())
+            .collect();
+        assert_eq!(keys, expected);
+    }
+}
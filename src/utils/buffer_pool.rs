@@ -0,0 +1,653 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utils::compression::Compression;
+use crate::utils::page::PageLayout;
+use crate::utils::storage::{Storage, StorageFile};
+use crate::utils::wal::{self, Wal, WalRecord};
+
+/// Identifies a file whose pages are cached by a buffer pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(u32);
+
+/// Identifies a page across every file of a buffer pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageId {
+    pub file: FileId,
+    pub page: u32,
+}
+
+/// Slot of the buffer pool holding a pinned page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameId(usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferPoolStats {
+    /// Number of pages the pool can hold
+    pub capacity: usize,
+    pub cached_pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Dirty pages written back to their file
+    pub writes: u64,
+}
+
+impl std::fmt::Display for BufferPoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} pages cached, {} hits, {} misses, {} evictions, {} writes",
+            self.cached_pages, self.capacity, self.hits, self.misses, self.evictions, self.writes
+        )
+    }
+}
+
+struct Frame {
+    /// Page currently held by the frame, `None` if the frame is free
+    page_id: Option<PageId>,
+    /// Whole page, including its checksum, which is up to date unless the page is pinned. Holds
+    /// the content of compressed pages, see `PageLayout::frame_size`.
+    data: Vec<u8>,
+    layout: PageLayout,
+    pin_count: u32,
+    dirty: bool,
+    /// Reference bit of the CLOCK algorithm, set every time the page is fetched
+    referenced: bool,
+}
+
+/// Content of a page before the running transaction changed it, empty if the page did not exist
+struct UndoImage {
+    path: PathBuf,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+struct Savepoint {
+    /// Identifies the savepoint in the write-ahead log
+    id: u64,
+    /// Content of the pages changed between the previous savepoint and this one, before those
+    /// changes
+    undo_images: HashMap<PageId, UndoImage>,
+    /// Files staged when the savepoint was created
+    staged_files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+struct PoolFile {
+    path: PathBuf,
+    file: Box<dyn StorageFile>,
+    layout: PageLayout,
+}
+
+/// Cache of fixed-size pages shared by every file of a database, which its storage holds. A page is pinned while it is
+/// used, and is only evicted once unpinned. Victims are chosen with the CLOCK algorithm, and
+/// dirty victims are written back to their file first.
+///
+/// Once a write-ahead log is attached, changes only reach the files through `commit`, or when a
+/// dirty page is evicted, both of which log them first (see `utils::wal`).
+pub struct BufferPool {
+    storage: Arc<dyn Storage>,
+    capacity: usize,
+    frames: Vec<Frame>,
+    page_table: HashMap<PageId, usize>,
+    files: HashMap<FileId, PoolFile>,
+    next_file_id: u32,
+    clock_hand: usize,
+    stats: BufferPoolStats,
+    wal: Option<Wal>,
+    /// Whole files written by the running transaction, kept until it commits
+    staged_files: BTreeMap<PathBuf, Vec<u8>>,
+    /// Content of the pages written back or created by the running transaction before it
+    /// changed them, which is already logged. Only covers changes made since the last savepoint.
+    undo_images: HashMap<PageId, UndoImage>,
+    /// Savepoints of the running transaction, from the oldest one
+    savepoints: Vec<Savepoint>,
+    next_savepoint_id: u64,
+    /// Files written since the last checkpoint, besides the ones of the pool
+    unsynced_files: HashSet<PathBuf>,
+}
+
+impl BufferPool {
+    /// Creates a pool holding up to `capacity` pages of the files of `storage`, whatever their size
+    pub fn new(storage: Arc<dyn Storage>, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        BufferPool {
+            storage,
+            capacity,
+            frames: Vec::new(),
+            page_table: HashMap::new(),
+            files: HashMap::new(),
+            next_file_id: 0,
+            clock_hand: 0,
+            stats: BufferPoolStats {
+                capacity,
+                ..Default::default()
+            },
+            wal: None,
+            staged_files: BTreeMap::new(),
+            undo_images: HashMap::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+            unsynced_files: HashSet::new(),
+        }
+    }
+
+    /// Logs every later change in `wal` before it reaches a file
+    pub fn attach_wal(&mut self, wal: Wal) {
+        self.wal = Some(wal);
+    }
+
+    /// Opens a file of the storage whose pages are cached, creating it if needed
+    pub fn open_file(&mut self, path: &Path, layout: PageLayout) -> io::Result<FileId> {
+        let file = self.storage.open_file(path)?;
+        let file_id = FileId(self.next_file_id);
+        self.next_file_id += 1;
+        self.files.insert(
+            file_id,
+            PoolFile {
+                path: path.to_path_buf(),
+                file,
+                layout,
+            },
+        );
+        Ok(file_id)
+    }
+
+    /// Size of a file, as written so far
+    pub fn file_size(&mut self, file_id: FileId) -> io::Result<u64> {
+        self.pool_file(file_id)?.file.size()
+    }
+
+    /// Replaces the content of a small file which is not cached, such as a table schema, see
+    /// `Storage::write`. With a write-ahead log, the file is only written once the running
+    /// transaction commits.
+    pub fn write_file(&mut self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        match self.wal {
+            Some(_) => {
+                self.staged_files.insert(path.to_path_buf(), contents);
+                Ok(())
+            }
+            None => self.storage.write(path, &contents),
+        }
+    }
+
+    /// Removes a file created with `write_file` by the running transaction, which must not be
+    /// written anymore
+    pub fn discard_file(&mut self, path: &Path) -> io::Result<()> {
+        self.staged_files.remove(path);
+        self.storage.remove(path)
+    }
+
+    /// Writes back the dirty pages of a file, then drops its pages from the pool and closes it
+    pub fn close_file(&mut self, file_id: FileId) -> io::Result<()> {
+        let result = self.flush_file(Some(file_id));
+        for frame in &mut self.frames {
+            if frame.page_id.is_some_and(|page_id| page_id.file == file_id) {
+                debug_assert_eq!(frame.pin_count, 0, "closing a file with pinned pages");
+                self.page_table.remove(&frame.page_id.take().unwrap());
+                frame.dirty = false;
+                frame.pin_count = 0;
+            }
+        }
+        self.files.remove(&file_id);
+        result
+    }
+
+    /// Pins a page, reading it from its file if it is not cached yet. A page read from its file
+    /// must match its checksum.
+    pub fn fetch(&mut self, page_id: PageId) -> io::Result<FrameId> {
+        if let Some(&index) = self.page_table.get(&page_id) {
+            self.stats.hits += 1;
+            let frame = &mut self.frames[index];
+            frame.pin_count += 1;
+            frame.referenced = true;
+            return Ok(FrameId(index));
+        }
+        let index = self.free_frame()?;
+        self.stats.misses += 1;
+        let pool_file = self.pool_file(page_id.file)?;
+        let layout = pool_file.layout;
+        let mut page = vec![0; layout.page_size];
+        let offset = page_id.page as u64 * layout.page_size as u64;
+        pool_file.file.read_at(offset, &mut page)?;
+        let data = layout.decode(page, &pool_file.path, page_id.page)?;
+        Ok(self.load(index, page_id, data, layout))
+    }
+
+    /// Appends a new page to its file, and pins it. `data` only holds the content of the page,
+    /// without its checksum. The page is written right away, so that the
+    /// file never holds a hole if later pages are written back first. With a write-ahead log, the
+    /// page is logged as not existing before, and stays dirty until its transaction commits.
+    pub fn create(&mut self, page_id: PageId, mut data: Vec<u8>) -> io::Result<FrameId> {
+        let index = self.free_frame()?;
+        let pool_file = self
+            .files
+            .get_mut(&page_id.file)
+            .ok_or_else(|| io::Error::other("File is not registered in the buffer pool"))?;
+        let offset = page_id.page as u64 * pool_file.layout.page_size as u64;
+        if let Some(wal) = &mut self.wal {
+            wal.append(&WalRecord::Undo {
+                file: wal::file_name(&pool_file.path),
+                offset,
+                data: Vec::new(),
+            })?;
+            wal.sync()?;
+            let image = UndoImage {
+                path: pool_file.path.clone(),
+                offset,
+                data: Vec::new(),
+            };
+            self.undo_images.insert(page_id, image);
+        }
+        let layout = pool_file.layout;
+        data.resize(layout.frame_size(), 0);
+        layout.seal(&mut data);
+        pool_file.file.write_at(offset, &layout.encode(&data)?)?;
+        let frame_id = self.load(index, page_id, data, layout);
+        self.frames[index].dirty = self.wal.is_some();
+        Ok(frame_id)
+    }
+
+    /// Content of a pinned page, without its checksum
+    pub fn data(&self, frame_id: FrameId) -> &[u8] {
+        let frame = &self.frames[frame_id.0];
+        &frame.data[..frame.layout.content_size()]
+    }
+
+    /// Content of a pinned page. Changes must be reported when unpinning it.
+    pub fn data_mut(&mut self, frame_id: FrameId) -> &mut [u8] {
+        let frame = &mut self.frames[frame_id.0];
+        let content_size = frame.layout.content_size();
+        &mut frame.data[..content_size]
+    }
+
+    /// Releases a page pinned by `fetch` or `create`, marking it dirty if it was modified
+    pub fn unpin(&mut self, frame_id: FrameId, dirty: bool) {
+        let frame = &mut self.frames[frame_id.0];
+        debug_assert!(frame.pin_count > 0, "unpinning a page that is not pinned");
+        frame.pin_count = frame.pin_count.saturating_sub(1);
+        frame.dirty |= dirty;
+        if dirty {
+            frame.layout.seal(&mut frame.data);
+        }
+    }
+
+    /// Makes the changes of the running transaction durable: they are logged and synced, then
+    /// written to their files. Without a write-ahead log, dirty pages are simply written back.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let Some(wal) = &mut self.wal else {
+            return self.flush_file(None);
+        };
+        let dirty = dirty_frames(&self.frames, None);
+        if dirty.is_empty()
+            && self.staged_files.is_empty()
+            && self.undo_images.is_empty()
+            && self.savepoints.is_empty()
+        {
+            return Ok(());
+        }
+        for &(page_id, index) in &dirty {
+            let pool_file = &self.files[&page_id.file];
+            let frame = &self.frames[index];
+            wal.append(&WalRecord::Redo {
+                file: wal::file_name(&pool_file.path),
+                offset: page_id.page as u64 * pool_file.layout.page_size as u64,
+                data: frame.layout.encode(&frame.data)?.into_owned(),
+            })?;
+        }
+        for (path, contents) in &self.staged_files {
+            wal.append(&WalRecord::File {
+                file: wal::file_name(path),
+                contents: contents.clone(),
+            })?;
+        }
+        wal.append(&WalRecord::Commit)?;
+        wal.sync()?;
+        let needs_checkpoint = wal.size() > wal::CHECKPOINT_SIZE;
+        for (_, index) in dirty {
+            self.write_page(index)?;
+        }
+        for (path, contents) in std::mem::take(&mut self.staged_files) {
+            self.storage.write(&path, &contents)?;
+        }
+        self.undo_images.clear();
+        self.savepoints.clear();
+        if needs_checkpoint {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Discards the changes of the running transaction: its dirty pages and staged files are
+    /// dropped, and the pages it wrote back get their previous content again. Returns whether
+    /// there was anything to discard.
+    pub fn rollback(&mut self) -> io::Result<bool> {
+        let wal = self.wal.as_mut().ok_or_else(missing_wal)?;
+        let dirty = dirty_frames(&self.frames, None);
+        if dirty.is_empty()
+            && self.staged_files.is_empty()
+            && self.undo_images.is_empty()
+            && self.savepoints.is_empty()
+        {
+            return Ok(false);
+        }
+        // Logged first, so that recovery finishes restoring pages if this is interrupted
+        wal.append(&WalRecord::Rollback)?;
+        wal.sync()?;
+        self.discard_dirty_pages();
+        let undo_images = std::mem::take(&mut self.undo_images);
+        self.restore_pages(undo_images)?;
+        while let Some(savepoint) = self.savepoints.pop() {
+            self.restore_pages(savepoint.undo_images)?;
+        }
+        self.staged_files.clear();
+        Ok(true)
+    }
+
+    /// Marks the current state of the running transaction, which `rollback_to_savepoint` can
+    /// come back to. Savepoints are nested, and designated by their depth, from 0 for the oldest
+    /// one.
+    pub fn savepoint(&mut self) -> io::Result<()> {
+        if self.wal.is_none() {
+            return Err(missing_wal());
+        }
+        // Files then hold the state of the savepoint, which pages changed later are restored to
+        self.flush_file(None)?;
+        let id = self.next_savepoint_id;
+        self.next_savepoint_id += 1;
+        self.wal
+            .as_mut()
+            .unwrap()
+            .append(&WalRecord::Savepoint { id })?;
+        self.savepoints.push(Savepoint {
+            id,
+            undo_images: std::mem::take(&mut self.undo_images),
+            staged_files: self.staged_files.clone(),
+        });
+        Ok(())
+    }
+
+    /// Forgets a savepoint and the ones created after it, keeping the changes made since
+    pub fn release_savepoint(&mut self, depth: usize) {
+        // Going back in time, so that the oldest content of every page is kept
+        for savepoint in self.savepoints.drain(depth..).rev() {
+            self.undo_images.extend(savepoint.undo_images);
+        }
+    }
+
+    /// Discards the changes made since a savepoint, which is kept while the ones created after
+    /// it are forgotten
+    pub fn rollback_to_savepoint(&mut self, depth: usize) -> io::Result<()> {
+        let wal = self.wal.as_mut().ok_or_else(missing_wal)?;
+        let id = self.savepoints[depth].id;
+        wal.append(&WalRecord::RollbackToSavepoint { id })?;
+        wal.sync()?;
+        self.discard_dirty_pages();
+        let undo_images = std::mem::take(&mut self.undo_images);
+        self.restore_pages(undo_images)?;
+        while self.savepoints.len() > depth + 1 {
+            let savepoint = self.savepoints.pop().unwrap();
+            self.restore_pages(savepoint.undo_images)?;
+        }
+        self.staged_files = self.savepoints[depth].staged_files.clone();
+        Ok(())
+    }
+
+    /// Content of a file written with `write_file`, including changes which are not committed
+    pub fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.staged_files.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => self.storage.read(path),
+        }
+    }
+
+    /// Syncs every file changed by committed transactions, then empties the write-ahead log. Must
+    /// not be called while a transaction has uncommitted changes.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let Some(wal) = &mut self.wal else {
+            return Ok(());
+        };
+        for pool_file in self.files.values_mut() {
+            pool_file.file.sync()?;
+        }
+        for path in self.unsynced_files.drain() {
+            if self.storage.exists(&path) {
+                self.storage.open_file(&path)?.sync()?;
+            }
+        }
+        log::debug!("Checkpoint: truncating {} bytes of log", wal.size());
+        wal.truncate()
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            cached_pages: self.page_table.len(),
+            ..self.stats
+        }
+    }
+
+    /// Drops the changes of dirty pages from the pool, without writing them
+    fn discard_dirty_pages(&mut self) {
+        for (page_id, index) in dirty_frames(&self.frames, None) {
+            debug_assert_eq!(self.frames[index].pin_count, 0, "discarding a pinned page");
+            self.page_table.remove(&page_id);
+            self.frames[index].page_id = None;
+            self.frames[index].dirty = false;
+        }
+    }
+
+    /// Gives pages written back to their files their previous content again
+    fn restore_pages(&mut self, undo_images: HashMap<PageId, UndoImage>) -> io::Result<()> {
+        for (page_id, image) in undo_images {
+            // The cached page may hold the content written back
+            if let Some(index) = self.page_table.remove(&page_id) {
+                self.frames[index].page_id = None;
+            }
+            wal::undo(&*self.storage, &image.path, image.offset, &image.data)?;
+            self.unsynced_files.insert(image.path);
+        }
+        Ok(())
+    }
+
+    /// Writes back the dirty pages of a file, or of every file, in page order
+    fn flush_file(&mut self, file_id: Option<FileId>) -> io::Result<()> {
+        for (_, index) in dirty_frames(&self.frames, file_id) {
+            self.write_back(index)?;
+        }
+        Ok(())
+    }
+
+    /// Finds a frame for a new page: an unused one while the pool is not full, otherwise the
+    /// first unpinned page whose reference bit is clear, clearing bits along the way
+    fn free_frame(&mut self) -> io::Result<usize> {
+        if let Some(index) = self.frames.iter().position(|frame| frame.page_id.is_none()) {
+            return Ok(index);
+        }
+        if self.frames.len() < self.capacity {
+            self.frames.push(Frame {
+                page_id: None,
+                data: Vec::new(),
+                layout: PageLayout {
+                    page_size: 0,
+                    checksums: false,
+                    compression: Compression::None,
+                },
+                pin_count: 0,
+                dirty: false,
+                referenced: false,
+            });
+            return Ok(self.frames.len() - 1);
+        }
+        // Two turns are enough: the first one clears every reference bit
+        for _ in 0..2 * self.frames.len() {
+            let index = self.clock_hand;
+            self.clock_hand = (self.clock_hand + 1) % self.frames.len();
+            let frame = &mut self.frames[index];
+            if frame.pin_count > 0 {
+                continue;
+            }
+            if frame.referenced {
+                frame.referenced = false;
+                continue;
+            }
+            self.write_back(index)?;
+            let page_id = self.frames[index].page_id.take().unwrap();
+            self.page_table.remove(&page_id);
+            self.stats.evictions += 1;
+            return Ok(index);
+        }
+        Err(io::Error::other(format!(
+            "Buffer pool is full: all of its {} pages are pinned",
+            self.capacity
+        )))
+    }
+
+    fn load(
+        &mut self,
+        index: usize,
+        page_id: PageId,
+        data: Vec<u8>,
+        layout: PageLayout,
+    ) -> FrameId {
+        self.frames[index] = Frame {
+            page_id: Some(page_id),
+            data,
+            layout,
+            pin_count: 1,
+            dirty: false,
+            referenced: true,
+        };
+        self.page_table.insert(page_id, index);
+        FrameId(index)
+    }
+
+    /// Writes a dirty page back to its file before its transaction commits. With a write-ahead
+    /// log, the page is logged first along with the content it replaces, so that recovery can
+    /// either undo or redo the change.
+    fn write_back(&mut self, index: usize) -> io::Result<()> {
+        let frame = &self.frames[index];
+        let Some(page_id) = frame.page_id.filter(|_| frame.dirty) else {
+            return Ok(());
+        };
+        if let Some(wal) = &mut self.wal {
+            let pool_file = self
+                .files
+                .get_mut(&page_id.file)
+                .ok_or_else(|| io::Error::other("Page of a closed file in the buffer pool"))?;
+            let file = wal::file_name(&pool_file.path);
+            let offset = page_id.page as u64 * pool_file.layout.page_size as u64;
+            if let Entry::Vacant(entry) = self.undo_images.entry(page_id) {
+                let mut previous = vec![0; pool_file.layout.page_size];
+                if let Err(error) = pool_file.file.read_at(offset, &mut previous) {
+                    if error.kind() != io::ErrorKind::UnexpectedEof {
+                        return Err(error);
+                    }
+                    previous.clear();
+                }
+                wal.append(&WalRecord::Undo {
+                    file: file.clone(),
+                    offset,
+                    data: previous.clone(),
+                })?;
+                entry.insert(UndoImage {
+                    path: pool_file.path.clone(),
+                    offset,
+                    data: previous,
+                });
+            }
+            wal.append(&WalRecord::Redo {
+                file,
+                offset,
+                data: frame.layout.encode(&frame.data)?.into_owned(),
+            })?;
+            wal.sync()?;
+        }
+        self.write_page(index)
+    }
+
+    fn write_page(&mut self, index: usize) -> io::Result<()> {
+        let frame = &self.frames[index];
+        let Some(page_id) = frame.page_id else {
+            return Ok(());
+        };
+        let pool_file = self
+            .files
+            .get_mut(&page_id.file)
+            .ok_or_else(|| io::Error::other("Page of a closed file in the buffer pool"))?;
+        let offset = page_id.page as u64 * pool_file.layout.page_size as u64;
+        pool_file
+            .file
+            .write_at(offset, &frame.layout.encode(&frame.data)?)?;
+        self.frames[index].dirty = false;
+        self.stats.writes += 1;
+        Ok(())
+    }
+
+    fn pool_file(&mut self, file_id: FileId) -> io::Result<&mut PoolFile> {
+        self.files
+            .get_mut(&file_id)
+            .ok_or_else(|| io::Error::other("File is not registered in the buffer pool"))
+    }
+}
+
+fn missing_wal() -> io::Error {
+    io::Error::other("Changes can only be rolled back with a write-ahead log")
+}
+
+/// Dirty pages of a file, or of every file, in page order, along with their frame
+fn dirty_frames(frames: &[Frame], file_id: Option<FileId>) -> Vec<(PageId, usize)> {
+    let mut dirty: Vec<(PageId, usize)> = frames
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| frame.dirty)
+        .filter_map(|(index, frame)| frame.page_id.map(|page_id| (page_id, index)))
+        .filter(|(page_id, _)| file_id.is_none_or(|file_id| page_id.file == file_id))
+        .collect();
+    dirty.sort_by_key(|(page_id, _)| (page_id.file.0, page_id.page));
+    dirty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::storage::MemoryStorage;
+
+    #[test]
+    fn test_clock_eviction_writes_back_dirty_pages() {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut pool = BufferPool::new(storage.clone(), 2);
+        let layout = PageLayout {
+            page_size: 4,
+            checksums: false,
+            compression: Compression::None,
+        };
+        let file = pool.open_file(Path::new("pool.bin"), layout).unwrap();
+        let page = |page| PageId { file, page };
+        for number in 0..3 {
+            let frame = pool.create(page(number), vec![number as u8; 4]).unwrap();
+            pool.unpin(frame, false);
+        }
+        let frame = pool.fetch(page(2)).unwrap();
+        pool.data_mut(frame).copy_from_slice(&[9; 4]);
+        // Page 2 stays pinned, so page 0 can only come back by evicting page 1
+        let first = pool.fetch(page(0)).unwrap();
+        assert_eq!(pool.data(first), [0; 4]);
+        assert!(pool.fetch(page(1)).is_err());
+        pool.unpin(first, false);
+        pool.unpin(frame, true);
+        // Evicts page 2, writing it back since it is dirty
+        let frame = pool.fetch(page(1)).unwrap();
+        pool.unpin(frame, false);
+
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses, stats.writes), (1, 2, 1));
+        pool.close_file(file).unwrap();
+        assert_eq!(
+            storage.read(Path::new("pool.bin")).unwrap(),
+            [0, 0, 0, 0, 1, 1, 1, 1, 9, 9, 9, 9]
+        );
+    }
+}
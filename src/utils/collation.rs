@@ -0,0 +1,85 @@
+use crate::sql_compilator::parser::ParsingError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Rules used to compare and sort text values
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Collation {
+    /// Byte order of the UTF-8 encoding
+    #[default]
+    Binary,
+    /// Byte order, except that ASCII letters are compared without their case (as in SQLite)
+    NoCase,
+    /// Language-agnostic Unicode order: letters are first compared without accents nor case,
+    /// then with accents, then with case. Only identical strings are equal.
+    Unicode,
+}
+
+impl Collation {
+    pub fn from_string(collation: String) -> Result<Collation, ParsingError> {
+        match collation.to_lowercase().as_str() {
+            "binary" => Ok(Collation::Binary),
+            "nocase" => Ok(Collation::NoCase),
+            "unicode" => Ok(Collation::Unicode),
+            &_ => Err(ParsingError::UnknownCollation { found: collation }),
+        }
+    }
+
+    pub fn compare(&self, left: &str, right: &str) -> Ordering {
+        match self {
+            Collation::Binary => left.cmp(right),
+            Collation::NoCase => left
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(right.bytes().map(|byte| byte.to_ascii_lowercase())),
+            Collation::Unicode => self.sort_key(left).cmp(&self.sort_key(right)),
+        }
+    }
+
+    /// Splits a text into strings that, compared one after the other in byte order, sort texts
+    /// the same way as this collation. Used by indexes, which only compare bytes.
+    pub fn sort_key<'text>(&self, text: &'text str) -> Vec<Cow<'text, str>> {
+        match self {
+            Collation::Binary => vec![Cow::Borrowed(text)],
+            Collation::NoCase => vec![Cow::Owned(text.to_ascii_lowercase())],
+            Collation::Unicode => {
+                let base_letters: String = text.nfd().filter(|&c| !is_combining_mark(c)).collect();
+                let without_case: String = text.nfd().flat_map(char::to_lowercase).collect();
+                vec![
+                    Cow::Owned(base_letters.to_lowercase()),
+                    Cow::Owned(without_case),
+                    Cow::Borrowed(text),
+                ]
+            }
+        }
+    }
+
+    /// Returns a form of the text that is identical for every text this collation considers
+    /// equal, used to look values up by key (unique constraints, DISTINCT, indexes)
+    pub fn key<'text>(&self, text: &'text str) -> Cow<'text, str> {
+        match self {
+            Collation::NoCase => Cow::Owned(text.to_ascii_lowercase()),
+            Collation::Binary | Collation::Unicode => Cow::Borrowed(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation_order() {
+        assert_eq!(Collation::Binary.compare("B", "a"), Ordering::Less);
+        assert_eq!(Collation::NoCase.compare("B", "a"), Ordering::Greater);
+        assert_eq!(Collation::NoCase.compare("ABC", "abc"), Ordering::Equal);
+        assert_eq!(Collation::Unicode.compare("é", "f"), Ordering::Less);
+        assert_eq!(Collation::Unicode.compare("e", "é"), Ordering::Less);
+        assert_eq!(Collation::Unicode.compare("é", "E"), Ordering::Greater);
+        assert_eq!(Collation::Unicode.compare("Zoé", "zoe"), Ordering::Greater);
+        assert_ne!(Collation::Unicode.compare("A", "a"), Ordering::Equal);
+    }
+}
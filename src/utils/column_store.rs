@@ -0,0 +1,535 @@
+use bincode::{Decode, Encode, config};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::buffer_pool::BufferPool;
+use crate::utils::file_handler::{self, Column, DataType, OverflowValue, ROW_DECODE_LIMIT, Value};
+use crate::utils::heap_file::{HeapFile, RowId};
+use crate::utils::index_key::{self, KeyBounds, KeyOrder};
+use crate::utils::page::PageLayout;
+
+// A columnar table keeps the values of each of its columns in a segment file of its own,
+// `<table>.<column>.seg.bin`, row `n` of the table being the `n`th value of every segment. A
+// segment is a heap file whose records are blocks of values of consecutive rows, values too large
+// for a block being stored in its overflow pages. The directory of the blocks,
+// `<table>.blocks.bin`, holds the rows of every block along with its smallest and largest values,
+// so that a scan only reads the blocks of the columns it needs, and skips the blocks whose values
+// are out of its bounds.
+//
+// Row versions are still records of the data file of the table, which hold the number of their
+// row instead of its values. Rows are only ever appended: the values of purged versions stay in
+// their segment.
+
+/// Rows of a block, which is also full once its values take half a page
+const MAX_BLOCK_ROWS: u32 = 1024;
+
+/// Bytes of a text beyond which a block keeps no smallest and largest values, so that entries of
+/// the directory stay small
+const MAX_STATISTIC_LENGTH: usize = 64;
+
+/// Entry of the directory describing a block of a segment
+#[derive(Encode, Decode, Debug, Clone)]
+struct BlockEntry {
+    column: u32,
+    first_row: u64,
+    row_count: u32,
+    /// Address of the block in its segment
+    page: u32,
+    slot: u16,
+    /// Smallest and largest non-null values of the block, in the order of the index keys of the
+    /// column. They are unknown for the blocks holding a value of another type, or a long text.
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+/// Block of a segment, along with the address of its entry in the directory
+struct Block {
+    entry: BlockEntry,
+    entry_id: RowId,
+}
+
+struct Segment {
+    heap_file: HeapFile,
+    /// Blocks of the segment, in the order of their rows
+    blocks: Vec<Block>,
+    /// How values of the column are ordered, if its blocks keep their smallest and largest values
+    order: Option<KeyOrder>,
+}
+
+/// Values of the rows of a columnar table, stored column by column
+pub struct ColumnStore {
+    segments: Vec<Segment>,
+    directory: HeapFile,
+    /// Number given to the next appended row
+    next_row: u64,
+    /// Values of more than this many bytes are stored in overflow pages
+    overflow_threshold: usize,
+}
+
+impl ColumnStore {
+    /// Opens the segments of the columns of a table and its directory of blocks, creating them if
+    /// they do not exist yet. Segments are stored with the layout of the data file, which may be
+    /// compressed.
+    pub fn open(
+        tables_dir: &Path,
+        table_name: &str,
+        columns: &[Column],
+        segment_layout: PageLayout,
+        directory_layout: PageLayout,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Self> {
+        let mut directory = HeapFile::open(
+            &directory_path(tables_dir, table_name),
+            directory_layout,
+            buffer_pool.clone(),
+        )?;
+        let mut segments = Vec::new();
+        for column in columns {
+            segments.push(Segment {
+                heap_file: HeapFile::open(
+                    &segment_path(tables_dir, table_name, &column.name),
+                    segment_layout,
+                    buffer_pool.clone(),
+                )?,
+                blocks: Vec::new(),
+                order: statistic_order(column),
+            });
+        }
+        for (entry_id, record) in directory.scan()? {
+            let entry: BlockEntry = decode(&record)?;
+            let segment = segments.get_mut(entry.column as usize).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Directory of table '{table_name}' describes a block of column #{}, which does not exist",
+                        entry.column
+                    ),
+                )
+            })?;
+            segment.blocks.push(Block { entry, entry_id });
+        }
+        let mut next_row = 0;
+        for segment in &mut segments {
+            segment.blocks.sort_by_key(|block| block.entry.first_row);
+            if let Some(last) = segment.blocks.last() {
+                next_row = next_row.max(last.entry.first_row + last.entry.row_count as u64);
+            }
+        }
+        Ok(ColumnStore {
+            segments,
+            directory,
+            next_row,
+            overflow_threshold: segment_layout.page_size / 4,
+        })
+    }
+
+    /// Files of the segments and of the directory of a table, in the tables directory
+    pub fn files(table_name: &str, columns: &[Column]) -> Vec<String> {
+        let mut files: Vec<String> = columns
+            .iter()
+            .map(|column| format!("{table_name}.{}.seg.bin", column.name))
+            .collect();
+        files.push(format!("{table_name}.blocks.bin"));
+        files
+    }
+
+    /// Appends the values of a row to the segments, returning the number of the row
+    pub fn append(&mut self, row: &[Value]) -> io::Result<u64> {
+        let row_number = self.next_row;
+        for (column, segment) in self.segments.iter_mut().enumerate() {
+            let value = row.get(column).cloned().unwrap_or(Value::Null);
+            segment.append(
+                column,
+                row_number,
+                value,
+                self.overflow_threshold,
+                &mut self.directory,
+            )?;
+        }
+        self.next_row += 1;
+        Ok(row_number)
+    }
+
+    /// Reads the values of a row
+    pub fn read_row(&mut self, row_number: u64) -> io::Result<Vec<Value>> {
+        let columns = vec![true; self.segments.len()];
+        let mut rows = self.read_rows(&[row_number], &columns, &[])?;
+        Ok(rows.pop().flatten().unwrap_or_default())
+    }
+
+    /// Reads the values of given rows, in the order of `row_numbers`, only reading the segments
+    /// of the columns flagged in `columns`: the values of the other ones are NULL. Rows whose
+    /// block holds no value within given bounds of a column are skipped, and read as `None`.
+    pub fn read_rows(
+        &mut self,
+        row_numbers: &[u64],
+        columns: &[bool],
+        bounds: &[(usize, KeyBounds)],
+    ) -> io::Result<Vec<Option<Vec<Value>>>> {
+        // Blocks of every bounded segment, flagged when they hold no value within the bounds
+        let skipped_blocks: Vec<(&Segment, Vec<bool>)> = bounds
+            .iter()
+            .filter_map(|(column, bounds)| {
+                let segment = self.segments.get(*column)?;
+                let skipped = segment
+                    .blocks
+                    .iter()
+                    .map(|block| !block.may_hold(bounds, segment.order))
+                    .collect();
+                Some((segment, skipped))
+            })
+            .collect();
+        let mut wanted: Vec<(u64, usize)> = Vec::new();
+        for (position, &row_number) in row_numbers.iter().enumerate() {
+            if row_number >= self.next_row {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Row #{row_number} is not stored in the segments"),
+                ));
+            }
+            let is_skipped = skipped_blocks.iter().any(|(segment, skipped)| {
+                segment
+                    .block_position(row_number)
+                    .is_some_and(|block| skipped[block])
+            });
+            if !is_skipped {
+                wanted.push((row_number, position));
+            }
+        }
+        wanted.sort_unstable();
+        let mut rows: Vec<Option<Vec<Value>>> = vec![None; row_numbers.len()];
+        for &(_, position) in &wanted {
+            rows[position] = Some(vec![Value::Null; self.segments.len()]);
+        }
+        for (column, segment) in self.segments.iter_mut().enumerate() {
+            if !columns.get(column).copied().unwrap_or(true) {
+                continue;
+            }
+            for block in 0..segment.blocks.len() {
+                let entry = &segment.blocks[block].entry;
+                let end = entry.first_row + entry.row_count as u64;
+                let start = wanted.partition_point(|&(row_number, _)| row_number < entry.first_row);
+                let count = wanted[start..].partition_point(|&(row_number, _)| row_number < end);
+                if count == 0 {
+                    continue;
+                }
+                let first_row = entry.first_row;
+                let values = segment.read_block(block)?;
+                for &(row_number, position) in &wanted[start..start + count] {
+                    let value = values
+                        .get((row_number - first_row) as usize)
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Block of row #{row_number} misses its value"),
+                            )
+                        })?;
+                    if let Some(row) = &mut rows[position] {
+                        row[column] = value.clone();
+                    }
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+impl Segment {
+    /// Position of the block holding the value of a row
+    fn block_position(&self, row_number: u64) -> Option<usize> {
+        let position = self
+            .blocks
+            .partition_point(|block| block.entry.first_row <= row_number)
+            .checked_sub(1)?;
+        let entry = &self.blocks[position].entry;
+        (row_number < entry.first_row + entry.row_count as u64).then_some(position)
+    }
+
+    /// Adds the value of a new row to the last block of the segment, or to a new block once the
+    /// last one is full
+    fn append(
+        &mut self,
+        column: usize,
+        row_number: u64,
+        value: Value,
+        overflow_threshold: usize,
+        directory: &mut HeapFile,
+    ) -> io::Result<()> {
+        let max_block_size = overflow_threshold * 2;
+        if let Some(last) = self.blocks.len().checked_sub(1)
+            && self.blocks[last].entry.row_count < MAX_BLOCK_ROWS
+        {
+            let (mut values, mut overflow_values) = self.read_stored_block(last)?;
+            let mut new_values = [value.clone()];
+            let new_overflow_values = file_handler::store_large_values(
+                &mut self.heap_file,
+                &mut new_values,
+                values.len(),
+                overflow_threshold,
+            )?;
+            values.extend(new_values);
+            overflow_values.extend(new_overflow_values.iter().cloned());
+            let record = encode(&(&values, &overflow_values))?;
+            if record.len() <= max_block_size {
+                let block = &mut self.blocks[last];
+                let block_id = RowId {
+                    page: block.entry.page,
+                    slot: block.entry.slot,
+                };
+                let block_id = self.heap_file.update(block_id, &record)?;
+                block.entry.page = block_id.page;
+                block.entry.slot = block_id.slot;
+                block.entry.row_count += 1;
+                (block.entry.min, block.entry.max) = match (&block.entry.min, &block.entry.max) {
+                    (Some(min), Some(max)) if overflow_values.is_empty() => {
+                        widen(min, max, values.last().unwrap(), self.order)
+                    }
+                    _ => statistics(&values, &overflow_values, self.order),
+                };
+                block.entry_id = directory.update(block.entry_id, &encode(&block.entry)?)?;
+                return Ok(());
+            }
+            for overflow_value in new_overflow_values {
+                self.heap_file.delete_overflow(overflow_value.first_page)?;
+            }
+        }
+        let mut values = vec![value];
+        let overflow_values = file_handler::store_large_values(
+            &mut self.heap_file,
+            &mut values,
+            0,
+            overflow_threshold,
+        )?;
+        let block_id = self
+            .heap_file
+            .insert(&encode(&(&values, &overflow_values))?)?;
+        let (min, max) = statistics(&values, &overflow_values, self.order);
+        let entry = BlockEntry {
+            column: column as u32,
+            first_row: row_number,
+            row_count: 1,
+            page: block_id.page,
+            slot: block_id.slot,
+            min,
+            max,
+        };
+        let entry_id = directory.insert(&encode(&entry)?)?;
+        self.blocks.push(Block { entry, entry_id });
+        Ok(())
+    }
+
+    /// Values of a block, along with the references to its values stored in overflow pages
+    fn read_stored_block(&mut self, block: usize) -> io::Result<(Vec<Value>, Vec<OverflowValue>)> {
+        let entry = &self.blocks[block].entry;
+        let block_id = RowId {
+            page: entry.page,
+            slot: entry.slot,
+        };
+        let record = self.heap_file.get(block_id)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Block of rows #{} and after is missing", entry.first_row),
+            )
+        })?;
+        let (values, overflow_values): (Vec<Value>, Vec<OverflowValue>) = decode(&record)?;
+        if values.len() != entry.row_count as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Block of rows #{} and after holds {} values instead of {}",
+                    entry.first_row,
+                    values.len(),
+                    entry.row_count
+                ),
+            ));
+        }
+        Ok((values, overflow_values))
+    }
+
+    fn read_block(&mut self, block: usize) -> io::Result<Vec<Value>> {
+        let (mut values, overflow_values) = self.read_stored_block(block)?;
+        file_handler::load_large_values(&mut self.heap_file, &mut values, overflow_values)?;
+        Ok(values)
+    }
+}
+
+impl Block {
+    /// Whether the block may hold a value within given bounds, which it does when its smallest
+    /// and largest values are unknown
+    fn may_hold(&self, bounds: &KeyBounds, order: Option<KeyOrder>) -> bool {
+        let (Some(min), Some(max), Some(order)) = (&self.entry.min, &self.entry.max, order) else {
+            return true;
+        };
+        let key = |value: &Value| index_key::encode(std::slice::from_ref(value), &[order]);
+        let lower = bounds.equal.first().or(bounds.lower.as_ref());
+        let upper = bounds.equal.first().or(bounds.upper.as_ref());
+        lower.is_none_or(|lower| key(max) >= key(lower))
+            && upper.is_none_or(|upper| key(min) <= key(upper))
+    }
+}
+
+/// How values of a column are ordered when its blocks keep their smallest and largest values,
+/// which only those of scalar types do
+fn statistic_order(column: &Column) -> Option<KeyOrder> {
+    match column.data_type {
+        DataType::Integer | DataType::Float | DataType::Text | DataType::Bool | DataType::Uuid => {
+            Some(KeyOrder {
+                collation: column.collation,
+                descending: false,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Smallest and largest non-null values of a block, when they are known
+fn statistics(
+    values: &[Value],
+    overflow_values: &[OverflowValue],
+    order: Option<KeyOrder>,
+) -> (Option<Value>, Option<Value>) {
+    let Some(order) = order.filter(|_| overflow_values.is_empty()) else {
+        return (None, None);
+    };
+    let mut keyed = Vec::new();
+    for value in values {
+        if *value == Value::Null {
+            continue;
+        }
+        if !is_statistic(value) {
+            return (None, None);
+        }
+        keyed.push((
+            index_key::encode(std::slice::from_ref(value), &[order]),
+            value,
+        ));
+    }
+    let min = keyed.iter().min_by(|left, right| left.0.cmp(&right.0));
+    let max = keyed.iter().max_by(|left, right| left.0.cmp(&right.0));
+    (
+        min.map(|(_, value)| (*value).clone()),
+        max.map(|(_, value)| (*value).clone()),
+    )
+}
+
+/// Smallest and largest values of a block once given value is added to it
+fn widen(
+    min: &Value,
+    max: &Value,
+    value: &Value,
+    order: Option<KeyOrder>,
+) -> (Option<Value>, Option<Value>) {
+    let Some(order) = order.filter(|_| is_statistic(value)) else {
+        return match value {
+            Value::Null => (Some(min.clone()), Some(max.clone())),
+            _ => (None, None),
+        };
+    };
+    let key = |value: &Value| index_key::encode(std::slice::from_ref(value), &[order]);
+    let value_key = key(value);
+    let min = if value_key < key(min) { value } else { min };
+    let max = if value_key > key(max) { value } else { max };
+    (Some(min.clone()), Some(max.clone()))
+}
+
+/// Whether a block may keep a value as its smallest or largest one
+fn is_statistic(value: &Value) -> bool {
+    match value {
+        Value::Integer(_) | Value::Real(_) | Value::Bool(_) | Value::Uuid(_) => true,
+        Value::Text(text) => text.len() <= MAX_STATISTIC_LENGTH,
+        _ => false,
+    }
+}
+
+fn segment_path(tables_dir: &Path, table_name: &str, column_name: &str) -> PathBuf {
+    tables_dir.join(format!("{table_name}.{column_name}.seg.bin"))
+}
+
+fn directory_path(tables_dir: &Path, table_name: &str) -> PathBuf {
+    tables_dir.join(format!("{table_name}.blocks.bin"))
+}
+
+fn encode(value: &impl Encode) -> io::Result<Vec<u8>> {
+    bincode::encode_to_vec(value, config::standard()).map_err(io::Error::other)
+}
+
+fn decode<T: Decode<()>>(record: &[u8]) -> io::Result<T> {
+    let config = config::standard().with_limit::<ROW_DECODE_LIMIT>();
+    bincode::decode_from_slice(record, config)
+        .map(|(value, _)| value)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::collation::Collation;
+    use crate::utils::storage::MemoryStorage;
+
+    #[test]
+    fn test_scans_read_bounded_blocks_of_given_columns() {
+        let storage = Arc::new(MemoryStorage::default());
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage, 64)));
+        let column = |name: &str, data_type: DataType| Column {
+            name: name.to_string(),
+            data_type,
+            values: Vec::new(),
+            is_primary_key: false,
+            is_unique: false,
+            nullable: true,
+            default: None,
+            collation: Collation::default(),
+        };
+        let columns = vec![column("n", DataType::Integer), column("s", DataType::Text)];
+        let layout = PageLayout::with_checksums(4096);
+        let tables_dir = Path::new("tables");
+        let open = || {
+            ColumnStore::open(
+                tables_dir,
+                "t",
+                &columns,
+                layout,
+                layout,
+                buffer_pool.clone(),
+            )
+            .unwrap()
+        };
+        let mut store = open();
+        let large = "x".repeat(5000);
+        for n in 0..3000 {
+            let text = if n == 10 {
+                large.clone()
+            } else {
+                n.to_string()
+            };
+            let row_number = store
+                .append(&[Value::Integer(n), Value::Text(text)])
+                .unwrap();
+            assert_eq!(row_number, n as u64);
+        }
+        assert!(store.segments[0].blocks.len() > 1);
+
+        // Blocks are found again once the store is opened again
+        drop(store);
+        let mut store = open();
+        assert_eq!(store.read_row(10).unwrap()[1], Value::Text(large));
+        let bounds = KeyBounds {
+            equal: Vec::new(),
+            lower: Some(Value::Integer(2990)),
+            upper: None,
+        };
+        let rows = store
+            .read_rows(&[2999, 5, 2995], &[true, false], &[(0, bounds)])
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                Some(vec![Value::Integer(2999), Value::Null]),
+                None,
+                Some(vec![Value::Integer(2995), Value::Null]),
+            ]
+        );
+        assert!(store.read_rows(&[3000], &[], &[]).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use crate::sql_compilator::parser::ParsingError;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Algorithm compressing the pages of a data file, set with `CREATE TABLE ... WITH (compression
+/// = 'lz4')` or the default of the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+impl Compression {
+    pub fn from_string(compression: String) -> Result<Compression, ParsingError> {
+        match compression.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            &_ => Err(ParsingError::UnknownCompression { found: compression }),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::block::compress(data),
+        }
+    }
+
+    /// Restores data of given size from its compressed form
+    pub fn decompress(self, compressed: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let data = match self {
+            Compression::None => compressed.to_vec(),
+            Compression::Lz4 => lz4_flex::block::decompress(compressed, size)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+        };
+        if data.len() != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {size} bytes once decompressed, found {}",
+                    data.len()
+                ),
+            ));
+        }
+        Ok(data)
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use bincode::{Decode, Encode, config};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::directory_lock::{self, AccessMode};
+use crate::utils::storage::{Storage, StorageFile};
+
+// A single-file database is a container holding every file of the database in blocks of
+// `BLOCK_SIZE` bytes. Blocks 0 and 1 are two copies of the header:
+//   [16 bytes magic][u32 format version][u64 generation][u64 directory block]
+//   [u64 directory blocks][u64 directory bytes][u32 CRC-32 of the directory][u32 CRC-32 of the
+//   header]
+// and the valid copy with the highest generation designates the directory, a bincode map from
+// every file name to its size and the extents (runs of consecutive blocks) holding it.
+//
+// Files grow by extents, each one at least as large as the file up to `MAX_EXTENT_BLOCKS`, so
+// that most writes do not change the directory. Changing the directory writes a new one to free
+// blocks, then the header copy holding the oldest generation: a process stopping at any point
+// leaves the previous directory in place. Blocks the durable directory references are only
+// reused once a new directory replaced it.
+//
+// The write-ahead log and the lock of the database cannot live in the container, as recovery
+// and locking happen before it can be trusted. The log is `<file>-wal`, next to it, which is
+// empty once the database is closed, and processes lock the container file itself.
+
+const MAGIC: &[u8; 16] = b"TrusDB container";
+const FORMAT_VERSION: u32 = 1;
+pub const BLOCK_SIZE: u64 = 4096;
+const HEADER_BLOCKS: u64 = 2;
+const HEADER_SIZE: usize = 60;
+const MAX_EXTENT_BLOCKS: u64 = 256;
+
+/// Run of consecutive blocks of the container
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+struct Extent {
+    start: u64,
+    blocks: u64,
+}
+
+impl Extent {
+    fn end(self) -> u64 {
+        self.start + self.blocks
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone, Default)]
+struct Entry {
+    size: u64,
+    /// Blocks holding the file, in order
+    extents: Vec<Extent>,
+}
+
+impl Entry {
+    fn capacity(&self) -> u64 {
+        self.extents.iter().map(|extent| extent.blocks).sum::<u64>() * BLOCK_SIZE
+    }
+
+    /// Position in the container of a byte of the file, along with the number of bytes of the
+    /// file stored contiguously from there
+    fn locate(&self, mut offset: u64) -> Option<(u64, u64)> {
+        for extent in &self.extents {
+            let length = extent.blocks * BLOCK_SIZE;
+            if offset < length {
+                return Some((extent.start * BLOCK_SIZE + offset, length - offset));
+            }
+            offset -= length;
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    generation: u64,
+    directory: Extent,
+    directory_size: u64,
+    directory_checksum: u32,
+}
+
+impl Header {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend(MAGIC);
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.extend(self.generation.to_le_bytes());
+        bytes.extend(self.directory.start.to_le_bytes());
+        bytes.extend(self.directory.blocks.to_le_bytes());
+        bytes.extend(self.directory_size.to_le_bytes());
+        bytes.extend(self.directory_checksum.to_le_bytes());
+        bytes.extend(crc32fast::hash(&bytes).to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a copy of the header, `None` if it was never written or was torn
+    fn from_bytes(bytes: &[u8]) -> io::Result<Option<Self>> {
+        let checksum = u32::from_le_bytes(bytes[HEADER_SIZE - 4..HEADER_SIZE].try_into().unwrap());
+        if &bytes[..16] != MAGIC || crc32fast::hash(&bytes[..HEADER_SIZE - 4]) != checksum {
+            return Ok(None);
+        }
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let version = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Container format {version} is not supported"),
+            ));
+        }
+        Ok(Some(Header {
+            generation: u64_at(20),
+            directory: Extent {
+                start: u64_at(28),
+                blocks: u64_at(36),
+            },
+            directory_size: u64_at(44),
+            directory_checksum: u32::from_le_bytes(bytes[52..56].try_into().unwrap()),
+        }))
+    }
+}
+
+struct Container {
+    path: PathBuf,
+    file: File,
+    mode: AccessMode,
+    entries: BTreeMap<String, Entry>,
+    /// Header last written, whose directory is the durable one
+    header: Header,
+    /// Blocks referenced by the durable directory, including its own
+    durable_extents: Vec<Extent>,
+    /// Whether `entries` changed since the durable directory was written
+    dirty: bool,
+    /// Blocks of the container, used or not
+    block_count: u64,
+}
+
+impl Container {
+    fn create(path: &Path, file: File, mode: AccessMode) -> io::Result<Self> {
+        let mut container = Container {
+            path: path.to_path_buf(),
+            file,
+            mode,
+            entries: BTreeMap::new(),
+            header: Header {
+                generation: 0,
+                directory: Extent {
+                    start: HEADER_BLOCKS,
+                    blocks: 0,
+                },
+                directory_size: 0,
+                directory_checksum: 0,
+            },
+            durable_extents: Vec::new(),
+            dirty: true,
+            block_count: HEADER_BLOCKS,
+        };
+        container.persist()?;
+        Ok(container)
+    }
+
+    fn load(path: &Path, mut file: File, mode: AccessMode) -> io::Result<Self> {
+        let mut headers = vec![0; HEADER_BLOCKS as usize * BLOCK_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut headers)?;
+        let mut header: Option<Header> = None;
+        for copy in headers.chunks(BLOCK_SIZE as usize) {
+            if let Some(copy) = Header::from_bytes(copy)?
+                && header.is_none_or(|header| copy.generation > header.generation)
+            {
+                header = Some(copy);
+            }
+        }
+        let header = header.ok_or_else(|| invalid_container(path, "has no valid header"))?;
+        let mut directory = vec![0; header.directory_size as usize];
+        file.seek(SeekFrom::Start(header.directory.start * BLOCK_SIZE))?;
+        file.read_exact(&mut directory)?;
+        if crc32fast::hash(&directory) != header.directory_checksum {
+            return Err(invalid_container(path, "has a corrupted directory"));
+        }
+        let (entries, _): (BTreeMap<String, Entry>, _) =
+            bincode::decode_from_slice(&directory, config::standard())
+                .map_err(|error| invalid_container(path, &error.to_string()))?;
+        let mut container = Container {
+            path: path.to_path_buf(),
+            block_count: file.metadata()?.len().div_ceil(BLOCK_SIZE),
+            file,
+            mode,
+            entries,
+            header,
+            durable_extents: Vec::new(),
+            dirty: false,
+        };
+        container.durable_extents = container.used_extents();
+        container.block_count = container
+            .durable_extents
+            .iter()
+            .map(|extent| extent.end())
+            .fold(container.block_count.max(HEADER_BLOCKS), u64::max);
+        Ok(container)
+    }
+
+    fn entry(&self, name: &str) -> io::Result<&Entry> {
+        self.entries.get(name).ok_or_else(|| self.not_found(name))
+    }
+
+    fn entry_mut(&mut self, name: &str) -> io::Result<&mut Entry> {
+        self.check_writable()?;
+        match self.entries.get_mut(name) {
+            Some(entry) => Ok(entry),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{name} does not exist in {}", self.path.display()),
+            )),
+        }
+    }
+
+    fn read_at(&mut self, name: &str, mut offset: u64, mut buffer: &mut [u8]) -> io::Result<()> {
+        let entry = self.entry(name)?;
+        if offset + buffer.len() as u64 > entry.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Read past the end of {name}"),
+            ));
+        }
+        while !buffer.is_empty() {
+            let (position, length) = entry.locate(offset).unwrap();
+            let length = length.min(buffer.len() as u64) as usize;
+            (&self.file).seek(SeekFrom::Start(position))?;
+            (&self.file).read_exact(&mut buffer[..length])?;
+            buffer = &mut buffer[length..];
+            offset += length as u64;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, name: &str, mut offset: u64, mut data: &[u8]) -> io::Result<()> {
+        let end = offset + data.len() as u64;
+        self.reserve(name, end)?;
+        let entry = self.entry_mut(name)?;
+        if end > entry.size {
+            entry.size = end;
+            self.dirty = true;
+        }
+        let entry = &self.entries[name];
+        while !data.is_empty() {
+            let (position, length) = entry.locate(offset).unwrap();
+            let length = length.min(data.len() as u64) as usize;
+            (&self.file).seek(SeekFrom::Start(position))?;
+            (&self.file).write_all(&data[..length])?;
+            data = &data[length..];
+            offset += length as u64;
+        }
+        Ok(())
+    }
+
+    fn set_size(&mut self, name: &str, size: u64) -> io::Result<()> {
+        let current_size = self.entry(name)?.size;
+        if size > current_size {
+            return self.write_at(name, current_size, &vec![0; (size - current_size) as usize]);
+        }
+        let entry = self.entry_mut(name)?;
+        entry.size = size;
+        // Blocks past the new end are released
+        let mut blocks = size.div_ceil(BLOCK_SIZE);
+        entry.extents.retain_mut(|extent| {
+            extent.blocks = extent.blocks.min(blocks);
+            blocks -= extent.blocks;
+            extent.blocks > 0
+        });
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Gives a file enough blocks to hold `size` bytes
+    fn reserve(&mut self, name: &str, size: u64) -> io::Result<()> {
+        let capacity = self.entry_mut(name)?.capacity();
+        if size <= capacity {
+            return Ok(());
+        }
+        let needed = (size - capacity).div_ceil(BLOCK_SIZE);
+        let blocks = needed.max((capacity / BLOCK_SIZE).clamp(1, MAX_EXTENT_BLOCKS));
+        let extent = self.allocate(blocks);
+        let extents = &mut self.entries.get_mut(name).unwrap().extents;
+        match extents.last_mut() {
+            Some(last) if last.end() == extent.start => last.blocks += extent.blocks,
+            _ => extents.push(extent),
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Finds blocks which neither the current nor the durable directory uses, preferring the
+    /// first gap large enough to the end of the container
+    fn allocate(&mut self, blocks: u64) -> Extent {
+        let mut used = self.used_extents();
+        used.extend(&self.durable_extents);
+        used.sort_by_key(|extent| extent.start);
+        let mut start = HEADER_BLOCKS;
+        for extent in used {
+            if extent.start >= start + blocks {
+                break;
+            }
+            start = start.max(extent.end());
+        }
+        self.block_count = self.block_count.max(start + blocks);
+        Extent { start, blocks }
+    }
+
+    fn used_extents(&self) -> Vec<Extent> {
+        let mut used: Vec<Extent> = self
+            .entries
+            .values()
+            .flat_map(|entry| entry.extents.iter().copied())
+            .collect();
+        used.push(self.header.directory);
+        used
+    }
+
+    /// Makes the written blocks durable, along with the directory if it changed
+    fn sync(&mut self) -> io::Result<()> {
+        match self.dirty {
+            true => self.persist(),
+            false => self.file.sync_all(),
+        }
+    }
+
+    /// Writes the directory to free blocks, then a new header designating it
+    fn persist(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        self.file.sync_all()?;
+        let directory =
+            bincode::encode_to_vec(&self.entries, config::standard()).map_err(io::Error::other)?;
+        let extent = self.allocate((directory.len() as u64).div_ceil(BLOCK_SIZE).max(1));
+        self.file.seek(SeekFrom::Start(extent.start * BLOCK_SIZE))?;
+        self.file.write_all(&directory)?;
+        self.file.sync_all()?;
+        let header = Header {
+            generation: self.header.generation + 1,
+            directory: extent,
+            directory_size: directory.len() as u64,
+            directory_checksum: crc32fast::hash(&directory),
+        };
+        let mut block = header.to_bytes();
+        block.resize(BLOCK_SIZE as usize, 0);
+        self.file.seek(SeekFrom::Start(
+            (header.generation % HEADER_BLOCKS) * BLOCK_SIZE,
+        ))?;
+        self.file.write_all(&block)?;
+        self.file.sync_all()?;
+        self.header = header;
+        self.durable_extents = self.used_extents();
+        self.dirty = false;
+        // Blocks freed at the end of the container are given back to the file system
+        let end = self
+            .durable_extents
+            .iter()
+            .map(|extent| extent.end())
+            .fold(HEADER_BLOCKS, u64::max);
+        if end < self.block_count {
+            self.block_count = end;
+            self.file.set_len(end * BLOCK_SIZE)?;
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        match self.mode {
+            AccessMode::ReadWrite => Ok(()),
+            AccessMode::ReadOnly => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is opened in read-only mode", self.path.display()),
+            )),
+        }
+    }
+
+    fn not_found(&self, name: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{name} does not exist in {}", self.path.display()),
+        )
+    }
+}
+
+fn invalid_container(path: &Path, problem: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} is not a valid database file: it {problem}",
+            path.display()
+        ),
+    )
+}
+
+/// Name of a file in the directory of a container, whatever the platform
+fn entry_name(name: &Path) -> String {
+    name.iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Database stored as a single file, see the layout above
+pub struct ContainerStorage {
+    container: Arc<Mutex<Container>>,
+    log_path: PathBuf,
+}
+
+impl ContainerStorage {
+    /// Opens a container and locks it, creating it first if it does not exist when opened for
+    /// writing
+    pub fn open(path: &Path, mode: AccessMode) -> io::Result<Self> {
+        let writable = mode == AccessMode::ReadWrite;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .create(writable)
+            .truncate(false)
+            .open(path)?;
+        directory_lock::lock_file(&file, path, mode)?;
+        let container = match file.metadata()?.len() {
+            0 if writable => Container::create(path, file, mode)?,
+            _ => Container::load(path, file, mode)?,
+        };
+        let mut log_path = OsString::from(path);
+        log_path.push("-wal");
+        Ok(ContainerStorage {
+            container: Arc::new(Mutex::new(container)),
+            log_path: PathBuf::from(log_path),
+        })
+    }
+}
+
+impl Storage for ContainerStorage {
+    fn mode(&self) -> AccessMode {
+        self.container.lock().unwrap().mode
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Box<dyn StorageFile>> {
+        let name = entry_name(name);
+        let mut container = self.container.lock().unwrap();
+        if !container.entries.contains_key(&name) {
+            container.check_writable()?;
+            container.entries.insert(name.clone(), Entry::default());
+            // Creating a file is durable right away, as it would be in a directory
+            container.persist()?;
+        }
+        Ok(Box::new(ContainerFile {
+            container: Arc::clone(&self.container),
+            name,
+        }))
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        let container = self.container.lock().unwrap();
+        container.entries.contains_key(&entry_name(name))
+    }
+
+    fn read(&self, name: &Path) -> io::Result<Vec<u8>> {
+        let name = entry_name(name);
+        let mut container = self.container.lock().unwrap();
+        let mut contents = vec![0; container.entry(&name)?.size as usize];
+        container.read_at(&name, 0, &mut contents)?;
+        Ok(contents)
+    }
+
+    fn write(&self, name: &Path, contents: &[u8]) -> io::Result<()> {
+        // Written to new blocks, which replace the previous ones along with the directory
+        let name = entry_name(name);
+        let mut container = self.container.lock().unwrap();
+        container.check_writable()?;
+        let replaced = container.entries.insert(name.clone(), Entry::default());
+        if let Err(error) = container.write_at(&name, 0, contents) {
+            match replaced {
+                Some(entry) => container.entries.insert(name, entry),
+                None => container.entries.remove(&name),
+            };
+            return Err(error);
+        }
+        container.persist()
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        let mut container = self.container.lock().unwrap();
+        container.check_writable()?;
+        if container.entries.remove(&entry_name(name)).is_some() {
+            container.persist()?;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut container = self.container.lock().unwrap();
+        container.check_writable()?;
+        let from = entry_name(from);
+        let entry = container.entry(&from)?.clone();
+        container.entries.remove(&from);
+        container.entries.insert(entry_name(to), entry);
+        container.persist()
+    }
+
+    fn list(&self) -> io::Result<Vec<PathBuf>> {
+        let container = self.container.lock().unwrap();
+        Ok(container.entries.keys().map(PathBuf::from).collect())
+    }
+
+    fn open_log(&self) -> io::Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.log_path)?;
+        Ok(Box::new(file))
+    }
+
+    fn log_size(&self) -> io::Result<u64> {
+        match std::fs::metadata(&self.log_path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// File of a container, which stays usable as long as it is not removed
+struct ContainerFile {
+    container: Arc<Mutex<Container>>,
+    name: String,
+}
+
+impl StorageFile for ContainerFile {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.container.lock().unwrap().entry(&self.name)?.size)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.container
+            .lock()
+            .unwrap()
+            .read_at(&self.name, offset, buffer)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.container
+            .lock()
+            .unwrap()
+            .write_at(&self.name, offset, data)
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.container.lock().unwrap().set_size(&self.name, size)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.container.lock().unwrap().sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_directory::TempDirectory;
+
+    #[test]
+    fn test_files_survive_reopening_and_free_blocks_are_reused() {
+        let directory = TempDirectory::new("container");
+        let path = directory.join("database");
+        let storage = ContainerStorage::open(&path, AccessMode::ReadWrite).unwrap();
+        let data_name = Path::new("tables/t.data.bin");
+        let mut data = storage.open_file(data_name).unwrap();
+        // Spans several extents, which are not contiguous once the schema is written
+        for page in 0..4 {
+            data.write_at(page * BLOCK_SIZE, &[page as u8 + 1; BLOCK_SIZE as usize])
+                .unwrap();
+            if page == 0 {
+                storage
+                    .write(Path::new("t.meta.ron"), b"(name: \"t\")")
+                    .unwrap();
+            }
+        }
+        data.sync().unwrap();
+        storage
+            .write(Path::new("t.meta.ron"), b"(name: \"u\")")
+            .unwrap();
+        drop((data, storage));
+
+        let storage = ContainerStorage::open(&path, AccessMode::ReadOnly).unwrap();
+        assert_eq!(
+            storage.list().unwrap(),
+            [Path::new("t.meta.ron"), data_name]
+        );
+        assert_eq!(
+            storage.read(Path::new("t.meta.ron")).unwrap(),
+            b"(name: \"u\")"
+        );
+        let expected: Vec<u8> = (1..=4)
+            .flat_map(|byte| [byte; BLOCK_SIZE as usize])
+            .collect();
+        let mut buffer = vec![0; expected.len() - 100];
+        let mut data = storage.open_file(data_name).unwrap();
+        data.read_at(100, &mut buffer).unwrap();
+        assert_eq!(buffer, expected[100..]);
+        assert!(data.read_at(101, &mut buffer).is_err());
+        assert!(storage.write(Path::new("x"), b"").is_err());
+        drop((data, storage));
+
+        // The blocks of a removed file are reused, and the container shrinks
+        let size = std::fs::metadata(&path).unwrap().len();
+        let storage = ContainerStorage::open(&path, AccessMode::ReadWrite).unwrap();
+        storage.remove(data_name).unwrap();
+        storage.write(Path::new("t.meta.ron"), b"()").unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < size);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Processes using the same database directory coordinate through an advisory lock on `lock`, in
+// that directory. A process writing to the database holds it exclusively, while processes only
+// reading it share it. The system releases the lock of a process when it stops, even if it
+// crashes, so a lock which can be taken is never held by anyone.
+//
+// The writer records its process id and when it opened the database in the file, which tells
+// other processes who uses the database. It empties the file when it closes the database, so
+// that a file found non-empty by the next writer was left by a process which crashed.
+
+pub const LOCK_FILE_NAME: &str = "lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    /// Only process using the database, which may change it
+    ReadWrite,
+    /// Shares the database with other readers, without changing it
+    ReadOnly,
+}
+
+/// Lock of a database directory, held until dropped
+#[derive(Debug)]
+pub struct DirectoryLock {
+    file: File,
+    mode: AccessMode,
+}
+
+impl DirectoryLock {
+    /// Locks a database directory, failing right away with `ResourceBusy` if another process
+    /// holds a conflicting lock
+    pub fn acquire(directory: &Path, mode: AccessMode) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(directory.join(LOCK_FILE_NAME))?;
+        match try_lock(&file, mode) {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                let owner = read_owner(&mut file)?;
+                return Err(io::Error::new(
+                    io::ErrorKind::ResourceBusy,
+                    match (mode, owner) {
+                        // A writer holds the lock, so the file describes it
+                        (AccessMode::ReadOnly, Some(owner)) => {
+                            format!("Database at {} is in use by {owner}", directory.display())
+                        }
+                        _ => in_use(directory),
+                    },
+                ));
+            }
+            Err(TryLockError::Error(error)) => return Err(error),
+        }
+        if let Some(owner) = read_owner(&mut file)? {
+            log::warn!(
+                "Found a stale lock of the database at {}, left by {owner} which stopped without closing it",
+                directory.display()
+            );
+        }
+        if mode == AccessMode::ReadWrite {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            writeln!(
+                file,
+                "process {}, which opened it at {}",
+                std::process::id(),
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            )?;
+        }
+        Ok(DirectoryLock { file, mode })
+    }
+
+    pub fn mode(&self) -> AccessMode {
+        self.mode
+    }
+}
+
+impl Drop for DirectoryLock {
+    /// Tells the next writer that the database was closed properly. The lock itself is released
+    /// along with the file.
+    fn drop(&mut self) {
+        if self.mode == AccessMode::ReadWrite
+            && let Err(error) = self.file.set_len(0)
+        {
+            log::error!("Could not clear the lock file: {error}");
+        }
+    }
+}
+
+/// Locks a database stored as a single file, which cannot describe the process holding it. The
+/// lock is released along with the file.
+pub fn lock_file(file: &File, path: &Path, mode: AccessMode) -> io::Result<()> {
+    match try_lock(file, mode) {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            Err(io::Error::new(io::ErrorKind::ResourceBusy, in_use(path)))
+        }
+        Err(TryLockError::Error(error)) => Err(error),
+    }
+}
+
+fn try_lock(file: &File, mode: AccessMode) -> Result<(), TryLockError> {
+    match mode {
+        AccessMode::ReadWrite => file.try_lock(),
+        AccessMode::ReadOnly => file.try_lock_shared(),
+    }
+}
+
+fn in_use(path: &Path) -> String {
+    format!(
+        "Database at {} is in use by another process",
+        path.display()
+    )
+}
+
+/// Description of the writer recorded in a lock file, if any
+fn read_owner(file: &mut File) -> io::Result<Option<String>> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)?;
+    let owner = content.trim();
+    Ok((!owner.is_empty()).then(|| owner.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_directory::TempDirectory;
+
+    #[test]
+    fn test_one_writer_or_several_readers() {
+        let directory = TempDirectory::new("directory-lock");
+        let lock_path = directory.join(LOCK_FILE_NAME);
+        let busy = |mode| DirectoryLock::acquire(&directory, mode).unwrap_err().kind();
+
+        let writer = DirectoryLock::acquire(&directory, AccessMode::ReadWrite).unwrap();
+        assert_eq!(busy(AccessMode::ReadWrite), io::ErrorKind::ResourceBusy);
+        let error = DirectoryLock::acquire(&directory, AccessMode::ReadOnly).unwrap_err();
+        let owner = format!("process {}", std::process::id());
+        assert!(error.to_string().contains(&owner), "{error}");
+        drop(writer);
+        assert!(std::fs::read(&lock_path).unwrap().is_empty());
+
+        let readers = [AccessMode::ReadOnly, AccessMode::ReadOnly]
+            .map(|mode| DirectoryLock::acquire(&directory, mode).unwrap());
+        assert_eq!(busy(AccessMode::ReadWrite), io::ErrorKind::ResourceBusy);
+        drop(readers);
+
+        // A writer which crashed left its description, but no lock
+        let stale_owner = "process 1, which opened it at 2000-01-01 00:00:00\n";
+        std::fs::write(&lock_path, stale_owner).unwrap();
+        let writer = DirectoryLock::acquire(&directory, AccessMode::ReadWrite).unwrap();
+        let content = std::fs::read_to_string(&lock_path).unwrap();
+        assert!(content.starts_with(&owner), "{content}");
+        drop(writer);
+    }
+}
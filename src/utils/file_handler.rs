@@ -1,24 +1,202 @@
 use bincode::{Decode, Encode, config};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::sql_compilator::parser::ParsingError;
+use crate::sql_compilator::expression::Expression;
+use crate::sql_compilator::parser::{LabelPosition, ParsingError};
+use crate::utils::btree::BTree;
+use crate::utils::buffer_pool::{BufferPool, BufferPoolStats};
+use crate::utils::collation::Collation;
+use crate::utils::column_store::ColumnStore;
+use crate::utils::compression::Compression;
+use crate::utils::directory_lock::AccessMode;
+use crate::utils::heap_file::{HeapFile, RowId};
+use crate::utils::index_key::{self, KeyBounds, KeyOrder};
+use crate::utils::lock_manager::{LockManager, LockMode, LockTarget};
+use crate::utils::mvcc::{self, IsolationLevel, RowVersion, Snapshot, TransactionId};
+use crate::utils::page::{MAX_PAGE_SIZE, PageLayout};
+use crate::utils::storage::{self, Storage, StorageLayout};
+use crate::utils::wal::{self, Wal};
 
 // Structure of file tree :
 // mydb/
 //   ├── metadata.ron          (metadata as ron file)
+//   ├── wal.log               (write-ahead log, see utils::wal)
+//   ├── lock                  (held by the processes using the database, see utils::directory_lock)
 //   └── tables/
 //       ├── users.meta.ron    (table schema)
-//       ├── users.data.bin    (data as binary)
-//       └── users.idx.bin     (index)
+//       ├── users.data.bin    (row versions in slotted pages, large values in overflow pages)
+//       ├── users.idx.bin     (B+tree index of the primary key)
+//       ├── users.by_email.idx.bin (B+tree of the index 'by_email')
+//       ├── events.kind.seg.bin (values of column 'kind' of columnar table 'events')
+//       └── events.blocks.bin (blocks of the segments of 'events', see utils::column_store)
+//
+// A single-file database holds the same files, see `utils::container`, its log being
+// `mydb-wal` next to it. Every file is named by its path within the database.
+
+const METADATA_FILE: &str = "metadata.ron";
+const TABLES_DIRECTORY: &str = "tables";
+
+/// Version of the on-disk format written by this binary. Databases of an older format are
+/// upgraded by the steps of `UPGRADES` when opened for writing, newer ones are refused.
+pub const FORMAT_VERSION: u32 = 2;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct DatabaseMetadata {
     name: String,
-    version: String,
+    /// Format of the files of the database, see `FORMAT_VERSION`
+    #[serde(deserialize_with = "deserialize_format_version")]
+    version: u32,
     tables: Vec<String>,
+    /// User-defined types, shared by every table
+    #[serde(default)]
+    types: Vec<EnumType>,
+    /// Transaction ids below this one may have been given already. Ids are reserved by blocks,
+    /// so that the metadata is not written by every transaction.
+    #[serde(default)]
+    reserved_transaction_ids: TransactionId,
+}
+
+/// Reads a format version, which databases written before formats were numbered store as the
+/// text "1.0"
+fn deserialize_format_version<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredVersion {
+        Number(u32),
+        Text(String),
+    }
+    match StoredVersion::deserialize(deserializer)? {
+        StoredVersion::Number(version) => Ok(version),
+        StoredVersion::Text(text) if text == "1.0" => Ok(1),
+        StoredVersion::Text(text) => Err(serde::de::Error::custom(format!(
+            "unknown format version '{text}'"
+        ))),
+    }
+}
+
+/// Step upgrading the files of a database from a format version to the next one
+struct Upgrade {
+    from: u32,
+    description: &'static str,
+    run: fn(&dyn Storage, &Arc<Mutex<BufferPool>>) -> io::Result<()>,
+}
+
+/// Upgrade steps, by the version they upgrade from
+const UPGRADES: [Upgrade; 1] = [Upgrade {
+    from: 1,
+    description: "store every table as row versions in checksummed pages",
+    run: convert_tables,
+}];
+
+/// Opens every table, which converts the files of the ones stored in an older format
+fn convert_tables(storage: &dyn Storage, buffer_pool: &Arc<Mutex<BufferPool>>) -> io::Result<()> {
+    let (_, (_, damaged_tables)) = Database::load(storage, buffer_pool)?;
+    match damaged_tables.into_values().next() {
+        Some(error) => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+        None => Ok(()),
+    }
+}
+
+/// Number of transaction ids reserved at once, see `DatabaseMetadata::reserved_transaction_ids`
+const TRANSACTION_ID_BLOCK: TransactionId = 1024;
+
+/// How long a session waits for a lock, unless `Database::set_lock_timeout` changed it
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Enumerated type created with `CREATE TYPE name AS ENUM (...)`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnumType {
+    pub name: String,
+    /// Labels in the order they were added. Rows store a label as its position in this list,
+    /// which never changes once the label exists.
+    pub labels: Vec<EnumLabel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnumLabel {
+    pub name: String,
+    /// Rank of the label when values of the type are sorted. Labels added between two existing
+    /// ones get a rank in between, so that no stored value has to change.
+    pub sort_order: f64,
+}
+
+impl EnumType {
+    pub fn new(name: &str, labels: Vec<String>) -> Self {
+        EnumType {
+            name: name.to_string(),
+            labels: (1..)
+                .zip(labels)
+                .map(|(sort_order, name)| EnumLabel {
+                    name,
+                    sort_order: sort_order as f64,
+                })
+                .collect(),
+        }
+    }
+
+    fn position_of(&self, label: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .position(|existing| existing.name == label)
+    }
+
+    /// Returns the value of the type having given label, if it exists
+    pub fn value_of(&self, label: &str) -> Option<Value> {
+        self.position_of(label)
+            .map(|position| self.value_at(position))
+    }
+
+    fn value_at(&self, position: usize) -> Value {
+        let label = &self.labels[position];
+        Value::Enum(EnumValue {
+            type_name: self.name.clone(),
+            label: label.name.clone(),
+            sort_order: label.sort_order,
+        })
+    }
+
+    /// Adds a label at the end of the type, or next to an existing label. Returns `false` if the
+    /// label the new one is placed against does not exist.
+    pub fn add_label(&mut self, label: &str, position: Option<&LabelPosition>) -> bool {
+        let mut sort_orders: Vec<f64> = self.labels.iter().map(|label| label.sort_order).collect();
+        sort_orders.sort_by(f64::total_cmp);
+        let sort_order = match position {
+            None => sort_orders.last().map_or(1.0, |last| last + 1.0),
+            Some(LabelPosition::Before(neighbour) | LabelPosition::After(neighbour)) => {
+                let Some(neighbour) = self.position_of(neighbour) else {
+                    return false;
+                };
+                let neighbour_order = self.labels[neighbour].sort_order;
+                let rank = sort_orders
+                    .iter()
+                    .position(|&order| order == neighbour_order)
+                    .unwrap_or_default();
+                match position {
+                    Some(LabelPosition::Before(_)) => match rank.checked_sub(1) {
+                        Some(previous) => (sort_orders[previous] + neighbour_order) / 2.0,
+                        None => neighbour_order - 1.0,
+                    },
+                    _ => match sort_orders.get(rank + 1) {
+                        Some(next) => (neighbour_order + next) / 2.0,
+                        None => neighbour_order + 1.0,
+                    },
+                }
+            }
+        };
+        self.labels.push(EnumLabel {
+            name: label.to_string(),
+            sort_order,
+        });
+        true
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,6 +205,145 @@ struct TableMetadata {
     columns: Vec<Column>,
     row_count: u64,
     page_size: usize,
+    /// Layout of the data file. Tables whose metadata predates this field use a flat file.
+    #[serde(default)]
+    storage: StorageFormat,
+    #[serde(default)]
+    indexes: Vec<IndexDefinition>,
+    /// Whether values are stored row by row in the data file, or column by column in segments
+    #[serde(default)]
+    layout: TableLayout,
+    /// Algorithm compressing the pages of the data file, and of the segments
+    #[serde(default)]
+    compression: Compression,
+    /// Bytes held by the pages of the data file to the size of the file once compressed, as of
+    /// the last change of the table
+    #[serde(default)]
+    compression_ratio: Option<f64>,
+}
+
+impl TableMetadata {
+    /// Layout of the pages of the index files, which only end with a checksum once converted to
+    /// `StorageFormat::PageChecksums`
+    fn page_layout(&self) -> PageLayout {
+        PageLayout {
+            page_size: self.page_size,
+            checksums: self.storage == StorageFormat::PageChecksums,
+            compression: Compression::None,
+        }
+    }
+
+    /// Layout of the pages of the data file and of the segments, which may be compressed
+    fn data_page_layout(&self) -> PageLayout {
+        PageLayout {
+            compression: self.compression,
+            ..self.page_layout()
+        }
+    }
+}
+
+/// Secondary index created with `CREATE [UNIQUE] INDEX`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexDefinition {
+    pub name: String,
+    /// Whether two rows may not have the same key, unless it holds a NULL
+    pub unique: bool,
+    pub columns: Vec<IndexColumn>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexColumn {
+    /// Column of the table, or expression of its columns, whose value is indexed
+    pub expression: Expression,
+    pub descending: bool,
+    /// Rules used to order texts, the ones used when comparing the expression
+    pub collation: Collation,
+}
+
+impl IndexDefinition {
+    /// Index enforcing the UNIQUE constraint of a column, created along with its table
+    pub fn unique_column(table_name: &str, column: &Column) -> Self {
+        IndexDefinition {
+            name: format!("{table_name}_{}_key", column.name),
+            unique: true,
+            columns: vec![IndexColumn {
+                expression: Expression::Column {
+                    table: None,
+                    name: column.name.clone(),
+                },
+                descending: false,
+                collation: column.collation,
+            }],
+        }
+    }
+
+    pub fn key_orders(&self) -> Vec<KeyOrder> {
+        self.columns
+            .iter()
+            .map(|column| KeyOrder {
+                collation: column.collation,
+                descending: column.descending,
+            })
+            .collect()
+    }
+}
+
+/// Values of the key of every secondary index of a row, in the order of `Database::table_indexes`.
+/// Indexes may hold expressions, which only the caller can evaluate.
+pub type IndexValues = Vec<Vec<Value>>;
+
+/// Computes the index values of a row of given table, see `Database::check_integrity`
+pub type IndexValuesOf<'a> = dyn Fn(&str, &[Value]) -> Result<IndexValues, String> + 'a;
+
+/// Row versions of a table, along with their values
+pub type TableVersions = (String, Vec<(RowId, Vec<Value>)>);
+
+/// Problems found by `Database::check_integrity`, along with what was checked
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub tables: usize,
+    pub row_versions: usize,
+    pub problems: Vec<IntegrityProblem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityProblem {
+    /// Table holding the problem, if it is not in the metadata of the database
+    pub table_name: Option<String>,
+    /// File or structure holding the problem, such as `data file` or `index 'by_email'`
+    pub object: String,
+    pub description: String,
+}
+
+impl IntegrityReport {
+    fn add(&mut self, table_name: Option<&str>, object: &str, description: String) {
+        self.problems.push(IntegrityProblem {
+            table_name: table_name.map(str::to_string),
+            object: object.to_string(),
+            description,
+        });
+    }
+}
+
+/// New version of a stored row, along with its index values
+pub struct RowChange {
+    pub row_id: RowId,
+    pub row: Vec<Value>,
+    pub index_values: IndexValues,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+enum StorageFormat {
+    /// `[u32 length][bincode row]` records one after the other
+    #[default]
+    FlatFile,
+    /// Pages of `page_size` bytes, see `utils::page`
+    SlottedPages,
+    /// Slotted pages whose records start with a `mvcc::RowVersion`. Every version of a row has
+    /// its own entries in the indexes, whose keys all end with the address of the version.
+    VersionedRows,
+    /// Versioned rows in pages ending with a checksum, as are the pages of the indexes
+    PageChecksums,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -35,16 +352,49 @@ pub struct Column {
     pub data_type: DataType,
     pub values: Vec<DataType>,
     pub is_primary_key: bool,
-    // TODO: nullable: bool,
+    /// Whether two rows may not hold the same non-null value in this column
+    #[serde(default)]
+    pub is_unique: bool,
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+    /// Expression evaluated for every inserted row that does not provide a value for the column
+    #[serde(default)]
+    pub default: Option<Expression>,
+    /// Rules used to compare the values of this column, which only matter for text
+    #[serde(default)]
+    pub collation: Collation,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+fn default_nullable() -> bool {
+    true
+}
+
+/// How the rows of a table are stored, set with `CREATE TABLE ... STORED AS COLUMNAR`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TableLayout {
+    /// Every row version is a record of the data file holding all its values
+    #[default]
+    Rows,
+    /// Values are stored column by column, see `utils::column_store`, which lets scans only read
+    /// the columns they use
+    Columnar,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum DataType {
     Float,
     Integer,
     Text,
     Bool,
     Uuid,
+    Json,
+    /// Byte string, written as text in hexadecimal after `\x`
+    Blob,
+    /// User-defined enumerated type, referenced by name
+    Enum(String),
+    /// One-dimensional array of values of the inner type
+    Array(Box<DataType>),
 }
 
 impl DataType {
@@ -55,132 +405,2996 @@ impl DataType {
             "text" => Ok(DataType::Text),
             "bool" => Ok(DataType::Bool),
             "uuid" => Ok(DataType::Uuid),
+            "json" => Ok(DataType::Json),
+            "blob" => Ok(DataType::Blob),
+            name if name.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                Ok(DataType::Enum(data_type))
+            }
             &_ => Err(ParsingError::UnexpectedDataTypeProvided { found: data_type }),
         }
     }
+
+    /// Type of the elements of an array type, or the type itself for any other type
+    pub fn base_type(&self) -> &DataType {
+        match self {
+            DataType::Array(element_type) => element_type.base_type(),
+            data_type => data_type,
+        }
+    }
 }
 
 pub struct Database {
-    path: PathBuf,
+    /// Files of the database, locked for this process, which tells whether it may write
+    storage: Arc<dyn Storage>,
     metadata: DatabaseMetadata,
     tables: HashMap<String, Table>,
+    /// Tables whose files could not be opened, such as damaged ones, along with why. Queries on
+    /// them fail, while the other tables stay usable.
+    damaged_tables: HashMap<String, String>,
+    /// Page cache shared by the data files of every table
+    buffer_pool: Arc<Mutex<BufferPool>>,
+    /// Open sessions, see `open_session`
+    sessions: HashMap<SessionId, SessionState>,
+    next_session_id: SessionId,
+    /// Session running the current statement, whose transaction reads and writes
+    session: SessionId,
+    /// Session whose transaction is writing. Only one transaction writes at a time, and its
+    /// changes are the only uncommitted ones in the buffer pool.
+    writer: Option<SessionId>,
+    next_transaction_id: TransactionId,
+    /// Row versions deleted by committed transactions, which some snapshots may still see. They
+    /// are purged by the first commit happening once none does.
+    garbage: Vec<DeletedVersion>,
+    /// Files created by the writing transaction, removed if its changes are rolled back
+    created_files: Vec<PathBuf>,
+    /// Files of indexes dropped by the writing transaction, removed once it commits
+    dropped_files: Vec<PathBuf>,
+    /// Locks of the sessions, released when their transaction ends
+    locks: Arc<LockManager>,
+    /// How long a session waits for a lock before its statement fails
+    lock_timeout: Duration,
+    /// Compression of the tables created without choosing one
+    default_compression: Compression,
+}
+
+/// Identifies a session of a database. Every session runs its own transactions.
+pub type SessionId = u32;
+
+/// Tables of a database once opened, along with those which could not be, see
+/// `Database::damaged_tables`
+type LoadedTables = (HashMap<String, Table>, HashMap<String, String>);
+
+#[derive(Default)]
+struct SessionState {
+    /// Transaction started with `BEGIN`, if any. Otherwise, the caller commits after every
+    /// statement.
+    transaction: Option<Transaction>,
+    /// Snapshot read by the running transaction (or statement), taken when it first reads
+    snapshot: Option<Snapshot>,
+    /// Id of the running transaction, given once it writes
+    transaction_id: Option<TransactionId>,
+    /// Row versions deleted by the running transaction
+    deleted_versions: Vec<DeletedVersion>,
+}
+
+struct DeletedVersion {
+    table_name: String,
+    row_id: RowId,
+    deleted_by: TransactionId,
+}
+
+struct Transaction {
+    /// Set once a statement of the transaction fails, leaving changes which can only be rolled
+    /// back
+    failed: bool,
+    isolation_level: IsolationLevel,
+    /// Set once a statement ran in the transaction, after which its isolation level is fixed
+    queried: bool,
+    /// Savepoints, from the oldest one
+    savepoints: Vec<Savepoint>,
+}
+
+struct Savepoint {
+    name: String,
+    /// Number of files created and dropped, and of row versions deleted, when the savepoint was
+    /// created
+    created_files: usize,
+    dropped_files: usize,
+    deleted_versions: usize,
+    /// Whether the transaction was writing when the savepoint was created, which gave the
+    /// savepoint a level in the buffer pool
+    writing: bool,
 }
 
 impl Database {
-    pub fn create(path: &str, name: &str) -> io::Result<Self> {
-        let db_path = PathBuf::from(path);
-        std::fs::create_dir_all(&db_path)?;
-        std::fs::create_dir_all(db_path.join("tables"))?;
+    /// Opens the database stored at `path`, creating its file tree first if it does not exist yet.
+    /// Up to `buffer_pool_pages` pages of its tables are cached in memory. Fails if another
+    /// process uses the database.
+    pub fn create(path: &str, name: &str, buffer_pool_pages: usize) -> io::Result<Self> {
+        Self::create_with_layout(path, name, buffer_pool_pages, StorageLayout::Directory)
+    }
+
+    /// Same as `create`, storing a new database with given layout. Existing databases are
+    /// opened whatever their layout, see `storage::convert` to change it.
+    pub fn create_with_layout(
+        path: &str,
+        name: &str,
+        buffer_pool_pages: usize,
+        layout: StorageLayout,
+    ) -> io::Result<Self> {
+        let db_path = Path::new(path);
+        let layout = StorageLayout::detect(db_path).unwrap_or(layout);
+        let storage = storage::open(db_path, layout, AccessMode::ReadWrite)?;
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(
+            storage.clone(),
+            buffer_pool_pages,
+        )));
+        if storage.exists(Path::new(METADATA_FILE)) {
+            return Self::open(db_path, storage, buffer_pool);
+        }
 
         let metadata = DatabaseMetadata {
             name: name.to_string(),
-            version: String::from("1.0"),
+            version: FORMAT_VERSION,
             tables: Vec::new(),
+            types: Vec::new(),
+            reserved_transaction_ids: 0,
         };
 
         let ron = ron::ser::to_string_pretty(&metadata, Default::default())
             .map_err(std::io::Error::other)?;
-        std::fs::write(db_path.join("metadata.ron"), ron)?;
+        storage.write(Path::new(METADATA_FILE), ron.as_bytes())?;
+        buffer_pool
+            .lock()
+            .unwrap()
+            .attach_wal(Wal::open(&*storage)?);
+        Ok(Self::new(
+            storage,
+            metadata,
+            (HashMap::new(), HashMap::new()),
+            buffer_pool,
+        ))
+    }
+
+    /// Opens the existing database stored at `path` without changing it, which other read-only
+    /// processes may use at the same time. Fails if a process writes to it, or if it has changes
+    /// to recover from its write-ahead log, which only a writer can do.
+    pub fn open_read_only(path: &str, buffer_pool_pages: usize) -> io::Result<Self> {
+        let db_path = Path::new(path);
+        let Some(layout) = StorageLayout::detect(db_path) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No database found at {path}"),
+            ));
+        };
+        let storage = storage::open(db_path, layout, AccessMode::ReadOnly)?;
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(
+            storage.clone(),
+            buffer_pool_pages,
+        )));
+        Self::open(db_path, storage, buffer_pool)
+    }
 
-        Ok(Self {
-            path: db_path,
+    fn new(
+        storage: Arc<dyn Storage>,
+        metadata: DatabaseMetadata,
+        (tables, damaged_tables): LoadedTables,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> Self {
+        Self {
+            storage,
+            // Ids given before the database was last closed are all below the reserved ones
+            next_transaction_id: metadata.reserved_transaction_ids.max(1),
             metadata,
-            tables: HashMap::new(),
-        })
+            tables,
+            damaged_tables,
+            buffer_pool,
+            sessions: HashMap::new(),
+            next_session_id: 0,
+            session: 0,
+            writer: None,
+            garbage: Vec::new(),
+            created_files: Vec::new(),
+            dropped_files: Vec::new(),
+            locks: Arc::new(LockManager::default()),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            default_compression: Compression::None,
+        }
+    }
+
+    /// Loads an existing database: its metadata, along with the schema and data file of every
+    /// table it references. Changes that were not durable yet when the database was last used
+    /// are first recovered from the write-ahead log.
+    fn open(
+        db_path: &Path,
+        storage: Arc<dyn Storage>,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Self> {
+        if storage.mode() == AccessMode::ReadOnly {
+            if wal::needs_recovery(&*storage)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Database at {} was not closed properly, it must be opened for writing first to recover it",
+                        db_path.display()
+                    ),
+                ));
+            }
+            Self::check_format(db_path, &*storage, &buffer_pool)?;
+            let (metadata, tables) = Self::load(&*storage, &buffer_pool)?;
+            log::info!("Opened database '{}' in read-only mode", metadata.name);
+            return Ok(Self::new(storage, metadata, tables, buffer_pool));
+        }
+        let recovery = wal::recover(&*storage)?;
+        if recovery != wal::Recovery::default() {
+            log::warn!(
+                "Recovered {} committed transaction(s) from the write-ahead log, and restored {} page(s) of unfinished or rolled back ones",
+                recovery.committed,
+                recovery.undone_pages
+            );
+        }
+        Self::check_format(db_path, &*storage, &buffer_pool)?;
+        let (metadata, tables) = Self::load(&*storage, &buffer_pool)?;
+        log::info!(
+            "Opened database '{}' with {} table(s)",
+            metadata.name,
+            tables.0.len()
+        );
+        buffer_pool
+            .lock()
+            .unwrap()
+            .attach_wal(Wal::open(&*storage)?);
+        let mut database = Self::new(storage, metadata, tables, buffer_pool);
+        database.backfill_primary_keys()?;
+        database.backfill_unique_indexes()?;
+        database.buffer_pool.lock().unwrap().commit()?;
+        Ok(database)
+    }
+
+    /// Refuses databases whose format is newer than `FORMAT_VERSION`, and upgrades older ones
+    /// once their files are backed up next to them, as `<name>.v<version>.backup`
+    fn check_format(
+        db_path: &Path,
+        storage: &dyn Storage,
+        buffer_pool: &Arc<Mutex<BufferPool>>,
+    ) -> io::Result<()> {
+        let mut metadata = Self::read_metadata(buffer_pool)?;
+        let version = metadata.version;
+        if version > FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Database at {} uses format version {version}, newer than the version {FORMAT_VERSION} supported by this binary",
+                    db_path.display()
+                ),
+            ));
+        }
+        if version == FORMAT_VERSION {
+            return Ok(());
+        }
+        if storage.mode() == AccessMode::ReadOnly {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "Database at {} uses format version {version}, it must be opened for writing first to upgrade it",
+                    db_path.display()
+                ),
+            ));
+        }
+        let layout = StorageLayout::detect(db_path).unwrap_or_default();
+        let mut backup_name = db_path.file_name().unwrap_or_default().to_os_string();
+        backup_name.push(format!(".v{version}.backup"));
+        let backup_path = db_path.with_file_name(backup_name);
+        storage::backup(storage, &backup_path, layout)?;
+        for from in version..FORMAT_VERSION {
+            let upgrade = UPGRADES
+                .iter()
+                .find(|upgrade| upgrade.from == from)
+                .ok_or_else(|| {
+                    io::Error::other(format!("No upgrade from format version {from}"))
+                })?;
+            log::info!(
+                "Upgrading database from format version {from} to {}: {}",
+                from + 1,
+                upgrade.description
+            );
+            (upgrade.run)(storage, buffer_pool)?;
+        }
+        // The log is not attached yet, the changes are written to the files directly
+        buffer_pool.lock().unwrap().commit()?;
+        metadata.version = FORMAT_VERSION;
+        let ron =
+            ron::ser::to_string_pretty(&metadata, Default::default()).map_err(io::Error::other)?;
+        storage.write(Path::new(METADATA_FILE), ron.as_bytes())?;
+        log::warn!(
+            "Upgraded database at {} from format version {version} to {FORMAT_VERSION}, its previous files are kept at {}",
+            db_path.display(),
+            backup_path.display()
+        );
+        Ok(())
+    }
+
+    /// Reads the metadata of a database, including changes which are not committed
+    fn read_metadata(buffer_pool: &Arc<Mutex<BufferPool>>) -> io::Result<DatabaseMetadata> {
+        let content = buffer_pool
+            .lock()
+            .unwrap()
+            .read_file(Path::new(METADATA_FILE))?;
+        let content = String::from_utf8(content).map_err(io::Error::other)?;
+        ron::from_str(&content).map_err(io::Error::other)
+    }
+
+    /// Reads the metadata of a database, including changes which are not committed, and opens
+    /// every table it references. Tables which cannot be opened are returned apart, see
+    /// `Database::damaged_tables`.
+    fn load(
+        storage: &dyn Storage,
+        buffer_pool: &Arc<Mutex<BufferPool>>,
+    ) -> io::Result<(DatabaseMetadata, LoadedTables)> {
+        let metadata = Self::read_metadata(buffer_pool)?;
+        let mut tables = HashMap::new();
+        let mut damaged_tables = HashMap::new();
+        for table_name in &metadata.tables {
+            match Table::open(storage, table_name, buffer_pool.clone()) {
+                Ok(table) => {
+                    tables.insert(table_name.clone(), table);
+                }
+                // Unless it only has to be converted, which a writer does
+                Err(error) if error.kind() != io::ErrorKind::PermissionDenied => {
+                    log::error!("Table '{table_name}' cannot be opened: {error}");
+                    damaged_tables.insert(table_name.clone(), error.to_string());
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok((metadata, (tables, damaged_tables)))
+    }
+
+    /// Fills the primary key index of tables whose index file was just created, as happens for
+    /// tables created before indexes existed
+    fn backfill_primary_keys(&mut self) -> io::Result<()> {
+        let table_names: Vec<String> = self
+            .tables
+            .iter()
+            .filter(|(_, table)| {
+                table.metadata.row_count > 0
+                    && table.primary_key.as_ref().is_some_and(BTree::is_empty)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for table_name in table_names {
+            for (row_id, _, row) in self.scan_versions(&table_name)? {
+                let table = self.get_table_mut(&table_name)?;
+                let key = append_row_id(table.row_key(&row).unwrap(), row_id);
+                table.primary_key.as_mut().unwrap().insert(&key, row_id)?;
+            }
+            log::info!("Built the primary key index of table '{table_name}'");
+        }
+        Ok(())
+    }
+
+    /// Creates the indexes enforcing the UNIQUE constraint of columns which have none, as happens
+    /// for tables created before these constraints were indexed
+    fn backfill_unique_indexes(&mut self) -> io::Result<()> {
+        let mut missing = Vec::new();
+        for (table_name, table) in &self.tables {
+            for (position, column) in table.metadata.columns.iter().enumerate() {
+                let definition = IndexDefinition::unique_column(table_name, column);
+                if column.is_unique && table.index_position(&definition.name).is_none() {
+                    missing.push((table_name.clone(), position, definition));
+                }
+            }
+        }
+        for (table_name, position, definition) in missing {
+            let entries: Vec<(RowId, Vec<Value>)> = self
+                .scan_versions(&table_name)?
+                .into_iter()
+                .map(|(row_id, _, row)| (row_id, vec![row[position].clone()]))
+                .collect();
+            let storage = self.storage.clone();
+            let table = self.get_table_mut(&table_name)?;
+            storage.remove(&table.index_path(&definition.name))?;
+            log::info!(
+                "Built the index '{}' of the unique column of table '{table_name}'",
+                definition.name
+            );
+            table.add_index(definition, &entries)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a table, which other transactions cannot see until the running one commits. Its
+    /// data file uses the default compression unless another one is given. If any step fails,
+    /// the files created so far are removed.
+    pub fn create_table(
+        &mut self,
+        name: &str,
+        columns: Vec<Column>,
+        layout: TableLayout,
+        compression: Option<Compression>,
+    ) -> io::Result<()> {
+        let transaction_id = self.acquire_write()?;
+        if self.tables.contains_key(name) || self.damaged_tables.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Table '{name}' already exists"),
+            ));
+        }
+        for (position, column) in columns.iter().enumerate() {
+            if columns[..position]
+                .iter()
+                .any(|previous| previous.name.eq_ignore_ascii_case(&column.name))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Column '{}' is declared more than once", column.name),
+                ));
+            }
+        }
+        for column in columns.iter().filter(|column| column.is_unique) {
+            let index_name = IndexDefinition::unique_column(name, column).name;
+            if self.index_table(&index_name).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Index '{index_name}' already exists"),
+                ));
+            }
+        }
+        let created_files = self.created_files.len();
+        let compression = compression.unwrap_or(self.default_compression);
+        let result = self.create_table_files(name, columns, layout, compression, transaction_id);
+        if result.is_err() {
+            self.metadata.tables.retain(|table_name| table_name != name);
+            let mut buffer_pool = self.buffer_pool.lock().unwrap();
+            for path in self.created_files.drain(created_files..) {
+                if let Err(error) = buffer_pool.discard_file(&path) {
+                    log::error!("Could not remove {}: {error}", path.display());
+                }
+            }
+        }
+        result
     }
 
-    pub fn create_table(&mut self, name: &str, columns: Vec<Column>) -> io::Result<()> {
+    fn create_table_files(
+        &mut self,
+        name: &str,
+        columns: Vec<Column>,
+        layout: TableLayout,
+        compression: Compression,
+        transaction_id: TransactionId,
+    ) -> io::Result<()> {
         let table_meta = TableMetadata {
             name: name.to_string(),
             columns,
             row_count: 0,
             page_size: 4096,
+            storage: StorageFormat::PageChecksums,
+            indexes: Vec::new(),
+            layout,
+            compression,
+            compression_ratio: None,
         };
 
-        // Save table schema
-        let tables_dir = self.path.join("tables");
+        // Create the data file, then save table schema
+        let tables_dir = Path::new(TABLES_DIRECTORY);
         let meta_path = tables_dir.join(format!("{}.meta.ron", name));
-        let ron = ron::ser::to_string_pretty(&table_meta, Default::default())
-            .map_err(std::io::Error::other)?;
-        std::fs::write(meta_path, ron)?;
-
-        // Save data to binary file
         let data_path = tables_dir.join(format!("{}.data.bin", name));
-        std::fs::File::create(data_path)?;
+        self.created_files.push(data_path.clone());
+        self.created_files.push(meta_path.clone());
+        let heap_file = HeapFile::open(
+            &data_path,
+            table_meta.data_page_layout(),
+            self.buffer_pool.clone(),
+        )?;
+        self.created_files
+            .push(tables_dir.join(format!("{}.idx.bin", name)));
+        let primary_key =
+            Table::open_primary_key(tables_dir, &table_meta, self.buffer_pool.clone())?;
+        if table_meta.layout == TableLayout::Columnar {
+            let files = ColumnStore::files(name, &table_meta.columns);
+            self.created_files
+                .extend(files.into_iter().map(|file| tables_dir.join(file)));
+        }
+        let column_store =
+            Table::open_column_store(tables_dir, &table_meta, self.buffer_pool.clone())?;
+        let mut table = Table {
+            metadata: table_meta,
+            meta_path,
+            heap_file,
+            primary_key,
+            column_store,
+            indexes: Vec::new(),
+            buffer_pool: self.buffer_pool.clone(),
+            created_by: Some(transaction_id),
+        };
+        // Unique columns are enforced through an index each
+        let definitions: Vec<IndexDefinition> = table
+            .metadata
+            .columns
+            .iter()
+            .filter(|column| column.is_unique)
+            .map(|column| IndexDefinition::unique_column(name, column))
+            .collect();
+        for definition in definitions {
+            let index_path = table.index_path(&definition.name);
+            self.created_files.push(index_path.clone());
+            self.storage.remove(&index_path)?;
+            table.add_index(definition, &[])?;
+        }
+        table.save_metadata()?;
 
         // Update metadata tables
         self.metadata.tables.push(name.to_string());
         self.save_metadata()?;
+        self.tables.insert(name.to_string(), table);
 
         Ok(())
     }
 
-    fn save_metadata(&self) -> io::Result<()> {
-        let ron = ron::ser::to_string_pretty(&self.metadata, Default::default())
-            .map_err(std::io::Error::other)?;
-        std::fs::write(self.path.join("metadata.ron"), ron)?;
+    /// Returns the columns of given table, if it exists
+    pub fn table_columns(&self, table_name: &str) -> Option<&[Column]> {
+        self.visible_table(table_name)
+            .map(|table| table.metadata.columns.as_slice())
+    }
+
+    /// Names of the tables seen by the current session
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables
+            .keys()
+            .filter(|name| self.visible_table(name).is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the secondary indexes of given table, if it exists
+    pub fn table_indexes(&self, table_name: &str) -> Option<&[IndexDefinition]> {
+        self.visible_table(table_name)
+            .map(|table| table.metadata.indexes.as_slice())
+    }
+
+    /// Name of the table holding given index, if it exists
+    pub fn index_table(&self, index_name: &str) -> Option<&str> {
+        self.tables
+            .values()
+            .find(|table| table.index_position(index_name).is_some())
+            .map(|table| table.metadata.name.as_str())
+    }
+
+    /// Creates a secondary index, filled with the key values of every stored version of the rows
+    /// (see `scan_versions`). Index names are unique across the database.
+    pub fn create_index(
+        &mut self,
+        table_name: &str,
+        definition: IndexDefinition,
+        entries: Vec<(RowId, Vec<Value>)>,
+    ) -> io::Result<()> {
+        self.acquire_write()?;
+        if self.index_table(&definition.name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Index '{}' already exists", definition.name),
+            ));
+        }
+        let index_path = self.get_table(table_name)?.index_path(&definition.name);
+        // Rolling back the drop needs the file of the dropped index
+        if self.dropped_files.contains(&index_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Index '{}' was dropped by the running transaction, which must be committed before creating it again",
+                    definition.name
+                ),
+            ));
+        }
+        self.created_files.push(index_path.clone());
+        // Left over by an index whose creation failed
+        self.storage.remove(&index_path)?;
+        let result = self
+            .get_table_mut(table_name)?
+            .add_index(definition, &entries);
+        if result.is_err() {
+            self.storage.remove(&index_path)?;
+        }
+        result
+    }
+
+    /// Removes a secondary index. Its file is removed once the change is committed.
+    pub fn drop_index(&mut self, table_name: &str, index_name: &str) -> io::Result<()> {
+        self.acquire_write()?;
+        let table = self.get_table_mut(table_name)?;
+        let position = table.index_position(index_name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Index '{index_name}' does not exist"),
+            )
+        })?;
+        if let Some(column) = table.metadata.columns.iter().find(|column| {
+            column.is_unique
+                && IndexDefinition::unique_column(table_name, column).name == index_name
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Index '{index_name}' enforces the unique constraint of column '{}'",
+                    column.name
+                ),
+            ));
+        }
+        table.metadata.indexes.remove(position);
+        drop(table.indexes.remove(position));
+        table.save_metadata()?;
+        let index_path = table.index_path(index_name);
+        self.dropped_files.push(index_path);
         Ok(())
     }
-}
 
-/// Structure of a table
-struct Table {
-    metadata: TableMetadata,
-    data_file: std::fs::File,
-}
+    pub fn insert_row(
+        &mut self,
+        table_name: &str,
+        row: Vec<Value>,
+        index_values: &IndexValues,
+    ) -> io::Result<RowId> {
+        let transaction_id = self.acquire_write()?;
+        let key = self.get_table(table_name)?.row_key(&row);
+        let row = self.encode_stored_row(table_name, row)?;
+        self.get_table_mut(table_name)?.insert_row(
+            &row,
+            key.as_deref(),
+            index_values,
+            transaction_id,
+        )
+    }
 
-impl Table {
-    /// Inserts a single row in current instance of Table
-    fn insert_row(&mut self, row: Vec<Value>) -> io::Result<()> {
-        let config = config::standard();
-        let encoded = bincode::encode_to_vec(&row, config).map_err(std::io::Error::other)?;
+    pub fn read_all_rows(&mut self, table_name: &str) -> io::Result<Vec<Vec<Value>>> {
+        Ok(self
+            .scan_rows(table_name)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect())
+    }
+
+    /// Reads every row of a table seen by the running transaction, along with its address
+    pub fn scan_rows(&mut self, table_name: &str) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let (snapshot, own) = self.read_view();
+        self.scan_filtered(table_name, |version| snapshot.sees(version, own))
+    }
 
-        // Aller à la fin du fichier
-        self.data_file.seek(SeekFrom::End(0))?;
+    /// Reads the rows which no transaction deleted, whether the running transaction sees them or
+    /// not: new keys of unique indexes must not clash with any of them
+    pub fn scan_live_rows(&mut self, table_name: &str) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        self.scan_filtered(table_name, RowVersion::is_live)
+    }
 
-        // Écrire [longueur][données]
-        self.data_file
-            .write_all(&(encoded.len() as u32).to_le_bytes())?;
-        self.data_file.write_all(&encoded)?;
-        self.data_file.flush()?;
+    /// Reads every version stored for the rows of a table, seen by the running transaction or
+    /// not, along with its address. Indexes hold an entry for each of them.
+    pub fn scan_versions(
+        &mut self,
+        table_name: &str,
+    ) -> io::Result<Vec<(RowId, RowVersion, Vec<Value>)>> {
+        let versions = self.get_table_mut(table_name)?.scan_versions()?;
+        versions
+            .into_iter()
+            .map(|(row_id, version, row)| {
+                Ok((row_id, version, self.decode_stored_row(table_name, row)?))
+            })
+            .collect()
+    }
 
-        self.metadata.row_count += 1;
+    /// Same as `scan_rows`, only reading the columns flagged in `columns` of a columnar table:
+    /// the other ones are NULL. Its rows whose values of a column are known to be out of given
+    /// bounds, from the smallest and largest values of their block, may be skipped, so the rows
+    /// still have to be filtered. Rows of other tables are read whole.
+    pub fn scan_columns(
+        &mut self,
+        table_name: &str,
+        columns: &[bool],
+        bounds: &[(usize, KeyBounds)],
+    ) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let (snapshot, own) = self.read_view();
+        self.scan_projected(
+            table_name,
+            |version| snapshot.sees(version, own),
+            columns,
+            bounds,
+        )
+    }
+
+    fn scan_filtered(
+        &mut self,
+        table_name: &str,
+        keep: impl Fn(RowVersion) -> bool,
+    ) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        self.scan_projected(table_name, keep, &[], &[])
+    }
+
+    fn scan_projected(
+        &mut self,
+        table_name: &str,
+        keep: impl Fn(RowVersion) -> bool,
+        columns: &[bool],
+        bounds: &[(usize, KeyBounds)],
+    ) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let rows = self
+            .get_table_mut(table_name)?
+            .scan_rows(keep, columns, bounds)?;
+        rows.into_iter()
+            .map(|(row_id, row)| Ok((row_id, self.decode_stored_row(table_name, row)?)))
+            .collect()
+    }
+
+    /// Reads the row stored at given address, unless the running transaction does not see it
+    pub fn get_row(&mut self, table_name: &str, row_id: RowId) -> io::Result<Option<Vec<Value>>> {
+        let (snapshot, own) = self.read_view();
+        match self.get_table_mut(table_name)?.get_version(row_id)? {
+            Some((version, row)) if snapshot.sees(version, own) => {
+                self.decode_stored_row(table_name, row).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Replaces rows by new versions, returning their address. The replaced versions are marked
+    /// as deleted, and stay readable by the transactions which still see them. Rows may swap
+    /// keys, but the new keys of unique indexes must be unique.
+    pub fn update_rows(
+        &mut self,
+        table_name: &str,
+        changes: Vec<RowChange>,
+    ) -> io::Result<Vec<RowId>> {
+        let transaction_id = self.acquire_write()?;
+        let mut updates = Vec::new();
+        for change in changes {
+            let key = self.get_table(table_name)?.row_key(&change.row);
+            updates.push(RowUpdate {
+                row_id: change.row_id,
+                row: self.encode_stored_row(table_name, change.row)?,
+                key,
+                index_values: change.index_values,
+            });
+        }
+        let replaced: Vec<RowId> = updates.iter().map(|update| update.row_id).collect();
+        let row_ids = self
+            .get_table_mut(table_name)?
+            .update_rows(updates, transaction_id)?;
+        self.record_deleted_versions(table_name, &replaced, transaction_id);
+        Ok(row_ids)
+    }
+
+    /// Marks rows as deleted. Their versions stay readable by the transactions which still see
+    /// them, until they are purged.
+    pub fn delete_rows(&mut self, table_name: &str, row_ids: &[RowId]) -> io::Result<()> {
+        let transaction_id = self.acquire_write()?;
+        self.get_table_mut(table_name)?
+            .delete_rows(row_ids, transaction_id)?;
+        self.record_deleted_versions(table_name, row_ids, transaction_id);
         Ok(())
     }
 
-    /// Reads all rows from given instance
-    fn read_all_rows(&mut self) -> io::Result<Vec<Vec<Value>>> {
-        self.data_file.seek(SeekFrom::Start(0))?;
+    fn record_deleted_versions(
+        &mut self,
+        table_name: &str,
+        row_ids: &[RowId],
+        deleted_by: TransactionId,
+    ) {
+        let deleted = row_ids.iter().map(|&row_id| DeletedVersion {
+            table_name: table_name.to_string(),
+            row_id,
+            deleted_by,
+        });
+        self.current_mut().deleted_versions.extend(deleted);
+    }
+
+    /// Whether another transaction deleted or updated one of given rows, which the running
+    /// transaction sees, since the snapshot of the running transaction was taken. The running
+    /// transaction cannot change those rows anymore, as it would overwrite that change.
+    pub fn updated_concurrently(
+        &mut self,
+        table_name: &str,
+        row_ids: &[RowId],
+    ) -> io::Result<bool> {
+        let table = self.get_table_mut(table_name)?;
+        for &row_id in row_ids {
+            if table
+                .get_version(row_id)?
+                .is_some_and(|(version, _)| !version.is_live())
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Looks up the live row (see `scan_live_rows`) holding given values of a unique key: the
+    /// primary key when `index_name` is `None`, or else a unique index. Returns `None` if no row
+    /// has this key, if the table has no primary key, or if the values hold a NULL.
+    pub fn find_by_key(
+        &mut self,
+        table_name: &str,
+        index_name: Option<&str>,
+        values: &[Value],
+    ) -> io::Result<Option<RowId>> {
+        let table = self.get_table_mut(table_name)?;
+        match index_name {
+            None if table.primary_key.is_none() => Ok(None),
+            None => {
+                let key = table.primary_key_of(values);
+                table.find_live(None, &key)
+            }
+            Some(index_name) => {
+                let position = table.find_index(index_name)?;
+                match table.unique_index_key(position, values) {
+                    Some(key) => table.find_live(Some(position), &key),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Reads the rows seen by the running transaction whose key lies within given bounds, in key
+    /// order: keys of the primary key when `index_name` is `None`, or else of a secondary index.
+    /// Fails if the table has no primary key.
+    pub fn index_range(
+        &mut self,
+        table_name: &str,
+        index_name: Option<&str>,
+        bounds: &KeyBounds,
+    ) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let (snapshot, own) = self.read_view();
+        let table = self.get_table_mut(table_name)?;
+        let (index, orders) = match index_name {
+            None => {
+                let orders = table.primary_key_orders();
+                let index = table.primary_key.as_mut().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Table '{table_name}' has no primary key"),
+                    )
+                })?;
+                (index, orders)
+            }
+            Some(index_name) => {
+                let position = table.find_index(index_name)?;
+                let orders = table.metadata.indexes[position].key_orders();
+                (&mut table.indexes[position], orders)
+            }
+        };
+        let (lower, upper) = bounds.encode(&orders);
+        let entries = index.range(
+            lower.as_ref().map(Vec::as_slice),
+            upper.as_ref().map(Vec::as_slice),
+        )?;
+        let index_description = match index_name {
+            Some(index_name) => format!("Index '{index_name}'"),
+            None => format!("Primary key index of table '{table_name}'"),
+        };
         let mut rows = Vec::new();
-        let config = config::standard();
+        for (_, row_id) in entries {
+            let (version, row) = table.get_version(row_id)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{index_description} points to missing row {row_id}"),
+                )
+            })?;
+            if snapshot.sees(version, own) {
+                rows.push((row_id, row));
+            }
+        }
+        rows.into_iter()
+            .map(|(row_id, row)| Ok((row_id, self.decode_stored_row(table_name, row)?)))
+            .collect()
+    }
+
+    /// Deleted versions which no snapshot sees anymore, grouped by table: those deleted by
+    /// committed transactions, and by the running one if it is about to commit. They are taken
+    /// out of the versions waiting to be purged, and are to be purged with `purge_versions`.
+    pub fn garbage_versions(&mut self) -> io::Result<Vec<TableVersions>> {
+        if !self.can_write() {
+            return Ok(Vec::new());
+        }
+        let snapshots = self.other_snapshots();
+        let mut candidates = std::mem::take(&mut self.garbage);
+        let committed_count = candidates.len();
+        candidates.append(&mut self.current_mut().deleted_versions);
+        let mut garbage: Vec<TableVersions> = Vec::new();
+        for (position, candidate) in candidates.into_iter().enumerate() {
+            let Some(table) = self.tables.get_mut(&candidate.table_name) else {
+                continue;
+            };
+            // The version may have been purged since, and its address given to another one
+            let Some((version, row)) = table
+                .get_version(candidate.row_id)?
+                .filter(|(version, _)| version.deleted_by == candidate.deleted_by)
+            else {
+                continue;
+            };
+            if snapshots
+                .iter()
+                .any(|snapshot| snapshot.sees(version, None))
+            {
+                match position < committed_count {
+                    true => self.garbage.push(candidate),
+                    false => self.current_mut().deleted_versions.push(candidate),
+                }
+                continue;
+            }
+            let row = self.decode_stored_row(&candidate.table_name, row)?;
+            match garbage
+                .iter_mut()
+                .find(|(table_name, _)| *table_name == candidate.table_name)
+            {
+                Some((_, versions)) => versions.push((candidate.row_id, row)),
+                None => garbage.push((candidate.table_name, vec![(candidate.row_id, row)])),
+            }
+        }
+        Ok(garbage)
+    }
+
+    /// Deleted versions of the rows of a table which no snapshot sees anymore, to be purged with
+    /// `purge_versions`
+    pub fn dead_versions(&mut self, table_name: &str) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let snapshots = self.other_snapshots();
+        self.scan_filtered(table_name, |version| {
+            !version.is_live()
+                && snapshots
+                    .iter()
+                    .all(|snapshot| !snapshot.sees(version, None))
+        })
+    }
 
-        loop {
-            let mut len_bytes = [0u8; 4];
-            match self.data_file.read_exact(&mut len_bytes) {
-                Ok(_) => {}
-                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e),
+    /// Removes row versions from a table and its indexes, given along with their index values
+    pub fn purge_versions(
+        &mut self,
+        table_name: &str,
+        versions: &[(RowId, IndexValues)],
+    ) -> io::Result<()> {
+        self.acquire_write()?;
+        for (row_id, index_values) in versions {
+            let key = self.row_key(table_name, *row_id)?;
+            self.get_table_mut(table_name)?
+                .purge_version(*row_id, key, index_values)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the metadata of the database against the schemas of its tables, then every table
+    /// seen by the current session: each stored row version must decode, respect the constraints
+    /// of its table when live, and have exactly one entry in every index. Keys of secondary
+    /// indexes are computed by `index_values`, given a table name and a row. Problems are
+    /// collected in the report rather than returned, so that one check finds all of them.
+    pub fn check_integrity(
+        &mut self,
+        index_values: &IndexValuesOf<'_>,
+    ) -> io::Result<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+        self.check_metadata_files(&mut report)?;
+        let mut table_names = self.table_names();
+        table_names.extend(self.damaged_tables.keys().cloned());
+        table_names.sort();
+        for table_name in table_names {
+            match self.damaged_tables.get(&table_name) {
+                Some(error) => {
+                    let description = format!("cannot be opened: {error}");
+                    report.add(Some(&table_name), "files", description);
+                }
+                None => self.check_table(&table_name, index_values, &mut report)?,
             }
+            report.tables += 1;
+        }
+        Ok(report)
+    }
 
-            let len = u32::from_le_bytes(len_bytes) as usize;
+    /// Cross-validates `metadata.ron` with the schema files of the tables it lists, and looks
+    /// for files of the tables directory which no table or index references
+    fn check_metadata_files(&self, report: &mut IntegrityReport) -> io::Result<()> {
+        let buffer_pool = self.buffer_pool.lock().unwrap();
+        let read_ron = |path: &Path| -> Result<String, String> {
+            let content = buffer_pool
+                .read_file(path)
+                .map_err(|error| error.to_string())?;
+            String::from_utf8(content).map_err(|error| error.to_string())
+        };
+        let metadata: DatabaseMetadata = match read_ron(Path::new(METADATA_FILE))
+            .and_then(|content| ron::from_str(&content).map_err(|error| error.to_string()))
+        {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                report.add(None, "metadata.ron", format!("cannot be read: {error}"));
+                return Ok(());
+            }
+        };
+        let tables_dir = Path::new(TABLES_DIRECTORY);
+        let mut referenced_files = HashSet::new();
+        let mut index_names: HashMap<String, String> = HashMap::new();
+        for (position, table_name) in metadata.tables.iter().enumerate() {
+            if metadata.tables[..position].contains(table_name) {
+                let description = format!("lists table '{table_name}' more than once");
+                report.add(None, "metadata.ron", description);
+                continue;
+            }
+            let meta_file = format!("{table_name}.meta.ron");
+            let data_file = format!("{table_name}.data.bin");
+            let table_meta: TableMetadata = match read_ron(&tables_dir.join(&meta_file))
+                .and_then(|content| ron::from_str(&content).map_err(|error| error.to_string()))
+            {
+                Ok(table_meta) => table_meta,
+                Err(error) => {
+                    let description = format!("cannot be read: {error}");
+                    report.add(Some(table_name), &meta_file, description);
+                    continue;
+                }
+            };
+            if table_meta.name != *table_name {
+                let description = format!("describes table '{}'", table_meta.name);
+                report.add(Some(table_name), &meta_file, description);
+            }
+            for (position, column) in table_meta.columns.iter().enumerate() {
+                if table_meta.columns[..position]
+                    .iter()
+                    .any(|other| other.name.eq_ignore_ascii_case(&column.name))
+                {
+                    let description = format!("defines column '{}' more than once", column.name);
+                    report.add(Some(table_name), &meta_file, description);
+                }
+                let mut data_type = &column.data_type;
+                while let DataType::Array(element_type) = data_type {
+                    data_type = element_type;
+                }
+                if let DataType::Enum(type_name) = data_type
+                    && !metadata
+                        .types
+                        .iter()
+                        .any(|enum_type| enum_type.name.eq_ignore_ascii_case(type_name))
+                {
+                    let description = format!(
+                        "column '{}' has type '{type_name}', which metadata.ron does not define",
+                        column.name
+                    );
+                    report.add(Some(table_name), &meta_file, description);
+                }
+            }
+            if !self.storage.exists(&tables_dir.join(&data_file)) {
+                report.add(Some(table_name), &data_file, String::from("is missing"));
+            }
+            referenced_files.extend([meta_file.clone(), data_file]);
+            if table_meta.layout == TableLayout::Columnar {
+                for file in ColumnStore::files(table_name, &table_meta.columns) {
+                    if !self.storage.exists(&tables_dir.join(&file)) {
+                        report.add(Some(table_name), &file, String::from("is missing"));
+                    }
+                    referenced_files.insert(file);
+                }
+            }
+            if table_meta
+                .columns
+                .iter()
+                .any(|column| column.is_primary_key)
+            {
+                referenced_files.insert(format!("{table_name}.idx.bin"));
+            }
+            for definition in &table_meta.indexes {
+                let index_file = format!("{table_name}.{}.idx.bin", definition.name);
+                if !self.storage.exists(&tables_dir.join(&index_file)) {
+                    report.add(Some(table_name), &index_file, String::from("is missing"));
+                }
+                referenced_files.insert(index_file);
+                let index_name = definition.name.to_lowercase();
+                if let Some(other_table) = index_names.insert(index_name, table_name.clone()) {
+                    let description = format!(
+                        "defines index '{}', which table '{other_table}' also defines",
+                        definition.name
+                    );
+                    report.add(Some(table_name), &meta_file, description);
+                }
+            }
+        }
+        let mut unreferenced_files = Vec::new();
+        for path in self.storage.list()? {
+            if path.parent() != Some(tables_dir) {
+                continue;
+            }
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            // Files of dropped indexes are only removed once the drop is committed
+            if !referenced_files.contains(&file_name) && !self.dropped_files.contains(&path) {
+                unreferenced_files.push(file_name);
+            }
+        }
+        unreferenced_files.sort();
+        for file_name in unreferenced_files {
+            let table_name = file_name.split('.').next().unwrap_or_default();
+            let table_name = metadata
+                .tables
+                .iter()
+                .any(|name| name == table_name)
+                .then_some(table_name);
+            let description = String::from("is referenced by no table or index");
+            report.add(table_name, &file_name, description);
+        }
+        Ok(())
+    }
 
-            let mut data = vec![0u8; len];
-            self.data_file.read_exact(&mut data)?;
+    /// Checks the row versions and indexes of a table, see `check_integrity`
+    fn check_table(
+        &mut self,
+        table_name: &str,
+        index_values: &IndexValuesOf<'_>,
+        report: &mut IntegrityReport,
+    ) -> io::Result<()> {
+        let mut versions = Vec::new();
+        for page_number in 0..self.get_table(table_name)?.heap_file.page_count() {
+            let records = match self
+                .get_table_mut(table_name)?
+                .heap_file
+                .scan_page(page_number)
+            {
+                Ok(records) => records,
+                Err(error) => {
+                    report.add(Some(table_name), "data file", error.to_string());
+                    continue;
+                }
+            };
+            let column_count = self.get_table(table_name)?.metadata.columns.len();
+            for (row_id, record) in records {
+                let loaded = self.get_table_mut(table_name)?.load_record(&record);
+                let decoded = loaded.and_then(|(version, row)| {
+                    if row.len() != column_count {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} values for {column_count} columns", row.len()),
+                        ));
+                    }
+                    Ok((version, self.decode_stored_row(table_name, row)?))
+                });
+                match decoded {
+                    Ok((version, row)) => versions.push((row_id, version, row)),
+                    Err(error) => {
+                        let description = format!("row version {row_id} cannot be read: {error}");
+                        report.add(Some(table_name), "data file", description);
+                    }
+                }
+            }
+        }
+        report.row_versions += versions.len();
 
-            let (row, _): (Vec<Value>, usize) =
-                bincode::decode_from_slice(&data, config).map_err(std::io::Error::other)?;
+        let table = self.get_table(table_name)?;
+        table.check_rows(&versions, report);
+        let mut primary_key_entries = Vec::new();
+        let mut index_entries = vec![Vec::new(); table.metadata.indexes.len()];
+        let mut unique_keys = vec![HashSet::new(); table.metadata.indexes.len()];
+        for (row_id, version, row) in &versions {
+            if let Some(key) = table.row_key(row) {
+                primary_key_entries.push((append_row_id(key, *row_id), *row_id));
+            }
+            let values = match index_values(table_name, row) {
+                Ok(values) => values,
+                Err(error) => {
+                    let description =
+                        format!("index keys of row version {row_id} cannot be computed: {error}");
+                    report.add(Some(table_name), "data file", description);
+                    continue;
+                }
+            };
+            for (position, values) in values.iter().enumerate() {
+                index_entries[position].push((table.index_key(position, values, *row_id), *row_id));
+                if let Some(key) = table.unique_index_key(position, values)
+                    && version.is_live()
+                    && !unique_keys[position].insert(key)
+                {
+                    let description = format!(
+                        "live rows share the key ({}) of unique index '{}'",
+                        join_values(values),
+                        table.metadata.indexes[position].name
+                    );
+                    report.add(Some(table_name), "data file", description);
+                }
+            }
+        }
+        let table = self.get_table_mut(table_name)?;
+        table.check_index(None, primary_key_entries, report);
+        for (position, entries) in index_entries.into_iter().enumerate() {
+            table.check_index(Some(position), entries, report);
+        }
+        Ok(())
+    }
 
-            rows.push(row);
+    /// Primary key of a stored row version, if its table has one
+    fn row_key(&mut self, table_name: &str, row_id: RowId) -> io::Result<Option<Vec<u8>>> {
+        if self.get_table(table_name)?.primary_key.is_none() {
+            return Ok(None);
         }
+        let (_, row) = self
+            .get_table_mut(table_name)?
+            .get_version(row_id)?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Row {row_id} does not exist"),
+                )
+            })?;
+        let row = self.decode_stored_row(table_name, row)?;
+        Ok(self.get_table(table_name)?.row_key(&row))
+    }
 
-        Ok(rows)
+    fn encode_stored_row(&self, table_name: &str, row: Vec<Value>) -> io::Result<Vec<Value>> {
+        let columns = &self.get_table(table_name)?.metadata.columns;
+        Ok(row
+            .into_iter()
+            .zip(columns)
+            .map(|(value, column)| self.encode_enum_value(value, &column.data_type))
+            .collect())
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Encode, Decode)]
-enum Value {
-    Integer(i64),
-    Text(String),
-    Real(f64),
-    Null,
+    fn decode_stored_row(&self, table_name: &str, row: Vec<Value>) -> io::Result<Vec<Value>> {
+        let columns = &self.get_table(table_name)?.metadata.columns;
+        row.into_iter()
+            .zip(columns)
+            .map(|(value, column)| self.decode_enum_value(value, &column.data_type, column))
+            .collect()
+    }
+
+    /// Returns the user-defined type with given name, if it exists
+    pub fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.metadata
+            .types
+            .iter()
+            .find(|enum_type| enum_type.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn create_enum_type(&mut self, enum_type: EnumType) -> io::Result<()> {
+        self.acquire_write()?;
+        if self.enum_type(&enum_type.name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Type '{}' already exists", enum_type.name),
+            ));
+        }
+        self.metadata.types.push(enum_type);
+        self.save_metadata()
+    }
+
+    /// Replaces the definition of an existing user-defined type
+    pub fn update_enum_type(&mut self, enum_type: EnumType) -> io::Result<()> {
+        self.acquire_write()?;
+        let existing = self
+            .metadata
+            .types
+            .iter_mut()
+            .find(|existing| existing.name.eq_ignore_ascii_case(&enum_type.name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Type '{}' does not exist", enum_type.name),
+                )
+            })?;
+        *existing = enum_type;
+        self.save_metadata()
+    }
+
+    /// Opens a session, in which statements run through `use_session`. Sessions run their own
+    /// transactions, each one reading a snapshot of the database taken when it first reads.
+    pub fn open_session(&mut self) -> SessionId {
+        let session = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(session, SessionState::default());
+        session
+    }
+
+    /// Closes a session, rolling back its running transaction if any
+    pub fn close_session(&mut self, session: SessionId) -> io::Result<()> {
+        self.use_session(session);
+        let rolled_back = self.rollback();
+        self.sessions.remove(&session);
+        rolled_back
+    }
+
+    /// Makes the following calls run in the transaction of given session. Statements of the
+    /// sessions run one at a time, each one being run by a single session.
+    pub fn use_session(&mut self, session: SessionId) {
+        self.session = session;
+    }
+
+    /// Starts a transaction, whose changes are only committed or rolled back as a whole
+    pub fn begin(&mut self) {
+        self.current_mut().transaction = Some(Transaction {
+            failed: false,
+            isolation_level: IsolationLevel::default(),
+            queried: false,
+            savepoints: Vec::new(),
+        });
+    }
+
+    /// Isolation level of the running transaction. Statements run outside of a transaction read
+    /// a single snapshot.
+    pub fn isolation_level(&self) -> IsolationLevel {
+        self.current()
+            .and_then(|session| session.transaction.as_ref())
+            .map_or(IsolationLevel::default(), |transaction| {
+                transaction.isolation_level
+            })
+    }
+
+    /// Sets the isolation level of the running transaction, before any of its statements ran
+    pub fn set_isolation_level(&mut self, isolation_level: IsolationLevel) -> io::Result<()> {
+        let Some(transaction) = &mut self.current_mut().transaction else {
+            return Err(io::Error::other(
+                "SET TRANSACTION can only be used in transactions",
+            ));
+        };
+        if transaction.queried {
+            return Err(io::Error::other(
+                "SET TRANSACTION ISOLATION LEVEL must be called before any query",
+            ));
+        }
+        transaction.isolation_level = isolation_level;
+        Ok(())
+    }
+
+    /// Ends a statement of the running transaction. Unless the transaction keeps its snapshot,
+    /// its next statement reads a new one.
+    pub fn end_statement(&mut self) {
+        let session = self.current_mut();
+        if let Some(transaction) = &mut session.transaction {
+            transaction.queried = true;
+            if transaction.isolation_level != IsolationLevel::RepeatableRead {
+                session.snapshot = None;
+            }
+        }
+    }
+
+    /// Locks of the sessions, which sessions wait for outside of statements
+    pub fn lock_manager(&self) -> Arc<LockManager> {
+        Arc::clone(&self.locks)
+    }
+
+    /// Whether the database was opened with `open_read_only`, which refuses every change
+    pub fn is_read_only(&self) -> bool {
+        self.storage.mode() == AccessMode::ReadOnly
+    }
+
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    pub fn set_lock_timeout(&mut self, lock_timeout: Duration) {
+        self.lock_timeout = lock_timeout;
+    }
+
+    pub fn set_default_compression(&mut self, compression: Compression) {
+        self.default_compression = compression;
+    }
+
+    /// Gives a lock to the running transaction, held until it ends, unless another transaction
+    /// holds a conflicting lock
+    pub fn try_lock(&mut self, target: &LockTarget, mode: LockMode) -> bool {
+        self.locks.try_acquire(self.session, target, mode)
+    }
+
+    /// Rolls back every change of the running transaction and releases its locks, after it
+    /// failed to get a lock. It is left failed, so that it still has to be rolled back.
+    pub fn abort_transaction(&mut self) -> io::Result<()> {
+        let session = self.current_mut();
+        session.snapshot = None;
+        if let Some(transaction) = &mut session.transaction {
+            transaction.failed = true;
+            transaction.savepoints.clear();
+        }
+        let writing = session.transaction_id.is_some();
+        self.locks.release_all(self.session);
+        if writing {
+            self.discard_changes()?;
+        }
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.current()
+            .is_some_and(|session| session.transaction.is_some())
+    }
+
+    /// Whether a statement of the running transaction failed
+    pub fn transaction_failed(&self) -> bool {
+        self.current()
+            .and_then(|session| session.transaction.as_ref())
+            .is_some_and(|transaction| transaction.failed)
+    }
+
+    /// Marks the running transaction as failed, so that it can only be rolled back
+    pub fn fail_transaction(&mut self) {
+        if let Some(transaction) = &mut self.current_mut().transaction {
+            transaction.failed = true;
+        }
+    }
+
+    /// Whether the running transaction may write, which it may unless another one is writing
+    fn can_write(&self) -> bool {
+        self.writer.is_none_or(|writer| writer == self.session)
+    }
+
+    /// Commits the changes of the running transaction (or statement), ending it: they are
+    /// logged, then written to the data files
+    pub fn commit(&mut self) -> io::Result<()> {
+        let session = self.current_mut();
+        session.transaction = None;
+        session.snapshot = None;
+        let writing = session.transaction_id.take().is_some();
+        let deleted_versions = std::mem::take(&mut session.deleted_versions);
+        // Sessions waiting for the locks only go on once the database is released
+        self.locks.release_all(self.session);
+        if !writing {
+            return Ok(());
+        }
+        self.writer = None;
+        self.buffer_pool.lock().unwrap().commit()?;
+        self.garbage.extend(deleted_versions);
+        for table in self.tables.values_mut() {
+            table.created_by = None;
+        }
+        self.created_files.clear();
+        for path in self.dropped_files.drain(..) {
+            self.storage.remove(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Discards the changes of the running transaction (or statement), including created
+    /// tables, ending it
+    pub fn rollback(&mut self) -> io::Result<()> {
+        let session = self.current_mut();
+        session.transaction = None;
+        session.snapshot = None;
+        let writing = session.transaction_id.is_some();
+        self.locks.release_all(self.session);
+        if !writing {
+            return Ok(());
+        }
+        self.discard_changes()
+    }
+
+    /// Rolls back every change of the writing transaction, which keeps running but stops
+    /// writing
+    fn discard_changes(&mut self) -> io::Result<()> {
+        let session = self.current_mut();
+        session.transaction_id = None;
+        session.deleted_versions.clear();
+        self.writer = None;
+        self.dropped_files.clear();
+        let created_files = std::mem::take(&mut self.created_files);
+        if !self.buffer_pool.lock().unwrap().rollback()? {
+            return Ok(());
+        }
+        self.reload(created_files)
+    }
+
+    /// Creates a savepoint in the running transaction. A savepoint with the same name as an
+    /// existing one hides it until it is released.
+    pub fn savepoint(&mut self, name: &str) -> io::Result<()> {
+        let writing = self.current_mut().transaction_id.is_some();
+        if self.current_mut().transaction.is_none() {
+            return Err(no_transaction());
+        }
+        if writing {
+            self.buffer_pool.lock().unwrap().savepoint()?;
+        }
+        let savepoint = Savepoint {
+            name: name.to_string(),
+            created_files: self.created_files.len(),
+            dropped_files: self.dropped_files.len(),
+            deleted_versions: self.current_mut().deleted_versions.len(),
+            writing,
+        };
+        if let Some(transaction) = &mut self.current_mut().transaction {
+            transaction.savepoints.push(savepoint);
+        }
+        Ok(())
+    }
+
+    /// Removes a savepoint and the ones created after it, keeping the changes made since
+    pub fn release_savepoint(&mut self, name: &str) -> io::Result<()> {
+        let depth = self.savepoint_depth(name)?;
+        let pool_depth = self.savepoint_pool_depth(depth);
+        let session = self.current_mut();
+        let writing = session.transaction_id.is_some();
+        if let Some(transaction) = &mut session.transaction {
+            transaction.savepoints.truncate(depth);
+        }
+        if writing {
+            self.buffer_pool
+                .lock()
+                .unwrap()
+                .release_savepoint(pool_depth);
+        }
+        Ok(())
+    }
+
+    /// Discards the changes made since a savepoint, which is kept while the ones created after
+    /// it are removed. A failed transaction can be used again afterwards.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> io::Result<()> {
+        let depth = self.savepoint_depth(name)?;
+        let pool_depth = self.savepoint_pool_depth(depth);
+        let session = self.current_mut();
+        let writing = session.transaction_id.is_some();
+        let Some(transaction) = &mut session.transaction else {
+            return Err(no_transaction());
+        };
+        transaction.failed = false;
+        transaction.savepoints.truncate(depth + 1);
+        let savepoint = &transaction.savepoints[depth];
+        let (created_files, dropped_files, deleted_versions) = (
+            savepoint.created_files,
+            savepoint.dropped_files,
+            savepoint.deleted_versions,
+        );
+        match (writing, savepoint.writing) {
+            (false, _) => Ok(()),
+            // The transaction only started writing after the savepoint
+            (true, false) => self.discard_changes(),
+            (true, true) => {
+                session.deleted_versions.truncate(deleted_versions);
+                self.buffer_pool
+                    .lock()
+                    .unwrap()
+                    .rollback_to_savepoint(pool_depth)?;
+                self.dropped_files.truncate(dropped_files);
+                let created_files = self.created_files.split_off(created_files);
+                self.reload(created_files)
+            }
+        }
+    }
+
+    /// Opens every table again once changes were discarded, as what tables keep in memory may
+    /// include those changes. Files created by the discarded changes are removed.
+    fn reload(&mut self, created_files: Vec<PathBuf>) -> io::Result<()> {
+        self.tables.clear();
+        for path in created_files {
+            self.storage.remove(&path)?;
+        }
+        (self.metadata, (self.tables, self.damaged_tables)) =
+            Self::load(&*self.storage, &self.buffer_pool)?;
+        Ok(())
+    }
+
+    /// Position of the latest savepoint with given name among those of the running transaction
+    fn savepoint_depth(&self, name: &str) -> io::Result<usize> {
+        let transaction = self
+            .current()
+            .and_then(|session| session.transaction.as_ref())
+            .ok_or_else(no_transaction)?;
+        transaction
+            .savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Savepoint '{name}' does not exist"),
+                )
+            })
+    }
+
+    /// Level in the buffer pool of the savepoint at given depth, or of the first one created
+    /// after it if the transaction was not writing yet
+    fn savepoint_pool_depth(&self, depth: usize) -> usize {
+        self.current()
+            .and_then(|session| session.transaction.as_ref())
+            .map_or(0, |transaction| {
+                let savepoints = &transaction.savepoints[..depth];
+                savepoints
+                    .iter()
+                    .filter(|savepoint| savepoint.writing)
+                    .count()
+            })
+    }
+
+    /// Gives write access to the running transaction, along with an id, unless another
+    /// transaction is writing
+    fn acquire_write(&mut self) -> io::Result<TransactionId> {
+        if self.is_read_only() {
+            return Err(read_only());
+        }
+        if !self.can_write() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Another transaction is writing to the database",
+            ));
+        }
+        if let Some(transaction_id) = self.current_mut().transaction_id {
+            return Ok(transaction_id);
+        }
+        let transaction_id = self.next_transaction_id;
+        self.next_transaction_id += 1;
+        self.writer = Some(self.session);
+        self.current_mut().transaction_id = Some(transaction_id);
+        if self.next_transaction_id > self.metadata.reserved_transaction_ids {
+            self.metadata.reserved_transaction_ids = transaction_id + TRANSACTION_ID_BLOCK;
+            self.save_metadata()?;
+        }
+        Ok(transaction_id)
+    }
+
+    /// Snapshot read by the running transaction, taken now if it did not read yet, along with
+    /// its id if it is writing
+    fn read_view(&mut self) -> (Snapshot, Option<TransactionId>) {
+        let snapshot = Snapshot {
+            next_id: self.next_transaction_id,
+            writer: self
+                .writer
+                .and_then(|writer| self.sessions.get(&writer)?.transaction_id),
+        };
+        let session = self.current_mut();
+        (
+            *session.snapshot.get_or_insert(snapshot),
+            session.transaction_id,
+        )
+    }
+
+    /// Snapshots read by the transactions of the other sessions
+    fn other_snapshots(&self) -> Vec<Snapshot> {
+        self.sessions
+            .iter()
+            .filter(|(session, _)| **session != self.session)
+            .filter_map(|(_, state)| state.snapshot)
+            .collect()
+    }
+
+    fn current(&self) -> Option<&SessionState> {
+        self.sessions.get(&self.session)
+    }
+
+    fn current_mut(&mut self) -> &mut SessionState {
+        self.sessions
+            .get_mut(&self.session)
+            .expect("statements run in an open session")
+    }
+
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.lock().unwrap().stats()
+    }
+
+    /// Enum labels, including those within arrays, are stored as their position in the list of
+    /// labels of their type
+    fn encode_enum_value(&self, value: Value, data_type: &DataType) -> Value {
+        match (data_type, value) {
+            (DataType::Enum(type_name), Value::Enum(enum_value)) => self
+                .enum_type(type_name)
+                .and_then(|enum_type| enum_type.position_of(&enum_value.label))
+                .map(|position| Value::Integer(position as i64))
+                .unwrap_or(Value::Null),
+            (DataType::Array(element_type), Value::Array(elements)) => Value::Array(
+                elements
+                    .into_iter()
+                    .map(|element| self.encode_enum_value(element, element_type))
+                    .collect(),
+            ),
+            (_, value) => value,
+        }
+    }
+
+    fn decode_enum_value(
+        &self,
+        value: Value,
+        data_type: &DataType,
+        column: &Column,
+    ) -> io::Result<Value> {
+        match (data_type, value) {
+            (DataType::Array(element_type), Value::Array(elements)) => elements
+                .into_iter()
+                .map(|element| self.decode_enum_value(element, element_type, column))
+                .collect::<io::Result<Vec<Value>>>()
+                .map(Value::Array),
+            (DataType::Enum(type_name), Value::Integer(position)) => self
+                .enum_type(type_name)
+                .filter(|enum_type| (0..enum_type.labels.len() as i64).contains(&position))
+                .map(|enum_type| enum_type.value_at(position as usize))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Column '{}' holds unknown label #{position} of type '{type_name}'",
+                            column.name
+                        ),
+                    )
+                }),
+            (_, value) => Ok(value),
+        }
+    }
+
+    /// Tables created by a transaction which is still running are only seen by that transaction
+    fn visible_table(&self, table_name: &str) -> Option<&Table> {
+        let own = self.current().and_then(|session| session.transaction_id);
+        self.tables
+            .get(table_name)
+            .filter(|table| table.created_by.is_none_or(|id| Some(id) == own))
+    }
+
+    fn get_table(&self, table_name: &str) -> io::Result<&Table> {
+        self.visible_table(table_name)
+            .ok_or_else(|| self.missing_table(table_name))
+    }
+
+    fn get_table_mut(&mut self, table_name: &str) -> io::Result<&mut Table> {
+        let own = self.current().and_then(|session| session.transaction_id);
+        let error = self.missing_table(table_name);
+        self.tables
+            .get_mut(table_name)
+            .filter(|table| table.created_by.is_none_or(|id| Some(id) == own))
+            .ok_or(error)
+    }
+
+    /// Error returned for a table which is not opened: the reason it could not be, if it exists
+    pub fn missing_table(&self, table_name: &str) -> io::Error {
+        match self.damaged_tables.get(table_name) {
+            Some(error) => io::Error::new(io::ErrorKind::InvalidData, error.clone()),
+            None => table_not_found(table_name),
+        }
+    }
+
+    fn save_metadata(&self) -> io::Result<()> {
+        let ron = ron::ser::to_string_pretty(&self.metadata, Default::default())
+            .map_err(std::io::Error::other)?;
+        self.buffer_pool
+            .lock()
+            .unwrap()
+            .write_file(Path::new(METADATA_FILE), ron.into_bytes())
+    }
+}
+
+fn no_transaction() -> io::Error {
+    io::Error::other("Savepoints can only be used in transactions")
+}
+
+/// Bytes of the address of a row version ending index keys
+const ROW_ID_SIZE: usize = 6;
+
+/// Bytes a decoded row may allocate. Rows hold at most a few pages of data, so larger lengths
+/// can only be read from a corrupted record.
+pub const ROW_DECODE_LIMIT: usize = MAX_PAGE_SIZE * 64;
+
+/// Whether a stored value belongs to given column type. Any column may hold NULL as far as types
+/// are concerned.
+fn fits_type(value: &Value, data_type: &DataType) -> bool {
+    match (data_type, value) {
+        (_, Value::Null)
+        | (DataType::Integer, Value::Integer(_))
+        | (DataType::Float, Value::Real(_))
+        | (DataType::Text, Value::Text(_))
+        | (DataType::Bool, Value::Bool(_))
+        | (DataType::Json, Value::Json(_))
+        | (DataType::Blob, Value::Blob(_))
+        | (DataType::Uuid, Value::Uuid(_)) => true,
+        (DataType::Enum(type_name), Value::Enum(enum_value)) => {
+            enum_value.type_name.eq_ignore_ascii_case(type_name)
+        }
+        (DataType::Array(element_type), Value::Array(elements)) => elements
+            .iter()
+            .all(|element| fits_type(element, element_type)),
+        _ => false,
+    }
+}
+
+fn join_values(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Splits the record of a row version of a columnar table into its version and row number
+fn split_row_number(record: &[u8]) -> io::Result<(RowVersion, u64)> {
+    let (version, row_number) = RowVersion::split_record(record)?;
+    let row_number = row_number.try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Record of {} bytes holds no row number", record.len()),
+        )
+    })?;
+    Ok((version, u64::from_le_bytes(row_number)))
+}
+
+/// Ends an index key with the address of a row version, which keeps apart the keys of versions
+/// holding the same values
+fn append_row_id(mut key: Vec<u8>, row_id: RowId) -> Vec<u8> {
+    key.extend(row_id.page.to_be_bytes());
+    key.extend(row_id.slot.to_be_bytes());
+    key
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Database is opened in read-only mode",
+    )
+}
+
+fn table_not_found(table_name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Table '{table_name}' does not exist"),
+    )
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // A clean shutdown leaves an empty log behind, and discards an unfinished transaction
+        let closed = match self.writer {
+            Some(writer) => {
+                log::warn!("Rolling back the transaction which was not committed");
+                self.session = writer;
+                self.discard_changes()
+            }
+            None => self.buffer_pool.lock().unwrap().commit(),
+        }
+        .and_then(|_| self.buffer_pool.lock().unwrap().checkpoint());
+        if let Err(error) = closed {
+            log::error!("Could not write back modified pages: {error}");
+        }
+        log::info!("Buffer pool: {}", self.buffer_pool_stats());
+    }
+}
+
+/// Structure of a table
+struct Table {
+    metadata: TableMetadata,
+    meta_path: PathBuf,
+    heap_file: HeapFile,
+    /// Index mapping the primary key of every row to its address, if the table has a primary key
+    primary_key: Option<BTree>,
+    /// Values of the rows of a columnar table, whose records only hold the number of their row
+    column_store: Option<ColumnStore>,
+    /// Secondary indexes, in the order of `metadata.indexes`
+    indexes: Vec<BTree>,
+    buffer_pool: Arc<Mutex<BufferPool>>,
+    /// Transaction which created the table, until it commits
+    created_by: Option<TransactionId>,
+}
+
+impl Table {
+    /// Opens the files of a table, converting them first if they use an older format. Read-only
+    /// processes cannot convert files, nor create the ones which are missing.
+    fn open(
+        storage: &dyn Storage,
+        name: &str,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Self> {
+        let tables_dir = Path::new(TABLES_DIRECTORY);
+        let meta_path = tables_dir.join(format!("{}.meta.ron", name));
+        let content = buffer_pool.lock().unwrap().read_file(&meta_path)?;
+        let content = String::from_utf8(content).map_err(io::Error::other)?;
+        let mut metadata: TableMetadata = ron::from_str(&content).map_err(io::Error::other)?;
+        let data_path = tables_dir.join(format!("{}.data.bin", name));
+        let is_converted = metadata.storage != StorageFormat::PageChecksums;
+        if storage.mode() == AccessMode::ReadOnly {
+            let primary_key_path = tables_dir.join(format!("{name}.idx.bin"));
+            let has_primary_key = metadata.columns.iter().any(|column| column.is_primary_key);
+            if is_converted
+                || !storage.exists(&data_path)
+                || has_primary_key && !storage.exists(&primary_key_path)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Files of table '{name}' must be converted or created, which a read-only process cannot do"
+                    ),
+                ));
+            }
+        }
+        if metadata.storage == StorageFormat::FlatFile {
+            Self::convert_flat_file(storage, &mut metadata, &data_path, buffer_pool.clone())?;
+        }
+        if is_converted {
+            Self::convert_to_page_checksums(storage, &mut metadata, buffer_pool.clone())?;
+        }
+        let mut indexes = Vec::new();
+        for definition in &metadata.indexes {
+            let index_path = tables_dir.join(format!("{name}.{}.idx.bin", definition.name));
+            // An empty index would silently miss rows, and only the caller can compute its keys
+            if !storage.exists(&index_path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "File of index '{}' is missing: {}",
+                        definition.name,
+                        index_path.display()
+                    ),
+                ));
+            }
+            indexes.push(BTree::open(
+                &index_path,
+                metadata.page_layout(),
+                buffer_pool.clone(),
+            )?);
+        }
+        let mut table = Self {
+            heap_file: HeapFile::open(
+                &data_path,
+                metadata.data_page_layout(),
+                buffer_pool.clone(),
+            )?,
+            primary_key: Self::open_primary_key(tables_dir, &metadata, buffer_pool.clone())?,
+            column_store: Self::open_column_store(tables_dir, &metadata, buffer_pool.clone())?,
+            indexes,
+            metadata,
+            meta_path,
+            buffer_pool,
+            created_by: None,
+        };
+        if is_converted {
+            table.save_metadata()?;
+        }
+        Ok(table)
+    }
+
+    /// Opens the primary key index of a table, creating an empty one if the file does not exist
+    fn open_primary_key(
+        tables_dir: &Path,
+        metadata: &TableMetadata,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Option<BTree>> {
+        if !metadata.columns.iter().any(|column| column.is_primary_key) {
+            return Ok(None);
+        }
+        let index_path = tables_dir.join(format!("{}.idx.bin", metadata.name));
+        BTree::open(&index_path, metadata.page_layout(), buffer_pool).map(Some)
+    }
+
+    /// Opens the segments of a columnar table
+    fn open_column_store(
+        tables_dir: &Path,
+        metadata: &TableMetadata,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Option<ColumnStore>> {
+        if metadata.layout != TableLayout::Columnar {
+            return Ok(None);
+        }
+        ColumnStore::open(
+            tables_dir,
+            &metadata.name,
+            &metadata.columns,
+            metadata.data_page_layout(),
+            metadata.page_layout(),
+            buffer_pool,
+        )
+        .map(Some)
+    }
+
+    /// Rewrites the data file of a table created before slotted pages, whose rows were stored
+    /// as `[u32 length][bincode row]` records one after the other
+    fn convert_flat_file(
+        storage: &dyn Storage,
+        metadata: &mut TableMetadata,
+        data_path: &Path,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<()> {
+        let content = match storage.read(data_path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        let converted_path = data_path.with_extension("bin.converting");
+        storage.remove(&converted_path)?;
+        let mut heap_file = HeapFile::open(&converted_path, metadata.page_layout(), buffer_pool)?;
+        let mut position = 0;
+        while position < content.len() {
+            let length_bytes = content
+                .get(position..position + 4)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            let length = u32::from_le_bytes(length_bytes.try_into().unwrap()) as usize;
+            let record = content
+                .get(position + 4..position + 4 + length)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            heap_file.insert(record)?;
+            position += 4 + length;
+        }
+        drop(heap_file);
+        storage.rename(&converted_path, data_path)?;
+        log::info!(
+            "Converted table '{}' to slotted pages of {} bytes",
+            metadata.name,
+            metadata.page_size
+        );
+        metadata.storage = StorageFormat::SlottedPages;
+        Ok(())
+    }
+
+    /// Rewrites the files of a table stored before page checksums existed, in pages ending with
+    /// a checksum. Rows stored before row versions existed get a version seen by all
+    /// transactions. Rows move, so entries of the indexes are rewritten with the new addresses.
+    fn convert_to_page_checksums(
+        storage: &dyn Storage,
+        metadata: &mut TableMetadata,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<()> {
+        let tables_dir = Path::new(TABLES_DIRECTORY);
+        let old_layout = metadata.page_layout();
+        let new_layout = PageLayout::with_checksums(metadata.page_size);
+        let versioned = metadata.storage == StorageFormat::VersionedRows;
+        let data_path = tables_dir.join(format!("{}.data.bin", metadata.name));
+        let records = HeapFile::open(&data_path, old_layout, buffer_pool.clone())?.scan()?;
+        // Files are written next to the ones they replace, which may be left over by a crash
+        let converting_path = |path: &Path| {
+            let converting_path = path.with_extension("bin.converting");
+            storage.remove(&converting_path).map(|_| converting_path)
+        };
+        let mut heap_file = HeapFile::open(
+            &converting_path(&data_path)?,
+            new_layout,
+            buffer_pool.clone(),
+        )?;
+        let mut converted_files = vec![data_path];
+        let mut row_ids = HashMap::new();
+        for (row_id, record) in records {
+            let new_row_id = match versioned {
+                true => heap_file.insert(&record)?,
+                false => {
+                    let version = RowVersion::new(mvcc::FROZEN).to_bytes();
+                    heap_file.insert(&[&version, &record[..]].concat())?
+                }
+            };
+            row_ids.insert(row_id, new_row_id);
+        }
+        drop(heap_file);
+        // Without row versions, keys of the primary key have no row id, and those of secondary
+        // indexes may have one
+        let mut index_files = vec![(tables_dir.join(format!("{}.idx.bin", metadata.name)), None)];
+        for definition in &metadata.indexes {
+            let index_path =
+                tables_dir.join(format!("{}.{}.idx.bin", metadata.name, definition.name));
+            index_files.push((index_path, Some(definition.key_orders())));
+        }
+        for (index_path, orders) in index_files {
+            if !storage.exists(&index_path) {
+                continue;
+            }
+            let entries = BTree::open(&index_path, old_layout, buffer_pool.clone())?
+                .range(Bound::Unbounded, Bound::Unbounded)?;
+            let mut tree = BTree::open(
+                &converting_path(&index_path)?,
+                new_layout,
+                buffer_pool.clone(),
+            )?;
+            for (key, row_id) in entries {
+                let length = match &orders {
+                    _ if versioned => key.len().checked_sub(ROW_ID_SIZE),
+                    None => Some(key.len()),
+                    Some(orders) => index_key::encoded_length(&key, orders),
+                };
+                let (Some(length), Some(&row_id)) = (length, row_ids.get(&row_id)) else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} holds an invalid entry", index_path.display()),
+                    ));
+                };
+                tree.insert(&append_row_id(key[..length].to_vec(), row_id), row_id)?;
+            }
+            converted_files.push(index_path);
+        }
+        for path in converted_files {
+            storage.rename(&path.with_extension("bin.converting"), &path)?;
+        }
+        log::info!(
+            "Converted table '{}' to pages with checksums",
+            metadata.name
+        );
+        metadata.storage = StorageFormat::PageChecksums;
+        Ok(())
+    }
+
+    fn primary_key_orders(&self) -> Vec<KeyOrder> {
+        self.metadata
+            .columns
+            .iter()
+            .filter(|column| column.is_primary_key)
+            .map(|column| KeyOrder {
+                collation: column.collation,
+                descending: false,
+            })
+            .collect()
+    }
+
+    /// Encodes the leading values of a primary key, in the order of the index
+    fn primary_key_of(&self, values: &[Value]) -> Vec<u8> {
+        index_key::encode(values, &self.primary_key_orders())
+    }
+
+    fn index_path(&self, index_name: &str) -> PathBuf {
+        Path::new(TABLES_DIRECTORY).join(format!("{}.{index_name}.idx.bin", self.metadata.name))
+    }
+
+    fn index_position(&self, index_name: &str) -> Option<usize> {
+        self.metadata
+            .indexes
+            .iter()
+            .position(|index| index.name.eq_ignore_ascii_case(index_name))
+    }
+
+    fn find_index(&self, index_name: &str) -> io::Result<usize> {
+        self.index_position(index_name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Index '{index_name}' does not exist on table '{}'",
+                    self.metadata.name
+                ),
+            )
+        })
+    }
+
+    /// Encoded values of a unique index key, which start the key of every version holding them.
+    /// Keys holding a NULL never clash, so they have none.
+    fn unique_index_key(&self, position: usize, values: &[Value]) -> Option<Vec<u8>> {
+        let definition = &self.metadata.indexes[position];
+        (definition.unique && !values.contains(&Value::Null))
+            .then(|| index_key::encode(values, &definition.key_orders()))
+    }
+
+    /// Key of a row version in a secondary index: its encoded values, followed by its address
+    /// Adds a secondary index holding given entries, in a file which must not exist yet
+    fn add_index(
+        &mut self,
+        definition: IndexDefinition,
+        entries: &[(RowId, Vec<Value>)],
+    ) -> io::Result<()> {
+        let index_path = self.index_path(&definition.name);
+        let layout = self.metadata.page_layout();
+        let mut tree = BTree::open(&index_path, layout, self.buffer_pool.clone())?;
+        self.metadata.indexes.push(definition);
+        let position = self.metadata.indexes.len() - 1;
+        for (row_id, values) in entries {
+            let key = self.index_key(position, values, *row_id);
+            if let Err(error) = tree
+                .insert(&key, *row_id)
+                .and_then(|inserted| match inserted {
+                    true => Ok(()),
+                    false => Err(self.duplicate_index_key(position)),
+                })
+            {
+                self.metadata.indexes.pop();
+                return Err(error);
+            }
+        }
+        self.indexes.push(tree);
+        self.save_metadata()
+    }
+
+    fn index_key(&self, position: usize, values: &[Value], row_id: RowId) -> Vec<u8> {
+        let key = index_key::encode(values, &self.metadata.indexes[position].key_orders());
+        append_row_id(key, row_id)
+    }
+
+    fn duplicate_index_key(&self, position: usize) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "Duplicate key in unique index '{}'",
+                self.metadata.indexes[position].name
+            ),
+        )
+    }
+
+    /// Primary key of a row, if the table has one. Versions of the row are indexed under this key
+    /// followed by their address.
+    fn row_key(&self, row: &[Value]) -> Option<Vec<u8>> {
+        self.primary_key.as_ref()?;
+        let values: Vec<Value> = row
+            .iter()
+            .zip(&self.metadata.columns)
+            .filter(|(_, column)| column.is_primary_key)
+            .map(|(value, _)| value.clone())
+            .collect();
+        Some(self.primary_key_of(&values))
+    }
+
+    fn duplicate_key(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Duplicate primary key in table '{}'", self.metadata.name),
+        )
+    }
+
+    /// Builds a record: the row version, the encoded row, then the references to its values
+    /// stored in overflow pages if it has any
+    fn encode_record(
+        version: RowVersion,
+        row: &[Value],
+        overflow_values: &[OverflowValue],
+    ) -> io::Result<Vec<u8>> {
+        let mut record = version.to_bytes().to_vec();
+        bincode::encode_into_std_write(row, &mut record, config::standard())
+            .map_err(io::Error::other)?;
+        if !overflow_values.is_empty() {
+            bincode::encode_into_std_write(overflow_values, &mut record, config::standard())
+                .map_err(io::Error::other)?;
+        }
+        Ok(record)
+    }
+
+    fn decode_record(record: &[u8]) -> io::Result<(RowVersion, Vec<Value>, Vec<OverflowValue>)> {
+        let invalid_data = |error: bincode::error::DecodeError| {
+            io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+        };
+        let (version, row) = RowVersion::split_record(record)?;
+        let config = config::standard().with_limit::<ROW_DECODE_LIMIT>();
+        let (row, length): (Vec<Value>, usize) =
+            bincode::decode_from_slice(row, config).map_err(invalid_data)?;
+        let overflow_values = match &record[RowVersion::SIZE + length..] {
+            [] => Vec::new(),
+            references => {
+                bincode::decode_from_slice(references, config)
+                    .map_err(invalid_data)?
+                    .0
+            }
+        };
+        Ok((version, row, overflow_values))
+    }
+
+    /// Builds the record of a row version, moving its values too large to be kept inline to
+    /// overflow pages. Those are larger than a quarter of a page, so that a page still holds a
+    /// few rows. If the record still does not fit in a page, its largest values are moved as
+    /// well until it does.
+    fn store_record(&mut self, version: RowVersion, row: &[Value]) -> io::Result<Vec<u8>> {
+        if let Some(column_store) = &mut self.column_store {
+            let row_number = column_store.append(row)?;
+            return Ok([&version.to_bytes()[..], &row_number.to_le_bytes()].concat());
+        }
+        let mut row = row.to_vec();
+        let threshold = self.metadata.page_size / 4;
+        let mut overflow_values = store_large_values(&mut self.heap_file, &mut row, 0, threshold)?;
+        let mut record = Self::encode_record(version, &row, &overflow_values)?;
+        while record.len() > self.heap_file.record_capacity()
+            && let Some(position) = largest_inline_value(&row)
+        {
+            let value = &mut row[position..=position];
+            overflow_values.extend(store_large_values(&mut self.heap_file, value, position, 0)?);
+            record = Self::encode_record(version, &row, &overflow_values)?;
+        }
+        Ok(record)
+    }
+
+    /// Reads the row version held by a record, along with its values stored in overflow pages
+    fn load_record(&mut self, record: &[u8]) -> io::Result<(RowVersion, Vec<Value>)> {
+        if let Some(column_store) = &mut self.column_store {
+            let (version, row_number) = split_row_number(record)?;
+            return Ok((version, column_store.read_row(row_number)?));
+        }
+        let (version, mut row, overflow_values) = Self::decode_record(record)?;
+        load_large_values(&mut self.heap_file, &mut row, overflow_values)?;
+        Ok((version, row))
+    }
+
+    /// Inserts a version of a new row, created by given transaction, along with its primary key
+    /// and the keys of its secondary indexes
+    fn insert_row(
+        &mut self,
+        row: &[Value],
+        key: Option<&[u8]>,
+        index_values: &IndexValues,
+        created_by: TransactionId,
+    ) -> io::Result<RowId> {
+        self.check_unique_keys(key, index_values)?;
+        let record = self.store_record(RowVersion::new(created_by), row)?;
+        let row_id = self.heap_file.insert(&record)?;
+        self.insert_keys(row_id, key, index_values)?;
+        self.metadata.row_count += 1;
+        self.save_metadata()?;
+        Ok(row_id)
+    }
+
+    /// Makes sure that no live row holds the primary key or a key of a unique index of a new
+    /// version
+    fn check_unique_keys(
+        &mut self,
+        key: Option<&[u8]>,
+        index_values: &IndexValues,
+    ) -> io::Result<()> {
+        if let Some(key) = key
+            && self.find_live(None, key)?.is_some()
+        {
+            return Err(self.duplicate_key());
+        }
+        for (position, values) in index_values.iter().enumerate() {
+            if let Some(key) = self.unique_index_key(position, values)
+                && self.find_live(Some(position), &key)?.is_some()
+            {
+                return Err(self.duplicate_index_key(position));
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_keys(
+        &mut self,
+        row_id: RowId,
+        key: Option<&[u8]>,
+        index_values: &IndexValues,
+    ) -> io::Result<()> {
+        if let (Some(index), Some(key)) = (&mut self.primary_key, key) {
+            index.insert(&append_row_id(key.to_vec(), row_id), row_id)?;
+        }
+        for (position, values) in index_values.iter().enumerate() {
+            let key = self.index_key(position, values, row_id);
+            self.indexes[position].insert(&key, row_id)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the row version stored at given address
+    fn get_version(&mut self, row_id: RowId) -> io::Result<Option<(RowVersion, Vec<Value>)>> {
+        self.heap_file
+            .get(row_id)?
+            .map(|record| self.load_record(&record))
+            .transpose()
+    }
+
+    /// Live version of a row whose key starts with given encoded values, in the primary key when
+    /// `position` is `None` or else in a secondary index
+    fn find_live(&mut self, position: Option<usize>, key: &[u8]) -> io::Result<Option<RowId>> {
+        let index = match position {
+            None => match &mut self.primary_key {
+                Some(index) => index,
+                None => return Ok(None),
+            },
+            Some(position) => &mut self.indexes[position],
+        };
+        let last_row_id = RowId {
+            page: u32::MAX,
+            slot: u16::MAX,
+        };
+        let upper = append_row_id(key.to_vec(), last_row_id);
+        let entries = index.range(Bound::Included(key), Bound::Included(&upper))?;
+        for (_, row_id) in entries {
+            if let Some(record) = self.heap_file.get(row_id)?
+                && RowVersion::split_record(&record)?.0.is_live()
+            {
+                return Ok(Some(row_id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Marks a row version as deleted by given transaction, which fails if another transaction
+    /// already deleted it
+    fn mark_deleted(&mut self, row_id: RowId, deleted_by: TransactionId) -> io::Result<()> {
+        let record = self.heap_file.get(row_id)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Row {row_id} does not exist"),
+            )
+        })?;
+        let (mut version, row) = RowVersion::split_record(&record)?;
+        if !version.is_live() {
+            return Err(io::Error::other(format!(
+                "Row {row_id} of table '{}' was changed by a concurrent transaction",
+                self.metadata.name
+            )));
+        }
+        version.deleted_by = deleted_by;
+        // The record keeps its size, so it stays in place
+        let new_row_id = self
+            .heap_file
+            .update(row_id, &[&version.to_bytes(), row].concat())?;
+        if new_row_id != row_id {
+            return Err(io::Error::other(format!(
+                "Row {row_id} of table '{}' moved to {new_row_id} when marked as deleted",
+                self.metadata.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stores new versions of rows, given along with their keys, marking the versions they replace
+    /// as deleted by given transaction. Replaced versions are all marked first, so that rows can
+    /// swap keys.
+    fn update_rows(
+        &mut self,
+        updates: Vec<RowUpdate>,
+        transaction_id: TransactionId,
+    ) -> io::Result<Vec<RowId>> {
+        for update in &updates {
+            self.mark_deleted(update.row_id, transaction_id)?;
+        }
+        let mut row_ids = Vec::new();
+        for update in &updates {
+            self.check_unique_keys(update.key.as_deref(), &update.index_values)?;
+            let record = self.store_record(RowVersion::new(transaction_id), &update.row)?;
+            let row_id = self.heap_file.insert(&record)?;
+            self.insert_keys(row_id, update.key.as_deref(), &update.index_values)?;
+            row_ids.push(row_id);
+        }
+        Ok(row_ids)
+    }
+
+    fn delete_rows(&mut self, row_ids: &[RowId], deleted_by: TransactionId) -> io::Result<()> {
+        for &row_id in row_ids {
+            self.mark_deleted(row_id, deleted_by)?;
+        }
+        self.metadata.row_count = self.metadata.row_count.saturating_sub(row_ids.len() as u64);
+        self.save_metadata()
+    }
+
+    /// Removes a row version from the data file, along with its keys given its primary key and
+    /// index values
+    fn purge_version(
+        &mut self,
+        row_id: RowId,
+        key: Option<Vec<u8>>,
+        index_values: &IndexValues,
+    ) -> io::Result<()> {
+        if self.column_store.is_none()
+            && let Some(record) = self.heap_file.get(row_id)?
+        {
+            for overflow_value in Self::decode_record(&record)?.2 {
+                self.heap_file.delete_overflow(overflow_value.first_page)?;
+            }
+        }
+        self.heap_file.delete(row_id)?;
+        if let (Some(index), Some(key)) = (&mut self.primary_key, key) {
+            index.delete(&append_row_id(key, row_id))?;
+        }
+        for (position, values) in index_values.iter().enumerate() {
+            let key = self.index_key(position, values, row_id);
+            self.indexes[position].delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the live row versions of the table against its row count and constraints
+    fn check_rows(
+        &self,
+        versions: &[(RowId, RowVersion, Vec<Value>)],
+        report: &mut IntegrityReport,
+    ) {
+        let table_name = Some(self.metadata.name.as_str());
+        let columns = &self.metadata.columns;
+        let live_rows: Vec<(RowId, &[Value])> = versions
+            .iter()
+            .filter(|(_, version, _)| version.is_live())
+            .map(|(row_id, _, row)| (*row_id, row.as_slice()))
+            .collect();
+        if live_rows.len() as u64 != self.metadata.row_count {
+            let description = format!(
+                "counts {} row(s), but {} are stored",
+                self.metadata.row_count,
+                live_rows.len()
+            );
+            report.add(table_name, "metadata", description);
+        }
+        // Every set of columns whose values must be unique, along with its description
+        let mut unique_keys: Vec<(Vec<usize>, String)> = Vec::new();
+        let primary_key: Vec<usize> = (0..columns.len())
+            .filter(|&position| columns[position].is_primary_key)
+            .collect();
+        if !primary_key.is_empty() {
+            unique_keys.push((primary_key, String::from("primary key")));
+        }
+        for (position, column) in columns.iter().enumerate() {
+            if column.is_unique {
+                unique_keys.push((vec![position], format!("unique column '{}'", column.name)));
+            }
+        }
+        let mut used_keys = vec![HashSet::new(); unique_keys.len()];
+        for (row_id, row) in live_rows {
+            for (value, column) in row.iter().zip(columns) {
+                let description = if *value == Value::Null && !column.nullable {
+                    format!("row {row_id} holds NULL in column '{}'", column.name)
+                } else if !fits_type(value, &column.data_type) {
+                    format!(
+                        "row {row_id} holds {value} in column '{}' of type {:?}",
+                        column.name, column.data_type
+                    )
+                } else {
+                    continue;
+                };
+                report.add(table_name, "data file", description);
+            }
+            for ((positions, constraint), used_keys) in unique_keys.iter().zip(&mut used_keys) {
+                let values: Vec<Value> = positions
+                    .iter()
+                    .map(|&position| row[position].clone())
+                    .collect();
+                if values.contains(&Value::Null) {
+                    continue;
+                }
+                let orders: Vec<KeyOrder> = positions
+                    .iter()
+                    .map(|&position| KeyOrder {
+                        collation: columns[position].collation,
+                        descending: false,
+                    })
+                    .collect();
+                if !used_keys.insert(index_key::encode(&values, &orders)) {
+                    let description = format!(
+                        "live rows share the key ({}) of the {constraint}",
+                        join_values(&values)
+                    );
+                    report.add(table_name, "data file", description);
+                }
+            }
+        }
+    }
+
+    /// Compares the entries of the primary key, when `position` is `None`, or else of a
+    /// secondary index, with the ones expected for the stored row versions
+    fn check_index(
+        &mut self,
+        position: Option<usize>,
+        expected: Vec<(Vec<u8>, RowId)>,
+        report: &mut IntegrityReport,
+    ) {
+        let table_name = Some(self.metadata.name.as_str());
+        let (object, index) = match position {
+            None => (String::from("primary key"), self.primary_key.as_mut()),
+            Some(position) => (
+                format!("index '{}'", self.metadata.indexes[position].name),
+                self.indexes.get_mut(position),
+            ),
+        };
+        let Some(index) = index else {
+            return;
+        };
+        let entries = match index.range(Bound::Unbounded, Bound::Unbounded) {
+            Ok(entries) => entries,
+            Err(error) => {
+                report.add(table_name, &object, error.to_string());
+                return;
+            }
+        };
+        if index.len() != entries.len() as u64 {
+            let description = format!(
+                "counts {} entries, but holds {}",
+                index.len(),
+                entries.len()
+            );
+            report.add(table_name, &object, description);
+        }
+        let mut expected: HashMap<Vec<u8>, RowId> = expected.into_iter().collect();
+        for (key, row_id) in entries {
+            if expected.remove(&key) != Some(row_id) {
+                let description = format!(
+                    "holds an entry for row version {row_id}, which is missing or holds other values"
+                );
+                report.add(table_name, &object, description);
+            }
+        }
+        let mut missing: Vec<RowId> = expected.into_values().collect();
+        missing.sort();
+        for row_id in missing {
+            let description = format!("has no entry for row version {row_id}");
+            report.add(table_name, &object, description);
+        }
+    }
+
+    /// Reads the rows whose version is kept, along with their address. Only the columns flagged
+    /// in `columns` (and the ones past its end) of a columnar table are read, the others being
+    /// NULL, and its rows whose block holds no value within given bounds are skipped. Rows of
+    /// other tables are read whole.
+    fn scan_rows(
+        &mut self,
+        keep: impl Fn(RowVersion) -> bool,
+        columns: &[bool],
+        bounds: &[(usize, KeyBounds)],
+    ) -> io::Result<Vec<(RowId, Vec<Value>)>> {
+        let Some(column_store) = &mut self.column_store else {
+            return Ok(self
+                .scan_versions()?
+                .into_iter()
+                .filter(|(_, version, _)| keep(*version))
+                .map(|(row_id, _, row)| (row_id, row))
+                .collect());
+        };
+        let mut row_ids = Vec::new();
+        let mut row_numbers = Vec::new();
+        for (row_id, record) in self.heap_file.scan()? {
+            let (version, row_number) = split_row_number(&record)?;
+            if keep(version) {
+                row_ids.push(row_id);
+                row_numbers.push(row_number);
+            }
+        }
+        let rows = column_store.read_rows(&row_numbers, columns, bounds)?;
+        Ok(row_ids
+            .into_iter()
+            .zip(rows)
+            .filter_map(|(row_id, row)| Some((row_id, row?)))
+            .collect())
+    }
+
+    /// Reads every row version of the data file, along with its address
+    fn scan_versions(&mut self) -> io::Result<Vec<(RowId, RowVersion, Vec<Value>)>> {
+        self.heap_file
+            .scan()?
+            .into_iter()
+            .map(|(row_id, record)| {
+                let (version, row) = self.load_record(&record)?;
+                Ok((row_id, version, row))
+            })
+            .collect()
+    }
+
+    fn save_metadata(&mut self) -> io::Result<()> {
+        self.metadata.compression_ratio = self.heap_file.compression_ratio()?;
+        let ron = ron::ser::to_string_pretty(&self.metadata, Default::default())
+            .map_err(std::io::Error::other)?;
+        self.buffer_pool
+            .lock()
+            .unwrap()
+            .write_file(&self.meta_path, ron.into_bytes())
+    }
+}
+
+/// Reference from a record to one of its values stored in overflow pages, in place of which the
+/// row holds an empty value of the same type
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct OverflowValue {
+    pub position: u32,
+    pub first_page: u32,
+    pub length: u64,
+}
+
+/// Moves the texts, JSON documents and byte strings of more than `threshold` bytes to overflow
+/// pages of a heap file, leaving an empty value in their place. References count positions from `first_position`.
+pub fn store_large_values(
+    heap_file: &mut HeapFile,
+    values: &mut [Value],
+    first_position: usize,
+    threshold: usize,
+) -> io::Result<Vec<OverflowValue>> {
+    let mut overflow_values = Vec::new();
+    for (position, value) in (first_position..).zip(values.iter_mut()) {
+        let bytes = match value {
+            Value::Text(text) if text.len() > threshold => std::mem::take(text).into_bytes(),
+            Value::Json(bytes) | Value::Blob(bytes) if bytes.len() > threshold => {
+                std::mem::take(bytes)
+            }
+            _ => continue,
+        };
+        overflow_values.push(OverflowValue {
+            position: position as u32,
+            first_page: heap_file.insert_overflow(&bytes)?,
+            length: bytes.len() as u64,
+        });
+    }
+    Ok(overflow_values)
+}
+
+/// Position of the largest value which `store_large_values` can move to overflow pages, unless
+/// all of them are empty
+fn largest_inline_value(values: &[Value]) -> Option<usize> {
+    values
+        .iter()
+        .enumerate()
+        .filter_map(|(position, value)| match value {
+            Value::Text(text) if !text.is_empty() => Some((text.len(), position)),
+            Value::Json(bytes) | Value::Blob(bytes) if !bytes.is_empty() => {
+                Some((bytes.len(), position))
+            }
+            _ => None,
+        })
+        .max()
+        .map(|(_, position)| position)
+}
+
+/// Reads back the values moved to overflow pages by `store_large_values`
+pub fn load_large_values(
+    heap_file: &mut HeapFile,
+    values: &mut [Value],
+    overflow_values: Vec<OverflowValue>,
+) -> io::Result<()> {
+    for overflow_value in overflow_values {
+        let bytes =
+            heap_file.get_overflow(overflow_value.first_page, overflow_value.length as usize)?;
+        match values.get_mut(overflow_value.position as usize) {
+            Some(Value::Text(text)) => {
+                *text = String::from_utf8(bytes)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            }
+            Some(Value::Json(stored) | Value::Blob(stored)) => *stored = bytes,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Overflow page {} holds a value of no text, JSON or BLOB column",
+                        overflow_value.first_page
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// New version of a stored row
+struct RowUpdate {
+    row_id: RowId,
+    row: Vec<Value>,
+    key: Option<Vec<u8>>,
+    index_values: IndexValues,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Value {
+    Integer(i64),
+    Text(String),
+    Real(f64),
+    Null,
+    Bool(bool),
+    /// JSON document, kept in the compact binary form produced by `utils::json`
+    Json(Vec<u8>),
+    Uuid([u8; 16]),
+    /// Label of a user-defined enumerated type
+    Enum(EnumValue),
+    Array(Vec<Value>),
+    Blob(Vec<u8>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Encode, Decode)]
+pub struct EnumValue {
+    pub type_name: String,
+    pub label: String,
+    /// Rank of the label in its type, see `EnumLabel::sort_order`
+    pub sort_order: f64,
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Integer(integer) => write!(f, "{integer}"),
+            Value::Text(text) => write!(f, "{text}"),
+            Value::Real(real) => write!(f, "{real}"),
+            Value::Null => write!(f, "NULL"),
+            Value::Bool(boolean) => write!(f, "{boolean}"),
+            Value::Json(bytes) => match crate::utils::json::to_text(bytes) {
+                Ok(text) => write!(f, "{text}"),
+                Err(_) => write!(f, "<corrupted json>"),
+            },
+            Value::Uuid(bytes) => write!(f, "{}", uuid::Uuid::from_bytes(*bytes).hyphenated()),
+            Value::Blob(bytes) => {
+                write!(f, "\\x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+            Value::Enum(enum_value) => write!(f, "{}", enum_value.label),
+            Value::Array(elements) => {
+                let elements: Vec<Option<String>> = elements
+                    .iter()
+                    .map(|element| match element {
+                        Value::Null => None,
+                        element => Some(element.to_string()),
+                    })
+                    .collect();
+                let text = crate::utils::array::format_text(elements.iter().map(Option::as_deref));
+                write!(f, "{text}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_directory::TempDirectory;
+
+    /// Nullable column without constraints
+    fn column(name: &str, data_type: DataType) -> Column {
+        Column {
+            name: String::from(name),
+            data_type,
+            values: Vec::new(),
+            is_primary_key: false,
+            is_unique: false,
+            nullable: true,
+            default: None,
+            collation: Collation::default(),
+        }
+    }
+
+    fn primary_key(name: &str) -> Column {
+        Column {
+            is_primary_key: true,
+            nullable: false,
+            ..column(name, DataType::Integer)
+        }
+    }
+
+    #[test]
+    fn test_enum_labels_keep_declaration_order() {
+        let mut status = EnumType::new("status", vec![String::from("a"), String::from("c")]);
+        assert!(status.add_label("b", Some(&LabelPosition::Before(String::from("c")))));
+        assert!(status.add_label("first", Some(&LabelPosition::Before(String::from("a")))));
+        assert!(status.add_label("last", None));
+        assert!(!status.add_label("x", Some(&LabelPosition::After(String::from("missing")))));
+        let mut labels = status.labels.clone();
+        labels.sort_by(|left, right| left.sort_order.total_cmp(&right.sort_order));
+        let names: Vec<&str> = labels.iter().map(|label| label.name.as_str()).collect();
+        assert_eq!(names, ["first", "a", "b", "c", "last"]);
+        // Existing labels keep their position, so that stored rows stay valid
+        assert_eq!(status.position_of("c"), Some(1));
+    }
+
+    #[test]
+    fn test_enum_types_are_committed_and_rolled_back_with_transactions() {
+        let mut database = Database::create(storage::MEMORY_PATH, "test", 16).unwrap();
+        let session = database.open_session();
+        database.use_session(session);
+        let columns = vec![primary_key("id")];
+        database
+            .create_table("t", columns, TableLayout::Rows, None)
+            .unwrap();
+        database.commit().unwrap();
+        let mood = EnumType::new("mood", vec![String::from("happy")]);
+        database.create_enum_type(mood.clone()).unwrap();
+        database.commit().unwrap();
+
+        // Rolling back an unrelated transaction keeps the committed type
+        database.begin();
+        database
+            .insert_row("t", vec![Value::Integer(1)], &Vec::new())
+            .unwrap();
+        database.rollback().unwrap();
+        assert!(database.enum_type("mood").is_some());
+
+        database.begin();
+        database
+            .create_enum_type(EnumType::new("color", vec![String::from("red")]))
+            .unwrap();
+        database.rollback().unwrap();
+        assert!(database.enum_type("color").is_none());
+
+        database.begin();
+        let mut changed_mood = mood;
+        assert!(changed_mood.add_label("sad", None));
+        database.update_enum_type(changed_mood).unwrap();
+        database.rollback().unwrap();
+        assert_eq!(database.enum_type("mood").unwrap().labels.len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_column_names_are_refused() {
+        let mut database = Database::create(storage::MEMORY_PATH, "test", 16).unwrap();
+        let session = database.open_session();
+        database.use_session(session);
+        let columns = vec![column("a", DataType::Integer), column("A", DataType::Text)];
+        let error = database
+            .create_table("y", columns, TableLayout::Rows, None)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(database.get_table("y").is_err());
+    }
+
+    #[test]
+    fn test_integrity_check_reports_every_problem() {
+        let mut database = Database::create(storage::MEMORY_PATH, "test", 16).unwrap();
+        let session = database.open_session();
+        database.use_session(session);
+        let columns = vec![primary_key("id"), column("n", DataType::Integer)];
+        database
+            .create_table("t", columns, TableLayout::Rows, None)
+            .unwrap();
+        for id in 1..=3 {
+            let row = vec![Value::Integer(id), Value::Null];
+            database.insert_row("t", row, &Vec::new()).unwrap();
+        }
+        database.commit().unwrap();
+        let no_indexes = |_: &str, _: &[Value]| Ok(Vec::new());
+        let report = database.check_integrity(&no_indexes).unwrap();
+        assert_eq!((report.tables, report.row_versions), (1, 3));
+        assert!(report.problems.is_empty());
+
+        // The second row takes the key of the first one, bypassing the primary key
+        let table = database.get_table_mut("t").unwrap();
+        table.metadata.row_count = 4;
+        let row = [Value::Integer(1), Value::Text(String::from("x"))];
+        let record = Table::encode_record(RowVersion::new(mvcc::FROZEN), &row, &[]).unwrap();
+        let row_id = RowId { page: 0, slot: 1 };
+        assert_eq!(table.heap_file.update(row_id, &record).unwrap(), row_id);
+        let unreferenced_file = Path::new("tables/old.data.bin");
+        database.storage.write(unreferenced_file, &[]).unwrap();
+        let problems: Vec<(Option<String>, String)> = database
+            .check_integrity(&no_indexes)
+            .unwrap()
+            .problems
+            .into_iter()
+            .map(|problem| (problem.table_name, problem.description))
+            .collect();
+        let table_problem = |description: &str| (Some(String::from("t")), description.to_string());
+        assert_eq!(
+            problems,
+            [
+                (None, String::from("is referenced by no table or index")),
+                table_problem("counts 4 row(s), but 3 are stored"),
+                table_problem("row (0,1) holds x in column 'n' of type Integer"),
+                table_problem("live rows share the key (1) of the primary key"),
+                table_problem(
+                    "holds an entry for row version (0,1), which is missing or holds other values"
+                ),
+                table_problem("has no entry for row version (0,1)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_single_file_database_converts_to_a_directory() {
+        let directory = TempDirectory::new("single-file");
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let mut database =
+            Database::create_with_layout(&path("db"), "test", 4, StorageLayout::SingleFile)
+                .unwrap();
+        let session = database.open_session();
+        database.use_session(session);
+        let columns = vec![primary_key("id")];
+        database
+            .create_table("t", columns, TableLayout::Rows, None)
+            .unwrap();
+        // Spans more pages than the buffer pool holds
+        for id in 0..500 {
+            database
+                .insert_row("t", vec![Value::Integer(id)], &Vec::new())
+                .unwrap();
+        }
+        database.commit().unwrap();
+        database.begin();
+        database
+            .insert_row("t", vec![Value::Integer(500)], &Vec::new())
+            .unwrap();
+        database.rollback().unwrap();
+        drop(database);
+        assert!(directory.join("db").is_file());
+
+        let ids = |path: &str| {
+            let mut database = Database::open_read_only(path, 4).unwrap();
+            let session = database.open_session();
+            database.use_session(session);
+            let mut ids: Vec<i64> = database
+                .read_all_rows("t")
+                .unwrap()
+                .into_iter()
+                .map(|row| match row[..] {
+                    [Value::Integer(id)] => id,
+                    _ => panic!("unexpected row {row:?}"),
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+        let expected: Vec<i64> = (0..500).collect();
+        assert_eq!(ids(&path("db")), expected);
+        let layout = storage::convert(&directory.join("db"), &directory.join("dir")).unwrap();
+        assert_eq!(layout, StorageLayout::Directory);
+        assert!(directory.join("dir/tables/t.idx.bin").is_file());
+        assert_eq!(ids(&path("dir")), expected);
+        storage::convert(&directory.join("dir"), &directory.join("copy")).unwrap();
+        assert_eq!(ids(&path("copy")), expected);
+        assert!(storage::convert(&directory.join("dir"), &directory.join("copy")).is_err());
+    }
+
+    #[test]
+    fn test_older_formats_are_upgraded_and_newer_ones_refused() {
+        let directory = TempDirectory::new("format-version");
+        let path = directory.join("db");
+        let path_name = path.to_str().unwrap();
+        drop(Database::create(path_name, "test", 4).unwrap());
+        let metadata_path = path.join(METADATA_FILE);
+        let set_version = |version: &str| {
+            let content = std::fs::read_to_string(&metadata_path).unwrap();
+            let content = content.replace(
+                &format!("version: {FORMAT_VERSION},"),
+                &format!("version: {version},"),
+            );
+            std::fs::write(&metadata_path, content).unwrap();
+        };
+
+        set_version("\"1.0\"");
+        let error = Database::open_read_only(path_name, 4).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        drop(Database::create(path_name, "test", 4).unwrap());
+        let backup = directory.join("db.v1.backup");
+        assert!(backup.join(METADATA_FILE).exists());
+        assert!(
+            std::fs::read_to_string(backup.join(METADATA_FILE))
+                .unwrap()
+                .contains("version: \"1.0\",")
+        );
+        drop(Database::open_read_only(path_name, 4).unwrap());
+
+        set_version(&(FORMAT_VERSION + 1).to_string());
+        let error = Database::create(path_name, "test", 4).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
 }
@@ -0,0 +1,613 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::utils::buffer_pool::{BufferPool, FileId, PageId};
+use crate::utils::page::{self, OVERFLOW_HEADER_SIZE, PageLayout, SLOT_SIZE, SlottedPage};
+
+/// Address of a row in a heap file. It does not change when other rows are inserted, updated or
+/// deleted, but it can change when the row itself grows too large for its page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RowId {
+    pub page: u32,
+    pub slot: u16,
+}
+
+impl std::fmt::Display for RowId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({},{})", self.page, self.slot)
+    }
+}
+
+/// Bytes kept free for every record of a page of a compressed file when inserting a record.
+/// Records then keep their place when updated with a record of the same size, such as when their
+/// version is marked as deleted, even though it may compress a little worse.
+const RECORD_RESERVE: usize = 8;
+
+/// Bytes an overflow page of a compressed file may leave unused, which saves compressing it
+/// again to find exactly how much of a value fits
+const OVERFLOW_FILL_SLACK: usize = 64;
+
+/// Bytes held by a page of a compressed file, which are not tracked for other files
+fn held_bytes(layout: PageLayout, content: &[u8]) -> usize {
+    match layout.is_compressed() {
+        true => page::used_bytes(content),
+        false => 0,
+    }
+}
+
+/// File made of fixed-size slotted pages, holding records in no particular order, along with
+/// the overflow pages of values too large for a record. Its pages are read and written through
+/// the buffer pool of the database, which checks them when they are read: a damaged page only
+/// fails the operations reading it.
+pub struct HeapFile {
+    buffer_pool: Arc<Mutex<BufferPool>>,
+    file_id: FileId,
+    layout: PageLayout,
+    /// Whether each page was read since the file was opened. The following fields only describe
+    /// the pages read.
+    known: Vec<bool>,
+    /// Free bytes of every page, used to find where a new record fits without reading pages
+    /// again. Overflow pages have none, nor do compressed pages which refused a record since
+    /// they last changed.
+    free_space: Vec<usize>,
+    overflow_pages: HashSet<u32>,
+    /// Bytes held by every page, see `held_bytes`
+    held: Vec<usize>,
+}
+
+impl HeapFile {
+    pub fn open(
+        path: &Path,
+        layout: PageLayout,
+        buffer_pool: Arc<Mutex<BufferPool>>,
+    ) -> io::Result<Self> {
+        page::check_page_size(layout.page_size)?;
+        let page_size = layout.page_size;
+        let file_id = buffer_pool.lock().unwrap().open_file(path, layout)?;
+        let file_size = buffer_pool.lock().unwrap().file_size(file_id)? as usize;
+        if !file_size.is_multiple_of(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Size of {} ({file_size} bytes) is not a multiple of the page size",
+                    path.display()
+                ),
+            ));
+        }
+        let page_count = file_size / page_size;
+        Ok(HeapFile {
+            buffer_pool,
+            file_id,
+            layout,
+            known: vec![false; page_count],
+            free_space: vec![0; page_count],
+            overflow_pages: HashSet::new(),
+            held: vec![0; page_count],
+        })
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.free_space.len() as u32
+    }
+
+    /// Bytes held by the pages of a compressed file, to the size of the file
+    pub fn compression_ratio(&mut self) -> io::Result<Option<f64>> {
+        if !self.layout.is_compressed() || self.page_count() == 0 {
+            return Ok(None);
+        }
+        self.read_usage()?;
+        let file_size = self.page_count() as usize * self.layout.page_size;
+        Ok(Some(
+            self.held.iter().sum::<usize>() as f64 / file_size as f64,
+        ))
+    }
+
+    /// Stores a record in the first page having enough free space, or in a new page
+    pub fn insert(&mut self, record: &[u8]) -> io::Result<RowId> {
+        self.check_record_size(record)?;
+        let mut candidate = None;
+        for page_number in 0..self.page_count() {
+            self.read_usage_of(page_number)?;
+            if self.free_space[page_number as usize] >= record.len() + SLOT_SIZE {
+                candidate = Some(page_number as usize);
+                break;
+            }
+        }
+        // A page may still refuse the record if its free space is fragmented across slots
+        if let Some(page_number) = candidate
+            && let Some(slot) =
+                self.write_page_if_fits(page_number as u32, RECORD_RESERVE, |page| {
+                    page.insert(record)
+                })?
+        {
+            return Ok(RowId {
+                page: page_number as u32,
+                slot,
+            });
+        }
+        let mut page = SlottedPage::new(self.layout.content_size());
+        let slot = page
+            .insert(record)
+            .expect("record size was checked against an empty page");
+        if !self.layout.fits(page.bytes(), 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Row of {} bytes does not fit in a page of {} bytes once compressed",
+                    record.len(),
+                    self.layout.page_size
+                ),
+            ));
+        }
+        let page_number = self.page_count();
+        self.known.push(true);
+        self.free_space.push(page.free_space());
+        self.held.push(held_bytes(self.layout, page.bytes()));
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.create(self.page_id(page_number), page.into_bytes())?;
+        buffer_pool.unpin(frame, false);
+        Ok(RowId {
+            page: page_number,
+            slot,
+        })
+    }
+
+    pub fn get(&mut self, row_id: RowId) -> io::Result<Option<Vec<u8>>> {
+        if row_id.page >= self.page_count() {
+            return Ok(None);
+        }
+        self.read_usage_of(row_id.page)?;
+        if self.overflow_pages.contains(&row_id.page) {
+            return Ok(None);
+        }
+        self.read_page(row_id.page, |page| {
+            page.get(row_id.slot).map(<[u8]>::to_vec)
+        })
+    }
+
+    /// Replaces a record, in place when its page has room for the new version. Otherwise the
+    /// record moves to another page, and its new address is returned.
+    pub fn update(&mut self, row_id: RowId, record: &[u8]) -> io::Result<RowId> {
+        self.check_record_size(record)?;
+        self.check_row_exists(row_id)?;
+        let updated = self.write_page_if_fits(row_id.page, 0, |page| {
+            page.update(row_id.slot, record).then_some(())
+        })?;
+        if updated.is_some() {
+            return Ok(row_id);
+        }
+        self.write_page(row_id.page, |page| page.delete(row_id.slot))?;
+        self.insert(record)
+    }
+
+    pub fn delete(&mut self, row_id: RowId) -> io::Result<()> {
+        self.check_row_exists(row_id)?;
+        self.write_page(row_id.page, |page| page.delete(row_id.slot))?;
+        Ok(())
+    }
+
+    /// Reads every record of the file, page after page
+    pub fn scan(&mut self) -> io::Result<Vec<(RowId, Vec<u8>)>> {
+        let mut records = Vec::new();
+        for page_number in 0..self.page_count() {
+            records.extend(self.scan_page(page_number)?);
+        }
+        Ok(records)
+    }
+
+    /// Reads every record of a page, overflow pages holding none
+    pub fn scan_page(&mut self, page_number: u32) -> io::Result<Vec<(RowId, Vec<u8>)>> {
+        self.read_usage_of(page_number)?;
+        if self.overflow_pages.contains(&page_number) {
+            return Ok(Vec::new());
+        }
+        self.read_page(page_number, |page| {
+            page.records()
+                .map(|(slot, record)| {
+                    let row_id = RowId {
+                        page: page_number,
+                        slot,
+                    };
+                    (row_id, record.to_vec())
+                })
+                .collect()
+        })
+    }
+
+    /// Stores a value in a chain of overflow pages, returning the first one. Pages left empty by
+    /// deleted records are used before new ones. Pages of a compressed file hold as much of the
+    /// value as fits once compressed.
+    pub fn insert_overflow(&mut self, value: &[u8]) -> io::Result<u32> {
+        self.read_usage()?;
+        let empty_page = SlottedPage::new(self.layout.content_size()).free_space();
+        let empty_pages: Vec<u32> = (0..self.page_count())
+            .filter(|&page_number| self.free_space[page_number as usize] == empty_page)
+            .collect();
+        let mut free_pages = empty_pages.into_iter().chain(self.page_count()..);
+        let first_page = free_pages.next().expect("page numbers never run out");
+        let mut page_number = first_page;
+        let mut rest = value;
+        // An empty value still gets a page, so that every value has a chain
+        loop {
+            let (mut page, mut length) = self.fill_overflow_page(rest, None);
+            let mut next = None;
+            if length < rest.len() {
+                // The next page must hold at least a byte
+                next = free_pages.next();
+                (page, length) = self.fill_overflow_page(&rest[..rest.len() - 1], next);
+            }
+            if page_number < self.page_count() {
+                self.write_bytes(page_number, |bytes| bytes.copy_from_slice(&page))?;
+                self.free_space[page_number as usize] = 0;
+            } else {
+                self.known.push(true);
+                self.free_space.push(0);
+                self.held.push(held_bytes(self.layout, &page));
+                let mut buffer_pool = self.buffer_pool.lock().unwrap();
+                let frame = buffer_pool.create(self.page_id(page_number), page)?;
+                buffer_pool.unpin(frame, false);
+            }
+            self.overflow_pages.insert(page_number);
+            rest = &rest[length..];
+            match next {
+                Some(next) => page_number = next,
+                None => return Ok(first_page),
+            }
+        }
+    }
+
+    /// Builds an overflow page holding the longest start of `value` which fits in it, give or
+    /// take `OVERFLOW_FILL_SLACK` bytes, returning it along with the length of that start
+    fn fill_overflow_page(&self, value: &[u8], next: Option<u32>) -> (Vec<u8>, usize) {
+        let content_size = self.layout.content_size();
+        let page = |length: usize| page::overflow_page(content_size, &value[..length], next);
+        let longest = value.len().min(content_size - OVERFLOW_HEADER_SIZE);
+        if self.layout.fits(&page(longest), 0) {
+            return (page(longest), longest);
+        }
+        // Compressed pages store at least this many bytes, even when they do not compress
+        let mut fitting = longest.min(self.layout.guaranteed_content_size() - OVERFLOW_HEADER_SIZE);
+        let mut too_long = longest;
+        if fitting < longest && !self.layout.fits(&page(fitting + 1), 0) {
+            too_long = fitting + 1;
+        }
+        while too_long - fitting > OVERFLOW_FILL_SLACK {
+            let middle = (fitting + too_long) / 2;
+            match self.layout.fits(&page(middle), 0) {
+                true => fitting = middle,
+                false => too_long = middle,
+            }
+        }
+        (page(fitting), fitting)
+    }
+
+    /// Reads a value of given length from the chain of overflow pages starting at given page
+    pub fn get_overflow(&mut self, first_page: u32, length: usize) -> io::Result<Vec<u8>> {
+        let mut value = Vec::with_capacity(length);
+        let mut next = Some(first_page);
+        while let Some(page_number) = next {
+            self.check_overflow_page(page_number)?;
+            next = self.read_bytes(page_number, |bytes| {
+                page::read_overflow_page(bytes).map(|(chunk, next)| {
+                    value.extend_from_slice(chunk);
+                    next
+                })
+            })??;
+            if value.len() > length {
+                break;
+            }
+        }
+        if value.len() != length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Overflow chain starting at page {first_page} does not hold a value of {length} bytes"
+                ),
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Frees the chain of overflow pages starting at given page, whose pages can then hold
+    /// records
+    pub fn delete_overflow(&mut self, first_page: u32) -> io::Result<()> {
+        let content_size = self.layout.content_size();
+        let mut next = Some(first_page);
+        while let Some(page_number) = next {
+            self.check_overflow_page(page_number)?;
+            next = self.read_bytes(page_number, |bytes| {
+                page::read_overflow_page(bytes).map(|(_, next)| next)
+            })??;
+            let empty_page = SlottedPage::new(content_size);
+            self.write_bytes(page_number, |bytes| {
+                bytes.copy_from_slice(empty_page.bytes())
+            })?;
+            self.free_space[page_number as usize] = empty_page.free_space();
+            self.overflow_pages.remove(&page_number);
+        }
+        Ok(())
+    }
+
+    /// Makes sure that a chain only goes through overflow pages, which also stops chains
+    /// looping back to a page already freed or read
+    fn check_overflow_page(&mut self, page_number: u32) -> io::Result<()> {
+        if page_number < self.page_count() {
+            self.read_usage_of(page_number)?;
+        }
+        if self.overflow_pages.contains(&page_number) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Page {page_number} is not an overflow page"),
+        ))
+    }
+
+    /// Size of the largest record a page holds
+    pub fn record_capacity(&self) -> usize {
+        SlottedPage::new(self.layout.content_size()).free_space() - SLOT_SIZE
+    }
+
+    fn check_record_size(&self, record: &[u8]) -> io::Result<()> {
+        if record.len() <= self.record_capacity() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Row of {} bytes does not fit in a page of {} bytes",
+                record.len(),
+                self.layout.page_size
+            ),
+        ))
+    }
+
+    fn check_row_exists(&mut self, row_id: RowId) -> io::Result<()> {
+        if self.get(row_id)?.is_some() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Row {row_id} does not exist"),
+        ))
+    }
+
+    /// Reads every page not read yet, see `read_usage_of`
+    fn read_usage(&mut self) -> io::Result<()> {
+        for page_number in 0..self.page_count() {
+            self.read_usage_of(page_number)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the free space, kind and usage of a page, unless they are known already
+    fn read_usage_of(&mut self, page_number: u32) -> io::Result<()> {
+        if self.known[page_number as usize] {
+            return Ok(());
+        }
+        let layout = self.layout;
+        let (is_overflow_page, held) = self.read_bytes(page_number, |bytes| {
+            (page::is_overflow_page(bytes), held_bytes(layout, bytes))
+        })?;
+        let free_space = match is_overflow_page {
+            true => {
+                self.overflow_pages.insert(page_number);
+                0
+            }
+            false => self.read_page(page_number, |page| page.free_space())?,
+        };
+        self.held[page_number as usize] = held;
+        self.free_space[page_number as usize] = free_space;
+        self.known[page_number as usize] = true;
+        Ok(())
+    }
+
+    fn page_id(&self, page_number: u32) -> PageId {
+        PageId {
+            file: self.file_id,
+            page: page_number,
+        }
+    }
+
+    /// Runs `action` on the content of a page pinned in the buffer pool
+    fn read_bytes<T>(
+        &mut self,
+        page_number: u32,
+        action: impl FnOnce(&[u8]) -> T,
+    ) -> io::Result<T> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(page_number))?;
+        let result = action(buffer_pool.data(frame));
+        buffer_pool.unpin(frame, false);
+        Ok(result)
+    }
+
+    /// Runs `action` on the content of a page pinned in the buffer pool, which is then marked
+    /// dirty
+    fn write_bytes<T>(
+        &mut self,
+        page_number: u32,
+        action: impl FnOnce(&mut [u8]) -> T,
+    ) -> io::Result<T> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(page_number))?;
+        let result = action(buffer_pool.data_mut(frame));
+        self.held[page_number as usize] = held_bytes(self.layout, buffer_pool.data(frame));
+        buffer_pool.unpin(frame, true);
+        Ok(result)
+    }
+
+    /// Runs `action` on a page pinned in the buffer pool
+    fn read_page<T>(
+        &mut self,
+        page_number: u32,
+        action: impl FnOnce(&SlottedPage<&[u8]>) -> T,
+    ) -> io::Result<T> {
+        let result = self.read_bytes(page_number, |bytes| {
+            SlottedPage::from_bytes(bytes).map(|page| action(&page))
+        })?;
+        result.map_err(|error| io::Error::new(error.kind(), format!("Page {page_number}: {error}")))
+    }
+
+    /// Runs `action` on a page like `write_page`. A compressed page must then still fit in the
+    /// file with `reserve` more bytes per record: otherwise its change is undone, and it is not
+    /// tried again for new records until it changes.
+    fn write_page_if_fits<T>(
+        &mut self,
+        page_number: u32,
+        reserve: usize,
+        action: impl FnOnce(&mut SlottedPage<&mut [u8]>) -> Option<T>,
+    ) -> io::Result<Option<T>> {
+        let layout = self.layout;
+        let mut refused = false;
+        let result = self.write_page(page_number, |page| {
+            let previous = layout.is_compressed().then(|| page.bytes().to_vec());
+            let result = action(page)?;
+            if let Some(previous) = previous
+                && !layout.fits(page.bytes(), reserve * page.slot_count() as usize)
+            {
+                page.restore(&previous);
+                refused = true;
+                return None;
+            }
+            Some(result)
+        })?;
+        if refused {
+            self.free_space[page_number as usize] = 0;
+        }
+        Ok(result)
+    }
+
+    /// Runs `action` on a page pinned in the buffer pool, which is then marked dirty
+    fn write_page<T>(
+        &mut self,
+        page_number: u32,
+        action: impl FnOnce(&mut SlottedPage<&mut [u8]>) -> T,
+    ) -> io::Result<T> {
+        let mut buffer_pool = self.buffer_pool.lock().unwrap();
+        let frame = buffer_pool.fetch(self.page_id(page_number))?;
+        let result = SlottedPage::from_bytes(buffer_pool.data_mut(frame)).map(|mut page| {
+            let result = action(&mut page);
+            self.free_space[page_number as usize] = page.free_space();
+            self.held[page_number as usize] = held_bytes(self.layout, page.bytes());
+            result
+        });
+        buffer_pool.unpin(frame, result.is_ok());
+        result.map_err(|error| io::Error::new(error.kind(), format!("Page {page_number}: {error}")))
+    }
+}
+
+impl Drop for HeapFile {
+    fn drop(&mut self) {
+        if let Err(error) = self.buffer_pool.lock().unwrap().close_file(self.file_id) {
+            log::error!("Could not write back the pages of a heap file: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::Compression;
+    use crate::utils::page::MIN_PAGE_SIZE;
+    use crate::utils::storage::{MemoryStorage, Storage};
+
+    #[test]
+    fn test_large_values_are_chained_and_their_pages_reused() {
+        let storage = Arc::new(MemoryStorage::default());
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage, 16)));
+        let layout = PageLayout::with_checksums(MIN_PAGE_SIZE);
+        let path = Path::new("t.data.bin");
+        let mut heap_file = HeapFile::open(path, layout, buffer_pool.clone()).unwrap();
+        let row_id = heap_file.insert(b"small record").unwrap();
+        let value: Vec<u8> = (0..5000).map(|byte| byte as u8).collect();
+        let first_page = heap_file.insert_overflow(&value).unwrap();
+        let page_count = heap_file.page_count();
+        assert_eq!(page_count, 11);
+
+        // Overflow pages hold no records, even once the file is opened again
+        drop(heap_file);
+        let mut heap_file = HeapFile::open(path, layout, buffer_pool).unwrap();
+        assert_eq!(heap_file.scan().unwrap().len(), 1);
+        assert_eq!(
+            heap_file.get_overflow(first_page, value.len()).unwrap(),
+            value
+        );
+        assert!(heap_file.get_overflow(first_page, 100).is_err());
+
+        heap_file.delete_overflow(first_page).unwrap();
+        heap_file.delete(row_id).unwrap();
+        assert!(heap_file.get_overflow(first_page, value.len()).is_err());
+        let first_page = heap_file.insert_overflow(&value).unwrap();
+        assert_eq!(heap_file.page_count(), page_count);
+        assert_eq!(first_page, 0);
+        assert_eq!(
+            heap_file.get_overflow(first_page, value.len()).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn test_overflow_pages_of_compressed_files_are_compressed() {
+        let storage = Arc::new(MemoryStorage::default());
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage, 16)));
+        let layout = PageLayout {
+            compression: Compression::Lz4,
+            ..PageLayout::with_checksums(4096)
+        };
+        let path = Path::new("t.data.bin");
+        let mut heap_file = HeapFile::open(path, layout, buffer_pool).unwrap();
+        let value = b"some repetitive text ".repeat(10_000);
+        let first_page = heap_file.insert_overflow(&value).unwrap();
+        let file_size = heap_file.page_count() as usize * layout.page_size;
+        assert!(file_size < value.len() / 4);
+        assert!(heap_file.compression_ratio().unwrap().unwrap() > 4.0);
+        assert_eq!(
+            heap_file.get_overflow(first_page, value.len()).unwrap(),
+            value
+        );
+
+        // Values which do not compress take about as many bytes as they hold
+        let mut state = 1u64;
+        let value: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let page_count = heap_file.page_count();
+        let first_page = heap_file.insert_overflow(&value).unwrap();
+        assert_eq!(heap_file.page_count() - page_count, 5);
+        assert_eq!(
+            heap_file.get_overflow(first_page, value.len()).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn test_damaged_pages_only_fail_operations_reading_them() {
+        let storage = Arc::new(MemoryStorage::default());
+        let layout = PageLayout::with_checksums(MIN_PAGE_SIZE);
+        let path = Path::new("t.data.bin");
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage.clone(), 16)));
+        let mut heap_file = HeapFile::open(path, layout, buffer_pool).unwrap();
+        let record = [1; 300];
+        let damaged = heap_file.insert(&record).unwrap();
+        let intact = heap_file.insert(&record).unwrap();
+        assert_ne!(damaged.page, intact.page);
+        drop(heap_file);
+        let mut file = storage.open_file(path).unwrap();
+        file.write_at(100, &[0xff]).unwrap();
+
+        let buffer_pool = Arc::new(Mutex::new(BufferPool::new(storage, 16)));
+        let mut heap_file = HeapFile::open(path, layout, buffer_pool).unwrap();
+        assert_eq!(heap_file.get(intact).unwrap().unwrap(), record);
+        let error = heap_file.get(damaged).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(heap_file.scan().is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use std::ops::Bound;
+
+use crate::utils::collation::Collation;
+use crate::utils::file_handler::Value;
+
+// Values are encoded so that comparing two keys byte by byte orders them like their values, which
+// lets B+tree nodes compare keys without decoding them. Every value starts with a tag, ordered
+// like the ranks of types in ORDER BY, NULL sorting last. Texts and byte strings are escaped so
+// that their terminator sorts before any byte they contain, making a prefix sort first. As no
+// encoded value is a prefix of another, inverting every byte of a value reverses its order,
+// which is how descending key columns are encoded.
+
+const TAG_BOOL: u8 = 0x10;
+const TAG_INTEGER: u8 = 0x20;
+const TAG_REAL: u8 = 0x21;
+const TAG_TEXT: u8 = 0x30;
+const TAG_UUID: u8 = 0x40;
+const TAG_JSON: u8 = 0x50;
+const TAG_ENUM: u8 = 0x60;
+const TAG_ARRAY: u8 = 0x70;
+const TAG_BLOB: u8 = 0x80;
+const TAG_NULL: u8 = 0xF0;
+
+/// Greater than the first byte of any encoded value: appending it to an encoded prefix gives a
+/// key sorting after every key starting with that prefix
+pub const PREFIX_END: u8 = 0xFF;
+
+/// How a column of an index key is ordered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyOrder {
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+/// Encodes the values of an index key, each one being ordered as given by `orders`
+pub fn encode(values: &[Value], orders: &[KeyOrder]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for (value, order) in values.iter().zip(orders) {
+        let start = key.len();
+        encode_value(value, order.collation, &mut key);
+        if order.descending {
+            key[start..].iter_mut().for_each(|byte| *byte = !*byte);
+        }
+    }
+    key
+}
+
+/// Length of the encoded values at the start of a key, which may be followed by other bytes (such
+/// as a row id). Returns `None` if the key does not start with values encoded with given orders.
+pub fn encoded_length(key: &[u8], orders: &[KeyOrder]) -> Option<usize> {
+    let mut length = 0;
+    for order in orders {
+        let bytes: Vec<u8> = match order.descending {
+            true => key[length..].iter().map(|byte| !byte).collect(),
+            false => key[length..].to_vec(),
+        };
+        length += value_length(&bytes, order.collation)?;
+    }
+    Some(length)
+}
+
+fn value_length(key: &[u8], collation: Collation) -> Option<usize> {
+    let length = match *key.first()? {
+        TAG_BOOL => 2,
+        TAG_INTEGER | TAG_REAL | TAG_ENUM => 9,
+        TAG_UUID => 17,
+        TAG_NULL => 1,
+        TAG_TEXT => {
+            let mut length = 1;
+            for _ in collation.sort_key("") {
+                length += bytes_length(&key[length..])?;
+            }
+            length
+        }
+        TAG_JSON | TAG_BLOB => 1 + bytes_length(&key[1..])?,
+        TAG_ARRAY => {
+            let mut length = 1;
+            while *key.get(length)? == 1 {
+                length += 1 + value_length(&key[length + 1..], collation)?;
+            }
+            length + 1
+        }
+        _ => return None,
+    };
+    (length <= key.len()).then_some(length)
+}
+
+/// Length of bytes written by `encode_bytes`, terminator included
+fn bytes_length(key: &[u8]) -> Option<usize> {
+    let mut position = 0;
+    loop {
+        match *key.get(position)? {
+            0 => match *key.get(position + 1)? {
+                0 => return Some(position + 2),
+                1 => position += 2,
+                _ => return None,
+            },
+            _ => position += 1,
+        }
+    }
+}
+
+/// Range of keys of an index holding every row that may match a WHERE clause: leading key
+/// columns equal to given values, then optionally one more column within a range. Bounds are
+/// included, and a missing bound leaves its side open.
+#[derive(Debug, PartialEq)]
+pub struct KeyBounds {
+    pub equal: Vec<Value>,
+    pub lower: Option<Value>,
+    pub upper: Option<Value>,
+}
+
+impl KeyBounds {
+    /// Encodes the range into bounds on index keys, which may be longer than the encoded values
+    /// (when they hold more columns, or a row id)
+    pub fn encode(&self, orders: &[KeyOrder]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let mut lower = self.equal.clone();
+        let mut upper = self.equal.clone();
+        // The range of a descending column starts at its upper bound
+        let (first, last) = match orders.get(self.equal.len()) {
+            Some(order) if order.descending => (&self.upper, &self.lower),
+            _ => (&self.lower, &self.upper),
+        };
+        lower.extend(first.clone());
+        upper.extend(last.clone());
+        let lower = match lower.is_empty() {
+            true => Bound::Unbounded,
+            false => Bound::Included(encode(&lower, orders)),
+        };
+        // Keys starting with the upper values sort before those values followed by PREFIX_END
+        let upper = match upper.is_empty() {
+            true => Bound::Unbounded,
+            false => {
+                let mut key = encode(&upper, orders);
+                key.push(PREFIX_END);
+                Bound::Excluded(key)
+            }
+        };
+        (lower, upper)
+    }
+}
+
+fn encode_value(value: &Value, collation: Collation, key: &mut Vec<u8>) {
+    match value {
+        Value::Bool(value) => key.extend([TAG_BOOL, *value as u8]),
+        Value::Integer(value) => {
+            key.push(TAG_INTEGER);
+            key.extend(((*value as u64) ^ (1 << 63)).to_be_bytes());
+        }
+        Value::Real(value) => {
+            key.push(TAG_REAL);
+            encode_real(*value, key);
+        }
+        Value::Text(text) => {
+            key.push(TAG_TEXT);
+            for part in collation.sort_key(text) {
+                encode_bytes(part.as_bytes(), key);
+            }
+        }
+        Value::Uuid(bytes) => {
+            key.push(TAG_UUID);
+            key.extend(bytes);
+        }
+        Value::Json(bytes) => {
+            key.push(TAG_JSON);
+            encode_bytes(bytes, key);
+        }
+        Value::Blob(bytes) => {
+            key.push(TAG_BLOB);
+            encode_bytes(bytes, key);
+        }
+        Value::Enum(value) => {
+            key.push(TAG_ENUM);
+            encode_real(value.sort_order, key);
+        }
+        Value::Array(elements) => {
+            key.push(TAG_ARRAY);
+            for element in elements {
+                key.push(1);
+                encode_value(element, collation, key);
+            }
+            key.push(0);
+        }
+        Value::Null => key.push(TAG_NULL),
+    }
+}
+
+/// Flips the sign bit of positive numbers, and every bit of negative ones
+fn encode_real(value: f64, key: &mut Vec<u8>) {
+    // -0.0 is equal to 0.0, so both must have the same key
+    let bits = if value == 0.0 { 0 } else { value.to_bits() };
+    let ordered = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    };
+    key.extend(ordered.to_be_bytes());
+}
+
+/// Writes bytes followed by a `[0, 0]` terminator, escaping every zero byte as `[0, 1]`
+fn encode_bytes(bytes: &[u8], key: &mut Vec<u8>) {
+    for &byte in bytes {
+        match byte {
+            0 => key.extend([0, 1]),
+            byte => key.push(byte),
+        }
+    }
+    key.extend([0, 0]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_sort_like_values() {
+        let sorted = [
+            vec![Value::Integer(-5), Value::Text(String::from("b"))],
+            vec![Value::Integer(2), Value::Text(String::from("a"))],
+            vec![Value::Integer(2), Value::Text(String::from("a\0"))],
+            vec![Value::Integer(2), Value::Text(String::from("ab"))],
+            vec![Value::Integer(2), Value::Blob(vec![0])],
+            vec![Value::Integer(2), Value::Blob(vec![0, 0])],
+            vec![Value::Integer(2), Value::Blob(vec![1])],
+            vec![Value::Integer(2), Value::Null],
+            vec![Value::Integer(300), Value::Text(String::new())],
+        ];
+        let ascending = KeyOrder {
+            collation: Collation::Binary,
+            descending: false,
+        };
+        let orders = [ascending, ascending];
+        let keys: Vec<Vec<u8>> = sorted.iter().map(|row| encode(row, &orders)).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let reals: Vec<Vec<u8>> = [-2.5, -0.5, 0.0, 1.0, 1e300]
+            .iter()
+            .map(|&real| encode(&[Value::Real(real)], &orders))
+            .collect();
+        assert!(reals.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let nocase = [KeyOrder {
+            collation: Collation::NoCase,
+            descending: false,
+        }];
+        let text = |text: &str| encode(&[Value::Text(text.to_string())], &nocase);
+        assert_eq!(text("ABC"), text("abc"));
+        assert!(text("abc") < text("ABD"));
+
+        let descending = [
+            ascending,
+            KeyOrder {
+                descending: true,
+                ..ascending
+            },
+        ];
+        let keys: Vec<Vec<u8>> = [("a", 5), ("a", 1), ("a", -3), ("ab", 9), ("b", 0)]
+            .iter()
+            .map(|&(text, integer)| {
+                let row = [Value::Text(text.to_string()), Value::Integer(integer)];
+                encode(&row, &descending)
+            })
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let unicode = [KeyOrder {
+            collation: Collation::Unicode,
+            descending: true,
+        }];
+        let row = [
+            Value::Array(vec![Value::Text(String::from("é\0")), Value::Null]),
+            Value::Json(vec![0, 1, 2]),
+        ];
+        let orders = [unicode[0], ascending];
+        let mut key = encode(&row, &orders);
+        let length = key.len();
+        key.extend([0, 0, 0, 7, 0, 1]);
+        assert_eq!(encoded_length(&key, &orders), Some(length));
+    }
+}
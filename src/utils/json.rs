@@ -0,0 +1,358 @@
+use crate::utils::file_handler::Value;
+use serde_json::Value as JsonValue;
+use thiserror::Error;
+
+// Binary layout of a JSON document: every node starts with a one byte tag, followed by
+//   - nothing for null, false and true
+//   - a zigzag varint for integers, 8 little-endian bytes for floats
+//   - a varint length and UTF-8 bytes for strings
+//   - a varint element count followed by the elements for arrays
+//   - a varint entry count followed by (string key, node) pairs for objects
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_ARRAY: u8 = 6;
+const TAG_OBJECT: u8 = 7;
+
+#[derive(Error, Debug)]
+pub enum JsonError {
+    #[error("Invalid JSON document: {0}")]
+    InvalidDocument(#[from] serde_json::Error),
+    #[error("Binary JSON document is corrupted at byte {position}")]
+    CorruptedBinary { position: usize },
+    #[error("Invalid JSON path '{path}'")]
+    InvalidPath { path: String },
+}
+
+/// One step of a JSON path: either an object key or an array index (negative indexes count from
+/// the end of the array)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathStep {
+    Key(String),
+    Index(i64),
+}
+
+/// Validates given JSON text and returns its compact binary form
+pub fn parse(text: &str) -> Result<Vec<u8>, JsonError> {
+    let value: JsonValue = serde_json::from_str(text)?;
+    Ok(encode(&value))
+}
+
+pub fn encode(value: &JsonValue) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    encode_node(value, &mut buffer);
+    buffer
+}
+
+fn encode_node(value: &JsonValue, buffer: &mut Vec<u8>) {
+    match value {
+        JsonValue::Null => buffer.push(TAG_NULL),
+        JsonValue::Bool(false) => buffer.push(TAG_FALSE),
+        JsonValue::Bool(true) => buffer.push(TAG_TRUE),
+        JsonValue::Number(number) => {
+            if let Some(integer) = number.as_i64() {
+                buffer.push(TAG_INTEGER);
+                write_varint(((integer << 1) ^ (integer >> 63)) as u64, buffer);
+            } else {
+                buffer.push(TAG_FLOAT);
+                buffer.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_le_bytes());
+            }
+        }
+        JsonValue::String(string) => {
+            buffer.push(TAG_STRING);
+            write_string(string, buffer);
+        }
+        JsonValue::Array(elements) => {
+            buffer.push(TAG_ARRAY);
+            write_varint(elements.len() as u64, buffer);
+            for element in elements {
+                encode_node(element, buffer);
+            }
+        }
+        JsonValue::Object(entries) => {
+            buffer.push(TAG_OBJECT);
+            write_varint(entries.len() as u64, buffer);
+            for (key, entry) in entries {
+                write_string(key, buffer);
+                encode_node(entry, buffer);
+            }
+        }
+    }
+}
+
+fn write_varint(mut value: u64, buffer: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_string(string: &str, buffer: &mut Vec<u8>) {
+    write_varint(string.len() as u64, buffer);
+    buffer.extend_from_slice(string.as_bytes());
+}
+
+pub fn decode(bytes: &[u8]) -> Result<JsonValue, JsonError> {
+    let mut reader = BinaryReader { bytes, position: 0 };
+    let value = reader.read_node()?;
+    if reader.position != bytes.len() {
+        return Err(JsonError::CorruptedBinary {
+            position: reader.position,
+        });
+    }
+    Ok(value)
+}
+
+/// Returns the textual representation of a binary JSON document
+pub fn to_text(bytes: &[u8]) -> Result<String, JsonError> {
+    Ok(decode(bytes)?.to_string())
+}
+
+struct BinaryReader<'bytes> {
+    bytes: &'bytes [u8],
+    position: usize,
+}
+
+impl BinaryReader<'_> {
+    fn corrupted(&self) -> JsonError {
+        JsonError::CorruptedBinary {
+            position: self.position,
+        }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&[u8], JsonError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| self.corrupted())?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, JsonError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_bytes(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.corrupted())
+    }
+
+    fn read_string(&mut self) -> Result<String, JsonError> {
+        let len = self.read_varint()? as usize;
+        let bytes = self.read_bytes(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| self.corrupted())
+    }
+
+    fn read_node(&mut self) -> Result<JsonValue, JsonError> {
+        let tag = self.read_bytes(1)?[0];
+        let value = match tag {
+            TAG_NULL => JsonValue::Null,
+            TAG_FALSE => JsonValue::Bool(false),
+            TAG_TRUE => JsonValue::Bool(true),
+            TAG_INTEGER => {
+                let zigzag = self.read_varint()?;
+                JsonValue::from(((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64))
+            }
+            TAG_FLOAT => {
+                let bytes: [u8; 8] = self.read_bytes(8)?.try_into().unwrap();
+                JsonValue::from(f64::from_le_bytes(bytes))
+            }
+            TAG_STRING => JsonValue::String(self.read_string()?),
+            TAG_ARRAY => {
+                let count = self.read_varint()?;
+                let mut elements = Vec::new();
+                for _ in 0..count {
+                    elements.push(self.read_node()?);
+                }
+                JsonValue::Array(elements)
+            }
+            TAG_OBJECT => {
+                let count = self.read_varint()?;
+                let mut entries = serde_json::Map::new();
+                for _ in 0..count {
+                    let key = self.read_string()?;
+                    entries.insert(key, self.read_node()?);
+                }
+                JsonValue::Object(entries)
+            }
+            _ => return Err(self.corrupted()),
+        };
+        Ok(value)
+    }
+}
+
+/// Parses a path such as `$.tags[0]` or `$."some key"[-1]` into its steps
+pub fn parse_path(path: &str) -> Result<Vec<PathStep>, JsonError> {
+    let invalid_path = || JsonError::InvalidPath {
+        path: path.to_string(),
+    };
+    let mut chars = path.chars().peekable();
+    if chars.next() != Some('$') {
+        return Err(invalid_path());
+    }
+    let mut steps = Vec::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                let mut key = String::new();
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => key.push(c),
+                            None => return Err(invalid_path()),
+                        }
+                    }
+                } else {
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        key.push(c);
+                        chars.next();
+                    }
+                }
+                if key.is_empty() {
+                    return Err(invalid_path());
+                }
+                steps.push(PathStep::Key(key));
+            }
+            '[' => {
+                let mut index = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => index.push(c),
+                        None => return Err(invalid_path()),
+                    }
+                }
+                let index = index.trim().trim_start_matches('#');
+                steps.push(PathStep::Index(index.parse().map_err(|_| invalid_path())?));
+            }
+            _ => return Err(invalid_path()),
+        }
+    }
+    Ok(steps)
+}
+
+/// Follows a single path step from given JSON node
+pub fn lookup<'json>(value: &'json JsonValue, step: &PathStep) -> Option<&'json JsonValue> {
+    match (value, step) {
+        (JsonValue::Object(entries), PathStep::Key(key)) => entries.get(key),
+        (JsonValue::Array(elements), PathStep::Index(index)) => {
+            let position = if *index < 0 {
+                elements.len().checked_sub(index.unsigned_abs() as usize)?
+            } else {
+                *index as usize
+            };
+            elements.get(position)
+        }
+        _ => None,
+    }
+}
+
+/// Follows every step of a path, returning `None` as soon as one of them does not match
+pub fn lookup_path<'json>(value: &'json JsonValue, steps: &[PathStep]) -> Option<&'json JsonValue> {
+    steps.iter().try_fold(value, lookup)
+}
+
+/// Converts a JSON node to the SQL value it is the closest to: scalars become plain values,
+/// while arrays and objects stay JSON documents.
+pub fn to_sql_value(value: &JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(boolean) => Value::Bool(*boolean),
+        JsonValue::Number(number) => match number.as_i64() {
+            Some(integer) => Value::Integer(integer),
+            None => Value::Real(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(string) => Value::Text(string.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => Value::Json(encode(value)),
+    }
+}
+
+/// Converts a JSON node to text, the way the `->>` operator does: strings lose their quotes and
+/// JSON null becomes SQL NULL.
+pub fn to_sql_text(value: &JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::String(string) => Value::Text(string.clone()),
+        _ => Value::Text(value.to_string()),
+    }
+}
+
+pub fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(true) => "true",
+        JsonValue::Bool(false) => "false",
+        JsonValue::Number(number) if number.is_i64() => "integer",
+        JsonValue::Number(_) => "real",
+        JsonValue::String(_) => "text",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let text =
+            r#"{"name":"ada","tags":["a","b"],"age":36,"score":-1.5,"admin":false,"x":null}"#;
+        let binary = parse(text).unwrap();
+        assert!(binary.len() < text.len());
+        let expected: JsonValue = serde_json::from_str(text).unwrap();
+        assert_eq!(decode(&binary).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_invalid_documents_are_rejected() {
+        for text in ["{", "[1,]", "{'a': 1}", "nope"] {
+            assert!(
+                parse(text).is_err(),
+                "Failed: `{}` should be rejected",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupted_binary_is_detected() {
+        let mut binary = parse(r#"{"a":[1,2,3]}"#).unwrap();
+        binary.truncate(binary.len() - 1);
+        assert!(matches!(
+            decode(&binary),
+            Err(JsonError::CorruptedBinary { .. })
+        ));
+    }
+
+    #[test]
+    fn test_path_lookup() {
+        let value: JsonValue = serde_json::from_str(r#"{"a":{"b c":[10,20,30]}}"#).unwrap();
+        let steps = parse_path(r#"$.a."b c"[-1]"#).unwrap();
+        assert_eq!(lookup_path(&value, &steps), Some(&JsonValue::from(30)));
+        let steps = parse_path("$.a.missing").unwrap();
+        assert_eq!(lookup_path(&value, &steps), None);
+        assert!(parse_path("a.b").is_err());
+        assert!(matches!(
+            parse_path("$[0"),
+            Err(JsonError::InvalidPath { .. })
+        ));
+        assert!(parse_path("$.a[1").is_err());
+    }
+}
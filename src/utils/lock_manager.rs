@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::utils::file_handler::SessionId;
+
+// Locks are held by sessions, for the transaction they run, and released once it ends. A single
+// transaction writes at a time, holding `LockTarget::Writes`, so writers never conflict over
+// tables: table locks order the writer and serializable transactions, which share the tables they
+// read while the writer holds the tables it changes exclusively. A session which cannot get a
+// lock waits for the sessions holding conflicting ones, without holding the database meanwhile:
+// it gets the lock through `wait`, then runs its statement again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn is_compatible(self, other: LockMode) -> bool {
+        self == LockMode::Shared && other == LockMode::Shared
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    /// Right to write to the database, which one transaction holds at a time
+    Writes,
+    Table(String),
+}
+
+impl fmt::Display for LockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockTarget::Writes => write!(f, "write access to the database"),
+            LockTarget::Table(table_name) => write!(f, "table '{table_name}'"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("Timed out after {} ms waiting for a lock on {target}", timeout.as_millis())]
+    Timeout {
+        target: LockTarget,
+        timeout: Duration,
+    },
+    #[error(
+        "Deadlock detected while waiting for a lock on {target}, the transaction was rolled back"
+    )]
+    Deadlock { target: LockTarget },
+}
+
+#[derive(Default)]
+struct LockTable {
+    /// Modes in which every session holds a target
+    granted: HashMap<LockTarget, HashMap<SessionId, HashSet<LockMode>>>,
+    /// Lock every waiting session waits for
+    waiting: HashMap<SessionId, (LockTarget, LockMode)>,
+}
+
+impl LockTable {
+    /// Sessions holding a target in a mode which conflicts with given one
+    fn blockers(&self, session: SessionId, target: &LockTarget, mode: LockMode) -> Vec<SessionId> {
+        self.granted.get(target).map_or(Vec::new(), |holders| {
+            holders
+                .iter()
+                .filter(|(holder, modes)| {
+                    **holder != session && modes.iter().any(|held| !mode.is_compatible(*held))
+                })
+                .map(|(holder, _)| *holder)
+                .collect()
+        })
+    }
+
+    fn try_grant(&mut self, session: SessionId, target: &LockTarget, mode: LockMode) -> bool {
+        if !self.blockers(session, target, mode).is_empty() {
+            return false;
+        }
+        self.granted
+            .entry(target.clone())
+            .or_default()
+            .entry(session)
+            .or_default()
+            .insert(mode);
+        true
+    }
+
+    /// Whether a waiting session waits, through other waiting sessions, for a session which
+    /// waits for it
+    fn in_deadlock(&self, session: SessionId) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![session];
+        while let Some(waiter) = pending.pop() {
+            let Some((target, mode)) = self.waiting.get(&waiter) else {
+                continue;
+            };
+            for blocker in self.blockers(waiter, target, *mode) {
+                if blocker == session {
+                    return true;
+                }
+                if visited.insert(blocker) {
+                    pending.push(blocker);
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Locks of the sessions of a database
+#[derive(Default)]
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    /// Notified whenever locks are released
+    released: Condvar,
+}
+
+impl LockManager {
+    /// Gives a lock to a session unless another session holds a conflicting one. Locks are kept
+    /// until `release_all`.
+    pub fn try_acquire(&self, session: SessionId, target: &LockTarget, mode: LockMode) -> bool {
+        self.table.lock().unwrap().try_grant(session, target, mode)
+    }
+
+    /// Blocks until a session gets a lock. Fails once `timeout` elapsed, or right away when the
+    /// sessions holding conflicting locks wait for this one, which must then be rolled back.
+    pub fn wait(
+        &self,
+        session: SessionId,
+        target: &LockTarget,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<(), LockError> {
+        let deadline = Instant::now() + timeout;
+        let mut table = self.table.lock().unwrap();
+        table.waiting.insert(session, (target.clone(), mode));
+        let result = loop {
+            if table.try_grant(session, target, mode) {
+                break Ok(());
+            }
+            if table.in_deadlock(session) {
+                log::warn!("Session {session} is in a deadlock waiting for a lock on {target}");
+                break Err(LockError::Deadlock {
+                    target: target.clone(),
+                });
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(LockError::Timeout {
+                    target: target.clone(),
+                    timeout,
+                });
+            }
+            table = self.released.wait_timeout(table, deadline - now).unwrap().0;
+        };
+        table.waiting.remove(&session);
+        result
+    }
+
+    /// Releases every lock of a session, once its transaction ended
+    pub fn release_all(&self, session: SessionId) {
+        let mut table = self.table.lock().unwrap();
+        table.granted.retain(|_, holders| {
+            holders.remove(&session);
+            !holders.is_empty()
+        });
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_locks_wait_and_deadlocks_are_detected() {
+        let locks = LockManager::default();
+        let table = |name: &str| LockTarget::Table(name.to_string());
+        assert!(locks.try_acquire(1, &LockTarget::Writes, LockMode::Exclusive));
+        assert!(!locks.try_acquire(2, &LockTarget::Writes, LockMode::Exclusive));
+        assert!(locks.try_acquire(1, &table("t"), LockMode::Shared));
+        assert!(locks.try_acquire(2, &table("t"), LockMode::Shared));
+        assert!(locks.try_acquire(1, &table("u"), LockMode::Exclusive));
+        // A session upgrades its own lock unless another session shares it
+        assert!(!locks.try_acquire(1, &table("t"), LockMode::Exclusive));
+        assert!(locks.try_acquire(1, &table("u"), LockMode::Shared));
+
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            locks.wait(2, &table("u"), LockMode::Shared, timeout),
+            Err(LockError::Timeout { .. })
+        ));
+        // Session 1 waits for session 2, which then waits for session 1
+        std::thread::scope(|scope| {
+            let waiter =
+                scope.spawn(|| locks.wait(1, &table("t"), LockMode::Exclusive, timeout * 50));
+            while locks.table.lock().unwrap().waiting.is_empty() {
+                std::thread::yield_now();
+            }
+            assert!(matches!(
+                locks.wait(2, &LockTarget::Writes, LockMode::Exclusive, timeout * 50),
+                Err(LockError::Deadlock { .. })
+            ));
+            locks.release_all(2);
+            assert!(waiter.join().unwrap().is_ok());
+        });
+        assert!(!locks.try_acquire(2, &table("t"), LockMode::Shared));
+        locks.release_all(1);
+        assert!(locks.try_acquire(2, &table("t"), LockMode::Exclusive));
+    }
+}
@@ -1 +1,20 @@
+pub mod array;
+pub mod atomic_file;
+pub mod btree;
+pub mod buffer_pool;
+pub mod collation;
+pub mod column_store;
+pub mod compression;
+pub mod container;
+pub mod directory_lock;
 pub mod file_handler;
+pub mod heap_file;
+pub mod index_key;
+pub mod json;
+pub mod lock_manager;
+pub mod mvcc;
+pub mod page;
+pub mod storage;
+#[cfg(test)]
+pub mod temp_directory;
+pub mod wal;
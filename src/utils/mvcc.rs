@@ -0,0 +1,132 @@
+use std::io;
+
+// Rows are never changed in place: an update stores a new version of the row and marks the old
+// one as deleted, so that transactions which started before the change keep reading the old one.
+// Every version records the transactions which created and deleted it, and a snapshot tells which
+// of those transactions a reader sees. One transaction writes at a time, and its changes are
+// physically undone when it rolls back, so a version was always created by a committed
+// transaction or by the one writing.
+
+/// Id given to a transaction when it first writes. Ids only grow, across restarts too.
+pub type TransactionId = u64;
+
+/// Creator of rows stored before versions existed, seen by every transaction. Also marks
+/// versions which are not deleted.
+pub const FROZEN: TransactionId = 0;
+
+/// Visibility info stored before the row in every record of a heap file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowVersion {
+    pub created_by: TransactionId,
+    pub deleted_by: TransactionId,
+}
+
+impl RowVersion {
+    pub const SIZE: usize = 16;
+
+    pub fn new(created_by: TransactionId) -> Self {
+        RowVersion {
+            created_by,
+            deleted_by: FROZEN,
+        }
+    }
+
+    /// Whether no transaction deleted this version, which makes it the latest one of its row
+    pub fn is_live(self) -> bool {
+        self.deleted_by == FROZEN
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.created_by.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.deleted_by.to_le_bytes());
+        bytes
+    }
+
+    /// Splits a record into its version and the encoded row following it
+    pub fn split_record(record: &[u8]) -> io::Result<(Self, &[u8])> {
+        if record.len() < Self::SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Record of {} bytes has no row version", record.len()),
+            ));
+        }
+        let (header, row) = record.split_at(Self::SIZE);
+        let version = RowVersion {
+            created_by: u64::from_le_bytes(header[..8].try_into().unwrap()),
+            deleted_by: u64::from_le_bytes(header[8..].try_into().unwrap()),
+        };
+        Ok((version, row))
+    }
+}
+
+/// Which changes of the other transactions a transaction sees, set by `SET TRANSACTION ISOLATION
+/// LEVEL`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IsolationLevel {
+    /// Every statement reads a new snapshot
+    ReadCommitted,
+    /// The transaction reads one snapshot, taken when it first reads, and fails to change rows
+    /// changed since by another transaction
+    #[default]
+    RepeatableRead,
+    /// Tables and rows read by the transaction are locked until it ends, so that no other
+    /// transaction changes them meanwhile. Every statement reads a new snapshot.
+    Serializable,
+}
+
+/// State of the transactions at the time a transaction started reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    /// Id the next writing transaction was to get: this one and later ones are not visible
+    pub next_id: TransactionId,
+    /// Transaction which was writing, whose changes are not visible even once committed
+    pub writer: Option<TransactionId>,
+}
+
+impl Snapshot {
+    /// Whether changes of given transaction were committed when the snapshot was taken
+    pub fn sees_committed(&self, id: TransactionId) -> bool {
+        id < self.next_id && Some(id) != self.writer
+    }
+
+    /// Whether a row version is visible to a transaction reading this snapshot, which also sees
+    /// its own changes when it has written under the id `own`
+    pub fn sees(&self, version: RowVersion, own: Option<TransactionId>) -> bool {
+        let sees = |id| Some(id) == own || self.sees_committed(id);
+        sees(version.created_by) && (version.is_live() || !sees(version.deleted_by))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_sees_versions_committed_before_it() {
+        let snapshot = Snapshot {
+            next_id: 10,
+            writer: Some(7),
+        };
+        let version = |created_by, deleted_by| RowVersion {
+            created_by,
+            deleted_by,
+        };
+        assert!(snapshot.sees(version(FROZEN, FROZEN), None));
+        assert!(snapshot.sees(version(3, FROZEN), None));
+        // Created by the writer, or after the snapshot
+        assert!(!snapshot.sees(version(7, FROZEN), None));
+        assert!(!snapshot.sees(version(10, FROZEN), None));
+        // Deleted by the writer or after the snapshot, so still visible
+        assert!(snapshot.sees(version(3, 7), None));
+        assert!(snapshot.sees(version(3, 12), None));
+        assert!(!snapshot.sees(version(3, 5), None));
+        // A transaction sees its own changes
+        assert!(snapshot.sees(version(12, FROZEN), Some(12)));
+        assert!(!snapshot.sees(version(3, 12), Some(12)));
+
+        let record = [version(3, 12).to_bytes(), [1; 16]].concat();
+        let (parsed, row) = RowVersion::split_record(&record).unwrap();
+        assert_eq!((parsed, row), (version(3, 12), &[1; 16][..]));
+    }
+}
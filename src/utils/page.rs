@@ -0,0 +1,582 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+
+use crate::utils::compression::Compression;
+
+// Pages of data and index files end with the CRC-32 of the rest of the page, which the buffer
+// pool writes and checks (see `PageLayout`). Page formats only see the bytes before it.
+//
+// Layout of a slotted page of `page_size` bytes:
+//   - header: [u8 kind][u8 reserved][u16 slot count][u16 free space end][u16 fragmented bytes]
+//   - slot directory, growing forward right after the header: one [u16 offset][u16 length]
+//     entry per slot, a zero offset marking a free slot
+//   - free space
+//   - records, growing backward from the end of the page
+// Every integer is little-endian. Fragmented bytes count the space of deleted or shrunk records
+// that sits between live records, which is reclaimed by compacting the page.
+//
+// Values too large for a record are stored in a chain of overflow pages of the same file:
+//   - header: [u8 kind][u8 reserved][u16 chunk length][u32 next page, u32::MAX for the last one]
+//   - chunk of the value
+//
+// Pages of a compressed data file hold the content of a page of `MAX_PAGE_SIZE` bytes, which must
+// fit once compressed, so that one page of the file stores the records of several:
+//   - header: [u8 encoding][u8 reserved][u16 stored length]
+//   - stored content: compressed, or as is without its trailing zeros when that is smaller
+//   - zeros, then the checksum
+// The buffer pool holds their content, and only compresses it when writing it to the file.
+
+pub const HEADER_SIZE: usize = 8;
+pub const SLOT_SIZE: usize = 4;
+pub const MIN_PAGE_SIZE: usize = 512;
+/// Offsets within a page, up to the page size itself, are stored on 16 bits
+pub const MAX_PAGE_SIZE: usize = 32768;
+
+const KIND_HEAP: u8 = 1;
+const KIND_OVERFLOW: u8 = 2;
+
+pub const OVERFLOW_HEADER_SIZE: usize = 8;
+const NO_NEXT_PAGE: u32 = u32::MAX;
+
+/// Bytes of the checksum ending every page of a file with checksums
+pub const CHECKSUM_SIZE: usize = 4;
+
+const COMPRESSED_HEADER_SIZE: usize = 4;
+const ENCODING_TRIMMED: u8 = 0;
+const ENCODING_COMPRESSED: u8 = 1;
+
+/// Size of the pages of a file, whether they end with a checksum, and how they are compressed.
+/// Files of tables created before checksums existed have none, until they are converted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageLayout {
+    pub page_size: usize,
+    pub checksums: bool,
+    pub compression: Compression,
+}
+
+impl PageLayout {
+    pub fn with_checksums(page_size: usize) -> Self {
+        PageLayout {
+            page_size,
+            checksums: true,
+            compression: Compression::None,
+        }
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self.compression != Compression::None
+    }
+
+    /// Bytes of a page available to its format
+    pub fn content_size(self) -> usize {
+        match (self.is_compressed(), self.checksums) {
+            (true, _) => MAX_PAGE_SIZE,
+            (false, true) => self.page_size - CHECKSUM_SIZE,
+            (false, false) => self.page_size,
+        }
+    }
+
+    /// Bytes of content a page holds whatever they are, as compressed pages store their content
+    /// as is when it does not compress
+    pub fn guaranteed_content_size(self) -> usize {
+        match self.is_compressed() {
+            true => self.stored_capacity(),
+            false => self.content_size(),
+        }
+    }
+
+    /// Bytes held by a frame of the buffer pool for a page: the whole page, or the content of a
+    /// compressed one
+    pub fn frame_size(self) -> usize {
+        match self.is_compressed() {
+            true => self.content_size(),
+            false => self.page_size,
+        }
+    }
+
+    /// Whether the content of a page fits in the file once compressed, along with `reserve`
+    /// more bytes
+    pub fn fits(self, content: &[u8], reserve: usize) -> bool {
+        !self.is_compressed() || self.store(content).1.len() + reserve <= self.stored_capacity()
+    }
+
+    /// Bytes of the file taken by the content of a page, which is less than a page once
+    /// compressed
+    pub fn stored_size(self, content: &[u8]) -> usize {
+        match self.is_compressed() {
+            true => COMPRESSED_HEADER_SIZE + self.store(content).1.len(),
+            false => self.page_size,
+        }
+    }
+
+    /// Writes the checksum at the end of a page held by a frame. Compressed pages get theirs
+    /// once encoded.
+    pub fn seal(self, page: &mut [u8]) {
+        if self.checksums && !self.is_compressed() {
+            self.write_checksum(page);
+        }
+    }
+
+    /// Page written to the file for the bytes of a frame
+    pub fn encode(self, frame: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+        if !self.is_compressed() {
+            return Ok(Cow::Borrowed(frame));
+        }
+        let (encoding, stored) = self.store(frame);
+        if stored.len() > self.stored_capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page content does not fit in {} bytes once compressed",
+                    self.page_size
+                ),
+            ));
+        }
+        let mut page = vec![0; self.page_size];
+        page[0] = encoding;
+        page[2..4].copy_from_slice(&(stored.len() as u16).to_le_bytes());
+        page[COMPRESSED_HEADER_SIZE..COMPRESSED_HEADER_SIZE + stored.len()]
+            .copy_from_slice(&stored);
+        if self.checksums {
+            self.write_checksum(&mut page);
+        }
+        Ok(Cow::Owned(page))
+    }
+
+    /// Bytes of a frame for a page read from the file, which must match its checksum
+    pub fn decode(self, page: Vec<u8>, path: &Path, page_number: u32) -> io::Result<Vec<u8>> {
+        self.verify(&page, path, page_number)?;
+        if !self.is_compressed() {
+            return Ok(page);
+        }
+        let corrupted =
+            |reason: &str| corrupted_page(path, page_number as u64 * self.page_size as u64, reason);
+        let length = u16::from_le_bytes([page[2], page[3]]) as usize;
+        if length > self.stored_capacity() {
+            return Err(corrupted("stored content overflows the page"));
+        }
+        let stored = &page[COMPRESSED_HEADER_SIZE..COMPRESSED_HEADER_SIZE + length];
+        match page[0] {
+            ENCODING_TRIMMED => {
+                let mut content = stored.to_vec();
+                content.resize(self.content_size(), 0);
+                Ok(content)
+            }
+            ENCODING_COMPRESSED => self
+                .compression
+                .decompress(stored, self.content_size())
+                .map_err(|error| corrupted(&error.to_string())),
+            _ => Err(corrupted("unknown encoding")),
+        }
+    }
+
+    /// Bytes of a compressed page available to its stored content
+    fn stored_capacity(self) -> usize {
+        let checksum_size = if self.checksums { CHECKSUM_SIZE } else { 0 };
+        self.page_size - COMPRESSED_HEADER_SIZE - checksum_size
+    }
+
+    /// Encoding and stored content of a compressed page, whichever of its compressed content or
+    /// its content without trailing zeros is smaller
+    fn store(self, content: &[u8]) -> (u8, Cow<'_, [u8]>) {
+        let end = content
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        let compressed = self.compression.compress(content);
+        match compressed.len() < end {
+            true => (ENCODING_COMPRESSED, Cow::Owned(compressed)),
+            false => (ENCODING_TRIMMED, Cow::Borrowed(&content[..end])),
+        }
+    }
+
+    fn write_checksum(self, page: &mut [u8]) {
+        let (content, checksum) = page.split_at_mut(self.page_size - CHECKSUM_SIZE);
+        checksum.copy_from_slice(&crc32fast::hash(content).to_le_bytes());
+    }
+
+    /// Makes sure that a page read from a file still matches its checksum
+    pub fn verify(self, page: &[u8], path: &Path, page_number: u32) -> io::Result<()> {
+        if !self.checksums {
+            return Ok(());
+        }
+        let (content, checksum) = page.split_at(self.page_size - CHECKSUM_SIZE);
+        if crc32fast::hash(content).to_le_bytes() == checksum {
+            return Ok(());
+        }
+        Err(corrupted_page(
+            path,
+            page_number as u64 * self.page_size as u64,
+            "checksum mismatch",
+        ))
+    }
+}
+
+/// Error reporting a damaged page of a table file, at given offset. Files are named after their
+/// table (see `file_handler`).
+pub fn corrupted_page(path: &Path, offset: u64, reason: &str) -> io::Error {
+    let file_name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    let table_name = file_name.split('.').next().unwrap_or_default();
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Table '{table_name}' is corrupted: page at offset {offset} of {file_name} is \
+             damaged ({reason})"
+        ),
+    )
+}
+
+/// Checks that a page size can be used by the slotted page format
+pub fn check_page_size(page_size: usize) -> io::Result<()> {
+    if page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Page size must be a power of two between {MIN_PAGE_SIZE} and {MAX_PAGE_SIZE}, \
+             found {page_size}"
+        ),
+    ))
+}
+
+/// Whether a page holds a chunk of a value rather than records
+pub fn is_overflow_page(page: &[u8]) -> bool {
+    page[0] == KIND_OVERFLOW
+}
+
+/// Builds an overflow page holding a chunk of a value, followed by the page holding the next
+/// chunk if any
+pub fn overflow_page(content_size: usize, chunk: &[u8], next: Option<u32>) -> Vec<u8> {
+    let mut page = vec![0; content_size];
+    page[0] = KIND_OVERFLOW;
+    page[2..4].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+    page[4..8].copy_from_slice(&next.unwrap_or(NO_NEXT_PAGE).to_le_bytes());
+    page[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+    page
+}
+
+/// Reads the chunk held by an overflow page, along with the page holding the next one if any
+pub fn read_overflow_page(page: &[u8]) -> io::Result<(&[u8], Option<u32>)> {
+    let length = u16::from_le_bytes([page[2], page[3]]) as usize;
+    if page[0] != KIND_OVERFLOW || OVERFLOW_HEADER_SIZE + length > page.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Corrupted overflow page",
+        ));
+    }
+    let next = u32::from_le_bytes(page[4..8].try_into().unwrap());
+    Ok((
+        &page[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + length],
+        (next != NO_NEXT_PAGE).then_some(next),
+    ))
+}
+
+/// Bytes of a page of a heap file holding data, either records along with their slots or a chunk
+/// of a value
+pub fn used_bytes(page: &[u8]) -> usize {
+    match read_overflow_page(page) {
+        Ok((chunk, _)) => OVERFLOW_HEADER_SIZE + chunk.len(),
+        Err(_) => SlottedPage::from_bytes(page)
+            .map_or(page.len(), |page| page.bytes().len() - page.free_space()),
+    }
+}
+
+/// A page of a heap file, holding variable-length records addressed by their slot number. Its
+/// bytes are either owned, or borrowed from a frame of the buffer pool.
+pub struct SlottedPage<D = Vec<u8>> {
+    data: D,
+}
+
+impl SlottedPage {
+    pub fn new(page_size: usize) -> Self {
+        let mut page = SlottedPage {
+            data: vec![0; page_size],
+        };
+        page.data[0] = KIND_HEAP;
+        page.set_free_space_end(page_size);
+        page
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl<D: AsRef<[u8]>> SlottedPage<D> {
+    /// Reads a page from its bytes, making sure that its header and slot directory are coherent
+    pub fn from_bytes(data: D) -> io::Result<Self> {
+        let page = SlottedPage { data };
+        let corrupted = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Corrupted page: {reason}"),
+            )
+        };
+        if page.bytes()[0] != KIND_HEAP {
+            return Err(corrupted("unknown page kind"));
+        }
+        let directory_end = HEADER_SIZE + page.slot_count() as usize * SLOT_SIZE;
+        if directory_end > page.free_space_end() || page.free_space_end() > page.bytes().len() {
+            return Err(corrupted("slot directory overlaps records"));
+        }
+        for slot in 0..page.slot_count() {
+            let (offset, length) = page.slot(slot);
+            if offset != 0
+                && (offset < page.free_space_end() || offset + length > page.bytes().len())
+            {
+                return Err(corrupted("slot points outside of the record area"));
+            }
+        }
+        Ok(page)
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    pub fn slot_count(&self) -> u16 {
+        self.read_u16(2)
+    }
+
+    /// Bytes available for a new record and its slot, once the page is compacted
+    pub fn free_space(&self) -> usize {
+        self.contiguous_free_space() + self.fragmented_bytes()
+    }
+
+    /// Returns the record stored in given slot, if the slot holds one
+    pub fn get(&self, slot: u16) -> Option<&[u8]> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        match self.slot(slot) {
+            (0, _) => None,
+            (offset, length) => Some(&self.bytes()[offset..offset + length]),
+        }
+    }
+
+    /// Iterates over every live record along with its slot number
+    pub fn records(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        (0..self.slot_count()).filter_map(|slot| self.get(slot).map(|record| (slot, record)))
+    }
+
+    fn contiguous_free_space(&self) -> usize {
+        self.free_space_end() - HEADER_SIZE - self.slot_count() as usize * SLOT_SIZE
+    }
+
+    fn free_space_end(&self) -> usize {
+        self.read_u16(4) as usize
+    }
+
+    fn fragmented_bytes(&self) -> usize {
+        self.read_u16(6) as usize
+    }
+
+    fn slot(&self, slot: u16) -> (usize, usize) {
+        let position = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        (
+            self.read_u16(position) as usize,
+            self.read_u16(position + 2) as usize,
+        )
+    }
+
+    fn read_u16(&self, position: usize) -> u16 {
+        let data = self.bytes();
+        u16::from_le_bytes([data[position], data[position + 1]])
+    }
+}
+
+impl<D: AsRef<[u8]> + AsMut<[u8]>> SlottedPage<D> {
+    /// Stores a record in a free slot (or a new one). Returns `None` when the page is too full.
+    pub fn insert(&mut self, record: &[u8]) -> Option<u16> {
+        let free_slot = (0..self.slot_count()).find(|&slot| self.slot(slot).0 == 0);
+        let needed = record.len() + if free_slot.is_some() { 0 } else { SLOT_SIZE };
+        if record.is_empty() || needed > self.free_space() {
+            return None;
+        }
+        if needed > self.contiguous_free_space() {
+            self.compact();
+        }
+        let slot = free_slot.unwrap_or_else(|| {
+            let slot = self.slot_count();
+            self.write_u16(2, slot + 1);
+            slot
+        });
+        let offset = self.free_space_end() - record.len();
+        self.data.as_mut()[offset..offset + record.len()].copy_from_slice(record);
+        self.set_free_space_end(offset);
+        self.set_slot(slot, offset, record.len());
+        Some(slot)
+    }
+
+    /// Replaces the record of a slot. Returns `false` if the slot is free, or if the new record
+    /// does not fit in the page, in which case the page is left unchanged.
+    pub fn update(&mut self, slot: u16, record: &[u8]) -> bool {
+        let Some(old_length) = self.get(slot).map(<[u8]>::len) else {
+            return false;
+        };
+        let (offset, _) = self.slot(slot);
+        if record.len() <= old_length {
+            self.data.as_mut()[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len());
+            self.set_fragmented_bytes(self.fragmented_bytes() + old_length - record.len());
+            return true;
+        }
+        if record.len() > self.free_space() + old_length {
+            return false;
+        }
+        // The old version is dropped first, so that compacting reclaims its space
+        self.set_slot(slot, 0, 0);
+        self.set_fragmented_bytes(self.fragmented_bytes() + old_length);
+        if record.len() > self.contiguous_free_space() {
+            self.compact();
+        }
+        let offset = self.free_space_end() - record.len();
+        self.data.as_mut()[offset..offset + record.len()].copy_from_slice(record);
+        self.set_free_space_end(offset);
+        self.set_slot(slot, offset, record.len());
+        true
+    }
+
+    /// Frees a slot. Returns `false` if it did not hold any record.
+    pub fn delete(&mut self, slot: u16) -> bool {
+        let Some(length) = self.get(slot).map(<[u8]>::len) else {
+            return false;
+        };
+        self.set_slot(slot, 0, 0);
+        self.set_fragmented_bytes(self.fragmented_bytes() + length);
+        // Trailing free slots are dropped from the directory, giving their space back
+        let mut slot_count = self.slot_count();
+        while slot_count > 0 && self.slot(slot_count - 1).0 == 0 {
+            slot_count -= 1;
+        }
+        self.write_u16(2, slot_count);
+        true
+    }
+
+    /// Gives the page back the content of a copy taken before changing it
+    pub fn restore(&mut self, bytes: &[u8]) {
+        self.data.as_mut().copy_from_slice(bytes);
+    }
+
+    /// Moves every live record to the end of the page, so that all free space is contiguous.
+    /// Slot numbers do not change.
+    fn compact(&mut self) {
+        let mut records: Vec<(u16, Vec<u8>)> = self
+            .records()
+            .map(|(slot, record)| (slot, record.to_vec()))
+            .collect();
+        // Records nearest to the end of the page are moved first, so that none is overwritten
+        records.sort_by_key(|(slot, _)| std::cmp::Reverse(self.slot(*slot).0));
+        let mut end = self.bytes().len();
+        for (slot, record) in records {
+            let offset = end - record.len();
+            self.data.as_mut()[offset..end].copy_from_slice(&record);
+            self.set_slot(slot, offset, record.len());
+            end = offset;
+        }
+        self.set_free_space_end(end);
+        self.set_fragmented_bytes(0);
+    }
+
+    fn set_free_space_end(&mut self, end: usize) {
+        self.write_u16(4, end as u16);
+    }
+
+    fn set_fragmented_bytes(&mut self, bytes: usize) {
+        self.write_u16(6, bytes as u16);
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: usize, length: usize) {
+        let position = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        self.write_u16(position, offset as u16);
+        self.write_u16(position + 2, length as u16);
+    }
+
+    fn write_u16(&mut self, position: usize, value: u16) {
+        self.data.as_mut()[position..position + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_survive_compaction() {
+        let mut page = SlottedPage::new(MIN_PAGE_SIZE);
+        let first = page.insert(&[1; 100]).unwrap();
+        let second = page.insert(&[2; 100]).unwrap();
+        let third = page.insert(&[3; 100]).unwrap();
+        assert!(page.delete(second));
+        // Only fits once the hole left by the deleted record is reclaimed
+        let large = page.insert(&[4; 180]).unwrap();
+        assert_eq!(large, second);
+        assert_eq!(page.get(first), Some(&[1; 100][..]));
+        assert_eq!(page.get(third), Some(&[3; 100][..]));
+        assert!(page.update(first, &[5; 20]));
+        assert!(page.update(third, &[6; 150]));
+        assert_eq!(page.get(third), Some(&[6; 150][..]));
+        assert!(page.insert(&[7; 200]).is_none());
+
+        let reread = SlottedPage::from_bytes(page.bytes()).unwrap();
+        let records: Vec<(u16, Vec<u8>)> = reread
+            .records()
+            .map(|(slot, record)| (slot, record.to_vec()))
+            .collect();
+        assert_eq!(
+            records,
+            [(0, vec![5; 20]), (1, vec![4; 180]), (2, vec![6; 150])]
+        );
+    }
+
+    #[test]
+    fn test_corrupted_page_is_rejected() {
+        let mut page = SlottedPage::new(MIN_PAGE_SIZE);
+        page.insert(b"record").unwrap();
+        let mut bytes = page.into_bytes();
+        bytes[HEADER_SIZE] = 1;
+        assert!(SlottedPage::from_bytes(bytes).is_err());
+
+        let layout = PageLayout::with_checksums(MIN_PAGE_SIZE);
+        let mut page = SlottedPage::new(layout.content_size());
+        page.insert(b"record").unwrap();
+        let mut bytes = page.into_bytes();
+        bytes.resize(MIN_PAGE_SIZE, 0);
+        layout.seal(&mut bytes);
+        let path = Path::new("tables/users.data.bin");
+        assert!(layout.verify(&bytes, path, 3).is_ok());
+        bytes[MIN_PAGE_SIZE - 20] ^= 1;
+        let error = layout.verify(&bytes, path, 3).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Table 'users'"));
+        assert!(error.to_string().contains("offset 1536"));
+    }
+
+    #[test]
+    fn test_compressed_page_round_trips() {
+        let layout = PageLayout {
+            page_size: 4096,
+            checksums: true,
+            compression: Compression::Lz4,
+        };
+        let mut page = SlottedPage::new(layout.frame_size());
+        for _ in 0..100 {
+            page.insert(b"repetitive record").unwrap();
+        }
+        let frame = page.into_bytes();
+        assert!(layout.fits(&frame, 0));
+        let stored = layout.encode(&frame).unwrap().into_owned();
+        assert_eq!(stored.len(), 4096);
+        assert!(layout.stored_size(&frame) < 1024);
+        let path = Path::new("tables/users.data.bin");
+        assert_eq!(layout.decode(stored.clone(), path, 0).unwrap(), frame);
+
+        let mut corrupted = stored;
+        corrupted[HEADER_SIZE] ^= 1;
+        assert!(layout.decode(corrupted, path, 0).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::atomic_file;
+use crate::utils::container::ContainerStorage;
+use crate::utils::directory_lock::{AccessMode, DirectoryLock, LOCK_FILE_NAME};
+use crate::utils::wal::{self, WAL_FILE_NAME};
+
+// Files of a database are named by their path within the database, such as `metadata.ron` or
+// `tables/users.data.bin`, whatever holds them. A storage gives access to them, along with the
+// write-ahead log, which is kept apart as it describes changes to the other files. Storages
+// lock the database for the process using them, see `utils::directory_lock`.
+
+/// Chunks in which files are copied from one storage to another
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+/// Path of a database kept in memory, see `StorageLayout::Memory`. Every database opened with
+/// this path is a new one.
+pub const MEMORY_PATH: &str = ":memory:";
+
+/// How the files of a database are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageLayout {
+    /// Directory holding one file per table, schema and index
+    #[default]
+    Directory,
+    /// Single file holding every file of the database as pages, see `utils::container`
+    SingleFile,
+    /// Files kept in memory, which are lost once the database is closed
+    Memory,
+}
+
+impl StorageLayout {
+    /// Layout of the database stored at `path`, if there is one
+    pub fn detect(path: &Path) -> Option<Self> {
+        if path == Path::new(MEMORY_PATH) {
+            Some(StorageLayout::Memory)
+        } else if path.is_file() {
+            Some(StorageLayout::SingleFile)
+        } else if path.join("metadata.ron").exists() {
+            Some(StorageLayout::Directory)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for StorageLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageLayout::Directory => write!(f, "directory"),
+            StorageLayout::SingleFile => write!(f, "single file"),
+            StorageLayout::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+/// File of a storage, read and written at given offsets
+pub trait StorageFile: Send {
+    fn size(&mut self) -> io::Result<u64>;
+    /// Fills `buffer` from `offset`, failing with `UnexpectedEof` past the end of the file
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()>;
+    /// Writes `data` at `offset`, growing the file if needed
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+    fn set_size(&mut self, size: u64) -> io::Result<()>;
+    /// Makes every change of the file durable
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// Files of a database, see `StorageLayout`
+pub trait Storage: Send + Sync {
+    /// Whether the process may change the files, which other processes cannot do meanwhile
+    fn mode(&self) -> AccessMode;
+    /// Opens a file, which is created empty if it does not exist
+    fn open_file(&self, name: &Path) -> io::Result<Box<dyn StorageFile>>;
+    fn exists(&self, name: &Path) -> bool;
+    fn read(&self, name: &Path) -> io::Result<Vec<u8>>;
+    /// Replaces the content of a file, creating it if needed. Whenever the process stops, the
+    /// file holds either its previous content or the new one.
+    fn write(&self, name: &Path, contents: &[u8]) -> io::Result<()>;
+    /// Removes a file, if it exists
+    fn remove(&self, name: &Path) -> io::Result<()>;
+    /// Renames a file, replacing the file already having the new name
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Names of every file, besides the write-ahead log
+    fn list(&self) -> io::Result<Vec<PathBuf>>;
+    /// Opens the write-ahead log, which is created empty if it does not exist
+    fn open_log(&self) -> io::Result<Box<dyn StorageFile>>;
+    /// Size of the write-ahead log, which is 0 if it does not exist
+    fn log_size(&self) -> io::Result<u64>;
+}
+
+/// Opens the storage of the database at `path`, creating it with given layout if needed when
+/// opened for writing
+pub fn open(path: &Path, layout: StorageLayout, mode: AccessMode) -> io::Result<Arc<dyn Storage>> {
+    Ok(match layout {
+        StorageLayout::Directory => Arc::new(DirectoryStorage::open(path, mode)?),
+        StorageLayout::SingleFile => Arc::new(ContainerStorage::open(path, mode)?),
+        StorageLayout::Memory if mode == AccessMode::ReadWrite => {
+            Arc::new(MemoryStorage::default())
+        }
+        StorageLayout::Memory => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "An in-memory database only exists once opened for writing",
+            ));
+        }
+    })
+}
+
+/// Copies the database stored at `source` to `destination`, which must not exist, using the
+/// other layout. Changes left in the write-ahead log of the source are recovered first. Returns
+/// the layout of the copy.
+pub fn convert(source: &Path, destination: &Path) -> io::Result<StorageLayout> {
+    let layout = StorageLayout::detect(source).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No database found at {}", source.display()),
+        )
+    })?;
+    if destination.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", destination.display()),
+        ));
+    }
+    let target_layout = match layout {
+        StorageLayout::Directory => StorageLayout::SingleFile,
+        StorageLayout::SingleFile => StorageLayout::Directory,
+        StorageLayout::Memory => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "An in-memory database cannot be converted",
+            ));
+        }
+    };
+    let source = open(source, layout, AccessMode::ReadWrite)?;
+    wal::recover(&*source)?;
+    let target = open(destination, target_layout, AccessMode::ReadWrite)?;
+    copy_files(&*source, &*target)?;
+    Ok(target_layout)
+}
+
+/// Copies the files of a database to `destination`, which must not exist, using the same
+/// layout. The write-ahead log is not copied, its changes must have been recovered already.
+pub fn backup(source: &dyn Storage, destination: &Path, layout: StorageLayout) -> io::Result<()> {
+    if destination.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", destination.display()),
+        ));
+    }
+    let target = open(destination, layout, AccessMode::ReadWrite)?;
+    copy_files(source, &*target)
+}
+
+/// Copies every file of a storage to another one, in chunks of `COPY_CHUNK_SIZE`
+fn copy_files(source: &dyn Storage, target: &dyn Storage) -> io::Result<()> {
+    let mut buffer = vec![0; COPY_CHUNK_SIZE];
+    for name in source.list()? {
+        let mut from = source.open_file(&name)?;
+        let mut to = target.open_file(&name)?;
+        let size = from.size()?;
+        let mut offset = 0;
+        while offset < size {
+            let chunk = &mut buffer[..COPY_CHUNK_SIZE.min((size - offset) as usize)];
+            from.read_at(offset, chunk)?;
+            to.write_at(offset, chunk)?;
+            offset += chunk.len() as u64;
+        }
+        to.sync()?;
+    }
+    Ok(())
+}
+
+/// Database stored as a directory tree, one file per file of the database
+pub struct DirectoryStorage {
+    root: PathBuf,
+    lock: DirectoryLock,
+}
+
+impl DirectoryStorage {
+    /// Locks the directory of a database, creating it first when opened for writing. Writers
+    /// remove the temporary files left by interrupted writes, see `utils::atomic_file`.
+    pub fn open(root: &Path, mode: AccessMode) -> io::Result<Self> {
+        if mode == AccessMode::ReadWrite {
+            std::fs::create_dir_all(root)?;
+        }
+        let lock = DirectoryLock::acquire(root, mode)?;
+        if mode == AccessMode::ReadWrite {
+            atomic_file::remove_temporary_files(root)?;
+            for entry in std::fs::read_dir(root)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    atomic_file::remove_temporary_files(&entry.path())?;
+                }
+            }
+        }
+        Ok(DirectoryStorage {
+            root: root.to_path_buf(),
+            lock,
+        })
+    }
+
+    /// Opens a file of the directory, creating it and its directory if needed when writing
+    fn open_path(&self, path: &Path) -> io::Result<File> {
+        let writable = self.mode() == AccessMode::ReadWrite;
+        if writable && let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .create(writable)
+            .truncate(false)
+            .open(path)
+    }
+
+    /// Adds the files of a directory and its subdirectories to `names`
+    fn list_directory(&self, directory: &Path, names: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in std::fs::read_dir(self.root.join(directory))? {
+            let entry = entry?;
+            let name = directory.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                self.list_directory(&name, names)?;
+            } else if name != Path::new(WAL_FILE_NAME) && name != Path::new(LOCK_FILE_NAME) {
+                names.push(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for DirectoryStorage {
+    fn mode(&self) -> AccessMode {
+        self.lock.mode()
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Box<dyn StorageFile>> {
+        Ok(Box::new(self.open_path(&self.root.join(name))?))
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        self.root.join(name).exists()
+    }
+
+    fn read(&self, name: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(name))
+    }
+
+    fn write(&self, name: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        atomic_file::write(&path, contents)
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        match std::fs::remove_file(self.root.join(name)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(self.root.join(from), self.root.join(to))
+    }
+
+    fn list(&self) -> io::Result<Vec<PathBuf>> {
+        let mut names = Vec::new();
+        self.list_directory(Path::new(""), &mut names)?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn open_log(&self) -> io::Result<Box<dyn StorageFile>> {
+        self.open_file(Path::new(WAL_FILE_NAME))
+    }
+
+    fn log_size(&self) -> io::Result<u64> {
+        match std::fs::metadata(self.root.join(WAL_FILE_NAME)) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl StorageFile for File {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buffer)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Database kept in memory, for throwaway databases such as those of tests. Nothing else can
+/// use it, so it needs no lock.
+#[derive(Default)]
+pub struct MemoryStorage {
+    files: Mutex<BTreeMap<PathBuf, MemoryFile>>,
+    log: MemoryFile,
+}
+
+/// Content of a file kept in memory, shared by its handles. A removed or replaced file stays
+/// readable through the handles opened before.
+#[derive(Clone, Default)]
+struct MemoryFile(Arc<Mutex<Vec<u8>>>);
+
+impl Storage for MemoryStorage {
+    fn mode(&self) -> AccessMode {
+        AccessMode::ReadWrite
+    }
+
+    fn open_file(&self, name: &Path) -> io::Result<Box<dyn StorageFile>> {
+        let mut files = self.files.lock().unwrap();
+        Ok(Box::new(
+            files.entry(name.to_path_buf()).or_default().clone(),
+        ))
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(name)
+    }
+
+    fn read(&self, name: &Path) -> io::Result<Vec<u8>> {
+        match self.files.lock().unwrap().get(name) {
+            Some(file) => Ok(file.0.lock().unwrap().clone()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist in memory", name.display()),
+            )),
+        }
+    }
+
+    fn write(&self, name: &Path, contents: &[u8]) -> io::Result<()> {
+        let file = MemoryFile(Arc::new(Mutex::new(contents.to_vec())));
+        self.files.lock().unwrap().insert(name.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        self.files.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = files.remove(from).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist in memory", from.display()),
+            )
+        })?;
+        files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self.files.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn open_log(&self) -> io::Result<Box<dyn StorageFile>> {
+        Ok(Box::new(self.log.clone()))
+    }
+
+    fn log_size(&self) -> io::Result<u64> {
+        Ok(self.log.0.lock().unwrap().len() as u64)
+    }
+}
+
+impl StorageFile for MemoryFile {
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.0.lock().unwrap().len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        let data = self.0.lock().unwrap();
+        let source = (offset as usize)
+            .checked_add(buffer.len())
+            .and_then(|end| data.get(offset as usize..end))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        buffer.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut content = self.0.lock().unwrap();
+        let end = offset as usize + data.len();
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.0.lock().unwrap().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// Tests which need real files work in a directory of their own, named after the test and the
+// process so that concurrent runs do not share it. A directory left by an interrupted run is
+// emptied when created again.
+
+/// Empty directory `trusdb-<name>-<process id>` in the temporary directory of the system, removed
+/// with its content once dropped
+pub struct TempDirectory(PathBuf);
+
+impl TempDirectory {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("trusdb-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDirectory(path)
+    }
+}
+
+impl Deref for TempDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDirectory {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,365 @@
+use bincode::{Decode, Encode, config};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utils::storage::{Storage, StorageFile};
+
+// The write-ahead log is `wal.log`, in the database directory, or next to the file of a
+// single-file database (see `Storage::open_log`). It holds records one after the
+// other, each one being `[u32 length][u32 CRC-32 of the payload][bincode payload]`. A record that
+// is cut short or whose checksum does not match marks the end of the log: it was being written
+// when the process stopped, so neither it nor anything after it was ever committed.
+//
+// Changes are logged and synced before they reach the data files: at commit for committed pages,
+// and before a dirty page is evicted otherwise, along with the content it replaces. Recovery
+// writes the changes of every committed transaction again, and restores the pages of the one
+// which was running, as well as those of rolled back transactions.
+
+pub const WAL_FILE_NAME: &str = "wal.log";
+
+/// Once the log is larger than this, it is truncated at the end of the next commit, after every
+/// data file has been synced
+pub const CHECKPOINT_SIZE: u64 = 4 << 20;
+
+#[derive(Encode, Decode, Debug, PartialEq)]
+pub enum WalRecord {
+    /// Content of a page before the running transaction changed it, logged before the change
+    /// reaches the data file. Empty if the page did not exist yet, in which case undoing the
+    /// change truncates the file.
+    Undo {
+        file: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Content of a page once changed by the running transaction
+    Redo {
+        file: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// New content of a whole file, such as a table schema
+    File { file: String, contents: Vec<u8> },
+    /// Every record since the previous commit belongs to a committed transaction
+    Commit,
+    /// Every record since the previous commit belongs to a rolled back transaction, whose pages
+    /// are being restored
+    Rollback,
+    /// Start of the changes made after a savepoint of the running transaction
+    Savepoint { id: u64 },
+    /// Every record since the given savepoint belongs to changes which were rolled back, whose
+    /// pages are being restored. The savepoint itself is kept.
+    RollbackToSavepoint { id: u64 },
+}
+
+pub struct Wal {
+    file: Box<dyn StorageFile>,
+    /// Records appended since the last sync
+    pending: Vec<u8>,
+    size: u64,
+}
+
+impl Wal {
+    /// Opens the log of a database, which must have been recovered first
+    pub fn open(storage: &dyn Storage) -> io::Result<Self> {
+        let mut file = storage.open_log()?;
+        let size = file.size()?;
+        Ok(Wal {
+            file,
+            pending: Vec::new(),
+            size,
+        })
+    }
+
+    /// Size of the log, including records that are not synced yet
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        let payload =
+            bincode::encode_to_vec(record, config::standard()).map_err(io::Error::other)?;
+        self.pending.extend((payload.len() as u32).to_le_bytes());
+        self.pending.extend(crc32fast::hash(&payload).to_le_bytes());
+        self.pending.extend(&payload);
+        self.size += 8 + payload.len() as u64;
+        Ok(())
+    }
+
+    /// Makes every appended record durable
+    pub fn sync(&mut self) -> io::Result<()> {
+        let offset = self.size - self.pending.len() as u64;
+        self.file.write_at(offset, &self.pending)?;
+        self.pending.clear();
+        self.file.sync()
+    }
+
+    /// Empties the log, once every change it holds is durable in the data files
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        self.file.set_size(0)?;
+        self.size = 0;
+        self.file.sync()
+    }
+}
+
+/// Name of a file in the log: its path within the database, so that the database can be moved
+pub fn file_name(name: &Path) -> String {
+    name.to_string_lossy().into_owned()
+}
+
+/// What recovery found in the log
+#[derive(Debug, Default, PartialEq)]
+pub struct Recovery {
+    /// Transactions whose changes were written again
+    pub committed: usize,
+    /// Pages of unfinished or rolled back transactions restored to their previous content, or
+    /// removed
+    pub undone_pages: usize,
+}
+
+/// Whether the log of a database holds records, which `recover` must apply before the files of
+/// the database can be read
+pub fn needs_recovery(storage: &dyn Storage) -> io::Result<bool> {
+    Ok(storage.log_size()? > 0)
+}
+
+/// Brings the files of a database back to their state at the last commit, then empties its log.
+/// Pages of files which no longer exist are skipped, as they belonged to a dropped table or
+/// index.
+pub fn recover(storage: &dyn Storage) -> io::Result<Recovery> {
+    if !needs_recovery(storage)? {
+        return Ok(Recovery::default());
+    }
+    let mut log = storage.open_log()?;
+    let mut content = vec![0; log.size()? as usize];
+    log.read_at(0, &mut content)?;
+    let mut recovery = Recovery::default();
+    let mut written = BTreeSet::new();
+    let mut transaction = Vec::new();
+    for record in read_records(&content) {
+        match record {
+            WalRecord::Commit => (),
+            WalRecord::Rollback => {
+                let records = std::mem::take(&mut transaction);
+                recovery.undone_pages += undo_records(storage, records, &mut written)?;
+                continue;
+            }
+            WalRecord::RollbackToSavepoint { id } => {
+                let start = transaction
+                    .iter()
+                    .rposition(|record| *record == WalRecord::Savepoint { id })
+                    .map_or(0, |position| position + 1);
+                let records = transaction.split_off(start);
+                recovery.undone_pages += undo_records(storage, records, &mut written)?;
+                continue;
+            }
+            record => {
+                transaction.push(record);
+                continue;
+            }
+        }
+        for record in transaction.drain(..) {
+            match record {
+                WalRecord::Redo { file, offset, data } => {
+                    write_page(storage, Path::new(&file), offset, &data, &mut written)?
+                }
+                WalRecord::File { file, contents } => storage.write(Path::new(&file), &contents)?,
+                WalRecord::Undo { .. }
+                | WalRecord::Commit
+                | WalRecord::Rollback
+                | WalRecord::Savepoint { .. }
+                | WalRecord::RollbackToSavepoint { .. } => (),
+            }
+        }
+        recovery.committed += 1;
+    }
+    recovery.undone_pages += undo_records(storage, transaction, &mut written)?;
+    for name in written {
+        storage.open_file(&name)?.sync()?;
+    }
+    log.set_size(0)?;
+    log.sync()?;
+    Ok(recovery)
+}
+
+/// Restores a page to the content it had before a transaction changed it, removing it if `data`
+/// is empty
+pub fn undo(storage: &dyn Storage, name: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+    undo_page(storage, name, offset, data, &mut BTreeSet::new())
+}
+
+/// Restores the pages changed by records of a transaction, returning how many there were
+fn undo_records(
+    storage: &dyn Storage,
+    records: Vec<WalRecord>,
+    written: &mut BTreeSet<PathBuf>,
+) -> io::Result<usize> {
+    let mut undone_pages = 0;
+    // Undoing in reverse order restores the oldest content of every page
+    for record in records.into_iter().rev() {
+        if let WalRecord::Undo { file, offset, data } = record {
+            undo_page(storage, Path::new(&file), offset, &data, written)?;
+            undone_pages += 1;
+        }
+    }
+    Ok(undone_pages)
+}
+
+fn undo_page(
+    storage: &dyn Storage,
+    name: &Path,
+    offset: u64,
+    data: &[u8],
+    written: &mut BTreeSet<PathBuf>,
+) -> io::Result<()> {
+    match data.is_empty() {
+        true => truncate_file(storage, name, offset, written),
+        false => write_page(storage, name, offset, data, written),
+    }
+}
+
+fn write_page(
+    storage: &dyn Storage,
+    name: &Path,
+    offset: u64,
+    data: &[u8],
+    written: &mut BTreeSet<PathBuf>,
+) -> io::Result<()> {
+    if !storage.exists(name) {
+        return Ok(());
+    }
+    storage.open_file(name)?.write_at(offset, data)?;
+    written.insert(name.to_path_buf());
+    Ok(())
+}
+
+fn truncate_file(
+    storage: &dyn Storage,
+    name: &Path,
+    size: u64,
+    written: &mut BTreeSet<PathBuf>,
+) -> io::Result<()> {
+    if !storage.exists(name) {
+        return Ok(());
+    }
+    let mut file = storage.open_file(name)?;
+    if file.size()? > size {
+        file.set_size(size)?;
+        written.insert(name.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Decodes the records of a log, up to the first one that is incomplete or corrupted
+fn read_records(content: &[u8]) -> Vec<WalRecord> {
+    let mut records = Vec::new();
+    let mut position = 0;
+    while let Some(header) = content.get(position..position + 8) {
+        let length = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let Some(payload) = content.get(position + 8..position + 8 + length) else {
+            break;
+        };
+        if crc32fast::hash(payload) != checksum {
+            break;
+        }
+        let Ok((record, _)) = bincode::decode_from_slice(payload, config::standard()) else {
+            break;
+        };
+        records.push(record);
+        position += 8 + length;
+    }
+    if position < content.len() {
+        log::warn!(
+            "Ignored the last {} bytes of the write-ahead log, which were never committed",
+            content.len() - position
+        );
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::directory_lock::AccessMode;
+    use crate::utils::storage::DirectoryStorage;
+    use crate::utils::temp_directory::TempDirectory;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn test_recovery_redoes_committed_and_undoes_other_changes() {
+        let directory = TempDirectory::new("wal");
+        std::fs::write(directory.join("data.bin"), [0; 8]).unwrap();
+        let page = |offset: u64, byte: u8| (String::from("data.bin"), offset, vec![byte; 4]);
+        let storage = DirectoryStorage::open(&directory, AccessMode::ReadWrite).unwrap();
+
+        let mut wal = Wal::open(&storage).unwrap();
+        let (file, offset, data) = page(0, 1);
+        wal.append(&WalRecord::Redo { file, offset, data }).unwrap();
+        wal.append(&WalRecord::File {
+            file: String::from("meta.ron"),
+            contents: b"v2".to_vec(),
+        })
+        .unwrap();
+        // The committed transaction also evicted a page of another file after a savepoint,
+        // then rolled back to the savepoint
+        let index_page = |byte: u8| (String::from("index.bin"), 0, vec![byte; 4]);
+        let (file, offset, data) = index_page(1);
+        wal.append(&WalRecord::Redo { file, offset, data }).unwrap();
+        wal.append(&WalRecord::Savepoint { id: 3 }).unwrap();
+        let (file, offset, data) = index_page(1);
+        wal.append(&WalRecord::Undo { file, offset, data }).unwrap();
+        let (file, offset, data) = index_page(6);
+        wal.append(&WalRecord::Redo { file, offset, data }).unwrap();
+        wal.append(&WalRecord::RollbackToSavepoint { id: 3 })
+            .unwrap();
+        wal.append(&WalRecord::Commit).unwrap();
+        // A rolled back transaction evicted page 0, and was interrupted while restoring it
+        let (file, offset, data) = page(0, 1);
+        wal.append(&WalRecord::Undo { file, offset, data }).unwrap();
+        let (file, offset, data) = page(0, 7);
+        wal.append(&WalRecord::Redo { file, offset, data }).unwrap();
+        wal.append(&WalRecord::Rollback).unwrap();
+        // The unfinished transaction evicted page 1 after logging its previous content, and
+        // created page 2
+        let (file, offset, data) = page(4, 0);
+        wal.append(&WalRecord::Undo { file, offset, data }).unwrap();
+        let (file, offset, data) = page(4, 2);
+        wal.append(&WalRecord::Redo { file, offset, data }).unwrap();
+        let (file, offset, _) = page(8, 0);
+        let data = Vec::new();
+        wal.append(&WalRecord::Undo { file, offset, data }).unwrap();
+        wal.sync().unwrap();
+        std::fs::write(
+            directory.join("data.bin"),
+            [7, 7, 7, 7, 2, 2, 2, 2, 3, 3, 3, 3],
+        )
+        .unwrap();
+        std::fs::write(directory.join("index.bin"), [6; 4]).unwrap();
+        // Torn record, which must be ignored
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(directory.join(WAL_FILE_NAME))
+            .unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let recovery = recover(&storage).unwrap();
+        assert_eq!(
+            recovery,
+            Recovery {
+                committed: 1,
+                undone_pages: 4
+            }
+        );
+        assert_eq!(
+            std::fs::read(directory.join("data.bin")).unwrap(),
+            [1, 1, 1, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(std::fs::read(directory.join("index.bin")).unwrap(), [1; 4]);
+        assert_eq!(std::fs::read(directory.join("meta.ron")).unwrap(), b"v2");
+        assert!(Wal::open(&storage).unwrap().size() == 0);
+    }
+}
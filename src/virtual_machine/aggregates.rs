@@ -0,0 +1,196 @@
+use crate::sql_compilator::expression::{AGGREGATE_FUNCTIONS, Expression};
+use crate::utils::file_handler::Value;
+use crate::virtual_machine::evaluator::{self, ColumnBinding, RowContext};
+use crate::virtual_machine::instruction_processor::ExecutionError;
+use std::cmp::Ordering;
+
+/// Replaces every aggregate call of an expression by its result over the rows of a group, so that
+/// what remains can be evaluated against any row of the group
+pub fn substitute_aggregates(
+    expression: &Expression,
+    bindings: &[ColumnBinding],
+    rows: &[Vec<Value>],
+) -> Result<Expression, ExecutionError> {
+    let substitute_all = |expressions: &[Expression]| {
+        expressions
+            .iter()
+            .map(|expression| substitute_aggregates(expression, bindings, rows))
+            .collect::<Result<Vec<Expression>, ExecutionError>>()
+    };
+    let substitute = |expression: &Expression| -> Result<Box<Expression>, ExecutionError> {
+        Ok(Box::new(substitute_aggregates(expression, bindings, rows)?))
+    };
+    Ok(match expression {
+        Expression::Function { name, arguments }
+            if AGGREGATE_FUNCTIONS.contains(&name.as_str()) =>
+        {
+            Expression::Literal(compute_aggregate(name, arguments, bindings, rows)?)
+        }
+        Expression::Function { name, arguments } => Expression::Function {
+            name: name.clone(),
+            arguments: substitute_all(arguments)?,
+        },
+        Expression::Literal(_) | Expression::Column { .. } => expression.clone(),
+        Expression::Unary { operator, operand } => Expression::Unary {
+            operator: operator.clone(),
+            operand: substitute(operand)?,
+        },
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => Expression::Binary {
+            left: substitute(left)?,
+            operator: operator.clone(),
+            right: substitute(right)?,
+        },
+        Expression::IsNull { operand, negated } => Expression::IsNull {
+            operand: substitute(operand)?,
+            negated: *negated,
+        },
+        Expression::IsTruth {
+            operand,
+            expected,
+            negated,
+        } => Expression::IsTruth {
+            operand: substitute(operand)?,
+            expected: *expected,
+            negated: *negated,
+        },
+        Expression::IsDistinctFrom {
+            left,
+            right,
+            negated,
+        } => Expression::IsDistinctFrom {
+            left: substitute(left)?,
+            right: substitute(right)?,
+            negated: *negated,
+        },
+        Expression::InList {
+            operand,
+            list,
+            negated,
+        } => Expression::InList {
+            operand: substitute(operand)?,
+            list: substitute_all(list)?,
+            negated: *negated,
+        },
+        Expression::Collate { operand, collation } => Expression::Collate {
+            operand: substitute(operand)?,
+            collation: *collation,
+        },
+        Expression::Array(elements) => Expression::Array(substitute_all(elements)?),
+        Expression::Subscript { operand, index } => Expression::Subscript {
+            operand: substitute(operand)?,
+            index: substitute(index)?,
+        },
+        Expression::Slice {
+            operand,
+            lower,
+            upper,
+        } => Expression::Slice {
+            operand: substitute(operand)?,
+            lower: lower.as_deref().map(substitute).transpose()?,
+            upper: upper.as_deref().map(substitute).transpose()?,
+        },
+        Expression::Quantified {
+            left,
+            operator,
+            all,
+            array,
+        } => Expression::Quantified {
+            left: substitute(left)?,
+            operator: operator.clone(),
+            all: *all,
+            array: substitute(array)?,
+        },
+    })
+}
+
+/// Computes an aggregate over the rows of a group. Like in PostgreSQL, NULL inputs are skipped,
+/// and every aggregate but `count` gives NULL when there is nothing left to aggregate.
+fn compute_aggregate(
+    name: &str,
+    arguments: &[Expression],
+    bindings: &[ColumnBinding],
+    rows: &[Vec<Value>],
+) -> Result<Value, ExecutionError> {
+    // `count(*)` counts rows, whatever they hold
+    if name == "count" && arguments.is_empty() {
+        return Ok(Value::Integer(rows.len() as i64));
+    }
+    let [argument] = arguments else {
+        return Err(ExecutionError::WrongArgumentCount {
+            name: name.to_string(),
+            expected: String::from("1"),
+            found: arguments.len(),
+        });
+    };
+    let mut values = Vec::new();
+    for row in rows {
+        let context = RowContext {
+            bindings,
+            values: row,
+        };
+        match evaluator::evaluate(argument, &context)? {
+            Value::Null => {}
+            value => values.push(value),
+        }
+    }
+    match name {
+        "count" => Ok(Value::Integer(values.len() as i64)),
+        "sum" => sum(name, &values),
+        "avg" => match sum(name, &values)? {
+            Value::Integer(total) => Ok(Value::Real(total as f64 / values.len() as f64)),
+            Value::Real(total) => Ok(Value::Real(total / values.len() as f64)),
+            _ => Ok(Value::Null),
+        },
+        "min" | "max" => {
+            let collation = evaluator::collation_of(argument, bindings).unwrap_or_default();
+            let expected = if name == "min" {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+            Ok(values
+                .into_iter()
+                .reduce(|best, value| {
+                    if evaluator::compare_for_sort(&value, &best, collation) == expected {
+                        value
+                    } else {
+                        best
+                    }
+                })
+                .unwrap_or(Value::Null))
+        }
+        _ => Err(ExecutionError::UnknownFunction {
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// Sums numbers, staying an integer as long as every value is one
+fn sum(name: &str, values: &[Value]) -> Result<Value, ExecutionError> {
+    let mut total = match values.first() {
+        None => return Ok(Value::Null),
+        Some(_) => Value::Integer(0),
+    };
+    for value in values {
+        total = match (total, value) {
+            (Value::Integer(total), Value::Integer(integer)) => total
+                .checked_add(*integer)
+                .map(Value::Integer)
+                .ok_or(ExecutionError::IntegerOverflow)?,
+            (Value::Integer(total), Value::Real(real)) => Value::Real(total as f64 + real),
+            (Value::Real(total), Value::Integer(integer)) => Value::Real(total + *integer as f64),
+            (Value::Real(total), Value::Real(real)) => Value::Real(total + real),
+            (_, other) => {
+                return Err(ExecutionError::InvalidArgument {
+                    name: name.to_string(),
+                    reason: format!("expected a number, found {other}"),
+                });
+            }
+        };
+    }
+    Ok(total)
+}
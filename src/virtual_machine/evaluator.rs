@@ -0,0 +1,860 @@
+use crate::sql_compilator::expression::{BinaryOperator, Expression, UnaryOperator};
+use crate::utils::collation::Collation;
+use crate::utils::file_handler::{EnumType, Value};
+use crate::utils::json;
+use crate::virtual_machine::functions;
+use crate::virtual_machine::instruction_processor::ExecutionError;
+use std::cmp::Ordering;
+
+/// Describes one column of the rows flowing through the executor
+#[derive(Debug, Clone)]
+pub struct ColumnBinding {
+    /// Name (or alias) of the table or function the column comes from
+    pub table: Option<String>,
+    pub name: String,
+    pub collation: Collation,
+    /// Type of the column when it holds labels of a user-defined enum
+    pub enum_type: Option<EnumType>,
+}
+
+/// A row along with the description of its columns, against which expressions are evaluated
+pub struct RowContext<'row> {
+    pub bindings: &'row [ColumnBinding],
+    pub values: &'row [Value],
+}
+
+impl RowContext<'_> {
+    /// Context used to evaluate expressions that do not reference any column
+    pub fn empty() -> RowContext<'static> {
+        RowContext {
+            bindings: &[],
+            values: &[],
+        }
+    }
+
+    fn resolve(&self, table: Option<&str>, name: &str) -> Result<&Value, ExecutionError> {
+        Ok(&self.values[resolve_position(self.bindings, table, name)?])
+    }
+}
+
+/// Finds which of the bindings a (possibly qualified) column name refers to
+pub fn resolve_position(
+    bindings: &[ColumnBinding],
+    table: Option<&str>,
+    name: &str,
+) -> Result<usize, ExecutionError> {
+    {
+        let mut matching = bindings.iter().enumerate().filter(|(_, binding)| {
+            binding.name.eq_ignore_ascii_case(name)
+                && table.is_none_or(|table| {
+                    binding
+                        .table
+                        .as_ref()
+                        .is_some_and(|binding_table| binding_table.eq_ignore_ascii_case(table))
+                })
+        });
+        let column_name = match table {
+            Some(table) => format!("{table}.{name}"),
+            None => name.to_string(),
+        };
+        let (position, _) = matching
+            .next()
+            .ok_or_else(|| ExecutionError::ColumnNotFound {
+                column_name: column_name.clone(),
+            })?;
+        if matching.next().is_some() {
+            return Err(ExecutionError::AmbiguousColumn { column_name });
+        }
+        Ok(position)
+    }
+}
+
+/// Returns the collation an expression is compared with: the explicit one of a COLLATE clause,
+/// else the one of the column it reads, else the one of its first operand that has one
+pub fn collation_of(expression: &Expression, bindings: &[ColumnBinding]) -> Option<Collation> {
+    match expression {
+        Expression::Collate { collation, .. } => Some(*collation),
+        Expression::Column { table, name } => resolve_position(bindings, table.as_deref(), name)
+            .ok()
+            .map(|position| bindings[position].collation),
+        Expression::Literal(_) => None,
+        _ => expression
+            .children()
+            .into_iter()
+            .find_map(|child| collation_of(child, bindings)),
+    }
+}
+
+/// Returns the enum type of an expression directly reading an enum column
+fn enum_type_of<'binding>(
+    expression: &Expression,
+    bindings: &'binding [ColumnBinding],
+) -> Option<&'binding EnumType> {
+    match expression {
+        Expression::Column { table, name } => resolve_position(bindings, table.as_deref(), name)
+            .ok()
+            .and_then(|position| bindings[position].enum_type.as_ref()),
+        Expression::Collate { operand, .. } => enum_type_of(operand, bindings),
+        _ => None,
+    }
+}
+
+/// Evaluates an expression compared with another one. When the other one reads an enum column,
+/// a text is converted to a label of its type, as PostgreSQL casts literals to the type of the
+/// column they are compared with.
+fn evaluate_compared(
+    expression: &Expression,
+    compared_with: &Expression,
+    row: &RowContext,
+) -> Result<Value, ExecutionError> {
+    match (
+        evaluate(expression, row)?,
+        enum_type_of(compared_with, row.bindings),
+    ) {
+        (Value::Text(label), Some(enum_type)) => to_enum_value(enum_type, &label),
+        (value, _) => Ok(value),
+    }
+}
+
+pub fn to_enum_value(enum_type: &EnumType, label: &str) -> Result<Value, ExecutionError> {
+    enum_type
+        .value_of(label)
+        .ok_or_else(|| ExecutionError::InvalidEnumLabel {
+            type_name: enum_type.name.clone(),
+            label: label.to_string(),
+        })
+}
+
+/// Collation used to compare two expressions: an explicit COLLATE clause wins over the
+/// collation of a column, and the left operand wins over the right one
+fn comparison_collation(
+    left: &Expression,
+    right: &Expression,
+    bindings: &[ColumnBinding],
+) -> Collation {
+    let is_explicit = |expression: &Expression| matches!(expression, Expression::Collate { .. });
+    let (first, second) = if is_explicit(right) && !is_explicit(left) {
+        (right, left)
+    } else {
+        (left, right)
+    };
+    collation_of(first, bindings)
+        .or_else(|| collation_of(second, bindings))
+        .unwrap_or_default()
+}
+
+pub fn evaluate(expression: &Expression, row: &RowContext) -> Result<Value, ExecutionError> {
+    match expression {
+        Expression::Literal(value) => Ok(value.clone()),
+        Expression::Column { table, name } => row.resolve(table.as_deref(), name).cloned(),
+        Expression::Unary { operator, operand } => {
+            let operand = evaluate(operand, row)?;
+            evaluate_unary(operator, operand)
+        }
+        Expression::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let collation = comparison_collation(left, right, row.bindings);
+            let (left, right) = if operator.is_comparison() {
+                (
+                    evaluate_compared(left, right, row)?,
+                    evaluate_compared(right, left, row)?,
+                )
+            } else {
+                (evaluate(left, row)?, evaluate(right, row)?)
+            };
+            evaluate_binary(operator, left, right, collation)
+        }
+        Expression::Collate { operand, .. } => evaluate(operand, row),
+        Expression::Array(elements) => elements
+            .iter()
+            .map(|element| evaluate(element, row))
+            .collect::<Result<Vec<Value>, ExecutionError>>()
+            .and_then(array_of),
+        Expression::Subscript { operand, index } => {
+            match (evaluate(operand, row)?, evaluate(index, row)?) {
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+                (Value::Array(elements), Value::Integer(index)) => Ok(usize::try_from(index)
+                    .ok()
+                    .and_then(|index| index.checked_sub(1))
+                    .and_then(|position| elements.into_iter().nth(position))
+                    .unwrap_or(Value::Null)),
+                (array, index) => Err(ExecutionError::InvalidOperands {
+                    operator: String::from("[]"),
+                    left: array.to_string(),
+                    right: index.to_string(),
+                }),
+            }
+        }
+        Expression::Slice {
+            operand,
+            lower,
+            upper,
+        } => {
+            let bound = |bound: &Option<Box<Expression>>| {
+                bound
+                    .as_deref()
+                    .map(|bound| evaluate(bound, row))
+                    .transpose()
+            };
+            match (evaluate(operand, row)?, bound(lower)?, bound(upper)?) {
+                (Value::Null, _, _) | (_, Some(Value::Null), _) | (_, _, Some(Value::Null)) => {
+                    Ok(Value::Null)
+                }
+                (
+                    Value::Array(elements),
+                    lower @ (None | Some(Value::Integer(_))),
+                    upper @ (None | Some(Value::Integer(_))),
+                ) => {
+                    let to_bound = |bound: Option<Value>, default: i64| match bound {
+                        Some(Value::Integer(bound)) => bound,
+                        _ => default,
+                    };
+                    // Bounds are inclusive and start at 1; out of range parts are ignored. They
+                    // are clamped to the elements first, as any integer is a valid bound.
+                    let length = elements.len() as i64;
+                    let first = to_bound(lower, 1).clamp(1, length + 1);
+                    let last = to_bound(upper, length).clamp(0, length);
+                    Ok(Value::Array(
+                        elements
+                            .into_iter()
+                            .skip(first as usize - 1)
+                            .take((last - first + 1).max(0) as usize)
+                            .collect(),
+                    ))
+                }
+                (array, _, _) => Err(ExecutionError::InvalidOperand {
+                    operator: String::from("[:]"),
+                    operand: array.to_string(),
+                }),
+            }
+        }
+        Expression::Quantified {
+            left,
+            operator,
+            all,
+            array,
+        } => {
+            let elements = match evaluate(array, row)? {
+                Value::Null => return Ok(Value::Null),
+                Value::Array(elements) => elements,
+                other => {
+                    return Err(ExecutionError::InvalidOperand {
+                        operator: String::from(if *all { "ALL" } else { "ANY" }),
+                        operand: other.to_string(),
+                    });
+                }
+            };
+            let left_value = match (evaluate(left, row)?, enum_type_of(array, row.bindings)) {
+                (Value::Text(label), Some(enum_type)) => to_enum_value(enum_type, &label)?,
+                (value, _) => value,
+            };
+            let collation = comparison_collation(left, array, row.bindings);
+            // ANY is a chain of ORs, and ALL a chain of ANDs, of the comparisons with each element
+            let combinator = if *all {
+                BinaryOperator::And
+            } else {
+                BinaryOperator::Or
+            };
+            let mut result = Value::Bool(*all);
+            for element in elements {
+                let comparison = evaluate_binary(operator, left_value.clone(), element, collation)?;
+                result = evaluate_logical(&combinator, result, comparison)?;
+                if result == Value::Bool(!*all) {
+                    break;
+                }
+            }
+            Ok(result)
+        }
+        Expression::IsNull { operand, negated } => {
+            let is_null = evaluate(operand, row)? == Value::Null;
+            Ok(Value::Bool(is_null != *negated))
+        }
+        Expression::IsTruth {
+            operand,
+            expected,
+            negated,
+        } => {
+            let is_expected = to_truth_value(evaluate(operand, row)?)? == Some(*expected);
+            Ok(Value::Bool(is_expected != *negated))
+        }
+        Expression::IsDistinctFrom {
+            left,
+            right,
+            negated,
+        } => {
+            let collation = comparison_collation(left, right, row.bindings);
+            let is_distinct = !is_not_distinct(
+                &evaluate_compared(left, right, row)?,
+                &evaluate_compared(right, left, row)?,
+                collation,
+            )?;
+            Ok(Value::Bool(is_distinct != *negated))
+        }
+        Expression::InList {
+            operand,
+            list,
+            negated,
+        } => {
+            let operand_value = evaluate(operand, row)?;
+            let operand_value = match (operand_value, enum_type_of(operand, row.bindings)) {
+                (Value::Text(label), Some(enum_type)) => to_enum_value(enum_type, &label)?,
+                (value, _) => value,
+            };
+            let mut result = Value::Bool(false);
+            for element in list {
+                let is_equal = evaluate_binary(
+                    &BinaryOperator::Equal,
+                    operand_value.clone(),
+                    evaluate_compared(element, operand, row)?,
+                    comparison_collation(operand, element, row.bindings),
+                )?;
+                result = evaluate_logical(&BinaryOperator::Or, result, is_equal)?;
+                if result == Value::Bool(true) {
+                    break;
+                }
+            }
+            if *negated {
+                evaluate_unary(&UnaryOperator::Not, result)
+            } else {
+                Ok(result)
+            }
+        }
+        Expression::Function { name, arguments } => match name.as_str() {
+            // Evaluated lazily: arguments after the first non-null one are never computed
+            "coalesce" | "ifnull" => {
+                if name == "ifnull" && arguments.len() != 2 {
+                    return Err(ExecutionError::WrongArgumentCount {
+                        name: name.to_string(),
+                        expected: String::from("2"),
+                        found: arguments.len(),
+                    });
+                }
+                for argument in arguments {
+                    let value = evaluate(argument, row)?;
+                    if value != Value::Null {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            _ => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| evaluate(argument, row))
+                    .collect::<Result<Vec<Value>, ExecutionError>>()?;
+                functions::call_scalar_function(name, arguments)
+            }
+        },
+    }
+}
+
+/// Reads a value used as a condition: NULL is the unknown truth value
+fn to_truth_value(value: Value) -> Result<Option<bool>, ExecutionError> {
+    match value {
+        Value::Bool(boolean) => Ok(Some(boolean)),
+        Value::Null => Ok(None),
+        other => Err(ExecutionError::NotABoolean {
+            found: other.to_string(),
+        }),
+    }
+}
+
+/// Equality where NULL is equal to NULL and different from any other value
+pub fn is_not_distinct(
+    left: &Value,
+    right: &Value,
+    collation: Collation,
+) -> Result<bool, ExecutionError> {
+    match (left, right) {
+        (Value::Null, Value::Null) => Ok(true),
+        (Value::Null, _) | (_, Value::Null) => Ok(false),
+        _ => Ok(compare_values(left, right, collation)?.is_some_and(Ordering::is_eq)),
+    }
+}
+
+/// Evaluates a predicate (WHERE clause): only a true result keeps the row
+pub fn is_true(expression: &Expression, row: &RowContext) -> Result<bool, ExecutionError> {
+    Ok(to_truth_value(evaluate(expression, row)?)? == Some(true))
+}
+
+fn evaluate_unary(operator: &UnaryOperator, operand: Value) -> Result<Value, ExecutionError> {
+    match (operator, operand) {
+        (_, Value::Null) => Ok(Value::Null),
+        (UnaryOperator::Not, Value::Bool(boolean)) => Ok(Value::Bool(!boolean)),
+        (UnaryOperator::Minus, Value::Integer(integer)) => integer
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or(ExecutionError::IntegerOverflow),
+        (UnaryOperator::Minus, Value::Real(real)) => Ok(Value::Real(-real)),
+        (operator, operand) => Err(ExecutionError::InvalidOperand {
+            operator: format!("{operator:?}"),
+            operand: operand.to_string(),
+        }),
+    }
+}
+
+fn evaluate_binary(
+    operator: &BinaryOperator,
+    left: Value,
+    right: Value,
+    collation: Collation,
+) -> Result<Value, ExecutionError> {
+    if matches!(operator, BinaryOperator::And | BinaryOperator::Or) {
+        return evaluate_logical(operator, left, right);
+    }
+    // Any other operator gives NULL as soon as one of its operands is NULL
+    if left == Value::Null || right == Value::Null {
+        return Ok(Value::Null);
+    }
+    let invalid_operands = |left: &Value, right: &Value| ExecutionError::InvalidOperands {
+        operator: format!("{operator:?}"),
+        left: left.to_string(),
+        right: right.to_string(),
+    };
+    let comparison = |expected: fn(Ordering) -> bool| {
+        compare_values(&left, &right, collation)?
+            .map(|ordering| Value::Bool(expected(ordering)))
+            .ok_or_else(|| invalid_operands(&left, &right))
+    };
+    match operator {
+        BinaryOperator::Equal => comparison(Ordering::is_eq),
+        BinaryOperator::NotEqual => comparison(Ordering::is_ne),
+        BinaryOperator::Less => comparison(Ordering::is_lt),
+        BinaryOperator::LessOrEqual => comparison(Ordering::is_le),
+        BinaryOperator::Greater => comparison(Ordering::is_gt),
+        BinaryOperator::GreaterOrEqual => comparison(Ordering::is_ge),
+        BinaryOperator::And | BinaryOperator::Or => unreachable!("handled by evaluate_logical"),
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => evaluate_arithmetic(operator, &left, &right)
+            .ok_or_else(|| invalid_operands(&left, &right))?,
+        BinaryOperator::Concat => Ok(Value::Text(format!(
+            "{}{}",
+            to_text(&left),
+            to_text(&right)
+        ))),
+        BinaryOperator::JsonGet | BinaryOperator::JsonGetText => {
+            let document = functions::to_json(&left, "->")?;
+            let step = match right {
+                Value::Text(key) => json::PathStep::Key(key),
+                Value::Integer(index) => json::PathStep::Index(index),
+                _ => return Err(invalid_operands(&left, &right)),
+            };
+            Ok(match json::lookup(&document, &step) {
+                None => Value::Null,
+                Some(node) if *operator == BinaryOperator::JsonGet => {
+                    Value::Json(json::encode(node))
+                }
+                Some(node) => json::to_sql_text(node),
+            })
+        }
+    }
+}
+
+/// Three-valued AND / OR: a NULL operand is unknown, so the result is only NULL when the known
+/// operands are not enough to decide
+fn evaluate_logical(
+    operator: &BinaryOperator,
+    left: Value,
+    right: Value,
+) -> Result<Value, ExecutionError> {
+    let (left, right) = (to_truth_value(left)?, to_truth_value(right)?);
+    // Value deciding the result on its own: false for AND, true for OR
+    let absorbing = *operator == BinaryOperator::Or;
+    let result = match (left, right) {
+        (Some(left), _) if left == absorbing => Some(absorbing),
+        (_, Some(right)) if right == absorbing => Some(absorbing),
+        (Some(_), Some(_)) => Some(!absorbing),
+        _ => None,
+    };
+    Ok(result.map(Value::Bool).unwrap_or(Value::Null))
+}
+
+/// Applies an arithmetic operator, returning `None` if operands are not numbers
+fn evaluate_arithmetic(
+    operator: &BinaryOperator,
+    left: &Value,
+    right: &Value,
+) -> Option<Result<Value, ExecutionError>> {
+    if let (Value::Integer(left), Value::Integer(right)) = (left, right) {
+        let result = match operator {
+            BinaryOperator::Plus => left.checked_add(*right),
+            BinaryOperator::Minus => left.checked_sub(*right),
+            BinaryOperator::Multiply => left.checked_mul(*right),
+            BinaryOperator::Divide | BinaryOperator::Modulo if *right == 0 => {
+                return Some(Err(ExecutionError::DivisionByZero));
+            }
+            BinaryOperator::Divide => left.checked_div(*right),
+            // Only the quotient of i64::MIN by -1 overflows, the remainder is 0
+            _ => Some(left.wrapping_rem(*right)),
+        };
+        return Some(
+            result
+                .map(Value::Integer)
+                .ok_or(ExecutionError::IntegerOverflow),
+        );
+    }
+    let (left, right) = (as_real(left)?, as_real(right)?);
+    let result = match operator {
+        BinaryOperator::Plus => left + right,
+        BinaryOperator::Minus => left - right,
+        BinaryOperator::Multiply => left * right,
+        BinaryOperator::Divide | BinaryOperator::Modulo if right == 0.0 => {
+            return Some(Err(ExecutionError::DivisionByZero));
+        }
+        BinaryOperator::Divide => left / right,
+        _ => left % right,
+    };
+    Some(Ok(Value::Real(result)))
+}
+
+fn as_real(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(integer) => Some(*integer as f64),
+        Value::Real(real) => Some(*real),
+        _ => None,
+    }
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Text(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Compares two non-null values, texts being compared with given collation. Returns `None` when
+/// they are of incomparable types. JSON documents compared with a scalar are first converted to
+/// the SQL value they hold.
+pub fn compare_values(
+    left: &Value,
+    right: &Value,
+    collation: Collation,
+) -> Result<Option<Ordering>, ExecutionError> {
+    let ordering = match (left, right) {
+        (Value::Integer(left), Value::Integer(right)) => Some(left.cmp(right)),
+        (Value::Integer(_) | Value::Real(_), Value::Integer(_) | Value::Real(_)) => {
+            as_real(left).partial_cmp(&as_real(right))
+        }
+        (Value::Text(left), Value::Text(right)) => Some(collation.compare(left, right)),
+        (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+        (Value::Uuid(left), Value::Uuid(right)) => Some(left.cmp(right)),
+        (Value::Blob(left), Value::Blob(right)) => Some(left.cmp(right)),
+        (Value::Array(left), Value::Array(right)) => Some(
+            left.iter()
+                .zip(right)
+                .map(|(left, right)| compare_for_sort(left, right, collation))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| left.len().cmp(&right.len())),
+        ),
+        (Value::Enum(left), Value::Enum(right))
+            if left.type_name.eq_ignore_ascii_case(&right.type_name) =>
+        {
+            Some(left.sort_order.total_cmp(&right.sort_order))
+        }
+        (Value::Uuid(left), Value::Text(text)) => Some(left.cmp(&parse_uuid(text)?)),
+        (Value::Text(text), Value::Uuid(right)) => Some(parse_uuid(text)?.cmp(right)),
+        (Value::Blob(left), Value::Text(text)) => Some(left.cmp(&parse_blob(text)?)),
+        (Value::Text(text), Value::Blob(right)) => Some(parse_blob(text)?.cmp(right)),
+        (Value::Json(left), Value::Json(right)) => Some(
+            json::decode(left)?
+                .to_string()
+                .cmp(&json::decode(right)?.to_string()),
+        ),
+        (Value::Json(document), scalar) => {
+            let converted = json::to_sql_value(&json::decode(document)?);
+            return if matches!(converted, Value::Json(_) | Value::Null) {
+                Ok(None)
+            } else {
+                compare_values(&converted, scalar, collation)
+            };
+        }
+        (_, Value::Json(_)) => {
+            return Ok(compare_values(right, left, collation)?.map(Ordering::reverse));
+        }
+        _ => None,
+    };
+    Ok(ordering)
+}
+
+/// Parses the textual form of a UUID (hyphenated or not, optionally within braces) into its 16
+/// bytes
+pub fn parse_uuid(text: &str) -> Result<[u8; 16], ExecutionError> {
+    uuid::Uuid::try_parse(text.trim())
+        .map(|uuid| uuid.into_bytes())
+        .map_err(|_| ExecutionError::InvalidUuid {
+            content: text.to_string(),
+        })
+}
+
+/// Parses the textual form of a byte string: `\x` followed by two hexadecimal digits per byte
+pub fn parse_blob(text: &str) -> Result<Vec<u8>, ExecutionError> {
+    let invalid_blob = || ExecutionError::InvalidBlob {
+        content: text.to_string(),
+    };
+    let digits = text.trim().strip_prefix("\\x").ok_or_else(invalid_blob)?;
+    if !digits.is_ascii() || digits.len() % 2 != 0 {
+        return Err(invalid_blob());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(&digits[start..start + 2], 16).map_err(|_| invalid_blob()))
+        .collect()
+}
+
+/// Builds an array whose elements share one type: integers become reals when mixed with them,
+/// and any other mix of types is refused. NULL elements are kept as they are.
+fn array_of(mut elements: Vec<Value>) -> Result<Value, ExecutionError> {
+    if elements
+        .iter()
+        .any(|element| matches!(element, Value::Real(_)))
+    {
+        for element in &mut elements {
+            if let Value::Integer(integer) = element {
+                *element = Value::Real(*integer as f64);
+            }
+        }
+    }
+    let mut non_null = elements.iter().filter(|element| **element != Value::Null);
+    if let Some(first) = non_null.next()
+        && let Some(other) = non_null.find(|element| !same_type(first, element))
+    {
+        return Err(ExecutionError::MixedArrayTypes {
+            first: first.to_string(),
+            other: other.to_string(),
+        });
+    }
+    Ok(Value::Array(elements))
+}
+
+fn same_type(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Enum(left), Value::Enum(right)) => {
+            left.type_name.eq_ignore_ascii_case(&right.type_name)
+        }
+        _ => std::mem::discriminant(left) == std::mem::discriminant(right),
+    }
+}
+
+/// Total order used by ORDER BY and GROUP BY: NULL sorts after every other value, and values that
+/// cannot be compared are ordered by type.
+pub fn compare_for_sort(left: &Value, right: &Value, collation: Collation) -> Ordering {
+    match (left, right) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => compare_values(left, right, collation)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| type_rank(left).cmp(&type_rank(right))),
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Bool(_) => 0,
+        Value::Integer(_) | Value::Real(_) => 1,
+        Value::Text(_) => 2,
+        Value::Uuid(_) => 3,
+        Value::Json(_) => 4,
+        Value::Enum(_) => 5,
+        Value::Array(_) => 6,
+        Value::Blob(_) => 7,
+        Value::Null => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_compilator::parser::Parser;
+    use crate::sql_compilator::tokenizer::tokenize_user_input;
+
+    fn evaluate_text(text: &str) -> Value {
+        let tokens = tokenize_user_input(text).unwrap();
+        let expression = Parser::new(&tokens).parse_expression().unwrap();
+        evaluate(&expression, &RowContext::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let cases = [
+            ("NULL AND false", Value::Bool(false)),
+            ("NULL AND true", Value::Null),
+            ("NULL OR true", Value::Bool(true)),
+            ("NULL OR false", Value::Null),
+            ("NOT NULL", Value::Null),
+            ("NULL = NULL", Value::Null),
+            ("1 + NULL", Value::Null),
+            ("NULL IS NULL", Value::Bool(true)),
+            ("NULL IS NOT TRUE", Value::Bool(true)),
+            ("NULL IS UNKNOWN", Value::Bool(true)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluate_text(text), expected, "Failed: `{}`", text);
+        }
+    }
+
+    #[test]
+    fn test_null_aware_comparisons() {
+        let cases = [
+            ("NULL IS DISTINCT FROM NULL", Value::Bool(false)),
+            ("1 IS DISTINCT FROM NULL", Value::Bool(true)),
+            ("1 IS NOT DISTINCT FROM 1", Value::Bool(true)),
+            ("1 IN (1, NULL)", Value::Bool(true)),
+            ("2 IN (1, NULL)", Value::Null),
+            ("2 NOT IN (1, NULL)", Value::Null),
+            ("2 NOT IN (1, 3)", Value::Bool(true)),
+            ("coalesce(NULL, NULL, 3)", Value::Integer(3)),
+            ("ifnull(NULL, 'a')", Value::Text(String::from("a"))),
+            ("nullif(1, 1)", Value::Null),
+            ("nullif(1, 2)", Value::Integer(1)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluate_text(text), expected, "Failed: `{}`", text);
+        }
+    }
+
+    #[test]
+    fn test_integer_limits() {
+        let cases = [
+            ("-9223372036854775808", Value::Integer(i64::MIN)),
+            ("9223372036854775807", Value::Integer(i64::MAX)),
+            ("-9223372036854775807 - 1", Value::Integer(i64::MIN)),
+            ("- -5", Value::Integer(5)),
+            ("-(2 + 3)", Value::Integer(-5)),
+            ("-9223372036854775808 % -1", Value::Integer(0)),
+            ("-7 % 3", Value::Integer(-1)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluate_text(text), expected, "Failed: `{}`", text);
+        }
+        for text in ["-(-9223372036854775808)", "-9223372036854775808 / -1"] {
+            let tokens = tokenize_user_input(text).unwrap();
+            let expression = Parser::new(&tokens).parse_expression().unwrap();
+            assert!(
+                matches!(
+                    evaluate(&expression, &RowContext::empty()),
+                    Err(ExecutionError::IntegerOverflow)
+                ),
+                "Failed: `{}`",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_uuids() {
+        let expected = [
+            0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x12, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14, 0x17,
+            0x40, 0x00,
+        ];
+        for text in [
+            "123e4567-e89b-12d3-a456-426614174000",
+            "123E4567-E89B-12D3-A456-426614174000",
+            "123e4567e89b12d3a456426614174000",
+            "{123e4567-e89b-12d3-a456-426614174000}",
+            " 123e4567-e89b-12d3-a456-426614174000 ",
+        ] {
+            assert_eq!(parse_uuid(text).unwrap(), expected, "Failed: `{}`", text);
+        }
+        for text in [
+            "",
+            "123e4567-e89b-12d3-a456-42661417400",
+            "123e4567-e89b-12d3-a456-4266141740000",
+            "123e4567-e89b-12d3-a456-42661417400g",
+            "123e4567-e89b12d3-a456-426614174000-",
+            "{123e4567-e89b-12d3-a456-426614174000",
+        ] {
+            assert!(
+                matches!(parse_uuid(text), Err(ExecutionError::InvalidUuid { .. })),
+                "Failed: `{}`",
+                text
+            );
+        }
+        assert_eq!(
+            evaluate_text("'123E4567E89B12D3A456426614174000' = uuidv4()"),
+            Value::Bool(false)
+        );
+
+        // Version 7 UUIDs start with their creation time, so that they sort in creation order
+        let mut previous = [0; 16];
+        for round in 0..1000 {
+            if round % 100 == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            }
+            let Value::Uuid(uuid) = evaluate_text("uuidv7()") else {
+                panic!("uuidv7() did not return a UUID");
+            };
+            assert_eq!(uuid[6] >> 4, 7);
+            assert!(uuid > previous);
+            previous = uuid;
+        }
+    }
+
+    #[test]
+    fn test_explicit_collation() {
+        let cases = [
+            ("'abc' = 'ABC'", Value::Bool(false)),
+            ("'abc' = 'ABC' COLLATE nocase", Value::Bool(true)),
+            ("'b' COLLATE nocase > 'A'", Value::Bool(true)),
+            ("'é' COLLATE unicode < 'f'", Value::Bool(true)),
+            ("'é' < 'f'", Value::Bool(false)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluate_text(text), expected, "Failed: `{}`", text);
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        let array =
+            |elements: &[i64]| Value::Array(elements.iter().copied().map(Value::Integer).collect());
+        let cases = [
+            ("ARRAY[1, 2, 3][2]", Value::Integer(2)),
+            ("ARRAY[1, 2, 3][4]", Value::Null),
+            ("ARRAY[1, 2, 3][2:]", array(&[2, 3])),
+            ("ARRAY[1, 2, 3][:1]", array(&[1])),
+            ("ARRAY[1, 2, 3][3:2]", array(&[])),
+            ("ARRAY[1, 2, 3][1:-9223372036854775808]", array(&[])),
+            ("ARRAY[1, 2, 3][9223372036854775807:-2]", array(&[])),
+            (
+                "ARRAY[1, 2, 3][-9223372036854775808:9223372036854775807]",
+                array(&[1, 2, 3]),
+            ),
+            ("ARRAY[]::INTEGER[][1:2]", array(&[])),
+            ("2 = ANY(ARRAY[1, 2])", Value::Bool(true)),
+            ("3 = ANY(ARRAY[1, NULL])", Value::Null),
+            ("3 > ALL(ARRAY[1, 2])", Value::Bool(true)),
+            ("ARRAY[1, 2] < ARRAY[1, 2, 0]", Value::Bool(true)),
+            (
+                "ARRAY[1, 2.5, NULL]",
+                Value::Array(vec![Value::Real(1.0), Value::Real(2.5), Value::Null]),
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluate_text(text), expected, "Failed: `{}`", text);
+        }
+        for text in [
+            "ARRAY[1, 'a']",
+            "ARRAY[NULL, 'a', true]",
+            "ARRAY[ARRAY[1], 2]",
+        ] {
+            let tokens = tokenize_user_input(text).unwrap();
+            let expression = Parser::new(&tokens).parse_expression().unwrap();
+            assert!(
+                matches!(
+                    evaluate(&expression, &RowContext::empty()),
+                    Err(ExecutionError::MixedArrayTypes { .. })
+                ),
+                "Failed: `{}`",
+                text
+            );
+        }
+    }
+}
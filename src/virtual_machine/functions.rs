@@ -0,0 +1,201 @@
+use crate::utils::collation::Collation;
+use crate::utils::file_handler::Value;
+use crate::utils::json;
+use crate::virtual_machine::evaluator;
+use crate::virtual_machine::instruction_processor::ExecutionError;
+use serde_json::Value as JsonValue;
+
+/// Rows produced by a table-valued function, along with the names of their columns
+pub struct FunctionTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+pub fn call_scalar_function(name: &str, arguments: Vec<Value>) -> Result<Value, ExecutionError> {
+    match name {
+        "json_extract" => json_extract(arguments),
+        "json_array_length" => json_array_length(arguments),
+        "array_length" => array_length(arguments),
+        "nullif" => {
+            check_argument_count(name, &arguments, 2..=2)?;
+            let [value, compared] = <[Value; 2]>::try_from(arguments).unwrap();
+            if value != Value::Null
+                && compared != Value::Null
+                && evaluator::is_not_distinct(&value, &compared, Collation::Binary)?
+            {
+                Ok(Value::Null)
+            } else {
+                Ok(value)
+            }
+        }
+        "gen_random_uuid" | "uuidv4" => {
+            check_argument_count(name, &arguments, 0..=0)?;
+            Ok(Value::Uuid(uuid::Uuid::new_v4().into_bytes()))
+        }
+        "uuidv7" => {
+            check_argument_count(name, &arguments, 0..=0)?;
+            Ok(Value::Uuid(uuid::Uuid::now_v7().into_bytes()))
+        }
+        _ => Err(ExecutionError::UnknownFunction {
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// Returns the names of the columns produced by given table-valued function
+pub fn table_function_columns(name: &str) -> Result<Vec<String>, ExecutionError> {
+    match name {
+        "json_each" => Ok(["key", "value", "type"].map(String::from).to_vec()),
+        "unnest" => Ok(vec![String::from("unnest")]),
+        _ => Err(ExecutionError::UnknownFunction {
+            name: name.to_string(),
+        }),
+    }
+}
+
+pub fn call_table_function(
+    name: &str,
+    arguments: Vec<Value>,
+) -> Result<FunctionTable, ExecutionError> {
+    let rows = match name {
+        "json_each" => json_each(arguments)?,
+        "unnest" => unnest(arguments)?,
+        _ => {
+            return Err(ExecutionError::UnknownFunction {
+                name: name.to_string(),
+            });
+        }
+    };
+    Ok(FunctionTable {
+        columns: table_function_columns(name)?,
+        rows,
+    })
+}
+
+/// Reads a JSON argument, which can either be a JSON document or some text holding one
+pub fn to_json(value: &Value, function_name: &str) -> Result<JsonValue, ExecutionError> {
+    match value {
+        Value::Json(bytes) => Ok(json::decode(bytes)?),
+        Value::Text(text) => Ok(serde_json::from_str(text).map_err(json::JsonError::from)?),
+        other => Err(ExecutionError::InvalidArgument {
+            name: function_name.to_string(),
+            reason: format!("expected a JSON document, found {other}"),
+        }),
+    }
+}
+
+fn check_argument_count(
+    name: &str,
+    arguments: &[Value],
+    expected: std::ops::RangeInclusive<usize>,
+) -> Result<(), ExecutionError> {
+    if expected.contains(&arguments.len()) {
+        return Ok(());
+    }
+    Err(ExecutionError::WrongArgumentCount {
+        name: name.to_string(),
+        expected: format!("{} to {}", expected.start(), expected.end()),
+        found: arguments.len(),
+    })
+}
+
+/// Reads the document of a JSON function and follows its optional path argument. Returns `None`
+/// if any argument is NULL or if the path does not match anything.
+fn json_argument_at_path(
+    name: &str,
+    arguments: &[Value],
+) -> Result<Option<JsonValue>, ExecutionError> {
+    if arguments.contains(&Value::Null) {
+        return Ok(None);
+    }
+    let document = to_json(&arguments[0], name)?;
+    match arguments.get(1) {
+        None => Ok(Some(document)),
+        Some(Value::Text(path)) => {
+            let steps = json::parse_path(path)?;
+            Ok(json::lookup_path(&document, &steps).cloned())
+        }
+        Some(other) => Err(ExecutionError::InvalidArgument {
+            name: name.to_string(),
+            reason: format!("expected a JSON path, found {other}"),
+        }),
+    }
+}
+
+/// `json_extract(document, path)`: value found at path, as a SQL value
+fn json_extract(arguments: Vec<Value>) -> Result<Value, ExecutionError> {
+    check_argument_count("json_extract", &arguments, 2..=2)?;
+    Ok(json_argument_at_path("json_extract", &arguments)?
+        .map(|node| json::to_sql_value(&node))
+        .unwrap_or(Value::Null))
+}
+
+/// `json_array_length(document[, path])`: number of elements of an array, NULL for anything else
+fn json_array_length(arguments: Vec<Value>) -> Result<Value, ExecutionError> {
+    check_argument_count("json_array_length", &arguments, 1..=2)?;
+    Ok(
+        match json_argument_at_path("json_array_length", &arguments)? {
+            Some(JsonValue::Array(elements)) => Value::Integer(elements.len() as i64),
+            _ => Value::Null,
+        },
+    )
+}
+
+/// `json_each(document[, path])`: one row per element of an array or per entry of an object,
+/// with columns (key, value, type)
+fn json_each(arguments: Vec<Value>) -> Result<Vec<Vec<Value>>, ExecutionError> {
+    check_argument_count("json_each", &arguments, 1..=2)?;
+    let make_row = |key: Value, node: &JsonValue| {
+        vec![
+            key,
+            json::to_sql_value(node),
+            Value::Text(json::type_name(node).to_string()),
+        ]
+    };
+    Ok(match json_argument_at_path("json_each", &arguments)? {
+        None => Vec::new(),
+        Some(JsonValue::Array(elements)) => elements
+            .iter()
+            .enumerate()
+            .map(|(index, node)| make_row(Value::Integer(index as i64), node))
+            .collect(),
+        Some(JsonValue::Object(entries)) => entries
+            .iter()
+            .map(|(key, node)| make_row(Value::Text(key.clone()), node))
+            .collect(),
+        Some(scalar) => vec![make_row(Value::Null, &scalar)],
+    })
+}
+
+/// `array_length(array, dimension)`: number of elements of an array. As in PostgreSQL, an empty
+/// array has no dimension and gives NULL.
+fn array_length(arguments: Vec<Value>) -> Result<Value, ExecutionError> {
+    check_argument_count("array_length", &arguments, 1..=2)?;
+    let dimension = arguments.get(1).cloned().unwrap_or(Value::Integer(1));
+    match (&arguments[0], dimension) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Array(elements), Value::Integer(1)) if !elements.is_empty() => {
+            Ok(Value::Integer(elements.len() as i64))
+        }
+        (Value::Array(_), Value::Integer(_)) => Ok(Value::Null),
+        (other, _) => Err(ExecutionError::InvalidArgument {
+            name: String::from("array_length"),
+            reason: format!("expected an array and a dimension, found {other}"),
+        }),
+    }
+}
+
+/// `unnest(array)`: one row per element of an array
+fn unnest(arguments: Vec<Value>) -> Result<Vec<Vec<Value>>, ExecutionError> {
+    check_argument_count("unnest", &arguments, 1..=1)?;
+    match arguments.into_iter().next() {
+        Some(Value::Array(elements)) => {
+            Ok(elements.into_iter().map(|element| vec![element]).collect())
+        }
+        Some(Value::Null) | None => Ok(Vec::new()),
+        Some(other) => Err(ExecutionError::InvalidArgument {
+            name: String::from("unnest"),
+            reason: format!("expected an array, found {other}"),
+        }),
+    }
+}
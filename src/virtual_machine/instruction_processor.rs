@@ -1,46 +1,1532 @@
-use crate::config::Config;
-use crate::config::get_project_root;
-use crate::utils::file_handler;
-use std::path::Path;
-use std::path::PathBuf;
+use crate::sql_compilator::expression::Expression;
+use crate::sql_compilator::parser::{
+    AlterTypeStatement, CreateIndexStatement, DeleteStatement, FromItem, InsertStatement,
+    Instruction, LabelPosition, OrderByItem, SelectItem, SelectStatement, UpdateStatement,
+};
+use crate::utils::array;
+use crate::utils::collation::Collation;
+use crate::utils::compression::Compression;
+use crate::utils::file_handler::{
+    self, Column, DataType, EnumType, IndexColumn, IndexDefinition, IndexValues, RowChange,
+    SessionId, TableLayout, Value,
+};
+use crate::utils::heap_file::RowId;
+use crate::utils::index_key::{self, KeyBounds, KeyOrder};
+use crate::utils::json::{self, JsonError};
+use crate::utils::lock_manager::{LockError, LockMode, LockTarget};
+use crate::utils::mvcc::IsolationLevel;
+use crate::virtual_machine::aggregates;
+use crate::virtual_machine::evaluator::{self, ColumnBinding, RowContext};
+use crate::virtual_machine::functions;
+use crate::virtual_machine::planner;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
 
-use crate::sql_compilator::parser;
-use crate::sql_compilator::tokenizer;
+#[derive(Error, Debug)]
+pub enum ExecutionError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] JsonError),
+    #[error("Table '{table_name}' does not exist")]
+    TableNotFound { table_name: String },
+    #[error("Column '{column_name}' does not exist")]
+    ColumnNotFound { column_name: String },
+    #[error("Column reference '{column_name}' is ambiguous")]
+    AmbiguousColumn { column_name: String },
+    #[error("{expected} values were expected for table '{table_name}', found {found}")]
+    ValueCountMismatch {
+        table_name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Cannot store {value} in column '{column_name}' of type {data_type:?}")]
+    TypeMismatch {
+        column_name: String,
+        data_type: DataType,
+        value: String,
+    },
+    #[error("Operator {operator} cannot be applied to {left} and {right}")]
+    InvalidOperands {
+        operator: String,
+        left: String,
+        right: String,
+    },
+    #[error("Operator {operator} cannot be applied to {operand}")]
+    InvalidOperand { operator: String, operand: String },
+    #[error("Expected a boolean condition, found {found}")]
+    NotABoolean { found: String },
+    #[error("Unknown function '{name}'")]
+    UnknownFunction { name: String },
+    #[error("Function '{name}' expects {expected} argument(s), found {found}")]
+    WrongArgumentCount {
+        name: String,
+        expected: String,
+        found: usize,
+    },
+    #[error("Invalid argument for function '{name}': {reason}")]
+    InvalidArgument { name: String, reason: String },
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Integer out of range")]
+    IntegerOverflow,
+    #[error("Invalid UUID '{content}'")]
+    InvalidUuid { content: String },
+    #[error("Invalid BLOB '{content}', expected \\x followed by hexadecimal digits")]
+    InvalidBlob { content: String },
+    #[error("Column '{column_name}' cannot hold NULL")]
+    NotNullViolation { column_name: String },
+    #[error("Duplicate key ({key}) violates {constraint}")]
+    DuplicateKey { constraint: String, key: String },
+    #[error("Malformed array literal '{content}'")]
+    InvalidArrayLiteral { content: String },
+    #[error("ARRAY elements must share a type, found {first} and {other}")]
+    MixedArrayTypes { first: String, other: String },
+    #[error("Type '{type_name}' does not exist")]
+    TypeNotFound { type_name: String },
+    #[error("Invalid input value for enum '{type_name}': '{label}'")]
+    InvalidEnumLabel { type_name: String, label: String },
+    #[error("Enum label '{label}' already exists in type '{type_name}'")]
+    DuplicateEnumLabel { type_name: String, label: String },
+    #[error("Column '{column_name}' must appear in the GROUP BY clause or be used in an aggregate")]
+    UngroupedColumn { column_name: String },
+    #[error("Index '{index_name}' already exists")]
+    IndexAlreadyExists { index_name: String },
+    #[error("Index '{index_name}' does not exist")]
+    IndexNotFound { index_name: String },
+    #[error("Index '{index_name}' cannot use {reason}")]
+    InvalidIndexExpression { index_name: String, reason: String },
+    #[error("There is already a transaction in progress")]
+    TransactionInProgress,
+    #[error("There is no transaction in progress")]
+    NoTransactionInProgress,
+    #[error("Current transaction is aborted, statements are ignored until ROLLBACK")]
+    TransactionAborted,
+    /// Returned before the statement changed anything. It runs again once its session got the
+    /// lock, see `Session`.
+    #[error("Waiting for a lock on {target}")]
+    LockWait { target: LockTarget, mode: LockMode },
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error("Could not serialize access to table '{table_name}' due to a concurrent update")]
+    SerializationFailure { table_name: String },
+    #[error("Database is opened in read-only mode")]
+    ReadOnlyDatabase,
+}
+
+/// What a processed instruction gives back to the user
+#[derive(Debug)]
+pub enum ExecutionOutput {
+    Message(String),
+    Rows(ResultSet),
+}
 
+#[derive(Debug)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Runs statements in a session of a database, see `Database::open_session`
 pub struct InstructionProcessor<'db> {
-    instruction: parser::Instruction,
-    config: Config,
-    tables_dir: PathBuf,
     database: &'db mut file_handler::Database,
+    session: SessionId,
 }
 
 impl<'db> InstructionProcessor<'db> {
-    pub fn new(
-        instruction: parser::Instruction,
-        config_path: &Path,
-        database: &'db mut file_handler::Database,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let config = Config::load(config_path)?;
-        let tables_dir = get_project_root().join(&config.database.url);
-        Ok(InstructionProcessor {
+    pub fn new(database: &'db mut file_handler::Database, session: SessionId) -> Self {
+        InstructionProcessor { database, session }
+    }
+
+    pub fn process_instruction(
+        &mut self,
+        instruction: &Instruction,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        log::debug!("{instruction:#?}");
+        self.database.use_session(self.session);
+        let ends_failure = matches!(
             instruction,
-            config,
-            tables_dir,
-            database,
-        })
+            Instruction::Commit | Instruction::Rollback | Instruction::RollbackToSavepoint { .. }
+        );
+        let is_transaction_control = ends_failure
+            || matches!(
+                instruction,
+                Instruction::Begin
+                    | Instruction::Savepoint { .. }
+                    | Instruction::ReleaseSavepoint { .. }
+                    | Instruction::SetTransaction { .. }
+            );
+        if self.database.transaction_failed() && !ends_failure {
+            return Err(ExecutionError::TransactionAborted);
+        }
+        let writes = matches!(
+            instruction,
+            Instruction::CreateTable { .. }
+                | Instruction::InsertInto(_)
+                | Instruction::CreateType { .. }
+                | Instruction::AlterType(_)
+                | Instruction::Update(_)
+                | Instruction::Delete(_)
+                | Instruction::CreateIndex(_)
+                | Instruction::DropIndex { .. }
+                | Instruction::Vacuum { .. }
+        );
+        if writes && self.database.is_read_only() {
+            return Err(ExecutionError::ReadOnlyDatabase);
+        }
+        // One transaction writes at a time, as changes are logged and undone for the whole
+        // database: it holds `Writes` until it ends, which other writing statements wait for.
+        // Nothing was changed yet, so the transaction can go on.
+        if writes {
+            self.lock(LockTarget::Writes, LockMode::Exclusive)?;
+        }
+        let output = match instruction {
+            Instruction::CreateTable {
+                target_table,
+                columns,
+                layout,
+                compression,
+            } => self.create_table_file(target_table, columns.clone(), *layout, *compression),
+            Instruction::InsertInto(insert) => self.insert_into(insert),
+            Instruction::Select(select) => self.select(select).map(ExecutionOutput::Rows),
+            Instruction::CreateType { name, labels } => self.create_type(name, labels),
+            Instruction::AlterType(alter) => self.alter_type(alter),
+            Instruction::Update(update) => self.update(update),
+            Instruction::Delete(delete) => self.delete(delete),
+            Instruction::CreateIndex(create) => self.create_index(create),
+            Instruction::DropIndex { name, if_exists } => self.drop_index(name, *if_exists),
+            Instruction::Begin => self.begin(),
+            Instruction::Commit => self.commit(),
+            Instruction::Rollback => self.rollback(),
+            Instruction::Savepoint { name } => self.savepoint(name),
+            Instruction::RollbackToSavepoint { name } => self.rollback_to_savepoint(name),
+            Instruction::ReleaseSavepoint { name } => self.release_savepoint(name),
+            Instruction::SetTransaction { isolation_level } => {
+                self.set_transaction(*isolation_level)
+            }
+            Instruction::Vacuum { target_table } => self.vacuum(target_table.as_deref()),
+            Instruction::CheckDatabase => self.check_database(),
+        };
+        let waits = matches!(output, Err(ExecutionError::LockWait { .. }));
+        if !is_transaction_control {
+            self.database.end_statement();
+        }
+        // Outside of a transaction, every statement is atomic. Within one, a failed statement
+        // may have left changes half-way, so the whole transaction can only be rolled back.
+        match self.database.in_transaction() {
+            true if output.is_err() && !is_transaction_control && !waits => {
+                self.database.fail_transaction()
+            }
+            true => (),
+            false if output.is_ok() => self.commit_changes()?,
+            false => self.database.rollback()?,
+        }
+        log::debug!("Buffer pool: {}", self.database.buffer_pool_stats());
+        output
     }
 
-    pub fn process_instruction(&mut self) -> std::io::Result<()>{
-        match self.instruction.base_command {
-            tokenizer::CommandType::CreateTable => self.create_table_file(),
-            _ => todo!(),
+    fn create_table_file(
+        &mut self,
+        target_table: &str,
+        columns: Vec<Column>,
+        layout: TableLayout,
+        compression: Option<Compression>,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        for column in &columns {
+            self.column_enum_type(column)?;
         }
+        self.database
+            .create_table(target_table, columns, layout, compression)?;
+        Ok(ExecutionOutput::Message(String::from("CREATE TABLE")))
     }
 
-    fn create_table_file(&mut self) -> std::io::Result<()> {
-        log::debug!("{:#?}", self.instruction);
-        let owned_columns = self.instruction.columns.clone();
+    fn create_type(
+        &mut self,
+        name: &str,
+        labels: &[String],
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        let duplicate = labels
+            .iter()
+            .enumerate()
+            .find_map(|(position, label)| labels[..position].contains(label).then_some(label));
+        if let Some(label) = duplicate {
+            return Err(ExecutionError::DuplicateEnumLabel {
+                type_name: name.to_string(),
+                label: label.to_string(),
+            });
+        }
         self.database
-            .create_table(&self.instruction.target_table, owned_columns)
+            .create_enum_type(EnumType::new(name, labels.to_vec()))?;
+        Ok(ExecutionOutput::Message(String::from("CREATE TYPE")))
+    }
+
+    fn alter_type(
+        &mut self,
+        alter: &AlterTypeStatement,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        let mut enum_type = self.enum_type(&alter.name)?.clone();
+        if enum_type.value_of(&alter.new_label).is_some() {
+            if alter.if_not_exists {
+                return Ok(ExecutionOutput::Message(String::from("ALTER TYPE")));
+            }
+            return Err(ExecutionError::DuplicateEnumLabel {
+                type_name: enum_type.name,
+                label: alter.new_label.to_string(),
+            });
+        }
+        if !enum_type.add_label(&alter.new_label, alter.position.as_ref()) {
+            let (LabelPosition::Before(neighbour) | LabelPosition::After(neighbour)) =
+                alter.position.as_ref().unwrap();
+            return Err(ExecutionError::InvalidEnumLabel {
+                type_name: enum_type.name,
+                label: neighbour.to_string(),
+            });
+        }
+        self.database.update_enum_type(enum_type)?;
+        Ok(ExecutionOutput::Message(String::from("ALTER TYPE")))
+    }
+
+    fn create_index(
+        &mut self,
+        create: &CreateIndexStatement,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        let table = LockTarget::Table(create.target_table.to_string());
+        self.lock(table, LockMode::Exclusive)?;
+        if self.database.index_table(&create.name).is_some() {
+            if create.if_not_exists {
+                return Ok(ExecutionOutput::Message(String::from("CREATE INDEX")));
+            }
+            return Err(ExecutionError::IndexAlreadyExists {
+                index_name: create.name.to_string(),
+            });
+        }
+        let bindings = self.table_bindings(&create.target_table, None)?;
+        let mut columns = Vec::new();
+        for (expression, descending) in &create.columns {
+            check_indexable(&create.name, expression, &bindings)?;
+            columns.push(IndexColumn {
+                expression: expression.clone(),
+                descending: *descending,
+                collation: evaluator::collation_of(expression, &bindings).unwrap_or_default(),
+            });
+        }
+        let definition = IndexDefinition {
+            name: create.name.to_string(),
+            unique: create.unique,
+            columns,
+        };
+        // Every stored version is indexed right away. Live rows must have unique keys if the
+        // index is unique.
+        let mut entries = Vec::new();
+        let mut used_keys = HashSet::new();
+        let orders = definition.key_orders();
+        for (row_id, version, values) in self.database.scan_versions(&create.target_table)? {
+            let row = RowContext {
+                bindings: &bindings,
+                values: &values,
+            };
+            let key_values = index_values(std::slice::from_ref(&definition), &row)?.remove(0);
+            if definition.unique
+                && version.is_live()
+                && !key_values.contains(&Value::Null)
+                && !used_keys.insert(index_key::encode(&key_values, &orders))
+            {
+                return Err(duplicate_index_key(&definition, &key_values));
+            }
+            entries.push((row_id, key_values));
+        }
+        self.database
+            .create_index(&create.target_table, definition, entries)?;
+        Ok(ExecutionOutput::Message(String::from("CREATE INDEX")))
+    }
+
+    fn drop_index(
+        &mut self,
+        name: &str,
+        if_exists: bool,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        match self.database.index_table(name).map(str::to_string) {
+            Some(table_name) => {
+                self.lock(
+                    LockTarget::Table(table_name.to_string()),
+                    LockMode::Exclusive,
+                )?;
+                self.database.drop_index(&table_name, name)?
+            }
+            None if if_exists => (),
+            None => {
+                return Err(ExecutionError::IndexNotFound {
+                    index_name: name.to_string(),
+                });
+            }
+        }
+        Ok(ExecutionOutput::Message(String::from("DROP INDEX")))
+    }
+
+    fn begin(&mut self) -> Result<ExecutionOutput, ExecutionError> {
+        if self.database.in_transaction() {
+            return Err(ExecutionError::TransactionInProgress);
+        }
+        self.database.begin();
+        Ok(ExecutionOutput::Message(String::from("BEGIN")))
+    }
+
+    fn set_transaction(
+        &mut self,
+        isolation_level: IsolationLevel,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        self.database.set_isolation_level(isolation_level)?;
+        Ok(ExecutionOutput::Message(String::from("SET")))
+    }
+
+    /// Rolls back the transaction of the session once it could not get a lock it waited for,
+    /// which releases its other locks
+    pub fn abort_transaction(&mut self, error: LockError) -> ExecutionError {
+        self.database.use_session(self.session);
+        if let Err(error) = self.database.abort_transaction() {
+            return error.into();
+        }
+        error.into()
+    }
+
+    /// Takes a lock for the running transaction, or fails with `LockWait` if another
+    /// transaction holds a conflicting one
+    fn lock(&mut self, target: LockTarget, mode: LockMode) -> Result<(), ExecutionError> {
+        if self.database.try_lock(&target, mode) {
+            return Ok(());
+        }
+        log::debug!(
+            "Session {} waits for a {mode:?} lock on {target}",
+            self.session
+        );
+        Err(ExecutionError::LockWait { target, mode })
+    }
+
+    /// Locks a table before changing its rows. Serializable transactions which read the table
+    /// are waited for, and cannot read it again until the writing transaction ends.
+    fn lock_written_table(&mut self, table_name: &str) -> Result<(), ExecutionError> {
+        self.lock(
+            LockTarget::Table(table_name.to_string()),
+            LockMode::Exclusive,
+        )
+    }
+
+    /// Locks a whole table read by a serializable transaction
+    fn lock_read_table(&mut self, table_name: &str) -> Result<(), ExecutionError> {
+        if self.database.isolation_level() != IsolationLevel::Serializable {
+            return Ok(());
+        }
+        self.lock(LockTarget::Table(table_name.to_string()), LockMode::Shared)
     }
+
+    /// Commits the running transaction, or rolls it back if one of its statements failed
+    fn commit(&mut self) -> Result<ExecutionOutput, ExecutionError> {
+        if !self.database.in_transaction() {
+            return Err(ExecutionError::NoTransactionInProgress);
+        }
+        if self.database.transaction_failed() {
+            return self.rollback();
+        }
+        self.commit_changes()?;
+        Ok(ExecutionOutput::Message(String::from("COMMIT")))
+    }
+
+    /// Commits the running transaction, along with the purge of the row versions that no
+    /// snapshot sees anymore
+    fn commit_changes(&mut self) -> Result<(), ExecutionError> {
+        for (table_name, versions) in self.database.garbage_versions()? {
+            self.purge_versions(&table_name, versions)?;
+        }
+        self.database.commit()?;
+        Ok(())
+    }
+
+    fn purge_versions(
+        &mut self,
+        table_name: &str,
+        versions: Vec<(RowId, Vec<Value>)>,
+    ) -> Result<(), ExecutionError> {
+        let bindings = self.table_bindings(table_name, None)?;
+        let indexes = self.table_indexes(table_name)?;
+        let versions = versions
+            .into_iter()
+            .map(|(row_id, values)| {
+                let row = RowContext {
+                    bindings: &bindings,
+                    values: &values,
+                };
+                Ok((row_id, index_values(&indexes, &row)?))
+            })
+            .collect::<Result<Vec<(RowId, IndexValues)>, ExecutionError>>()?;
+        self.database.purge_versions(table_name, &versions)?;
+        Ok(())
+    }
+
+    /// Purges the deleted row versions of a table, or of every table, which no snapshot sees
+    /// anymore. Versions are otherwise purged by the commits following their deletion, but those
+    /// left when the database was closed are only purged here.
+    fn vacuum(&mut self, target_table: Option<&str>) -> Result<ExecutionOutput, ExecutionError> {
+        let table_names = match target_table {
+            Some(table_name) => {
+                self.table_columns(table_name)?;
+                vec![table_name.to_string()]
+            }
+            None => self.database.table_names(),
+        };
+        for table_name in table_names {
+            let versions = self.database.dead_versions(&table_name)?;
+            if !versions.is_empty() {
+                log::info!(
+                    "Purging {} dead row version(s) of table '{table_name}'",
+                    versions.len()
+                );
+                self.purge_versions(&table_name, versions)?;
+            }
+        }
+        Ok(ExecutionOutput::Message(String::from("VACUUM")))
+    }
+
+    /// Checks the files of the database, see `Database::check_integrity`. Every problem found is
+    /// listed as a row.
+    fn check_database(&mut self) -> Result<ExecutionOutput, ExecutionError> {
+        let mut tables = HashMap::new();
+        for table_name in self.database.table_names() {
+            let bindings = self.table_bindings(&table_name, None)?;
+            let indexes = self.table_indexes(&table_name)?;
+            tables.insert(table_name, (bindings, indexes));
+        }
+        let report = self.database.check_integrity(&|table_name, values| {
+            let (bindings, indexes) = &tables[table_name];
+            let row = RowContext { bindings, values };
+            index_values(indexes, &row).map_err(|error| error.to_string())
+        })?;
+        log::info!(
+            "Checked {} table(s) and {} row version(s), found {} problem(s)",
+            report.tables,
+            report.row_versions,
+            report.problems.len()
+        );
+        if report.problems.is_empty() {
+            return Ok(ExecutionOutput::Message(format!(
+                "CHECK DATABASE: {} table(s) and {} row version(s) checked, no problem found",
+                report.tables, report.row_versions
+            )));
+        }
+        let rows = report
+            .problems
+            .into_iter()
+            .map(|problem| {
+                vec![
+                    problem.table_name.map_or(Value::Null, Value::Text),
+                    Value::Text(problem.object),
+                    Value::Text(problem.description),
+                ]
+            })
+            .collect();
+        Ok(ExecutionOutput::Rows(ResultSet {
+            columns: ["table", "object", "problem"].map(String::from).to_vec(),
+            rows,
+        }))
+    }
+
+    fn rollback(&mut self) -> Result<ExecutionOutput, ExecutionError> {
+        if !self.database.in_transaction() {
+            return Err(ExecutionError::NoTransactionInProgress);
+        }
+        self.database.rollback()?;
+        Ok(ExecutionOutput::Message(String::from("ROLLBACK")))
+    }
+
+    fn savepoint(&mut self, name: &str) -> Result<ExecutionOutput, ExecutionError> {
+        if !self.database.in_transaction() {
+            return Err(ExecutionError::NoTransactionInProgress);
+        }
+        self.database.savepoint(name)?;
+        Ok(ExecutionOutput::Message(String::from("SAVEPOINT")))
+    }
+
+    /// Discards the changes made since a savepoint, which also recovers a failed transaction
+    fn rollback_to_savepoint(&mut self, name: &str) -> Result<ExecutionOutput, ExecutionError> {
+        if !self.database.in_transaction() {
+            return Err(ExecutionError::NoTransactionInProgress);
+        }
+        self.database.rollback_to_savepoint(name)?;
+        Ok(ExecutionOutput::Message(String::from("ROLLBACK")))
+    }
+
+    fn release_savepoint(&mut self, name: &str) -> Result<ExecutionOutput, ExecutionError> {
+        if !self.database.in_transaction() {
+            return Err(ExecutionError::NoTransactionInProgress);
+        }
+        self.database.release_savepoint(name)?;
+        Ok(ExecutionOutput::Message(String::from("RELEASE")))
+    }
+
+    fn enum_type(&self, type_name: &str) -> Result<&EnumType, ExecutionError> {
+        self.database
+            .enum_type(type_name)
+            .ok_or_else(|| ExecutionError::TypeNotFound {
+                type_name: type_name.to_string(),
+            })
+    }
+
+    /// Enum type of a column, if it holds labels of a user-defined type (or arrays of them)
+    fn column_enum_type(&self, column: &Column) -> Result<Option<EnumType>, ExecutionError> {
+        match column.data_type.base_type() {
+            DataType::Enum(type_name) => Ok(Some(self.enum_type(type_name)?.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    fn table_columns(&self, table_name: &str) -> Result<Vec<Column>, ExecutionError> {
+        self.database
+            .table_columns(table_name)
+            .map(<[Column]>::to_vec)
+            .ok_or_else(|| self.table_not_found(table_name))
+    }
+
+    fn table_indexes(&self, table_name: &str) -> Result<Vec<IndexDefinition>, ExecutionError> {
+        self.database
+            .table_indexes(table_name)
+            .map(<[IndexDefinition]>::to_vec)
+            .ok_or_else(|| self.table_not_found(table_name))
+    }
+
+    /// Error for a table which does not exist, or whose files could not be opened
+    fn table_not_found(&self, table_name: &str) -> ExecutionError {
+        let error = self.database.missing_table(table_name);
+        match error.kind() {
+            std::io::ErrorKind::NotFound => ExecutionError::TableNotFound {
+                table_name: table_name.to_string(),
+            },
+            _ => ExecutionError::Io(error),
+        }
+    }
+
+    fn insert_into(&mut self, insert: &InsertStatement) -> Result<ExecutionOutput, ExecutionError> {
+        let columns = self.table_columns(&insert.target_table)?;
+        let bindings = self.table_bindings(&insert.target_table, None)?;
+        let indexes = self.table_indexes(&insert.target_table)?;
+        // Position in the table schema of every value given in a row
+        let targets: Vec<usize> = match &insert.columns {
+            None => (0..columns.len()).collect(),
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    columns
+                        .iter()
+                        .position(|column| column.name.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| ExecutionError::ColumnNotFound {
+                            column_name: name.to_string(),
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        let enum_types = columns
+            .iter()
+            .map(|column| self.column_enum_type(column))
+            .collect::<Result<Vec<Option<EnumType>>, ExecutionError>>()?;
+        let mut rows = Vec::new();
+        for expressions in &insert.rows {
+            if expressions.len() != targets.len() {
+                return Err(ExecutionError::ValueCountMismatch {
+                    table_name: insert.target_table.to_string(),
+                    expected: targets.len(),
+                    found: expressions.len(),
+                });
+            }
+            let mut row: Vec<Option<Value>> = vec![None; columns.len()];
+            for (expression, &target) in expressions.iter().zip(&targets) {
+                let value = evaluator::evaluate(expression, &RowContext::empty())?;
+                row[target] = Some(coerce_to_column(
+                    value,
+                    &columns[target],
+                    &enum_types[target],
+                )?);
+            }
+            let row = row
+                .into_iter()
+                .zip(columns.iter().zip(&enum_types))
+                .map(|(value, (column, enum_type))| match value {
+                    Some(value) => Ok(value),
+                    None => default_value(column, enum_type),
+                })
+                .collect::<Result<Vec<Value>, ExecutionError>>()?;
+            rows.push(row);
+        }
+        let rows_index_values = rows
+            .iter()
+            .map(|values| {
+                let row = RowContext {
+                    bindings: &bindings,
+                    values,
+                };
+                index_values(&indexes, &row)
+            })
+            .collect::<Result<Vec<IndexValues>, ExecutionError>>()?;
+        self.check_constraints(
+            &insert.target_table,
+            &columns,
+            &rows,
+            &rows_index_values,
+            &HashSet::new(),
+        )?;
+        self.lock_written_table(&insert.target_table)?;
+        // Rows are only written once all of them are known to be valid
+        let inserted_count = rows.len();
+        for (row, index_values) in rows.into_iter().zip(&rows_index_values) {
+            self.database
+                .insert_row(&insert.target_table, row, index_values)?;
+        }
+        Ok(ExecutionOutput::Message(format!(
+            "INSERT 0 {inserted_count}"
+        )))
+    }
+
+    fn update(&mut self, update: &UpdateStatement) -> Result<ExecutionOutput, ExecutionError> {
+        let columns = self.table_columns(&update.target_table)?;
+        let bindings = self.table_bindings(&update.target_table, None)?;
+        let indexes = self.table_indexes(&update.target_table)?;
+        let targets: Vec<usize> = update
+            .assignments
+            .iter()
+            .map(|(name, _)| {
+                columns
+                    .iter()
+                    .position(|column| column.name.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| ExecutionError::ColumnNotFound {
+                        column_name: name.to_string(),
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        let matching = self.matching_rows(&update.target_table, &bindings, &update.filter)?;
+        self.lock_written_table(&update.target_table)?;
+        let mut changes = Vec::new();
+        for (row_id, values) in matching {
+            let row = RowContext {
+                bindings: &bindings,
+                values: &values,
+            };
+            // Every assignment sees the row as it was before the update
+            let mut new_row = values.clone();
+            for ((_, expression), &target) in update.assignments.iter().zip(&targets) {
+                let value = evaluator::evaluate(expression, &row)?;
+                new_row[target] =
+                    coerce_to_column(value, &columns[target], &bindings[target].enum_type)?;
+            }
+            let new_index_values = index_values(
+                &indexes,
+                &RowContext {
+                    bindings: &bindings,
+                    values: &new_row,
+                },
+            )?;
+            changes.push(RowChange {
+                row_id,
+                row: new_row,
+                index_values: new_index_values,
+            });
+        }
+        let replaced: HashSet<RowId> = changes.iter().map(|change| change.row_id).collect();
+        let row_ids: Vec<RowId> = changes.iter().map(|change| change.row_id).collect();
+        self.check_not_updated(&update.target_table, &row_ids)?;
+        let new_rows: Vec<Vec<Value>> = changes.iter().map(|change| change.row.clone()).collect();
+        let new_index_values: Vec<IndexValues> = changes
+            .iter()
+            .map(|change| change.index_values.clone())
+            .collect();
+        self.check_constraints(
+            &update.target_table,
+            &columns,
+            &new_rows,
+            &new_index_values,
+            &replaced,
+        )?;
+        let updated_count = changes.len();
+        self.database.update_rows(&update.target_table, changes)?;
+        Ok(ExecutionOutput::Message(format!("UPDATE {updated_count}")))
+    }
+
+    fn delete(&mut self, delete: &DeleteStatement) -> Result<ExecutionOutput, ExecutionError> {
+        let bindings = self.table_bindings(&delete.target_table, None)?;
+        let matching = self.matching_rows(&delete.target_table, &bindings, &delete.filter)?;
+        self.lock_written_table(&delete.target_table)?;
+        let row_ids: Vec<RowId> = matching.into_iter().map(|(row_id, _)| row_id).collect();
+        self.check_not_updated(&delete.target_table, &row_ids)?;
+        self.database.delete_rows(&delete.target_table, &row_ids)?;
+        Ok(ExecutionOutput::Message(format!(
+            "DELETE {}",
+            row_ids.len()
+        )))
+    }
+
+    /// Makes sure that no other transaction changed given rows since the snapshot of the running
+    /// transaction was taken. As the first change wins, the running transaction must then be
+    /// retried.
+    fn check_not_updated(
+        &mut self,
+        table_name: &str,
+        row_ids: &[RowId],
+    ) -> Result<(), ExecutionError> {
+        if self.database.updated_concurrently(table_name, row_ids)? {
+            return Err(ExecutionError::SerializationFailure {
+                table_name: table_name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Rows of a table satisfying an optional WHERE clause, along with their address
+    fn matching_rows(
+        &mut self,
+        table_name: &str,
+        bindings: &[ColumnBinding],
+        filter: &Option<Expression>,
+    ) -> Result<Vec<(RowId, Vec<Value>)>, ExecutionError> {
+        let mut matching = Vec::new();
+        for (row_id, values) in self.candidate_rows(table_name, bindings, filter.as_ref(), &[])? {
+            let row = RowContext {
+                bindings,
+                values: &values,
+            };
+            if let Some(filter) = filter
+                && !evaluator::is_true(filter, &row)?
+            {
+                continue;
+            }
+            matching.push((row_id, values));
+        }
+        Ok(matching)
+    }
+
+    /// Makes sure that new rows hold no NULL in non-nullable columns, and that their primary key,
+    /// unique columns and keys of unique indexes do not clash with another row of the table, nor
+    /// with another new row. As in PostgreSQL, a key holding a NULL never clashes with anything.
+    /// Texts are compared with the collation of their column. Rows being replaced by the new ones
+    /// are left out of the comparison.
+    fn check_constraints(
+        &mut self,
+        table_name: &str,
+        columns: &[Column],
+        new_rows: &[Vec<Value>],
+        new_index_values: &[IndexValues],
+        replaced: &HashSet<RowId>,
+    ) -> Result<(), ExecutionError> {
+        for row in new_rows {
+            if let Some((_, column)) = row
+                .iter()
+                .zip(columns)
+                .find(|(value, column)| !column.nullable && **value == Value::Null)
+            {
+                return Err(ExecutionError::NotNullViolation {
+                    column_name: column.name.to_string(),
+                });
+            }
+        }
+        let indexes = self.table_indexes(table_name)?;
+        for (position, index) in indexes.iter().enumerate() {
+            if !index.unique {
+                continue;
+            }
+            let orders = index.key_orders();
+            let mut used_keys = HashSet::new();
+            for values in new_index_values.iter().map(|row| &row[position]) {
+                if values.contains(&Value::Null) {
+                    continue;
+                }
+                let is_duplicate = !used_keys.insert(index_key::encode(values, &orders))
+                    || self
+                        .database
+                        .find_by_key(table_name, Some(&index.name), values)?
+                        .is_some_and(|row_id| !replaced.contains(&row_id));
+                if is_duplicate {
+                    return Err(duplicate_index_key(index, values));
+                }
+            }
+        }
+        // Unique columns are enforced by their index above, and the primary key by its own
+        let primary_key: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.is_primary_key)
+            .map(|(position, _)| position)
+            .collect();
+        if primary_key.is_empty() {
+            return Ok(());
+        }
+        let orders: Vec<KeyOrder> = primary_key
+            .iter()
+            .map(|&position| KeyOrder {
+                collation: columns[position].collation,
+                descending: false,
+            })
+            .collect();
+        let mut used_keys = HashSet::new();
+        for row in new_rows {
+            let values: Vec<Value> = primary_key
+                .iter()
+                .map(|&position| row[position].clone())
+                .collect();
+            if values.contains(&Value::Null) {
+                continue;
+            }
+            let is_duplicate = !used_keys.insert(index_key::encode(&values, &orders))
+                || self
+                    .database
+                    .find_by_key(table_name, None, &values)?
+                    .is_some_and(|row_id| !replaced.contains(&row_id));
+            if is_duplicate {
+                return Err(ExecutionError::DuplicateKey {
+                    constraint: format!("primary key of table '{table_name}'"),
+                    key: values
+                        .iter()
+                        .map(Value::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, select: &SelectStatement) -> Result<ResultSet, ExecutionError> {
+        // Expressions reading the columns of the tables, unless every column is read
+        let mut read_expressions: Option<Vec<&Expression>> = Some(Vec::new());
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard => read_expressions = None,
+                SelectItem::Expression { expression, .. } => {
+                    if let Some(expressions) = &mut read_expressions {
+                        expressions.push(expression);
+                    }
+                }
+            }
+        }
+        if let Some(expressions) = &mut read_expressions {
+            expressions.extend(&select.filter);
+            expressions.extend(&select.group_by);
+            expressions.extend(&select.having);
+            expressions.extend(select.order_by.iter().map(|item| &item.expression));
+            for item in &select.from {
+                if let FromItem::Function { arguments, .. } = item {
+                    expressions.extend(arguments);
+                }
+            }
+        }
+        let (bindings, source_rows) = self.scan_from_items(
+            &select.from,
+            select.filter.as_ref(),
+            read_expressions.as_deref(),
+        )?;
+        let mut rows = Vec::new();
+        for values in source_rows {
+            let row = RowContext {
+                bindings: &bindings,
+                values: &values,
+            };
+            let is_kept = match &select.filter {
+                Some(filter) => evaluator::is_true(filter, &row)?,
+                None => true,
+            };
+            if is_kept {
+                rows.push(values);
+            }
+        }
+
+        let mut columns = Vec::new();
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard => {
+                    columns.extend(bindings.iter().map(|binding| binding.name.clone()))
+                }
+                SelectItem::Expression { expression, alias } => {
+                    columns.push(alias.clone().unwrap_or_else(|| expression.output_name()))
+                }
+            }
+        }
+        let sort_expressions = resolve_order_by_aliases(&select.order_by, &select.projection);
+        let is_aggregate_query = !select.group_by.is_empty()
+            || select.having.is_some()
+            || select.projection.iter().any(|item| {
+                matches!(item, SelectItem::Expression { expression, .. } if expression.contains_aggregate())
+            })
+            || sort_expressions
+                .iter()
+                .any(|expression| expression.contains_aggregate());
+
+        // Every output row, along with the values it is sorted by
+        let mut sortable_rows: Vec<(Vec<Value>, Vec<Value>)> = Vec::new();
+        if is_aggregate_query {
+            let mut checked_expressions: Vec<&Expression> = sort_expressions.clone();
+            checked_expressions.extend(select.having.as_ref());
+            for item in &select.projection {
+                match item {
+                    SelectItem::Wildcard => {
+                        if let Some(binding) = bindings.first() {
+                            return Err(ExecutionError::UngroupedColumn {
+                                column_name: binding.name.to_string(),
+                            });
+                        }
+                    }
+                    SelectItem::Expression { expression, .. } => {
+                        checked_expressions.push(expression)
+                    }
+                }
+            }
+            for expression in checked_expressions {
+                check_grouped(expression, &select.group_by)?;
+            }
+            for group in group_rows(rows, &bindings, &select.group_by)? {
+                // Columns outside of aggregates are read from the first row of the group. A group
+                // can only be empty when there is no GROUP BY, in which case no column is read.
+                let first_row = group
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| vec![Value::Null; bindings.len()]);
+                let evaluate_in_group = |expression: &Expression| {
+                    let substituted =
+                        aggregates::substitute_aggregates(expression, &bindings, &group)?;
+                    let row = RowContext {
+                        bindings: &bindings,
+                        values: &first_row,
+                    };
+                    evaluator::evaluate(&substituted, &row)
+                };
+                if let Some(having) = &select.having
+                    && evaluate_in_group(having)? != Value::Bool(true)
+                {
+                    continue;
+                }
+                let mut projected = Vec::new();
+                for item in &select.projection {
+                    if let SelectItem::Expression { expression, .. } = item {
+                        projected.push(evaluate_in_group(expression)?);
+                    }
+                }
+                let keys = sort_expressions
+                    .iter()
+                    .map(|expression| evaluate_in_group(expression))
+                    .collect::<Result<Vec<Value>, ExecutionError>>()?;
+                sortable_rows.push((keys, projected));
+            }
+        } else {
+            for values in rows {
+                let row = RowContext {
+                    bindings: &bindings,
+                    values: &values,
+                };
+                let keys = sort_expressions
+                    .iter()
+                    .map(|expression| evaluator::evaluate(expression, &row))
+                    .collect::<Result<Vec<Value>, ExecutionError>>()?;
+                sortable_rows.push((keys, project_row(&select.projection, &row)?));
+            }
+        }
+
+        if select.distinct {
+            let mut output_collations = Vec::new();
+            for item in &select.projection {
+                match item {
+                    SelectItem::Wildcard => {
+                        output_collations.extend(bindings.iter().map(|binding| binding.collation))
+                    }
+                    SelectItem::Expression { expression, .. } => output_collations
+                        .push(evaluator::collation_of(expression, &bindings).unwrap_or_default()),
+                }
+            }
+            let compare_rows = |left: &[Value], right: &[Value]| {
+                left.iter()
+                    .zip(right)
+                    .zip(&output_collations)
+                    .map(|((left, right), collation)| {
+                        evaluator::compare_for_sort(left, right, *collation)
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            };
+            sortable_rows.sort_by(|(_, left), (_, right)| compare_rows(left, right));
+            sortable_rows.dedup_by(|(_, row), (_, kept_row)| compare_rows(row, kept_row).is_eq());
+        }
+        let sort_collations: Vec<Collation> = sort_expressions
+            .iter()
+            .map(|expression| evaluator::collation_of(expression, &bindings).unwrap_or_default())
+            .collect();
+        sortable_rows.sort_by(|(left_keys, _), (right_keys, _)| {
+            left_keys
+                .iter()
+                .zip(right_keys)
+                .zip(select.order_by.iter().zip(&sort_collations))
+                .map(|((left, right), (item, collation))| {
+                    compare_sort_keys(left, right, item, *collation)
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mut rows: Vec<Vec<Value>> = sortable_rows.into_iter().map(|(_, row)| row).collect();
+        if let Some(limit) = select.limit {
+            rows.truncate(limit as usize);
+        }
+        Ok(ResultSet { columns, rows })
+    }
+
+    /// Produces the rows of a FROM clause, cross joining its items from left to right. A single
+    /// table is read through one of its indexes when the WHERE clause allows it. Columns of
+    /// columnar tables which `read_expressions` do not reference are not read, and left NULL.
+    fn scan_from_items(
+        &mut self,
+        from: &[FromItem],
+        filter: Option<&Expression>,
+        read_expressions: Option<&[&Expression]>,
+    ) -> Result<(Vec<ColumnBinding>, Vec<Vec<Value>>), ExecutionError> {
+        let mut bindings: Vec<ColumnBinding> = Vec::new();
+        let mut rows: Vec<Vec<Value>> = vec![Vec::new()];
+        for item in from {
+            match item {
+                FromItem::Table { name, alias } => {
+                    let table_bindings = self.table_bindings(name, alias.as_deref())?;
+                    let read_columns: Vec<bool> = self
+                        .table_columns(name)?
+                        .iter()
+                        .map(|column| {
+                            read_expressions.is_none_or(|expressions| {
+                                expressions
+                                    .iter()
+                                    .any(|expression| expression.references_column(&column.name))
+                            })
+                        })
+                        .collect();
+                    let table_rows: Vec<Vec<Value>> = match filter {
+                        Some(filter) if from.len() == 1 => self
+                            .candidate_rows(name, &table_bindings, Some(filter), &read_columns)?
+                            .into_iter()
+                            .map(|(_, row)| row)
+                            .collect(),
+                        _ => {
+                            self.lock_read_table(name)?;
+                            self.database
+                                .scan_columns(name, &read_columns, &[])?
+                                .into_iter()
+                                .map(|(_, row)| row)
+                                .collect()
+                        }
+                    };
+                    bindings.extend(table_bindings);
+                    rows = rows
+                        .into_iter()
+                        .flat_map(|left| {
+                            table_rows
+                                .iter()
+                                .map(move |right| [left.as_slice(), right].concat())
+                        })
+                        .collect();
+                }
+                FromItem::Function {
+                    name,
+                    arguments,
+                    alias,
+                } => {
+                    let mut joined_rows = Vec::new();
+                    for left in rows {
+                        let row = RowContext {
+                            bindings: &bindings,
+                            values: &left,
+                        };
+                        let arguments = arguments
+                            .iter()
+                            .map(|argument| evaluator::evaluate(argument, &row))
+                            .collect::<Result<Vec<Value>, ExecutionError>>()?;
+                        let table = functions::call_table_function(name, arguments)?;
+                        joined_rows.extend(
+                            table
+                                .rows
+                                .into_iter()
+                                .map(|right| [left.as_slice(), &right].concat()),
+                        );
+                    }
+                    let binding_table = alias.clone().unwrap_or_else(|| name.to_string());
+                    bindings.extend(functions::table_function_columns(name)?.into_iter().map(
+                        |column| ColumnBinding {
+                            table: Some(binding_table.clone()),
+                            name: column,
+                            collation: Collation::default(),
+                            enum_type: None,
+                        },
+                    ));
+                    rows = joined_rows;
+                }
+            }
+        }
+        Ok((bindings, rows))
+    }
+
+    /// Rows of a table that may satisfy a WHERE clause: the ones within the range of keys it
+    /// restricts rows to in the primary key or in a secondary index, read through that index, or
+    /// else every row of the table. The index having the most key parts compared for equality is
+    /// preferred, then one whose next part is compared with a range. Scans of a columnar table
+    /// only read the columns flagged in `read_columns` (all of them when it is empty), and skip
+    /// the blocks of rows which the WHERE clause rules out.
+    fn candidate_rows(
+        &mut self,
+        table_name: &str,
+        bindings: &[ColumnBinding],
+        filter: Option<&Expression>,
+        read_columns: &[bool],
+    ) -> Result<Vec<(RowId, Vec<Value>)>, ExecutionError> {
+        self.lock_read_table(table_name)?;
+        let Some(filter) = filter else {
+            return Ok(self.database.scan_columns(table_name, read_columns, &[])?);
+        };
+        let columns = self.table_columns(table_name)?;
+        // Parts of the key of every index, the primary key being the index without a name
+        let mut keys: Vec<(Option<String>, Vec<Expression>)> = Vec::new();
+        let primary_key: Vec<Expression> = columns
+            .iter()
+            .filter(|column| column.is_primary_key)
+            .map(|column| Expression::Column {
+                table: None,
+                name: column.name.to_string(),
+            })
+            .collect();
+        if !primary_key.is_empty() {
+            keys.push((None, primary_key));
+        }
+        for index in self.table_indexes(table_name)? {
+            let parts = index.columns.into_iter().map(|column| column.expression);
+            keys.push((Some(index.name), parts.collect()));
+        }
+        let rank = |bounds: &KeyBounds| {
+            (
+                bounds.equal.len(),
+                bounds.lower.is_some() || bounds.upper.is_some(),
+            )
+        };
+        let mut best: Option<(Option<String>, KeyBounds)> = None;
+        for (index_name, parts) in &keys {
+            // Parts following one whose type is unknown cannot be used
+            let typed_parts: Vec<(&Expression, DataType)> = parts
+                .iter()
+                .map_while(|part| Some((part, planner::expression_type(part, &columns)?)))
+                .collect();
+            if let Some(bounds) = planner::index_bounds(filter, bindings, &typed_parts)
+                && best
+                    .as_ref()
+                    .is_none_or(|(_, best)| rank(&bounds) > rank(best))
+            {
+                best = Some((index_name.clone(), bounds));
+            }
+        }
+        if let Some((index_name, bounds)) = best {
+            match &index_name {
+                Some(index_name) => {
+                    log::debug!("Reading '{table_name}' through index '{index_name}': {bounds:?}")
+                }
+                None => log::debug!("Reading '{table_name}' through its primary key: {bounds:?}"),
+            }
+            return Ok(self
+                .database
+                .index_range(table_name, index_name.as_deref(), &bounds)?);
+        }
+        let bounds = planner::column_bounds(filter, bindings, &columns);
+        Ok(self
+            .database
+            .scan_columns(table_name, read_columns, &bounds)?)
+    }
+
+    /// Bindings of the columns of a table, qualified by its alias if it has one
+    fn table_bindings(
+        &self,
+        table_name: &str,
+        alias: Option<&str>,
+    ) -> Result<Vec<ColumnBinding>, ExecutionError> {
+        let binding_table = alias.unwrap_or(table_name);
+        self.table_columns(table_name)?
+            .into_iter()
+            .map(|column| {
+                Ok(ColumnBinding {
+                    table: Some(binding_table.to_string()),
+                    enum_type: self.column_enum_type(&column)?,
+                    name: column.name,
+                    collation: column.collation,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Key values of every secondary index of a table for given row
+fn index_values(
+    indexes: &[IndexDefinition],
+    row: &RowContext,
+) -> Result<IndexValues, ExecutionError> {
+    indexes
+        .iter()
+        .map(|index| {
+            index
+                .columns
+                .iter()
+                .map(|column| evaluator::evaluate(&column.expression, row))
+                .collect()
+        })
+        .collect()
+}
+
+/// Makes sure that an indexed expression only reads columns of the table, and always gives the
+/// same result for the same row
+fn check_indexable(
+    index_name: &str,
+    expression: &Expression,
+    bindings: &[ColumnBinding],
+) -> Result<(), ExecutionError> {
+    let invalid = |reason: String| ExecutionError::InvalidIndexExpression {
+        index_name: index_name.to_string(),
+        reason,
+    };
+    match expression {
+        Expression::Column { table, name } => {
+            evaluator::resolve_position(bindings, table.as_deref(), name)?;
+        }
+        expression if expression.is_aggregate_call() => {
+            return Err(invalid(String::from("aggregate functions")));
+        }
+        Expression::Function { name, .. }
+            if ["gen_random_uuid", "uuidv4", "uuidv7"].contains(&name.as_str()) =>
+        {
+            return Err(invalid(format!(
+                "function '{name}', whose result changes with every call"
+            )));
+        }
+        _ => (),
+    }
+    expression
+        .children()
+        .into_iter()
+        .try_for_each(|child| check_indexable(index_name, child, bindings))
+}
+
+fn duplicate_index_key(index: &IndexDefinition, values: &[Value]) -> ExecutionError {
+    ExecutionError::DuplicateKey {
+        constraint: format!("unique index '{}'", index.name),
+        key: values
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Returns the expressions rows are sorted by. An ORDER BY item naming an alias of the select
+/// list stands for the aliased expression.
+fn resolve_order_by_aliases<'select>(
+    order_by: &'select [OrderByItem],
+    projection: &'select [SelectItem],
+) -> Vec<&'select Expression> {
+    order_by
+        .iter()
+        .map(|item| match &item.expression {
+            Expression::Column { table: None, name } => projection
+                .iter()
+                .find_map(|select_item| match select_item {
+                    SelectItem::Expression {
+                        expression,
+                        alias: Some(alias),
+                    } if alias.eq_ignore_ascii_case(name) => Some(expression),
+                    _ => None,
+                })
+                .unwrap_or(&item.expression),
+            expression => expression,
+        })
+        .collect()
+}
+
+/// Compares two sort keys according to an ORDER BY item. Unless told otherwise, NULLs come last
+/// in ascending order and first in descending order.
+fn compare_sort_keys(
+    left: &Value,
+    right: &Value,
+    item: &OrderByItem,
+    collation: Collation,
+) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    let nulls_first = item.nulls_first.unwrap_or(item.descending);
+    match (left, right) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) if nulls_first => Ordering::Less,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) if nulls_first => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ if item.descending => evaluator::compare_for_sort(left, right, collation).reverse(),
+        _ => evaluator::compare_for_sort(left, right, collation),
+    }
+}
+
+/// Splits rows into groups sharing the same GROUP BY values (with respect to their collation),
+/// NULLs being grouped together.
+/// Without any GROUP BY expression, every row (possibly none) belongs to a single group.
+fn group_rows(
+    rows: Vec<Vec<Value>>,
+    bindings: &[ColumnBinding],
+    group_by: &[Expression],
+) -> Result<Vec<Vec<Vec<Value>>>, ExecutionError> {
+    if group_by.is_empty() {
+        return Ok(vec![rows]);
+    }
+    let mut keyed_rows = Vec::new();
+    for values in rows {
+        let row = RowContext {
+            bindings,
+            values: &values,
+        };
+        let key = group_by
+            .iter()
+            .map(|expression| evaluator::evaluate(expression, &row))
+            .collect::<Result<Vec<Value>, ExecutionError>>()?;
+        keyed_rows.push((key, values));
+    }
+    let collations: Vec<Collation> = group_by
+        .iter()
+        .map(|expression| evaluator::collation_of(expression, bindings).unwrap_or_default())
+        .collect();
+    let compare_keys = |left: &[Value], right: &[Value]| {
+        left.iter()
+            .zip(right)
+            .zip(&collations)
+            .map(|((left, right), collation)| evaluator::compare_for_sort(left, right, *collation))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    };
+    keyed_rows.sort_by(|(left, _), (right, _)| compare_keys(left, right));
+    let mut groups: Vec<(Vec<Value>, Vec<Vec<Value>>)> = Vec::new();
+    for (key, values) in keyed_rows {
+        match groups.last_mut() {
+            Some((group_key, group)) if compare_keys(group_key, &key).is_eq() => group.push(values),
+            _ => groups.push((key, vec![values])),
+        }
+    }
+    Ok(groups.into_iter().map(|(_, group)| group).collect())
+}
+
+/// Makes sure that an expression of an aggregate query only reads columns through aggregates or
+/// GROUP BY expressions
+fn check_grouped(expression: &Expression, group_by: &[Expression]) -> Result<(), ExecutionError> {
+    if expression.is_aggregate_call() || group_by.contains(expression) {
+        return Ok(());
+    }
+    if let Expression::Column { name, .. } = expression {
+        return Err(ExecutionError::UngroupedColumn {
+            column_name: name.to_string(),
+        });
+    }
+    expression
+        .children()
+        .into_iter()
+        .try_for_each(|child| check_grouped(child, group_by))
+}
+
+fn project_row(projection: &[SelectItem], row: &RowContext) -> Result<Vec<Value>, ExecutionError> {
+    let mut projected = Vec::new();
+    for item in projection {
+        match item {
+            SelectItem::Wildcard => projected.extend_from_slice(row.values),
+            SelectItem::Expression { expression, .. } => {
+                projected.push(evaluator::evaluate(expression, row)?)
+            }
+        }
+    }
+    Ok(projected)
+}
+
+/// Value given to a column that was not listed in an INSERT statement
+fn default_value(column: &Column, enum_type: &Option<EnumType>) -> Result<Value, ExecutionError> {
+    match &column.default {
+        Some(expression) => {
+            let value = evaluator::evaluate(expression, &RowContext::empty())?;
+            coerce_to_column(value, column, enum_type)
+        }
+        None => Ok(Value::Null),
+    }
+}
+
+/// Converts a value to the type of the column it is inserted in, validating it on the way. Enum
+/// columns come with their type.
+fn coerce_to_column(
+    value: Value,
+    column: &Column,
+    enum_type: &Option<EnumType>,
+) -> Result<Value, ExecutionError> {
+    let type_mismatch = |value: &Value| ExecutionError::TypeMismatch {
+        column_name: column.name.to_string(),
+        data_type: column.data_type.clone(),
+        value: value.to_string(),
+    };
+    coerce_to_type(value, &column.data_type, enum_type, &type_mismatch)
+}
+
+fn coerce_to_type(
+    value: Value,
+    data_type: &DataType,
+    enum_type: &Option<EnumType>,
+    type_mismatch: &dyn Fn(&Value) -> ExecutionError,
+) -> Result<Value, ExecutionError> {
+    match (data_type, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (DataType::Integer, value @ Value::Integer(_)) => Ok(value),
+        (DataType::Float, Value::Integer(integer)) => Ok(Value::Real(integer as f64)),
+        (DataType::Float, value @ Value::Real(_)) => Ok(value),
+        (DataType::Text, value @ Value::Text(_)) => Ok(value),
+        (DataType::Bool, value @ Value::Bool(_)) => Ok(value),
+        (DataType::Json, value @ Value::Json(_)) => Ok(value),
+        (DataType::Json, Value::Text(text)) => Ok(Value::Json(json::parse(&text)?)),
+        (DataType::Uuid, value @ Value::Uuid(_)) => Ok(value),
+        (DataType::Uuid, Value::Text(text)) => Ok(Value::Uuid(evaluator::parse_uuid(&text)?)),
+        (DataType::Blob, value @ Value::Blob(_)) => Ok(value),
+        (DataType::Blob, Value::Text(text)) => Ok(Value::Blob(evaluator::parse_blob(&text)?)),
+        (DataType::Enum(_), Value::Text(label)) => match enum_type {
+            Some(enum_type) => evaluator::to_enum_value(enum_type, &label),
+            None => Err(type_mismatch(&Value::Text(label))),
+        },
+        (DataType::Enum(type_name), Value::Enum(enum_value))
+            if enum_value.type_name.eq_ignore_ascii_case(type_name) =>
+        {
+            Ok(Value::Enum(enum_value))
+        }
+        (DataType::Array(element_type), Value::Array(elements)) => elements
+            .into_iter()
+            .map(|element| coerce_to_type(element, element_type, enum_type, type_mismatch))
+            .collect::<Result<Vec<Value>, ExecutionError>>()
+            .map(Value::Array),
+        (DataType::Array(element_type), Value::Text(text)) => {
+            let elements =
+                array::parse_text(&text).ok_or_else(|| ExecutionError::InvalidArrayLiteral {
+                    content: text.to_string(),
+                })?;
+            elements
+                .into_iter()
+                .map(|element| match element {
+                    None => Ok(Value::Null),
+                    Some(element) => {
+                        let value = parse_array_element(element, element_type)?;
+                        coerce_to_type(value, element_type, enum_type, type_mismatch)
+                    }
+                })
+                .collect::<Result<Vec<Value>, ExecutionError>>()
+                .map(Value::Array)
+        }
+        (_, value) => Err(type_mismatch(&value)),
+    }
+}
+
+/// Reads an element of the textual form of an array as a value of the element type, leaving it
+/// as text when it does not look like one
+fn parse_array_element(element: String, element_type: &DataType) -> Result<Value, ExecutionError> {
+    let parsed = match element_type {
+        DataType::Integer => element.parse().ok().map(Value::Integer),
+        DataType::Float => element.parse().ok().map(Value::Real),
+        DataType::Bool => match element.to_lowercase().as_str() {
+            "t" | "true" => Some(Value::Bool(true)),
+            "f" | "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    };
+    Ok(parsed.unwrap_or(Value::Text(element)))
 }
@@ -1,2 +1,6 @@
+pub mod aggregates;
+pub mod evaluator;
+pub mod functions;
 pub mod instruction_processor;
-
+pub mod planner;
+pub mod session;
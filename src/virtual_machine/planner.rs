@@ -0,0 +1,258 @@
+use crate::sql_compilator::expression::{BinaryOperator, Expression};
+use crate::utils::file_handler::{Column, DataType, Value};
+use crate::utils::index_key::KeyBounds;
+use crate::virtual_machine::evaluator::{self, ColumnBinding, RowContext};
+
+/// Comparison of a column or expression with a constant, found in a WHERE clause
+struct Comparison<'filter> {
+    expression: &'filter Expression,
+    operator: BinaryOperator,
+    value: Value,
+}
+
+/// Finds which keys of an index a WHERE clause restricts rows to, out of its AND-ed comparisons:
+/// leading parts of the key compared for equality with a constant, then at most one part compared
+/// with a range. Rows within the bounds must still be filtered. `key_parts` are the columns or
+/// expressions of the key, in key order, along with their type; a part is only matched by the
+/// same expression, columns excepted which may be qualified.
+pub fn index_bounds(
+    filter: &Expression,
+    bindings: &[ColumnBinding],
+    key_parts: &[(&Expression, DataType)],
+) -> Option<KeyBounds> {
+    let mut comparisons = Vec::new();
+    collect_comparisons(filter, &mut comparisons);
+    let mut bounds = KeyBounds {
+        equal: Vec::new(),
+        lower: None,
+        upper: None,
+    };
+    for (part, data_type) in key_parts {
+        let part_comparisons = || {
+            comparisons.iter().filter_map(|comparison| {
+                let value = key_value(&comparison.value, data_type)?;
+                is_same_part(comparison.expression, part, bindings)
+                    .then_some((&comparison.operator, value))
+            })
+        };
+        if let Some((_, value)) =
+            part_comparisons().find(|(operator, _)| **operator == BinaryOperator::Equal)
+        {
+            bounds.equal.push(value);
+            continue;
+        }
+        bounds.lower = part_comparisons()
+            .find(|(operator, _)| {
+                matches!(
+                    operator,
+                    BinaryOperator::Greater | BinaryOperator::GreaterOrEqual
+                )
+            })
+            .map(|(_, value)| value);
+        bounds.upper = part_comparisons()
+            .find(|(operator, _)| {
+                matches!(operator, BinaryOperator::Less | BinaryOperator::LessOrEqual)
+            })
+            .map(|(_, value)| value);
+        break;
+    }
+    if bounds.equal.is_empty() && bounds.lower.is_none() && bounds.upper.is_none() {
+        return None;
+    }
+    Some(bounds)
+}
+
+/// Bounds a WHERE clause gives to the values of the columns of a table, found like the bounds of
+/// an index on each column alone. Blocks of a columnar table holding no value within the bounds
+/// of a column can be skipped.
+pub fn column_bounds(
+    filter: &Expression,
+    bindings: &[ColumnBinding],
+    columns: &[Column],
+) -> Vec<(usize, KeyBounds)> {
+    columns
+        .iter()
+        .enumerate()
+        .filter_map(|(position, column)| {
+            let part = Expression::Column {
+                table: None,
+                name: column.name.clone(),
+            };
+            let bounds = index_bounds(filter, bindings, &[(&part, column.data_type.clone())])?;
+            Some((position, bounds))
+        })
+        .collect()
+}
+
+/// Whether an expression of a WHERE clause is the part of an index key
+fn is_same_part(expression: &Expression, part: &Expression, bindings: &[ColumnBinding]) -> bool {
+    let position = |table: &Option<String>, name: &str| {
+        evaluator::resolve_position(bindings, table.as_deref(), name).ok()
+    };
+    match (expression, part) {
+        (
+            Expression::Column { table, name },
+            Expression::Column {
+                table: part_table,
+                name: part_name,
+            },
+        ) => position(table, name)
+            .is_some_and(|found| position(part_table, part_name) == Some(found)),
+        (expression, part) => expression == part,
+    }
+}
+
+/// Type of the values of an index key part, when it can be told from the columns of the table
+pub fn expression_type(expression: &Expression, columns: &[Column]) -> Option<DataType> {
+    match expression {
+        Expression::Column { name, .. } => columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(name))
+            .map(|column| column.data_type.clone()),
+        Expression::Collate { operand, .. } => expression_type(operand, columns),
+        Expression::Subscript { operand, .. } => match expression_type(operand, columns)? {
+            DataType::Array(element_type) => Some(*element_type),
+            _ => None,
+        },
+        Expression::Binary {
+            operator: BinaryOperator::Concat | BinaryOperator::JsonGetText,
+            ..
+        } => Some(DataType::Text),
+        Expression::Function { name, .. }
+            if name == "array_length" || name == "json_array_length" =>
+        {
+            Some(DataType::Integer)
+        }
+        _ => None,
+    }
+}
+
+/// Collects the `expression <operator> constant` comparisons of a conjunction, constants being
+/// expressions without columns nor function calls (which may not give the same result for every
+/// row) nor explicit collations (which may not be the one of the index)
+fn collect_comparisons<'filter>(
+    expression: &'filter Expression,
+    comparisons: &mut Vec<Comparison<'filter>>,
+) {
+    let Expression::Binary {
+        left,
+        operator,
+        right,
+    } = expression
+    else {
+        return;
+    };
+    if *operator == BinaryOperator::And {
+        collect_comparisons(left, comparisons);
+        collect_comparisons(right, comparisons);
+        return;
+    }
+    if !operator.is_comparison() || *operator == BinaryOperator::NotEqual {
+        return;
+    }
+    let (expression, constant, operator) = match (is_constant(left), is_constant(right)) {
+        (false, true) => (left.as_ref(), right.as_ref(), operator.clone()),
+        (true, false) => (right.as_ref(), left.as_ref(), flip(operator)),
+        _ => return,
+    };
+    if let Ok(value) = evaluator::evaluate(constant, &RowContext::empty()) {
+        comparisons.push(Comparison {
+            expression,
+            operator,
+            value,
+        });
+    }
+}
+
+fn is_constant(expression: &Expression) -> bool {
+    !matches!(
+        expression,
+        Expression::Column { .. } | Expression::Function { .. } | Expression::Collate { .. }
+    ) && expression.children().into_iter().all(is_constant)
+}
+
+/// Operator giving the same result once both operands are swapped
+fn flip(operator: &BinaryOperator) -> BinaryOperator {
+    match operator {
+        BinaryOperator::Less => BinaryOperator::Greater,
+        BinaryOperator::LessOrEqual => BinaryOperator::GreaterOrEqual,
+        BinaryOperator::Greater => BinaryOperator::Less,
+        BinaryOperator::GreaterOrEqual => BinaryOperator::LessOrEqual,
+        operator => operator.clone(),
+    }
+}
+
+/// Converts a constant to the type of a key column, when both are ordered the same way by the
+/// index and by comparisons
+fn key_value(value: &Value, data_type: &DataType) -> Option<Value> {
+    match (data_type, value) {
+        (DataType::Integer, Value::Integer(_))
+        | (DataType::Float, Value::Real(_))
+        | (DataType::Text, Value::Text(_))
+        | (DataType::Bool, Value::Bool(_))
+        | (DataType::Uuid, Value::Uuid(_))
+        | (DataType::Blob, Value::Blob(_)) => Some(value.clone()),
+        (DataType::Float, Value::Integer(integer)) => Some(Value::Real(*integer as f64)),
+        (DataType::Uuid, Value::Text(text)) => evaluator::parse_uuid(text).ok().map(Value::Uuid),
+        (DataType::Blob, Value::Text(text)) => evaluator::parse_blob(text).ok().map(Value::Blob),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_compilator::parser::{Instruction, Parser};
+    use crate::sql_compilator::tokenizer;
+    use crate::utils::collation::Collation;
+
+    #[test]
+    fn test_index_bounds() {
+        let bindings: Vec<ColumnBinding> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| ColumnBinding {
+                table: Some(String::from("t")),
+                name: name.to_string(),
+                collation: Collation::Binary,
+                enum_type: None,
+            })
+            .collect();
+        let column = |name: &str| Expression::Column {
+            table: None,
+            name: name.to_string(),
+        };
+        let (a, b) = (column("a"), column("b"));
+        let key_parts = [(&a, DataType::Integer), (&b, DataType::Text)];
+        let bounds = |filter: &str| {
+            let tokens =
+                tokenizer::tokenize_user_input(&format!("SELECT * FROM t WHERE {filter};"))
+                    .unwrap();
+            let Some(Instruction::Select(select)) = Parser::new(&tokens).parse_tokens().unwrap()
+            else {
+                panic!("not a select");
+            };
+            index_bounds(&select.filter.unwrap(), &bindings, &key_parts)
+        };
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_eq!(
+            bounds("c = 1 AND 1 + 1 = t.a AND b > 'x' AND b < 'z'"),
+            Some(KeyBounds {
+                equal: vec![Value::Integer(2)],
+                lower: Some(text("x")),
+                upper: Some(text("z")),
+            })
+        );
+        assert_eq!(
+            bounds("a >= 5"),
+            Some(KeyBounds {
+                equal: vec![],
+                lower: Some(Value::Integer(5)),
+                upper: None,
+            })
+        );
+        assert_eq!(bounds("b = 'x'"), None);
+        assert_eq!(bounds("a = 1 OR a = 2"), None);
+        assert_eq!(bounds("a = abs(-1)"), None);
+        assert_eq!(bounds("a < 2.5"), None);
+    }
+}
@@ -0,0 +1,458 @@
+use std::sync::{Arc, Mutex};
+
+use crate::sql_compilator::parser::Instruction;
+use crate::utils::file_handler::{Database, SessionId};
+use crate::utils::lock_manager::LockManager;
+use crate::virtual_machine::instruction_processor::{
+    ExecutionError, ExecutionOutput, InstructionProcessor,
+};
+
+/// Database shared by the sessions of several threads
+pub type SharedDatabase = Arc<Mutex<Database>>;
+
+/// Connection to a shared database, which runs its own transactions. Statements of the sessions
+/// run one at a time, while their transactions interleave: each one reads its own snapshot, and
+/// one transaction writes at a time. A statement needing a lock held by another transaction waits
+/// for it without holding the database, then runs again.
+pub struct Session {
+    database: SharedDatabase,
+    locks: Arc<LockManager>,
+    id: SessionId,
+}
+
+impl Session {
+    pub fn open(database: &SharedDatabase) -> Self {
+        let mut shared = database.lock().unwrap();
+        Session {
+            id: shared.open_session(),
+            locks: shared.lock_manager(),
+            database: Arc::clone(database),
+        }
+    }
+
+    pub fn process_instruction(
+        &self,
+        instruction: &Instruction,
+    ) -> Result<ExecutionOutput, ExecutionError> {
+        loop {
+            let (target, mode, timeout) = {
+                let mut database = self.database.lock().unwrap();
+                match InstructionProcessor::new(&mut database, self.id)
+                    .process_instruction(instruction)
+                {
+                    Err(ExecutionError::LockWait { target, mode }) => {
+                        (target, mode, database.lock_timeout())
+                    }
+                    output => return output,
+                }
+            };
+            if let Err(error) = self.locks.wait(self.id, &target, mode, timeout) {
+                let mut database = self.database.lock().unwrap();
+                return Err(
+                    InstructionProcessor::new(&mut database, self.id).abort_transaction(error)
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    /// Rolls back the transaction left running
+    fn drop(&mut self) {
+        let mut database = self
+            .database
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if let Err(error) = database.close_session(self.id) {
+            log::error!(
+                "Could not roll back the transaction of session {}: {error}",
+                self.id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_compilator::{parser::Parser, tokenizer};
+    use crate::utils::file_handler::Value;
+    use crate::utils::lock_manager::LockError;
+    use crate::utils::storage::MEMORY_PATH;
+    use std::time::Duration;
+
+    fn run(session: &Session, sql: &str) -> Result<Vec<Vec<Value>>, ExecutionError> {
+        let tokens = tokenizer::tokenize_user_input(sql).unwrap();
+        let instruction = Parser::new(&tokens).parse_tokens().unwrap().unwrap();
+        match session.process_instruction(&instruction)? {
+            ExecutionOutput::Rows(result_set) => Ok(result_set.rows),
+            ExecutionOutput::Message(_) => Ok(Vec::new()),
+        }
+    }
+
+    fn open_database() -> SharedDatabase {
+        let mut database = Database::create(MEMORY_PATH, "test", 16).unwrap();
+        database.set_lock_timeout(Duration::from_millis(50));
+        Arc::new(Mutex::new(database))
+    }
+
+    #[test]
+    fn test_sessions_read_snapshots_and_conflict_on_writes() {
+        let database = open_database();
+        let (reader, writer) = (Session::open(&database), Session::open(&database));
+        run(
+            &writer,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);",
+        )
+        .unwrap();
+        run(&writer, "INSERT INTO t (id, n) VALUES (1, 10);").unwrap();
+
+        run(&reader, "BEGIN;").unwrap();
+        let select = "SELECT n FROM t;";
+        assert_eq!(
+            run(&reader, select).unwrap(),
+            vec![vec![Value::Integer(10)]]
+        );
+        run(&writer, "UPDATE t SET n = 20 WHERE id = 1;").unwrap();
+        // The reader keeps its snapshot, and cannot change the row updated since
+        assert_eq!(
+            run(&reader, select).unwrap(),
+            vec![vec![Value::Integer(10)]]
+        );
+        assert!(matches!(
+            run(&reader, "UPDATE t SET n = 30 WHERE id = 1;"),
+            Err(ExecutionError::SerializationFailure { .. })
+        ));
+        run(&reader, "ROLLBACK;").unwrap();
+        assert_eq!(
+            run(&reader, select).unwrap(),
+            vec![vec![Value::Integer(20)]]
+        );
+
+        // A read committed transaction reads a new snapshot at every statement
+        run(&reader, "BEGIN;").unwrap();
+        run(&reader, "SET TRANSACTION ISOLATION LEVEL READ COMMITTED;").unwrap();
+        run(&writer, "UPDATE t SET n = 20 WHERE id = 1;").unwrap();
+        assert_eq!(
+            run(&reader, select).unwrap(),
+            vec![vec![Value::Integer(20)]]
+        );
+        run(&reader, "COMMIT;").unwrap();
+
+        // One transaction writes at a time, the others wait for it
+        run(&writer, "BEGIN;").unwrap();
+        run(&writer, "DELETE FROM t WHERE id = 1;").unwrap();
+        assert!(matches!(
+            run(&reader, "INSERT INTO t (id, n) VALUES (2, 0);"),
+            Err(ExecutionError::Lock(LockError::Timeout { .. }))
+        ));
+        assert_eq!(
+            run(&reader, select).unwrap(),
+            vec![vec![Value::Integer(20)]]
+        );
+        run(&writer, "COMMIT;").unwrap();
+        assert!(run(&reader, select).unwrap().is_empty());
+
+        // No snapshot sees the old versions anymore, so the commit purged them
+        assert!(
+            database
+                .lock()
+                .unwrap()
+                .dead_versions("t")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_serializable_transactions_lock_reads_and_detect_deadlocks() {
+        let database = open_database();
+        database
+            .lock()
+            .unwrap()
+            .set_lock_timeout(Duration::from_secs(10));
+        let sessions = [Session::open(&database), Session::open(&database)];
+        run(
+            &sessions[0],
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);",
+        )
+        .unwrap();
+        run(
+            &sessions[0],
+            "INSERT INTO t (id, n) VALUES (1, 10), (2, 20);",
+        )
+        .unwrap();
+        // Both transactions read a row, then update the one the other read
+        for (session, id) in sessions.iter().zip([1, 2]) {
+            run(session, "BEGIN;").unwrap();
+            run(session, "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;").unwrap();
+            run(session, &format!("SELECT n FROM t WHERE id = {id};")).unwrap();
+        }
+        let results: Vec<Result<Vec<Vec<Value>>, ExecutionError>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = sessions
+                .iter()
+                .zip([2, 1])
+                .map(|(session, id)| {
+                    scope.spawn(move || {
+                        run(session, &format!("UPDATE t SET n = 0 WHERE id = {id};"))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        // One transaction was rolled back, which let the other one go on
+        let victim = results
+            .iter()
+            .position(|result| {
+                matches!(
+                    result,
+                    Err(ExecutionError::Lock(LockError::Deadlock { .. }))
+                )
+            })
+            .expect("a transaction is rolled back");
+        assert!(results[1 - victim].is_ok());
+        assert!(matches!(
+            run(&sessions[victim], "SELECT n FROM t;"),
+            Err(ExecutionError::TransactionAborted)
+        ));
+        run(&sessions[victim], "ROLLBACK;").unwrap();
+        run(&sessions[1 - victim], "COMMIT;").unwrap();
+        let updated = if victim == 0 { 1 } else { 2 };
+        assert_eq!(
+            run(
+                &sessions[victim],
+                &format!("SELECT n FROM t WHERE id = {updated};")
+            )
+            .unwrap(),
+            vec![vec![Value::Integer(0)]]
+        );
+    }
+
+    #[test]
+    fn test_transactions_commit_or_roll_back_created_tables() {
+        let database = open_database();
+        let (session, other) = (Session::open(&database), Session::open(&database));
+        run(&session, "BEGIN;").unwrap();
+        run(&session, "CREATE TABLE x (id INTEGER PRIMARY KEY);").unwrap();
+        run(&session, "INSERT INTO x (id) VALUES (1);").unwrap();
+        assert!(matches!(
+            run(&other, "SELECT id FROM x;"),
+            Err(ExecutionError::TableNotFound { .. })
+        ));
+        run(&session, "ROLLBACK;").unwrap();
+        assert!(matches!(
+            run(&session, "SELECT id FROM x;"),
+            Err(ExecutionError::TableNotFound { .. })
+        ));
+        assert!(
+            !database
+                .lock()
+                .unwrap()
+                .table_names()
+                .contains(&String::from("x"))
+        );
+
+        run(&session, "BEGIN;").unwrap();
+        run(&session, "CREATE TABLE x (id INTEGER PRIMARY KEY);").unwrap();
+        run(&session, "INSERT INTO x (id) VALUES (2);").unwrap();
+        assert!(matches!(
+            run(&session, "BEGIN;"),
+            Err(ExecutionError::TransactionInProgress)
+        ));
+        run(&session, "COMMIT;").unwrap();
+        assert_eq!(
+            run(&other, "SELECT id FROM x;").unwrap(),
+            vec![vec![Value::Integer(2)]]
+        );
+        assert!(matches!(
+            run(&session, "COMMIT;"),
+            Err(ExecutionError::NoTransactionInProgress)
+        ));
+    }
+
+    #[test]
+    fn test_memory_databases_are_independent_and_leave_no_files() {
+        let entries = || {
+            let mut names: Vec<_> = std::fs::read_dir(".")
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            names.sort();
+            names
+        };
+        let before = entries();
+        let (first, second) = (open_database(), open_database());
+        let (first, second) = (Session::open(&first), Session::open(&second));
+        run(&first, "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);").unwrap();
+        // More rows than the buffer pool holds pages, so that pages are written to the storage
+        let rows: Vec<String> = (0..500)
+            .map(|id| format!("({id}, '{}')", "x".repeat(200)))
+            .collect();
+        run(
+            &first,
+            &format!("INSERT INTO t (id, s) VALUES {};", rows.join(", ")),
+        )
+        .unwrap();
+        assert!(matches!(
+            run(&second, "SELECT id FROM t;"),
+            Err(ExecutionError::TableNotFound { .. })
+        ));
+        run(&second, "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);").unwrap();
+        run(&second, "INSERT INTO t (id, s) VALUES (1, 'second');").unwrap();
+        assert_eq!(
+            run(&first, "SELECT count(*) FROM t;").unwrap(),
+            vec![vec![Value::Integer(500)]]
+        );
+        assert_eq!(
+            run(&second, "SELECT s FROM t;").unwrap(),
+            vec![vec![Value::Text(String::from("second"))]]
+        );
+        drop((first, second));
+        assert!(!std::path::Path::new(MEMORY_PATH).exists());
+        assert_eq!(entries(), before);
+    }
+
+    #[test]
+    fn test_unique_columns_are_enforced_by_an_index() {
+        let database = open_database();
+        let session = Session::open(&database);
+        run(
+            &session,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, code TEXT UNIQUE COLLATE nocase);",
+        )
+        .unwrap();
+        let duplicate = |sql: &str| {
+            matches!(
+                run(&session, sql),
+                Err(ExecutionError::DuplicateKey { constraint, .. }) if constraint.contains("t_code_key")
+            )
+        };
+        run(
+            &session,
+            "INSERT INTO t (id, code) VALUES (1, 'a'), (2, 'b'), (3, NULL), (4, NULL);",
+        )
+        .unwrap();
+        assert!(duplicate("INSERT INTO t (id, code) VALUES (5, 'A');"));
+        assert!(duplicate(
+            "INSERT INTO t (id, code) VALUES (5, 'c'), (6, 'C');"
+        ));
+        assert!(duplicate("UPDATE t SET code = 'B' WHERE id = 1;"));
+        // The rows an update replaces no longer hold their values
+        run(&session, "UPDATE t SET code = code || 'x';").unwrap();
+        run(&session, "DELETE FROM t WHERE id = 1;").unwrap();
+        run(&session, "INSERT INTO t (id, code) VALUES (1, 'AX');").unwrap();
+        assert!(run(&session, "DROP INDEX t_code_key;").is_err());
+        assert_eq!(
+            run(&session, "SELECT id FROM t WHERE code = 'bx';").unwrap(),
+            vec![vec![Value::Integer(2)]]
+        );
+    }
+
+    #[test]
+    fn test_uuid_primary_keys() {
+        let database = open_database();
+        let session = Session::open(&database);
+        run(
+            &session,
+            "CREATE TABLE u (id UUID PRIMARY KEY DEFAULT uuidv7(), n INTEGER);",
+        )
+        .unwrap();
+        run(
+            &session,
+            "INSERT INTO u (id, n) VALUES ('0190b6d4-0000-7000-8000-000000000002', 2), \
+             ('0190b6d4-0000-7000-8000-000000000001', 1);",
+        )
+        .unwrap();
+        // The same key written differently is still a duplicate
+        assert!(matches!(
+            run(
+                &session,
+                "INSERT INTO u (id, n) VALUES ('{0190B6D4-0000-7000-8000-000000000001}', 3);"
+            ),
+            Err(ExecutionError::DuplicateKey { .. })
+        ));
+        assert!(matches!(
+            run(&session, "INSERT INTO u (id, n) VALUES ('not a uuid', 3);"),
+            Err(ExecutionError::InvalidUuid { .. })
+        ));
+        run(&session, "INSERT INTO u (n) VALUES (3);").unwrap();
+        run(&session, "INSERT INTO u (n) VALUES (4);").unwrap();
+        assert_eq!(
+            run(
+                &session,
+                "SELECT n FROM u WHERE id = '0190b6d4000070008000000000000002';"
+            )
+            .unwrap(),
+            vec![vec![Value::Integer(2)]]
+        );
+        // Generated keys are created after the fixed ones, and sort after them
+        assert_eq!(
+            run(&session, "SELECT n FROM u ORDER BY id;").unwrap(),
+            (1..=4).map(|n| vec![Value::Integer(n)]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_savepoints_discard_and_keep_changes() {
+        let database = open_database();
+        let (session, other) = (Session::open(&database), Session::open(&database));
+        run(&session, "CREATE TABLE t (id INTEGER PRIMARY KEY);").unwrap();
+        let ids = |session: &Session| -> Vec<i64> {
+            run(session, "SELECT id FROM t ORDER BY id;")
+                .unwrap()
+                .into_iter()
+                .map(|row| match row[0] {
+                    Value::Integer(id) => id,
+                    ref value => panic!("unexpected value {value}"),
+                })
+                .collect()
+        };
+
+        // Nested savepoints: rolling back to the outer one also discards the inner one
+        run(&session, "BEGIN;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (1);").unwrap();
+        run(&session, "SAVEPOINT a;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (2);").unwrap();
+        run(&session, "SAVEPOINT b;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (3);").unwrap();
+        run(&session, "ROLLBACK TO SAVEPOINT b;").unwrap();
+        assert_eq!(ids(&session), [1, 2]);
+        run(&session, "INSERT INTO t (id) VALUES (4);").unwrap();
+        run(&session, "ROLLBACK TO a;").unwrap();
+        assert_eq!(ids(&session), [1]);
+        assert!(run(&session, "RELEASE b;").is_err());
+        // The savepoint rolled back to is kept
+        run(&session, "INSERT INTO t (id) VALUES (5);").unwrap();
+        run(&session, "ROLLBACK TO a;").unwrap();
+        run(&session, "COMMIT;").unwrap();
+        assert_eq!(ids(&session), [1]);
+
+        // Released changes still belong to the transaction, and are discarded with it
+        run(&session, "BEGIN;").unwrap();
+        run(&session, "SAVEPOINT a;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (6);").unwrap();
+        run(&session, "RELEASE SAVEPOINT a;").unwrap();
+        assert_eq!(ids(&session), [1, 6]);
+        assert!(run(&session, "ROLLBACK TO a;").is_err());
+        run(&session, "ROLLBACK;").unwrap();
+        assert_eq!(ids(&session), [1]);
+
+        // Rolling back to a savepoint taken before the first write discards every change, and
+        // the transaction can write again afterwards
+        run(&session, "BEGIN;").unwrap();
+        run(&session, "SAVEPOINT a;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (7);").unwrap();
+        run(&session, "ROLLBACK TO a;").unwrap();
+        assert_eq!(ids(&session), [1]);
+        assert_eq!(ids(&other), [1]);
+        run(&session, "INSERT INTO t (id) VALUES (8);").unwrap();
+        run(&session, "ROLLBACK TO a;").unwrap();
+        run(&session, "INSERT INTO t (id) VALUES (9);").unwrap();
+        assert_eq!(ids(&other), [1]);
+        run(&session, "COMMIT;").unwrap();
+        assert_eq!(ids(&other), [1, 9]);
+    }
+}